@@ -0,0 +1,48 @@
+use bitcoin::bip158::{BitStreamReader, GcsFilterWriter};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kyoto::bench::{decode_golomb_set, GOLOMB_M as M, GOLOMB_P as P};
+
+// A set with as many elements as the scripts of a busy block
+fn encoded_set(n_elements: u32) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut writer = GcsFilterWriter::new(&mut encoded, 7, 11, M, P);
+    for i in 0..n_elements {
+        writer.add_element(&i.to_le_bytes());
+    }
+    writer.finish().unwrap();
+    // Remove the number of elements
+    encoded.split_off(3)
+}
+
+fn decode_bitwise(mut data: &[u8], n_elements: u64) -> Vec<u64> {
+    let mut reader = BitStreamReader::new(&mut data);
+    let mut element = 0;
+    (0..n_elements)
+        .map(|_| {
+            let mut quotient = 0;
+            while reader.read(1).unwrap() == 1 {
+                quotient += 1;
+            }
+            element += (quotient << P) + reader.read(P).unwrap();
+            element
+        })
+        .collect()
+}
+
+fn decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("golomb_rice_decoding");
+    for n_elements in [1_000u32, 5_000, 20_000] {
+        let data = encoded_set(n_elements);
+        let n = u64::from(n_elements);
+        group.bench_with_input(BenchmarkId::new("bitwise", n), &data, |b, data| {
+            b.iter(|| decode_bitwise(black_box(data), n))
+        });
+        group.bench_with_input(BenchmarkId::new("word", n), &data, |b, data| {
+            b.iter(|| decode_golomb_set(black_box(data), n))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decoding);
+criterion_main!(benches);
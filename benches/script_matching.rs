@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+use bitcoin::{
+    bip158::BlockFilter, block::Header, constants::genesis_block, hashes::Hash, transaction,
+    Amount, Block, Network, ScriptBuf, Transaction, TxOut,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kyoto::bench::ScriptMatcher;
+
+fn script(i: u32) -> ScriptBuf {
+    let mut bytes = vec![0x00, 0x14];
+    bytes.extend_from_slice(&i.to_le_bytes());
+    bytes.resize(22, 0);
+    ScriptBuf::from_bytes(bytes)
+}
+
+// A block paying to as many scripts as a busy block, none of them watched
+fn block(n_outputs: u32) -> Block {
+    let output = (0..n_outputs)
+        .map(|i| TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey: script(u32::MAX - i),
+        })
+        .collect();
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: Vec::new(),
+        output,
+    };
+    Block {
+        header: Header {
+            merkle_root: bitcoin::TxMerkleNode::all_zeros(),
+            ..genesis_block(Network::Regtest).header
+        },
+        txdata: vec![tx],
+    }
+}
+
+fn matching(c: &mut Criterion) {
+    let block = block(5_000);
+    let filter = BlockFilter::new_script_filter(&block, |outpoint| {
+        Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+    })
+    .unwrap();
+    let block_hash = block.block_hash();
+    let mut group = c.benchmark_group("script_matching");
+    for n_scripts in [100u32, 1_000, 10_000, 50_000] {
+        let scripts = (0..n_scripts).map(script).collect::<HashSet<ScriptBuf>>();
+        for shard_size in [None, Some(5_000)] {
+            let mut matcher = ScriptMatcher::new(scripts.clone(), shard_size);
+            let label = match shard_size {
+                Some(size) => format!("shards_of_{size}"),
+                None => "one_shard".to_string(),
+            };
+            group.bench_with_input(BenchmarkId::new(label, n_scripts), &filter, |b, filter| {
+                b.iter(|| matcher.matches(black_box(filter), block_hash))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
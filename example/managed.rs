@@ -96,6 +96,6 @@ async fn main() {
             }
         }
     }
-    let _ = requester.shutdown();
+    let _ = requester.shutdown().await;
     tracing::info!("Shutting down");
 }
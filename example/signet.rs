@@ -82,6 +82,7 @@ async fn main() {
                         Event::BlocksDisconnected(_) => {
                             tracing::warn!("Some blocks were reorganized")
                         },
+                        _ => (),
                     }
                 }
             }
@@ -106,6 +107,6 @@ async fn main() {
             }
         }
     }
-    let _ = requester.shutdown();
+    let _ = requester.shutdown().await;
     tracing::info!("Shutting down");
 }
@@ -74,6 +74,7 @@ async fn main() {
                         Event::BlocksDisconnected(_) => {
                             tracing::warn!("Some blocks were reorganized")
                         },
+                        _ => (),
                     }
                 }
             }
@@ -98,6 +99,6 @@ async fn main() {
             }
         }
     }
-    let _ = requester.shutdown();
+    let _ = requester.shutdown().await;
     tracing::info!("Shutting down");
 }
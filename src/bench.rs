@@ -0,0 +1,47 @@
+//! Internal routines exposed to the benchmarks. These are not a stable API.
+
+#[cfg(not(feature = "filter-control"))]
+use std::collections::HashSet;
+
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::{bip158::BlockFilter, BlockHash, ScriptBuf};
+
+#[cfg(not(feature = "filter-control"))]
+use crate::chain::{golomb, script_shards::ScriptShards, Filter};
+
+/// The number of bits of the remainder of each element of a basic filter.
+#[cfg(not(feature = "filter-control"))]
+pub const GOLOMB_P: u8 = golomb::P;
+
+/// The inverse of the false positive rate of a basic filter.
+#[cfg(not(feature = "filter-control"))]
+pub const GOLOMB_M: u64 = golomb::M;
+
+/// Decode the sorted elements of a Golomb-Rice coded set, without the leading number of elements.
+#[cfg(not(feature = "filter-control"))]
+pub fn decode_golomb_set(data: &[u8], n_elements: u64) -> Option<Vec<u64>> {
+    golomb::decode(data, n_elements)
+}
+
+/// Check filters for a set of scripts split into shards, as the node does while syncing.
+#[cfg(not(feature = "filter-control"))]
+#[derive(Debug)]
+pub struct ScriptMatcher {
+    shards: ScriptShards,
+}
+
+#[cfg(not(feature = "filter-control"))]
+impl ScriptMatcher {
+    /// Split the scripts into shards of at most `shard_size` scripts.
+    pub fn new(scripts: HashSet<ScriptBuf>, shard_size: Option<usize>) -> Self {
+        Self {
+            shards: ScriptShards::new(scripts, shard_size),
+        }
+    }
+
+    /// If any of the scripts are in the filter of the block.
+    pub fn matches(&mut self, filter: &BlockFilter, block_hash: BlockHash) -> bool {
+        let filter = Filter::new(filter.content.clone(), block_hash);
+        self.shards.matches(&filter).unwrap_or(false)
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashSet, fmt::Debug};
+
+use bitcoin::{Block, BlockHash};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{error::BlockSourceError, prelude::FutureResult};
+
+// A block fetched from the block source, or the reason it was not
+pub(crate) type FetchedBlock = (BlockHash, Result<Block, BlockSourceError>);
+
+/// A source of block bodies other than the peer-to-peer network, such as a local archive,
+/// a content delivery network, or a trusted Bitcoin Core node.
+///
+/// Block headers and compact block filters are always downloaded from peers. When a block
+/// matches a filter, the node first asks the configured [`BlockSource`] for the block. If the
+/// source fails to provide the block, the block is requested from a random peer instead.
+/// Blocks are checked against the chain of most work before they are sent to the client.
+pub trait BlockSource: Debug + Send + Sync {
+    /// Fetch the block with the given hash.
+    fn get_block(&mut self, hash: BlockHash) -> FutureResult<Block, BlockSourceError>;
+}
+
+// Fetches blocks from the block source on a task of its own, so a slow source does not hold up the
+// node. The results are sent back to the node through a channel.
+#[derive(Debug)]
+pub(crate) struct BlockFetcher {
+    requests: UnboundedSender<BlockHash>,
+    // Blocks requested of the source that it has not answered yet
+    pending: HashSet<BlockHash>,
+}
+
+impl BlockFetcher {
+    pub(crate) fn spawn(
+        mut source: Box<dyn BlockSource>,
+    ) -> (Self, UnboundedReceiver<FetchedBlock>) {
+        let (requests, mut request_rx) = mpsc::unbounded_channel::<BlockHash>();
+        let (fetched_tx, fetched_rx) = mpsc::unbounded_channel::<FetchedBlock>();
+        tokio::task::spawn(async move {
+            while let Some(hash) = request_rx.recv().await {
+                let fetched = source.get_block(hash).await;
+                if fetched_tx.send((hash, fetched)).is_err() {
+                    return;
+                }
+            }
+        });
+        let fetcher = Self {
+            requests,
+            pending: HashSet::new(),
+        };
+        (fetcher, fetched_rx)
+    }
+
+    // Ask the source for a block. Returns false if the block should be requested from a peer
+    // instead, such as when the source has not answered an earlier request for the block.
+    pub(crate) fn fetch(&mut self, hash: BlockHash) -> bool {
+        if self.pending.contains(&hash) || self.requests.send(hash).is_err() {
+            return false;
+        }
+        self.pending.insert(hash);
+        true
+    }
+
+    // The source answered the request for a block
+    pub(crate) fn answered(&mut self, hash: &BlockHash) {
+        self.pending.remove(hash);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use bitcoin::{constants::genesis_block, Network};
+
+    use super::*;
+
+    impl BlockSource for HashMap<BlockHash, Block> {
+        fn get_block(&mut self, hash: BlockHash) -> FutureResult<Block, BlockSourceError> {
+            let block = self.get(&hash).cloned();
+            async fn do_impl(block: Option<Block>) -> Result<Block, BlockSourceError> {
+                block.ok_or(BlockSourceError::NotFound)
+            }
+            Box::pin(do_impl(block))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_blocks() {
+        let genesis = genesis_block(Network::Regtest);
+        let hash = genesis.block_hash();
+        let mut source: Box<dyn BlockSource> = Box::new(HashMap::from([(hash, genesis)]));
+        let block = source.get_block(hash).await.unwrap();
+        assert_eq!(block.block_hash(), hash);
+        assert!(block.check_merkle_root());
+        let unknown = block.header.prev_blockhash;
+        assert!(matches!(
+            source.get_block(unknown).await,
+            Err(BlockSourceError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetcher_sends_results_back() {
+        let genesis = genesis_block(Network::Regtest);
+        let hash = genesis.block_hash();
+        let source = Box::new(HashMap::from([(hash, genesis)]));
+        let (mut fetcher, mut fetched) = BlockFetcher::spawn(source);
+        assert!(fetcher.fetch(hash));
+        // A block the source has not answered for yet is requested from a peer instead
+        assert!(!fetcher.fetch(hash));
+        let (fetched_hash, block) = fetched.recv().await.unwrap();
+        assert_eq!(fetched_hash, hash);
+        assert_eq!(block.unwrap().block_hash(), hash);
+        fetcher.answered(&hash);
+        let unknown = genesis_block(Network::Bitcoin).block_hash();
+        assert!(fetcher.fetch(unknown));
+        let (fetched_hash, block) = fetched.recv().await.unwrap();
+        assert_eq!(fetched_hash, unknown);
+        assert!(matches!(block, Err(BlockSourceError::NotFound)));
+    }
+}
@@ -1,23 +1,236 @@
-use crate::TxBroadcast;
+use std::collections::{HashMap, HashSet};
 
+use bitcoin::{Block, BlockHash, OutPoint, Transaction, Txid};
+
+use crate::{db::PersistedBroadcast, HeaderCheckpoint, TxBroadcast};
+
+// Confirmed transactions are sent again if their block is reorganized up to this deep
+const CONFIRMED_DEPTH: u32 = 100;
+// A transaction that is not confirmed after it was sent this many times is forgotten
+const MAX_ATTEMPTS: u32 = 10;
+
+// The unconfirmed transactions found in a block, to be confirmed once the block is checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Confirmation {
+    height: u32,
+    block_hash: BlockHash,
+    txids: Vec<Txid>,
+    // Unconfirmed transactions that spend an output the block also spends
+    conflicts: Vec<Txid>,
+}
+
+#[derive(Debug, Clone)]
+struct Unconfirmed {
+    tx: TxBroadcast,
+    // The number of times the transaction was sent to peers
+    attempts: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Confirmed {
+    height: u32,
+    block_hash: BlockHash,
+    tx: Unconfirmed,
+}
+
+// Transactions waiting to be sent to peers, and every transaction added that has not been seen in
+// a block yet. The unconfirmed transactions are persisted, so they are sent again if the node
+// stops before they confirm. Confirmed transactions are kept and persisted for a while, so they
+// are sent again if their block is reorganized out of the chain, even after a restart.
 #[derive(Debug, Clone)]
 pub(crate) struct Broadcaster {
     queue: Vec<TxBroadcast>,
+    unconfirmed: HashMap<Txid, Unconfirmed>,
+    confirmed: HashMap<Txid, Confirmed>,
+    // The transactions changed since they were last persisted
+    changed: bool,
 }
 
 impl Broadcaster {
     pub(crate) fn new() -> Self {
-        Self { queue: Vec::new() }
+        Self {
+            queue: Vec::new(),
+            unconfirmed: HashMap::new(),
+            confirmed: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    // Queue a transaction, returning the IDs of the unconfirmed transactions it replaces
+    pub(crate) fn add(&mut self, tx: TxBroadcast) -> Vec<Txid> {
+        let txid = tx.tx.compute_txid();
+        let replaced = self.conflicts_with(&spent_by(&tx.tx), |id| id.ne(&txid));
+        self.forget(&replaced);
+        let unconfirmed = Unconfirmed {
+            tx: tx.clone(),
+            attempts: 1,
+        };
+        self.unconfirmed.insert(txid, unconfirmed);
+        self.changed = true;
+        self.queue.push(tx);
+        replaced
+    }
+
+    // Queue the transactions that were not confirmed when the node last stopped, and track the
+    // confirmed transactions, returning the IDs of the transactions that were sent too many times
+    // to be sent again
+    pub(crate) fn load(&mut self, broadcasts: Vec<PersistedBroadcast>) -> Vec<Txid> {
+        let mut expired = Vec::new();
+        for persisted in broadcasts {
+            let txid = persisted.broadcast.tx.compute_txid();
+            if self.unconfirmed.contains_key(&txid) || self.confirmed.contains_key(&txid) {
+                continue;
+            }
+            if let Some(block) = persisted.confirmed {
+                let confirmed = Confirmed {
+                    height: block.height,
+                    block_hash: block.hash,
+                    tx: Unconfirmed {
+                        tx: persisted.broadcast,
+                        attempts: persisted.attempts,
+                    },
+                };
+                self.confirmed.insert(txid, confirmed);
+                continue;
+            }
+            // The transaction is dropped from the store with the next write
+            self.changed = true;
+            if persisted.attempts >= MAX_ATTEMPTS {
+                expired.push(txid);
+                continue;
+            }
+            let unconfirmed = Unconfirmed {
+                tx: persisted.broadcast.clone(),
+                attempts: persisted.attempts + 1,
+            };
+            self.unconfirmed.insert(txid, unconfirmed);
+            self.queue.push(persisted.broadcast);
+        }
+        expired
     }
 
-    pub(crate) fn add(&mut self, tx: TxBroadcast) {
-        self.queue.push(tx)
+    // The unconfirmed transactions in the block at this height, and those it conflicts with
+    pub(crate) fn confirmed_in(&self, height: u32, block: &Block) -> Confirmation {
+        let mut confirmation = Confirmation {
+            height,
+            block_hash: block.block_hash(),
+            txids: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        if self.unconfirmed.is_empty() {
+            return confirmation;
+        }
+        let txids: HashSet<Txid> = block.txdata.iter().map(|tx| tx.compute_txid()).collect();
+        confirmation.txids = self
+            .unconfirmed
+            .keys()
+            .filter(|txid| txids.contains(*txid))
+            .copied()
+            .collect();
+        let spent = block.txdata.iter().flat_map(spent_by).collect();
+        confirmation.conflicts = self.conflicts_with(&spent, |txid| !txids.contains(txid));
+        confirmation
+    }
+
+    // Confirm the transactions in a checked block, returning the IDs of the transactions that can
+    // no longer confirm
+    pub(crate) fn confirm(&mut self, confirmation: Confirmation) -> Vec<Txid> {
+        self.forget(&confirmation.conflicts);
+        for txid in confirmation.txids {
+            if let Some(tx) = self.unconfirmed.remove(&txid) {
+                self.changed = true;
+                let confirmed = Confirmed {
+                    height: confirmation.height,
+                    block_hash: confirmation.block_hash,
+                    tx,
+                };
+                self.confirmed.insert(txid, confirmed);
+            }
+        }
+        let tracked = self.confirmed.len();
+        self.confirmed
+            .retain(|_, confirmed| confirmed.height + CONFIRMED_DEPTH > confirmation.height);
+        self.changed |= self.confirmed.len() < tracked;
+        confirmation.conflicts
+    }
+
+    // The unconfirmed transactions that spend any of the outputs
+    fn conflicts_with(
+        &self,
+        spent: &HashSet<OutPoint>,
+        include: impl Fn(&Txid) -> bool,
+    ) -> Vec<Txid> {
+        self.unconfirmed
+            .iter()
+            .filter(|(txid, _)| include(txid))
+            .filter(|(_, unconfirmed)| {
+                unconfirmed
+                    .tx
+                    .tx
+                    .input
+                    .iter()
+                    .any(|input| spent.contains(&input.previous_output))
+            })
+            .map(|(txid, _)| *txid)
+            .collect()
+    }
+
+    // Stop sending the transactions, which were replaced or conflict with a block
+    fn forget(&mut self, txids: &[Txid]) {
+        for txid in txids {
+            if self.unconfirmed.remove(txid).is_some() {
+                self.changed = true;
+            }
+        }
+        self.queue
+            .retain(|tx| !txids.contains(&tx.tx.compute_txid()));
+    }
+
+    // Queue the transactions confirmed in blocks that were reorganized out of the chain, returning
+    // their IDs
+    pub(crate) fn unconfirm(&mut self, disconnected: &[BlockHash]) -> Vec<Txid> {
+        let txids = self
+            .confirmed
+            .iter()
+            .filter(|(_, confirmed)| disconnected.contains(&confirmed.block_hash))
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<Txid>>();
+        for txid in &txids {
+            if let Some(confirmed) = self.confirmed.remove(txid) {
+                let mut unconfirmed = confirmed.tx;
+                unconfirmed.attempts += 1;
+                self.queue.push(unconfirmed.tx.clone());
+                self.unconfirmed.insert(*txid, unconfirmed);
+                self.changed = true;
+            }
+        }
+        txids
+    }
+
+    // Every unconfirmed or recently confirmed transaction, if any were added or confirmed since
+    // the last call
+    pub(crate) fn take_changed(&mut self) -> Option<Vec<PersistedBroadcast>> {
+        if !core::mem::take(&mut self.changed) {
+            return None;
+        }
+        let unconfirmed = self.unconfirmed.values().map(|unconfirmed| {
+            PersistedBroadcast::new(unconfirmed.tx.clone(), unconfirmed.attempts, None)
+        });
+        let confirmed = self.confirmed.values().map(|confirmed| {
+            let block = HeaderCheckpoint::new(confirmed.height, confirmed.block_hash);
+            PersistedBroadcast::new(confirmed.tx.tx.clone(), confirmed.tx.attempts, Some(block))
+        });
+        Some(unconfirmed.chain(confirmed).collect())
     }
 
     pub(crate) fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+
     pub(crate) fn queue(&mut self) -> Vec<TxBroadcast> {
         core::mem::take(&mut self.queue)
     }
@@ -28,13 +241,18 @@ impl Broadcaster {
     }
 }
 
+// The outputs spent by a transaction
+fn spent_by(tx: &Transaction) -> HashSet<OutPoint> {
+    tx.input.iter().map(|input| input.previous_output).collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use bitcoin::{consensus::deserialize, Transaction};
+    use bitcoin::{consensus::deserialize, hashes::Hash, Transaction};
 
     use crate::TxBroadcast;
 
-    use super::Broadcaster;
+    use super::*;
 
     #[test]
     fn test_broadcast_queue_works() {
@@ -59,4 +277,108 @@ mod tests {
         queue.add(tx_2);
         assert!(!queue.is_empty());
     }
+
+    #[test]
+    fn test_unconfirmed_broadcasts_are_tracked() {
+        let mut block = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let coinbase = block.txdata[0].clone();
+        let txid = coinbase.compute_txid();
+        let mut queue = Broadcaster::new();
+        assert!(queue.take_changed().is_none());
+        queue.add(TxBroadcast::random_broadcast(coinbase.clone()));
+        assert_eq!(queue.take_changed().unwrap().len(), 1);
+        assert!(queue.take_changed().is_none());
+        // Sending the transaction does not confirm it
+        assert_eq!(queue.queue().len(), 1);
+        // Loading a transaction already tracked does not queue it again
+        let persisted =
+            PersistedBroadcast::new(TxBroadcast::random_broadcast(coinbase.clone()), 1, None);
+        assert!(queue.load(vec![persisted.clone()]).is_empty());
+        assert!(queue.is_empty());
+        assert!(queue.take_changed().is_none());
+        let confirmation = queue.confirmed_in(0, &block);
+        assert_eq!(confirmation.txids, vec![txid]);
+        queue.confirm(confirmation);
+        // The confirmed transaction is persisted with its block
+        let confirmed = queue.take_changed().unwrap();
+        assert_eq!(confirmed.len(), 1);
+        let block_0 = HeaderCheckpoint::new(0, block.block_hash());
+        assert_eq!(confirmed[0].confirmed, Some(block_0));
+        assert!(queue.confirmed_in(0, &block).txids.is_empty());
+        // The confirmed transaction is sent again if its block is reorganized after a restart
+        let mut restarted = Broadcaster::new();
+        assert!(restarted.load(confirmed).is_empty());
+        assert!(restarted.is_empty());
+        assert_eq!(restarted.unconfirm(&[block.block_hash()]), vec![txid]);
+        assert_eq!(restarted.len(), 1);
+        // The transaction is sent again if its block is reorganized
+        assert!(queue.unconfirm(&[BlockHash::all_zeros()]).is_empty());
+        assert_eq!(queue.unconfirm(&[block.block_hash()]), vec![txid]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.take_changed().unwrap().len(), 1);
+        // Transactions buried deep enough are forgotten
+        queue.confirm(queue.confirmed_in(0, &block));
+        let mut later = block.clone();
+        later.header.nonce += 1;
+        queue.confirm(queue.confirmed_in(CONFIRMED_DEPTH, &later));
+        assert!(queue.unconfirm(&[block.block_hash()]).is_empty());
+        assert!(queue.take_changed().unwrap().is_empty());
+        // Transactions loaded after a restart are sent again, counting the attempt
+        let mut restarted = Broadcaster::new();
+        assert!(restarted.load(vec![persisted]).is_empty());
+        assert_eq!(restarted.len(), 1);
+        assert_eq!(restarted.take_changed().unwrap()[0].attempts, 2);
+        block.txdata.clear();
+        assert!(restarted.confirmed_in(0, &block).txids.is_empty());
+    }
+
+    #[test]
+    fn test_replaced_broadcasts_are_forgotten() {
+        let block = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let spend = |value: u64| Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: OutPoint::new(block.txdata[0].compute_txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(value),
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        };
+        let original = spend(1_000);
+        let replacement = spend(900);
+        let mut queue = Broadcaster::new();
+        assert!(queue
+            .add(TxBroadcast::random_broadcast(original.clone()))
+            .is_empty());
+        // A transaction spending the same output replaces the one that was not sent yet
+        assert_eq!(
+            queue.add(TxBroadcast::random_broadcast(replacement.clone())),
+            vec![original.compute_txid()]
+        );
+        let queued = queue.queue();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].tx, replacement);
+        // A block with another spend of the output conflicts with the transaction
+        let mut conflicting = block.clone();
+        conflicting.txdata.push(original.clone());
+        let confirmation = queue.confirmed_in(1, &conflicting);
+        assert!(confirmation.txids.is_empty());
+        assert_eq!(
+            queue.confirm(confirmation),
+            vec![replacement.compute_txid()]
+        );
+        assert!(queue.take_changed().unwrap().is_empty());
+        // Transactions sent too many times are not sent again
+        let persisted = PersistedBroadcast::new(
+            TxBroadcast::random_broadcast(original.clone()),
+            MAX_ATTEMPTS,
+            None,
+        );
+        assert_eq!(queue.load(vec![persisted]), vec![original.compute_txid()]);
+        assert!(queue.is_empty());
+        assert!(queue.take_changed().unwrap().is_empty());
+    }
 }
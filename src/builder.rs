@@ -1,29 +1,78 @@
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
 use std::{path::PathBuf, time::Duration};
 
-use bitcoin::Network;
+use bitcoin::p2p::address::AddrV2;
+use bitcoin::{BlockHash, Network};
 #[cfg(not(feature = "filter-control"))]
-use bitcoin::ScriptBuf;
+use bitcoin::{NetworkKind, OutPoint, ScriptBuf};
 
-use super::{client::Client, config::NodeConfig, node::Node};
-#[cfg(feature = "rusqlite")]
-use crate::db::error::SqlInitializationError;
+use super::{
+    client::{Client, ClientChannels},
+    config::{tip_agreement_reachable, NodeConfig, MAX_PEERS},
+    node::Node,
+};
 #[cfg(feature = "rusqlite")]
 use crate::db::sqlite::{headers::SqliteHeaderDb, peers::SqlitePeerDb};
 use crate::network::dns::{DnsResolver, DNS_RESOLVER_PORT};
 use crate::network::ConnectionType;
+#[cfg(feature = "rest")]
+use crate::{block_source::BlockSource, network::rest::RestClient};
+use crate::{
+    chain::checkpoints::{
+        HeaderCheckpoint, MAINNET_HEADER_CP, REGTEST_HEADER_CP, SIGNET_HEADER_CP,
+        TESTNET4_HEADER_CP,
+    },
+    checkpoint_provider::CheckpointProvider,
+    db::traits::{CFHeaderStore, FilterStore, HeaderStore, PeerStore},
+    error::BuilderError,
+    peer_selector::PeerSelector,
+    prelude::MAX_FUTURE_BLOCK_TIME,
+};
+#[cfg(feature = "journal")]
+use crate::{
+    db::{DATA_DIR, DEFAULT_CWD},
+    journal::EventJournal,
+};
+#[cfg(not(feature = "filter-control"))]
+use crate::{filter_matcher::FilterMatcher, XpubWatch};
 use crate::{
-    chain::checkpoints::HeaderCheckpoint,
-    db::traits::{HeaderStore, PeerStore},
+    EventMask, LogLevel, MissingFiltersPolicy, PeerStoreSizeConfig, Preset, Subsystem, TrustedPeer,
 };
-use crate::{LogLevel, PeerStoreSizeConfig, TrustedPeer};
 
 #[cfg(feature = "rusqlite")]
 /// The default node returned from the [`NodeBuilder`].
 pub type NodeDefault = Node<SqliteHeaderDb, SqlitePeerDb>;
 
 const MIN_PEERS: u8 = 1;
-const MAX_PEERS: u8 = 15;
+const LOW_LATENCY_PEERS: u8 = 3;
+const LOW_LATENCY_INTERVAL_SECS: u64 = 30;
+
+const MOBILE_PEERS: u8 = 2;
+const MOBILE_PEER_DB_SIZE: u32 = 256;
+const MOBILE_POLL_INTERVAL_SECS: u64 = 15 * 60;
+const MOBILE_PING_INTERVAL_SECS: u64 = 5 * 60;
+const MOBILE_HANDSHAKE_TIMEOUT_SECS: u64 = 5;
+const MOBILE_RESPONSE_TIMEOUT_SECS: u64 = 15;
+const SERVER_PEERS: u8 = 4;
+const SERVER_TIP_AGREEMENT: u8 = 2;
+const SERVER_POLL_INTERVAL_SECS: u64 = 60;
+const SERVER_MAX_PEER_LAG: u32 = 144;
+const PARANOID_PEERS: u8 = 6;
+const PARANOID_TIP_AGREEMENT: u8 = 3;
+const PARANOID_CONNECTION_TIME_SECS: u64 = 30 * 60;
+const PARANOID_FILTER_SPOT_CHECKS: u32 = 8;
+const PARANOID_REORG_DEPTH: u32 = 100;
+const PARANOID_MAX_PEER_LAG: u32 = 6;
+const REGTEST_INTERVAL_SECS: u64 = 1;
+const REGTEST_TIMEOUT_SECS: u64 = 2;
+
+const KNOWN_CHECKPOINTS: [(Network, &[(u32, &str)]); 4] = [
+    (Network::Bitcoin, MAINNET_HEADER_CP),
+    (Network::Testnet4, TESTNET4_HEADER_CP),
+    (Network::Signet, SIGNET_HEADER_CP),
+    (Network::Regtest, REGTEST_HEADER_CP),
+];
 
 /// Build a [`Node`] in an additive way.
 ///
@@ -114,6 +163,29 @@ impl NodeBuilder {
         self
     }
 
+    /// Watch for the spends of Bitcoin [`OutPoint`](bitcoin::OutPoint), such as the coins of a
+    /// wallet. You may add more later with
+    /// [`Requester::add_outpoints`](crate::Requester::add_outpoints), which describes when the
+    /// spend of an outpoint is found.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn add_outpoints(mut self, outpoints: impl IntoIterator<Item = OutPoint>) -> Self {
+        self.config.outpoints.extend(outpoints);
+        self
+    }
+
+    /// Split the scripts into shards of at most `max_scripts_per_shard` scripts. Each shard is
+    /// checked against compact block filters on its own thread, and the memory used to query a
+    /// filter is bounded by the size of a shard. Wallets with hundreds of thousands of scripts
+    /// may check filters faster with shards, and statistics for each shard are available from the
+    /// [`Requester`](crate::Requester).
+    ///
+    /// If none is provided, all scripts are checked in a single set.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn shard_scripts(mut self, max_scripts_per_shard: usize) -> Self {
+        self.config.script_shard_size = Some(max_scripts_per_shard);
+        self
+    }
+
     /// Add a path to the directory where data should be stored. If none is provided, the current
     /// working directory will be used.
     pub fn data_dir(mut self, path: impl Into<PathBuf>) -> Self {
@@ -124,9 +196,13 @@ impl NodeBuilder {
     /// Add the minimum number of peer connections that should be maintained by the node.
     /// Adding more connections increases the node's anonymity, but requires waiting for more responses,
     /// higher bandwidth, and higher memory requirements. If none is provided, a single connection will be maintained.
-    /// The number of connections will be clamped to a range of 1 to 15.
+    /// The number of connections is capped at 15, and a node always maintains at least one
+    /// connection, so zero is raised to one when the node is built.
+    ///
+    /// Requiring zero peers without adding a trusted peer fails to build with
+    /// [`BuilderError::NoPeers`]. Earlier versions silently raised zero to one in every case.
     pub fn required_peers(mut self, num_peers: u8) -> Self {
-        self.config.required_peers = num_peers.clamp(MIN_PEERS, MAX_PEERS);
+        self.config.required_peers = num_peers.min(MAX_PEERS);
         self
     }
 
@@ -149,12 +225,82 @@ impl NodeBuilder {
         self
     }
 
+    /// Supply checkpoints newer than those compiled into the crate with a [`CheckpointProvider`].
+    /// The provider is asked for checkpoints when the node is built, and again if a peer
+    /// advertises a height far past the newest checkpoint. If the checkpoints are still outdated,
+    /// [`Warning::OutdatedCheckpoints`](crate::Warning::OutdatedCheckpoints) is sent.
+    pub fn checkpoint_provider(mut self, provider: impl CheckpointProvider + 'static) -> Self {
+        self.config.checkpoint_provider = Some(Box::new(provider));
+        self
+    }
+
+    /// Set how many blocks below the anchor checkpoint are loaded from the header store on
+    /// startup and checked against peers again. A reorganization of up to this many blocks while
+    /// the node was offline is sent as [`Event::BlocksDisconnected`](crate::Event::BlocksDisconnected).
+    /// The recent history of [`SyncUpdate`](crate::SyncUpdate) has at least this many headers,
+    /// so a later anchor may be chosen to keep the same depth.
+    ///
+    /// If none is provided, seven blocks are checked again and ten recent headers are reported.
+    /// Headers are only loaded if the header store has them, so a deeper check requires the
+    /// headers below the anchor to have been stored by a previous run.
+    pub fn reorg_depth(mut self, blocks: u32) -> Self {
+        self.config.reorg_depth = Some(blocks);
+        self
+    }
+
+    /// Skip the proof of work check for headers below the most recent checkpoint compiled into
+    /// the library. Headers must still link together and match each checkpoint, so a peer cannot
+    /// substitute a different chain below the checkpoint. This speeds up header sync on devices
+    /// with very slow processors.
+    ///
+    /// If none is provided, the proof of work of every header is checked.
+    pub fn trust_checkpoints(mut self, trust: bool) -> Self {
+        self.config.trust_checkpoints = trust;
+        self
+    }
+
+    /// Reject headers with a timestamp at or below the median time of the eleven blocks before
+    /// them, and penalize the peers that send them. Headers more than `drift` ahead of the
+    /// network-adjusted time are left for a later request without a penalty, as the local clock
+    /// may be behind. The network-adjusted time is the local clock moved by the median offset of
+    /// the clocks of connected peers, which is reported by
+    /// [`Requester::time_offset`](crate::Requester::time_offset). Bitcoin Core allows two hours
+    /// of drift.
+    ///
+    /// If none is provided, header timestamps are not checked.
+    pub fn header_time_drift(mut self, drift: impl Into<Duration>) -> Self {
+        self.config.header_time_drift = Some(drift.into());
+        self
+    }
+
     /// Set the [`LogLevel`]. Omitting log messages may improve performance.
     pub fn log_level(mut self, log_level: LogLevel) -> Self {
         self.config.log_level = log_level;
         self
     }
 
+    /// Set the [`LogLevel`] of a single [`Subsystem`], overriding the level set with
+    /// [`NodeBuilder::log_level`]. For instance, debug messages may be enabled for peers only
+    /// while the rest of the node emits warnings.
+    pub fn subsystem_log_level(mut self, subsystem: Subsystem, log_level: LogLevel) -> Self {
+        self.config
+            .subsystem_log_levels
+            .insert(subsystem, log_level);
+        self
+    }
+
+    /// Include the transactions and blocks of the wallet in log messages and [`Info`](crate::Info).
+    /// By default, block hashes in log messages are redacted, and info that identifies a
+    /// transaction or block of the wallet is not sent, such as
+    /// [`Info::TxGossiped`](crate::Info::TxGossiped) or a request for a block in
+    /// [`Info::RequestSent`](crate::Info::RequestSent). Logs may then be shared without revealing
+    /// the contents of the wallet. The scripts of `Warning::BroadScripts` are left out as well.
+    /// Events are not redacted.
+    pub fn log_sensitive_data(mut self, enabled: bool) -> Self {
+        self.config.log_sensitive_data = enabled;
+        self
+    }
+
     /// Set the time a peer has to complete the initial TCP handshake. Even on unstable
     /// connections this may be fast.
     ///
@@ -193,6 +339,283 @@ impl NodeBuilder {
         self
     }
 
+    /// Set how long a connected peer may go without sending a message before it is sent a ping.
+    /// Peers that do not answer the ping within the response timeout are disconnected.
+    /// Longer intervals save bandwidth and battery, while shorter intervals find dead
+    /// connections sooner.
+    ///
+    /// If none is provided, an interval of two minutes will be used.
+    pub fn ping_interval(mut self, ping_interval: impl Into<Duration>) -> Self {
+        self.config.peer_timeout_config.ping_interval = ping_interval.into();
+        self
+    }
+
+    /// Set how often peers are asked for new block headers after the node is synced, in addition
+    /// to listening for block announcements. Servers that need the latest block as soon as
+    /// possible may poll aggressively, while battery-powered devices may prefer long intervals.
+    ///
+    /// If none is provided, an interval of five minutes will be used.
+    pub fn tip_poll_interval(mut self, tip_poll_interval: impl Into<Duration>) -> Self {
+        self.config.tip_poll_interval = tip_poll_interval.into();
+        self
+    }
+
+    /// Prioritize learning about new blocks as quickly as possible over bandwidth, as may be
+    /// required by Lightning Network nodes. This profile:
+    ///
+    /// - Asks peers to push new blocks in high-bandwidth compact block mode, so the header of a
+    ///   new block is received before the peer has fully validated the block.
+    /// - Polls peers for new headers and pings quiet peers every thirty seconds.
+    /// - Maintains at least three connections, so a single slow peer cannot delay the tip.
+    ///
+    /// New blocks are always announced with headers, and blocks that match a filter are requested
+    /// as soon as the filter is checked. Settings configured after this method take precedence.
+    pub fn low_latency(mut self) -> Self {
+        let interval = Duration::from_secs(LOW_LATENCY_INTERVAL_SECS);
+        self.config.compact_block_announcements = true;
+        self.config.tip_poll_interval = interval;
+        self.config.peer_timeout_config.ping_interval = interval;
+        self.config.required_peers = self.config.required_peers.max(LOW_LATENCY_PEERS);
+        self
+    }
+
+    /// Apply the settings of a [`Preset`] for a common deployment of the node. Settings configured
+    /// after this method take precedence, so a preset may be used as a starting point:
+    ///
+    /// - [`Preset::MobileWallet`]: two connections, a peer store of 256 peers, polling for new
+    ///   headers every fifteen minutes and pinging every five, generous timeouts, and a single
+    ///   block worker.
+    /// - [`Preset::Server`]: four connections, two of which must agree on the tip, polling every
+    ///   minute, rejecting peers more than a day of blocks behind, and searching for peers that
+    ///   serve filters.
+    /// - [`Preset::Paranoid`]: six connections, three of which must agree on the tip, rotating
+    ///   connections every thirty minutes, spot checking eight filters, checking a hundred
+    ///   blocks below the checkpoint on startup, rejecting peers more than six blocks behind,
+    ///   checking the proof of work and timestamp of every header, and redacting wallet data from
+    ///   logs.
+    /// - [`Preset::Regtest`]: a single connection to a node on the local host, polling every
+    ///   second with two second timeouts, and including wallet data in logs.
+    ///
+    /// The stores of the node are chosen when it is built, so a mobile wallet without a database
+    /// may pass in-memory stores to [`NodeBuilder::build_with_databases`].
+    pub fn preset(mut self, preset: Preset) -> Self {
+        match preset {
+            Preset::MobileWallet => {
+                self.config.required_peers = MOBILE_PEERS;
+                self.config.target_peer_size = PeerStoreSizeConfig::Limit(MOBILE_PEER_DB_SIZE);
+                self.config.tip_poll_interval = Duration::from_secs(MOBILE_POLL_INTERVAL_SECS);
+                self.config.peer_timeout_config.ping_interval =
+                    Duration::from_secs(MOBILE_PING_INTERVAL_SECS);
+                self.config.peer_timeout_config.handshake_timeout =
+                    Duration::from_secs(MOBILE_HANDSHAKE_TIMEOUT_SECS);
+                self.config.peer_timeout_config.response_timeout =
+                    Duration::from_secs(MOBILE_RESPONSE_TIMEOUT_SECS);
+                self.config.block_workers = 1;
+            }
+            Preset::Server => {
+                self.config.required_peers = SERVER_PEERS;
+                self.config.tip_agreement = SERVER_TIP_AGREEMENT;
+                self.config.tip_poll_interval = Duration::from_secs(SERVER_POLL_INTERVAL_SECS);
+                self.config.max_peer_lag = Some(SERVER_MAX_PEER_LAG);
+                self.config.missing_filters = MissingFiltersPolicy::Search;
+            }
+            Preset::Paranoid => {
+                self.config.required_peers = PARANOID_PEERS;
+                self.config.tip_agreement = PARANOID_TIP_AGREEMENT;
+                self.config.peer_timeout_config.max_connection_time =
+                    Duration::from_secs(PARANOID_CONNECTION_TIME_SECS);
+                self.config.filter_spot_checks = PARANOID_FILTER_SPOT_CHECKS;
+                self.config.reorg_depth = Some(PARANOID_REORG_DEPTH);
+                self.config.max_peer_lag = Some(PARANOID_MAX_PEER_LAG);
+                self.config.missing_filters = MissingFiltersPolicy::Search;
+                self.config.trust_checkpoints = false;
+                self.config.header_time_drift =
+                    Some(Duration::from_secs(MAX_FUTURE_BLOCK_TIME as u64));
+                self.config.log_sensitive_data = false;
+            }
+            Preset::Regtest => {
+                let interval = Duration::from_secs(REGTEST_INTERVAL_SECS);
+                let timeout = Duration::from_secs(REGTEST_TIMEOUT_SECS);
+                let local = TrustedPeer::from_ip(IpAddr::from(Ipv4Addr::LOCALHOST));
+                self.config.white_list.push(local);
+                self.config.required_peers = MIN_PEERS;
+                self.config.tip_poll_interval = interval;
+                self.config.peer_timeout_config.ping_interval = interval;
+                self.config.peer_timeout_config.handshake_timeout = timeout;
+                self.config.peer_timeout_config.response_timeout = timeout;
+                self.config.stall_timeout = timeout;
+                self.config.log_sensitive_data = true;
+            }
+        }
+        self
+    }
+
+    /// Only send the kinds of [`Event`](crate::Event) in the mask to the client. Other events are
+    /// dropped by the node, such as [`Event::Block`](crate::Event::Block) for applications that
+    /// receive blocks from a [`BlockStream`](crate::BlockStream) or do not need them.
+    ///
+    /// If none is provided, every event is sent.
+    pub fn event_mask(mut self, event_mask: EventMask) -> Self {
+        self.config.event_mask = event_mask;
+        self
+    }
+
+    /// Write the events sent to the client to a journal in the data directory. Events the client
+    /// does not acknowledge with [`Requester::acknowledge_events`](crate::Requester::acknowledge_events)
+    /// are sent again, in order, the next time the node runs, so a client that crashes while
+    /// processing an event may continue without scanning the chain again.
+    ///
+    /// The journal is written on a task of its own and flushed to disk when events are
+    /// acknowledged, when the node is synced, and periodically in between. It is emptied once
+    /// every event is acknowledged. If the journal grows past 128 MiB because events are not
+    /// acknowledged, further events are sent but not journaled until the client catches up, and a
+    /// [`Warning::FailedPersistence`](crate::Warning::FailedPersistence) is sent. Events excluded by the
+    /// [`EventMask`], compact block filters and [`Event::ShutdownInitiated`](crate::Event::ShutdownInitiated)
+    /// are not journaled.
+    ///
+    /// If none is provided, events are not journaled.
+    #[cfg(feature = "journal")]
+    pub fn journal_events(mut self) -> Self {
+        self.config.journal_events = true;
+        self
+    }
+
+    /// Only maintain a validated chain of block headers, without downloading compact block filter
+    /// headers, filters, or blocks. The node follows the chain of most work and reports new tips,
+    /// which may serve as a trust-minimized source of block heights and times with minimal
+    /// bandwidth, for instance to monitor timelocks.
+    ///
+    /// Peers are not required to serve compact block filters, scripts are never checked, and
+    /// requests to rescan are ignored. [`Event::Synced`](crate::Event::Synced) is sent when the
+    /// headers are synced to the tip.
+    pub fn headers_only(mut self) -> Self {
+        self.config.headers_only = true;
+        self
+    }
+
+    /// Ask peers to announce the unconfirmed transactions they relay, and report those that pay to
+    /// or spend from the scripts of the node with
+    /// [`Event::MempoolTransaction`](crate::Event::MempoolTransaction), so a wallet may show
+    /// pending receives and spends.
+    ///
+    /// Peers announce every transaction that enters their mempool, and each new transaction is
+    /// downloaded to be checked against the scripts, which uses significantly more bandwidth.
+    /// Transactions that spend from the scripts are only detected with
+    /// [`NodeBuilder::index_scripts`].
+    pub fn monitor_mempool(mut self) -> Self {
+        self.config.monitor_mempool = true;
+        self
+    }
+
+    /// Keep an index of the transactions that pay to or spend from the scripts of the node, over
+    /// every block the node scans. The index is persisted with the header store, so a wallet may
+    /// look up the history of a script with
+    /// [`Requester::history_for_script`](crate::Requester::history_for_script) without
+    /// rescanning.
+    ///
+    /// Spends are only found for outputs the index has already seen, so the history of a script
+    /// is complete from the block the script was first scanned for.
+    pub fn index_scripts(mut self) -> Self {
+        self.config.index_scripts = true;
+        self
+    }
+
+    /// Track the unspent outputs that pay to the scripts of the node, over every block the node
+    /// scans. New and spent outputs are sent as [`Event::UtxoCreated`](crate::Event::UtxoCreated)
+    /// and [`Event::UtxoSpent`](crate::Event::UtxoSpent), and the set may be listed with
+    /// [`Requester::list_unspent`](crate::Requester::list_unspent).
+    ///
+    /// The set is written to the [`HeaderStore`](crate::HeaderStore) if it supports it, and loaded
+    /// when the node starts. Only blocks that matched a filter are scanned, in the order of the
+    /// chain. Blocks requested with [`Requester::get_block`](crate::Requester::get_block) alone are
+    /// not scanned. When blocks are reorganized out of the chain, the outputs they created are
+    /// removed and the outputs they spent are unspent again, which is sent as
+    /// [`Event::UtxosReorganized`](crate::Event::UtxosReorganized).
+    pub fn track_utxos(mut self) -> Self {
+        self.config.track_utxos = true;
+        self
+    }
+
+    /// Recover a wallet from a conservative checkpoint, such as one well before the wallet could
+    /// have been created, and record the first block that pays to a script of the node as the
+    /// wallet birthday. The birthday is written to the header store and sent as
+    /// [`Info::BirthdayFound`](crate::Info::BirthdayFound). Filters before the birthday are not
+    /// checked when the node restarts or rescans, so scripts added later that were used before
+    /// the birthday require a new node with an earlier checkpoint.
+    pub fn detect_birthday(mut self) -> Self {
+        self.config.detect_birthday = true;
+        self
+    }
+
+    /// Check `samples` randomly chosen filters of each range of up to 1,000 synced filters
+    /// against their block. The block is downloaded, and every output script of the block must be
+    /// in the filter. Each check is sent as [`Info::FilterSpotCheck`](crate::Info::FilterSpotCheck),
+    /// and a filter that is missing an output is sent as
+    /// [`Warning::InvalidFilter`](crate::Warning::InvalidFilter).
+    ///
+    /// This is far cheaper than checking every filter, at the cost of downloading a few blocks
+    /// that do not pay to the scripts of the node. Sampled blocks are not sent to the client. If
+    /// none is provided, filters are not checked against their block.
+    pub fn spot_check_filters(mut self, samples: u32) -> Self {
+        self.config.filter_spot_checks = samples;
+        self
+    }
+
+    /// Download and deserialize up to `workers` matched blocks at once, each from a selected peer.
+    /// Peers that are not already sending a block are preferred, and blocks are still emitted in
+    /// the order of the chain. Servers may use every core to process blocks, while single-core
+    /// devices should use a single worker to avoid contention.
+    ///
+    /// If none is provided, the number of workers is the available parallelism of the system.
+    /// A value of zero is treated as one.
+    pub fn block_workers(mut self, workers: usize) -> Self {
+        self.config.block_workers = workers;
+        self
+    }
+
+    /// Disconnect from peers that advertise a starting height more than `max_blocks_behind` below
+    /// the chain of most work, as known from the stored headers or the most recent checkpoint.
+    /// Peers that are far behind cannot serve recent filters and may stall the sync.
+    ///
+    /// If none is provided, peers are not rejected based on their height.
+    pub fn reject_stale_peers(mut self, max_blocks_behind: u32) -> Self {
+        self.config.max_peer_lag = Some(max_blocks_behind);
+        self
+    }
+
+    /// Require at least `peers` connected peers to agree on the tip before the node is considered
+    /// synced and [`Event::Synced`](crate::Event::Synced) is sent. A peer agrees if the height it
+    /// has reported is within one block of the tip of the chain of most work. This protects
+    /// applications that act on [`Event::Synced`](crate::Event::Synced) from a single peer with a
+    /// stale or fabricated tip, at the cost of waiting for more peers to connect.
+    ///
+    /// If none is provided, a single peer is enough. The number of peers may not exceed the
+    /// number of [`NodeBuilder::required_peers`].
+    pub fn require_tip_agreement(mut self, peers: u8) -> Self {
+        self.config.tip_agreement = peers;
+        self
+    }
+
+    /// Set how long the node may go without any peer answering a request for headers, filter
+    /// headers, filters, or blocks before the sync is considered stalled. A stalled node
+    /// disconnects from the peers it is waiting on and requests the data again.
+    ///
+    /// If none is provided, a timeout of three minutes will be used.
+    pub fn stall_timeout(mut self, stall_timeout: impl Into<Duration>) -> Self {
+        self.config.stall_timeout = stall_timeout.into();
+        self
+    }
+
+    /// Set what the node does when a peer does not serve compact block filters. Nodes that run
+    /// without supervision may prefer to fail with an error rather than wait for a peer that
+    /// serves filters to be found.
+    ///
+    /// If none is provided, the node disconnects from the peer and waits.
+    pub fn missing_filters_policy(mut self, policy: MissingFiltersPolicy) -> Self {
+        self.config.missing_filters = policy;
+        self
+    }
+
     /// Configure the DNS resolver to use when querying DNS seeds.
     /// Default is `1.1.1.1:53`.
     pub fn dns_resolver(mut self, resolver: impl Into<IpAddr>) -> Self {
@@ -202,8 +625,20 @@ impl NodeBuilder {
         self
     }
 
+    /// Query these DNS seeds for peers when the peer database is empty, in place of the seeds
+    /// of the network. Seeds are asked only for peers that serve compact block filters.
+    ///
+    /// If none are provided, the default seeds of the network are used. There are no default
+    /// seeds for [`Network::Regtest`].
+    pub fn dns_seeds(mut self, seeds: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config.dns_seeds = Some(seeds.into_iter().map(Into::into).collect());
+        self
+    }
+
     /// Route network traffic through a Tor daemon using a Socks5 proxy. Currently, proxies
-    /// must be reachable by IP address.
+    /// must be reachable by IP address. Connections to both configured and gossiped peers use the
+    /// proxy, and DNS seeds are queried over TCP through the proxy, so no lookups are made from
+    /// this host.
     pub fn socks5_proxy(mut self, proxy: impl Into<SocketAddr>) -> Self {
         let ip_addr = proxy.into();
         let connection = ConnectionType::Socks5Proxy(ip_addr);
@@ -211,34 +646,448 @@ impl NodeBuilder {
         self
     }
 
+    /// Dial I2P peers through the SAM bridge of an I2P router, typically listening on
+    /// `127.0.0.1:7656`. Configured I2P peers and I2P addresses gossiped by other peers become
+    /// reachable, and connections to all other peers are unaffected.
+    #[cfg(feature = "i2p")]
+    pub fn i2p_sam_bridge(mut self, sam: impl Into<SocketAddr>) -> Self {
+        self.config.i2p_sam = Some(sam.into());
+        self
+    }
+
+    /// Fetch blocks from a trusted Bitcoin Core node over the REST interface. Block headers and
+    /// compact block filters are still downloaded from peers on the P2P network, but the bodies of
+    /// relevant blocks are requested from this node first. If the node fails to serve a block, the
+    /// request falls back to a random peer.
+    ///
+    /// ## Note
+    ///
+    /// The Bitcoin Core node must be ran with `-rest=1`, and the socket address should point
+    /// to the RPC port of the node.
+    #[cfg(feature = "rest")]
+    pub fn trusted_rest_node(mut self, rest_addr: impl Into<SocketAddr>) -> Self {
+        self.config.block_source = Some(Box::new(RestClient::new(rest_addr.into())));
+        self
+    }
+
+    /// Fetch the bodies of relevant blocks from a [`BlockSource`], such as a local archive
+    /// or content delivery network. If the source fails to provide a block, the request falls back
+    /// to a random peer. If none is provided, blocks are requested from peers on the P2P network.
+    ///
+    /// This replaces any trusted REST node configured with [`NodeBuilder::trusted_rest_node`].
+    #[cfg(feature = "rest")]
+    pub fn block_source(mut self, block_source: impl BlockSource + 'static) -> Self {
+        self.config.block_source = Some(Box::new(block_source));
+        self
+    }
+
+    /// Persist compact block filters to a [`FilterStore`], such as a
+    /// [`SqliteFilterDb`](crate::SqliteFilterDb). Filters are written as they are checked, and the
+    /// filters of a rescan, or of a restart from an earlier checkpoint, are loaded from the store in
+    /// place of downloading them. Stored filters are checked against the filter headers of the
+    /// chain of most work, and filters the store does not have are requested from peers.
+    ///
+    /// If none is provided, filters are always downloaded from peers.
+    pub fn filter_store(mut self, store: impl FilterStore + 'static) -> Self {
+        self.config.filter_store = Some(Box::new(store));
+        self
+    }
+
+    /// Persist compact filter headers to a [`CFHeaderStore`], such as a
+    /// [`SqliteCFHeaderDb`](crate::SqliteCFHeaderDb). Filter headers are written once enough peers
+    /// agree on them, and are loaded when the node starts, so the filter headers are not
+    /// downloaded from peers again. Stored filter headers are checked against the chain of most
+    /// work, and the filter headers after the last one that matches are requested from peers.
+    ///
+    /// Stored filter headers are only loaded for the block headers the [`HeaderStore`] loads when
+    /// the node starts, so with a [`HeaderStore`] that does not persist headers, such as a
+    /// [`MemoryHeaderDb`](crate::MemoryHeaderDb), the store is written to but nothing is loaded.
+    ///
+    /// If none is provided, filter headers are downloaded from peers every time the node starts.
+    pub fn cf_header_store(mut self, store: impl CFHeaderStore + 'static) -> Self {
+        self.config.cf_header_store = Some(Box::new(store));
+        self
+    }
+
+    /// Decide which peers to connect to and which connected peer is sent block requests and
+    /// transactions with a [`PeerSelector`]. This may be used for deterministic selection in tests,
+    /// or to prefer certain peers in production.
+    ///
+    /// If none is provided, peers are selected at random.
+    pub fn peer_selector(mut self, peer_selector: impl PeerSelector + 'static) -> Self {
+        self.config.peer_selector = Box::new(peer_selector);
+        self
+    }
+
+    /// Decide which blocks to download with a [`FilterMatcher`], in place of checking compact
+    /// block filters for the scripts added to the node. Scripts added with
+    /// [`NodeBuilder::add_scripts`] or the [`Client`] are ignored.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn filter_matcher(mut self, matcher: impl FilterMatcher + 'static) -> Self {
+        self.config.filter_matcher = Some(Box::new(matcher));
+        self
+    }
+
+    /// Watch the scripts derived from an extended public key, in addition to the scripts added
+    /// to the node. Scripts are derived past the last used index of each keychain as blocks pay
+    /// to them, and [`Event::NewDerivationUsed`](crate::Event::NewDerivationUsed) is sent for
+    /// each new last used index. An output descriptor may be watched with
+    /// [`XpubWatch::from_descriptor`]. Building the node fails with
+    /// [`BuilderError::XpubNetwork`] if the key is for a different network.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn watch_xpub(mut self, watch: XpubWatch) -> Self {
+        self.config.xpub_watch = Some(watch);
+        self
+    }
+
+    /// Stop checking filters for the scripts reported by
+    /// [`Warning::BroadScripts`](crate::Warning::BroadScripts). When most of the blocks
+    /// downloaded for a filter match have no relevant transaction, the scripts that matched most
+    /// of them are removed from the scripts checked by the node. Transactions paying to these
+    /// scripts are no longer found, so this should only be used if the scripts are known to be
+    /// shared with other wallets. By default, the scripts are only reported.
+    ///
+    /// A block that only spends an output paying to a script is counted as a false positive unless
+    /// the output is known, so scripts are only excluded along with
+    /// [`NodeBuilder::index_scripts`] or [`NodeBuilder::track_utxos`].
+    #[cfg(not(feature = "filter-control"))]
+    pub fn exclude_broad_scripts(mut self) -> Self {
+        self.config.exclude_broad_scripts = true;
+        self
+    }
+
+    /// Report the blocks whose filters match with
+    /// [`Event::FilterMatched`](crate::Event::FilterMatched), in place of downloading them, for
+    /// applications that have another source of block data. Blocks requested by the [`Client`]
+    /// are still downloaded.
+    ///
+    /// As the node does not see the transactions of matched blocks, scripts derived from a watched
+    /// extended public key are not extended past the gap limit, and broad scripts are not
+    /// detected.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn filter_matches_only(mut self) -> Self {
+        self.config.filter_matches_only = true;
+        self
+    }
+
+    // If the peer is reachable with the configured transports
+    fn can_connect(&self, addr: &AddrV2) -> bool {
+        #[cfg(feature = "i2p")]
+        if matches!(addr, AddrV2::I2p(_)) {
+            return self.config.i2p_sam.is_some();
+        }
+        self.config.connection_type.can_connect(addr)
+    }
+
+    // Catch configurations that are certain to fail once the node is running
+    fn validate(&self) -> Result<(), BuilderError> {
+        if !KNOWN_CHECKPOINTS
+            .iter()
+            .any(|(network, _)| network.eq(&self.network))
+        {
+            return Err(BuilderError::UnsupportedNetwork(self.network));
+        }
+        if let Some(anchor) = self.config.header_checkpoint {
+            for (network, checkpoints) in KNOWN_CHECKPOINTS {
+                for (height, hash) in checkpoints {
+                    let hash = BlockHash::from_str(hash).expect("checkpoint hash is hardcoded");
+                    // A checkpoint from another network, or a known height with a different hash
+                    let conflicts = if network.eq(&self.network) {
+                        height.eq(&anchor.height) ^ hash.eq(&anchor.hash)
+                    } else {
+                        hash.eq(&anchor.hash)
+                    };
+                    if conflicts {
+                        return Err(BuilderError::CheckpointMismatch(anchor));
+                    }
+                }
+            }
+        }
+        if self.config.required_peers.eq(&0) && self.config.white_list.is_empty() {
+            return Err(BuilderError::NoPeers);
+        }
+        if let Some(peer) = self
+            .config
+            .white_list
+            .iter()
+            .find(|peer| !self.can_connect(&peer.address))
+        {
+            return Err(BuilderError::UnreachablePeer(peer.clone()));
+        }
+        if !tip_agreement_reachable(self.config.tip_agreement, self.config.required_peers) {
+            return Err(BuilderError::TipAgreement {
+                agreeing: self.config.tip_agreement,
+                required_peers: self.config.required_peers,
+            });
+        }
+        #[cfg(not(feature = "filter-control"))]
+        if let Some(watch) = &self.config.xpub_watch {
+            if watch.network_kind().ne(&NetworkKind::from(self.network)) {
+                return Err(BuilderError::XpubNetwork);
+            }
+        }
+        Ok(())
+    }
+
+    // Take the validated configuration, opening the event journal if one was requested
+    fn take_config(&mut self) -> Result<NodeConfig, BuilderError> {
+        self.config.required_peers = self.config.required_peers.max(MIN_PEERS);
+        #[cfg(feature = "journal")]
+        if self.config.journal_events {
+            let mut path = self
+                .config
+                .data_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_CWD));
+            path.push(DATA_DIR);
+            path.push(self.network.to_string());
+            let journal = EventJournal::open(&path).map_err(BuilderError::EventJournal)?;
+            self.config.event_journal = Some(journal);
+        }
+        Ok(core::mem::take(&mut self.config))
+    }
+
     /// Consume the node builder and receive a [`Node`] and [`Client`].
     ///
     /// # Errors
     ///
-    /// Building a node and client will error if a database connection is denied or cannot be found.
+    /// Building a node and client will error if the configuration is invalid, if a database
+    /// connection is denied or cannot be found, or if another node is using the data directory.
     #[cfg(feature = "rusqlite")]
-    pub fn build(&mut self) -> Result<(NodeDefault, Client), SqlInitializationError> {
+    pub fn build(&mut self) -> Result<(NodeDefault, Client), BuilderError> {
+        self.validate()?;
         let peer_store = SqlitePeerDb::new(self.network, self.config.data_path.clone())?;
         let header_store = SqliteHeaderDb::new(self.network, self.config.data_path.clone())?;
-        Ok(Node::new(
+        let config = self.take_config()?;
+        Ok(Node::new(self.network, config, peer_store, header_store))
+    }
+
+    /// Consume the node builder by using custom database implementations, receiving a [`Node`] and [`Client`].
+    ///
+    /// # Errors
+    ///
+    /// Building a node and client will error if the configuration is invalid.
+    pub fn build_with_databases<H: HeaderStore + 'static, P: PeerStore + 'static>(
+        &mut self,
+        peer_store: P,
+        header_store: H,
+    ) -> Result<(Node<H, P>, Client), BuilderError> {
+        self.validate()?;
+        let config = self.take_config()?;
+        Ok(Node::new(self.network, config, peer_store, header_store))
+    }
+
+    /// Consume the node builder to start another node for an existing [`Client`], with the
+    /// channels taken from the previous node by [`Node::into_client_channels`].
+    ///
+    /// # Errors
+    ///
+    /// Building a node will error if the configuration is invalid, if a database connection is
+    /// denied or cannot be found, or if another node is using the data directory.
+    #[cfg(feature = "rusqlite")]
+    pub fn rebuild(&mut self, channels: ClientChannels) -> Result<NodeDefault, BuilderError> {
+        self.validate()?;
+        let peer_store = SqlitePeerDb::new(self.network, self.config.data_path.clone())?;
+        let header_store = SqliteHeaderDb::new(self.network, self.config.data_path.clone())?;
+        let config = self.take_config()?;
+        Ok(Node::with_client_channels(
             self.network,
-            core::mem::take(&mut self.config),
+            config,
             peer_store,
             header_store,
+            channels,
         ))
     }
 
-    /// Consume the node builder by using custom database implementations, receiving a [`Node`] and [`Client`].
-    pub fn build_with_databases<H: HeaderStore + 'static, P: PeerStore + 'static>(
+    /// Consume the node builder by using custom database implementations to start another node
+    /// for an existing [`Client`], with the channels taken from the previous node by
+    /// [`Node::into_client_channels`].
+    ///
+    /// # Errors
+    ///
+    /// Building a node will error if the configuration is invalid.
+    pub fn rebuild_with_databases<H: HeaderStore + 'static, P: PeerStore + 'static>(
         &mut self,
+        channels: ClientChannels,
         peer_store: P,
         header_store: H,
-    ) -> (Node<H, P>, Client) {
-        Node::new(
+    ) -> Result<Node<H, P>, BuilderError> {
+        self.validate()?;
+        let config = self.take_config()?;
+        Ok(Node::with_client_channels(
             self.network,
-            core::mem::take(&mut self.config),
+            config,
             peer_store,
             header_store,
-        )
+            channels,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bitcoin::p2p::ServiceFlags;
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_configurations() {
+        let local = TrustedPeer::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(NodeBuilder::new(Network::Regtest)
+            .add_peer(local.clone())
+            .required_peers(0)
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            NodeBuilder::new(Network::Testnet).validate(),
+            Err(BuilderError::UnsupportedNetwork(Network::Testnet))
+        ));
+        let signet_checkpoint = HeaderCheckpoint::most_recent(Network::Signet);
+        assert!(matches!(
+            NodeBuilder::new(Network::Bitcoin)
+                .after_checkpoint(signet_checkpoint)
+                .validate(),
+            Err(BuilderError::CheckpointMismatch(_))
+        ));
+        let wrong_height =
+            HeaderCheckpoint::new(signet_checkpoint.height + 1, signet_checkpoint.hash);
+        assert!(matches!(
+            NodeBuilder::new(Network::Signet)
+                .after_checkpoint(wrong_height)
+                .validate(),
+            Err(BuilderError::CheckpointMismatch(_))
+        ));
+        assert!(NodeBuilder::new(Network::Signet)
+            .after_checkpoint(signet_checkpoint)
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            NodeBuilder::new(Network::Regtest)
+                .required_peers(0)
+                .validate(),
+            Err(BuilderError::NoPeers)
+        ));
+        let onion = TrustedPeer::new(AddrV2::TorV3([0; 32]), None, ServiceFlags::P2P_V2);
+        assert!(matches!(
+            NodeBuilder::new(Network::Regtest)
+                .add_peer(onion)
+                .socks5_proxy(SocketAddr::from((Ipv4Addr::LOCALHOST, 9050)))
+                .validate(),
+            Err(BuilderError::UnreachablePeer(_))
+        ));
+        #[cfg(feature = "i2p")]
+        {
+            let i2p = TrustedPeer::from_i2p([0; 32]);
+            assert!(matches!(
+                NodeBuilder::new(Network::Regtest)
+                    .add_peer(i2p.clone())
+                    .validate(),
+                Err(BuilderError::UnreachablePeer(_))
+            ));
+            assert!(NodeBuilder::new(Network::Regtest)
+                .add_peer(i2p)
+                .i2p_sam_bridge(SocketAddr::from((Ipv4Addr::LOCALHOST, 7656)))
+                .validate()
+                .is_ok());
+        }
+        assert!(NodeBuilder::new(Network::Regtest)
+            .required_peers(3)
+            .require_tip_agreement(3)
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            NodeBuilder::new(Network::Regtest)
+                .require_tip_agreement(2)
+                .validate(),
+            Err(BuilderError::TipAgreement { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "filter-control"))]
+    fn test_xpub_network_must_match() {
+        use bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+
+        use crate::DerivedScriptKind;
+
+        let secp = bitcoin::key::Secp256k1::new();
+        let watch = |network: Network| {
+            let xpriv = Xpriv::new_master(network, &[7; 32]).unwrap();
+            let external = DerivationPath::from_str("m/0").unwrap();
+            let internal = DerivationPath::from_str("m/1").unwrap();
+            XpubWatch::new(
+                Xpub::from_priv(&secp, &xpriv),
+                DerivedScriptKind::P2wpkh,
+                &external,
+                &internal,
+            )
+            .unwrap()
+        };
+        assert!(NodeBuilder::new(Network::Signet)
+            .watch_xpub(watch(Network::Signet))
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            NodeBuilder::new(Network::Bitcoin)
+                .watch_xpub(watch(Network::Signet))
+                .validate(),
+            Err(BuilderError::XpubNetwork)
+        ));
+        assert!(matches!(
+            NodeBuilder::new(Network::Signet)
+                .watch_xpub(watch(Network::Bitcoin))
+                .validate(),
+            Err(BuilderError::XpubNetwork)
+        ));
+    }
+
+    #[test]
+    fn test_presets_are_overridable() {
+        let builder = NodeBuilder::new(Network::Regtest).preset(Preset::Regtest);
+        assert_eq!(builder.config.white_list.len(), 1);
+        assert!(builder.validate().is_ok());
+        let builder = NodeBuilder::new(Network::Signet)
+            .preset(Preset::Paranoid)
+            .required_peers(2);
+        assert_eq!(builder.config.required_peers, 2);
+        assert!(matches!(
+            builder.validate(),
+            Err(BuilderError::TipAgreement { .. })
+        ));
+        let builder = NodeBuilder::new(Network::Signet)
+            .preset(Preset::Server)
+            .require_tip_agreement(1)
+            .required_peers(1);
+        assert!(builder.validate().is_ok());
+        let builder = NodeBuilder::new(Network::Signet)
+            .preset(Preset::MobileWallet)
+            .block_workers(2);
+        assert_eq!(builder.config.block_workers, 2);
+        assert!(builder.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_keeps_client() {
+        let local = TrustedPeer::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let (node, client) = NodeBuilder::new(Network::Regtest)
+            .add_peer(local.clone())
+            .build_with_databases((), ())
+            .unwrap();
+        let Client {
+            requester,
+            event_rx: _event_rx,
+            ..
+        } = client;
+        assert!(requester.is_running());
+        let channels = node.into_client_channels();
+        let node = NodeBuilder::new(Network::Regtest)
+            .add_peer(local)
+            .rebuild_with_databases(channels, (), ())
+            .unwrap();
+        assert!(requester.is_running());
+        drop(node);
+        assert!(!requester.is_running());
     }
 }
@@ -0,0 +1,186 @@
+use bitcoin::{
+    consensus::Decodable,
+    hashes::{siphash24, Hash},
+    BlockHash, ScriptBuf, VarInt,
+};
+
+use super::{
+    error::FilterError,
+    golomb::{self, M, P},
+};
+
+// Bits in the bitmap for each element of the filter, so a script hashed to a value that is not in
+// the filter usually lands on an unset bit
+const BITS_PER_ELEMENT: u64 = 32;
+
+// The elements of a compact block filter, decoded once, with a bitmap over the range of values
+// they fall in.
+//
+// Checking a filter for a set of scripts normally hashes and sorts every script before walking
+// the Golomb-coded elements of the filter, which dominates matching for large sets of scripts.
+// The hashes are keyed by the block, so a bloom or xor filter over the watched scripts cannot be
+// built ahead of time. Instead, the range of hashed values is split into buckets, with one bit
+// set for each bucket that holds an element of this filter. Each script is hashed and probed
+// against its bucket, and only the scripts that land on a set bit are searched for among the
+// decoded elements.
+#[derive(Debug)]
+pub(crate) struct BitmapFilter {
+    k0: u64,
+    k1: u64,
+    range: u64,
+    elements: Vec<u64>,
+    bits: Vec<u64>,
+    num_bits: u64,
+}
+
+impl BitmapFilter {
+    pub(crate) fn decode(block_hash: &BlockHash, contents: &[u8]) -> Result<Self, FilterError> {
+        let key = block_hash.to_byte_array();
+        let k0 = u64::from_le_bytes(key[0..8].try_into().expect("8 byte slice"));
+        let k1 = u64::from_le_bytes(key[8..16].try_into().expect("8 byte slice"));
+        let mut reader = contents;
+        let n_elements = VarInt::consensus_decode(&mut reader)
+            .map_err(|_| FilterError::IORead)?
+            .0;
+        // Every element takes at least a terminating zero and the remainder, so a count beyond
+        // what the data can hold is rejected before anything is allocated for it
+        let max_elements = (reader.len() as u64).saturating_mul(8) / (u64::from(P) + 1);
+        if n_elements > max_elements {
+            return Err(FilterError::IORead);
+        }
+        let elements = golomb::decode(reader, n_elements).ok_or(FilterError::IORead)?;
+        let range = n_elements.checked_mul(M).ok_or(FilterError::IORead)?;
+        // The elements are sorted, so the last is the largest. An element outside the range could
+        // not be hashed from a script and would fall outside the bitmap.
+        if elements.last().map_or(false, |last| *last >= range) {
+            return Err(FilterError::IORead);
+        }
+        let num_bits = n_elements
+            .checked_mul(BITS_PER_ELEMENT)
+            .ok_or(FilterError::IORead)?
+            .max(64);
+        let mut filter = Self {
+            k0,
+            k1,
+            range,
+            elements,
+            bits: vec![0; (num_bits / 64 + u64::from(num_bits % 64 != 0)) as usize],
+            num_bits,
+        };
+        for index in 0..filter.elements.len() {
+            let bit = filter.bit(filter.elements[index]);
+            filter.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        Ok(filter)
+    }
+
+    pub(crate) fn contains_any<'a>(
+        &self,
+        mut scripts: impl Iterator<Item = &'a ScriptBuf>,
+    ) -> bool {
+        if self.elements.is_empty() {
+            return false;
+        }
+        scripts.any(|script| {
+            let hash = siphash24::Hash::hash_to_u64_with_keys(self.k0, self.k1, script.as_bytes());
+            let element = ((hash as u128 * self.range as u128) >> 64) as u64;
+            let bit = self.bit(element);
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+                && self.elements.binary_search(&element).is_ok()
+        })
+    }
+
+    // Elements are spread evenly over the range, so nearby elements share a bit
+    fn bit(&self, element: u64) -> u64 {
+        ((element as u128 * self.num_bits as u128) / self.range as u128) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        bip158::{BitStreamReader, BlockFilter, GcsFilterWriter},
+        constants::genesis_block,
+        Network,
+    };
+
+    use super::*;
+
+    // Decode one bit at a time, as the reference implementation does
+    fn decode_bitwise(mut data: &[u8], n_elements: u64) -> Vec<u64> {
+        let mut reader = BitStreamReader::new(&mut data);
+        let mut element = 0;
+        (0..n_elements)
+            .map(|_| {
+                let mut quotient = 0;
+                while reader.read(1).unwrap() == 1 {
+                    quotient += 1;
+                }
+                element += (quotient << P) + reader.read(P).unwrap();
+                element
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_word_decoding_matches_bitwise_decoding() {
+        let mut encoded = Vec::new();
+        let mut writer = GcsFilterWriter::new(&mut encoded, 7, 11, M, P);
+        for i in 0..5_000u32 {
+            writer.add_element(&i.to_le_bytes());
+        }
+        writer.finish().unwrap();
+        // Skip the number of elements, which fits in three bytes
+        assert_eq!(encoded[0], 0xfd);
+        let data = &encoded[3..];
+        let elements = golomb::decode(data, 5_000).unwrap();
+        assert_eq!(elements, decode_bitwise(data, 5_000));
+        assert!(golomb::decode(data, 5_001).is_none());
+        assert!(golomb::decode(&[], 1).is_none());
+        // A quotient longer than a peeked word
+        let mut long = vec![0xff; 20];
+        long.extend([0x00; 3]);
+        assert_eq!(golomb::decode(&long, 1), Some(vec![160 << P]));
+    }
+
+    #[test]
+    fn test_bitmap_filter_agrees_with_golomb_matching() {
+        let script = |i: u16| {
+            let [lo, hi] = i.to_le_bytes();
+            ScriptBuf::from_bytes(vec![0x51, lo, hi])
+        };
+        // Pay to a range of scripts from the coinbase, which has no inputs to look up
+        let mut block = genesis_block(Network::Regtest);
+        for i in 0..200 {
+            let mut output = block.txdata[0].output[0].clone();
+            output.script_pubkey = script(i);
+            block.txdata[0].output.push(output);
+        }
+        let block_hash = block.block_hash();
+        let block_filter = BlockFilter::new_script_filter(&block, |outpoint| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+        })
+        .unwrap();
+        let decoded = BitmapFilter::decode(&block_hash, &block_filter.content).unwrap();
+        for i in 0..2_000 {
+            let query = [script(i)];
+            let expected = block_filter
+                .match_any(&block_hash, query.iter().map(|script| script.as_bytes()))
+                .unwrap();
+            assert_eq!(decoded.contains_any(query.iter()), expected);
+            assert_eq!(expected, i < 200);
+        }
+        let scripts = (200..2_000).map(script).collect::<Vec<ScriptBuf>>();
+        assert!(!decoded.contains_any(scripts.iter()));
+        let empty = BitmapFilter::decode(&block_hash, &[0x00]).unwrap();
+        assert!(!empty.contains_any(scripts.iter()));
+        assert!(BitmapFilter::decode(&block_hash, &[0x02]).is_err());
+        // A count of elements the data cannot hold is rejected without decoding
+        let huge = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+        assert!(BitmapFilter::decode(&block_hash, &huge).is_err());
+        // An element past the range of one element, with a quotient of two and no remainder
+        let out_of_range = [0x01, 0xc0, 0x00, 0x00];
+        assert_eq!(golomb::decode(&out_of_range[1..], 1), Some(vec![2 << P]));
+        assert!(BitmapFilter::decode(&block_hash, &out_of_range).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use bitcoin::{
+    hashes::{sha256d, Hash, HashEngine},
+    Block,
+};
+
+use super::error::BlockScanError;
+
+// Check that the transactions of a block are the ones committed to by its header. A header does
+// not commit to the transactions on its own, so a peer could otherwise attach transactions to a
+// valid header.
+pub(crate) fn check_block_body(block: &Block) -> Result<(), BlockScanError> {
+    if !block.check_merkle_root() {
+        return Err(BlockScanError::InvalidMerkleRoot);
+    }
+    if is_merkle_tree_mutated(block) {
+        return Err(BlockScanError::DuplicateTransactions);
+    }
+    if !block.check_witness_commitment() {
+        return Err(BlockScanError::InvalidWitnessCommitment);
+    }
+    Ok(())
+}
+
+// Repeating the last transactions of a level of the merkle tree does not change the merkle root,
+// as described in CVE-2012-2459. The transactions of a valid block never repeat a branch.
+fn is_merkle_tree_mutated(block: &Block) -> bool {
+    let mut level = block
+        .txdata
+        .iter()
+        .map(|tx| tx.compute_txid().to_raw_hash())
+        .collect::<Vec<sha256d::Hash>>();
+    while level.len() > 1 {
+        if level
+            .chunks(2)
+            .any(|pair| pair.len() == 2 && pair[0] == pair[1])
+        {
+            return true;
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let left = pair[0];
+                let right = pair.get(1).copied().unwrap_or(left);
+                let mut engine = sha256d::Hash::engine();
+                engine.input(left.as_byte_array());
+                engine.input(right.as_byte_array());
+                sha256d::Hash::from_engine(engine)
+            })
+            .collect();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{absolute::LockTime, constants::genesis_block, Network, Witness};
+
+    use super::*;
+
+    #[test]
+    fn test_block_bodies_are_checked() {
+        let genesis = genesis_block(Network::Regtest);
+        assert!(check_block_body(&genesis).is_ok());
+        let mut block = genesis.clone();
+        let coinbase = block.txdata[0].clone();
+        for i in 1..3 {
+            let mut tx = coinbase.clone();
+            tx.lock_time = LockTime::from_consensus(i);
+            block.txdata.push(tx);
+        }
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        assert!(check_block_body(&block).is_ok());
+        // A transaction that is not committed to by the header
+        let mut attached = block.clone();
+        attached.txdata.push(coinbase.clone());
+        assert!(matches!(
+            check_block_body(&attached),
+            Err(BlockScanError::InvalidMerkleRoot)
+        ));
+        // Repeating the last transaction leaves the merkle root unchanged
+        let mut mutated = block.clone();
+        mutated.txdata.push(mutated.txdata[2].clone());
+        assert!(mutated.check_merkle_root());
+        assert!(matches!(
+            check_block_body(&mutated),
+            Err(BlockScanError::DuplicateTransactions)
+        ));
+        // Witness data without a commitment in the coinbase
+        let mut witness = block.clone();
+        witness.txdata[1].input[0].witness = Witness::from_slice(&[vec![1; 32]]);
+        witness.header.merkle_root = witness.compute_merkle_root().unwrap();
+        assert!(matches!(
+            check_block_body(&witness),
+            Err(BlockScanError::InvalidWitnessCommitment)
+        ));
+    }
+}
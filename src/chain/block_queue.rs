@@ -1,95 +1,173 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Instant};
 
 use bitcoin::BlockHash;
-use tokio::time::Instant;
 
-#[cfg(feature = "filter-control")]
-use crate::messages::BlockRequest;
-use crate::messages::BlockSender;
+use crate::messages::{BlockRequest, BlockSender};
 
 const SPAM_LIMIT: u64 = 5;
+// The number of times a block the client asked for is requested before giving up
+const MAX_CLIENT_ATTEMPTS: u32 = 5;
 
+// Blocks explicitly requested by the client are downloaded before
+// blocks that matched a filter during a background sync
 #[derive(Debug)]
 pub(crate) struct BlockQueue {
+    priority: VecDeque<Request>,
     queue: VecDeque<Request>,
-    want: Option<Request>,
+    // Requests sent to peers, oldest first
+    in_flight: Vec<Request>,
+    max_in_flight: usize,
     last_req: Instant,
+    // Client requests that no peer served
+    unserved: Vec<BlockSender>,
 }
 
 impl BlockQueue {
     pub(crate) fn new() -> Self {
         Self {
+            priority: VecDeque::new(),
             queue: VecDeque::new(),
-            want: None,
+            in_flight: Vec::new(),
+            max_in_flight: 1,
             last_req: Instant::now(),
+            unserved: Vec::new(),
         }
     }
 
+    // Allow up to `max` blocks to be downloaded at once
+    pub(crate) fn set_max_in_flight(&mut self, max: usize) {
+        self.max_in_flight = max.max(1);
+    }
+
     pub(crate) fn add(&mut self, request: impl Into<Request>) {
-        let request: Request = request.into();
+        let mut request: Request = request.into();
+        if request.sender.is_some() {
+            // A block that is already in flight may be handed to the client directly
+            if let Some(want) = self
+                .in_flight
+                .iter_mut()
+                .find(|want| want.hash.eq(&request.hash))
+            {
+                if want.sender.is_none() {
+                    want.sender = request.sender;
+                    return;
+                }
+            }
+            let queued = self.queue.len();
+            self.queue.retain(|queued| queued.hash.ne(&request.hash));
+            request.matched = queued > self.queue.len();
+        }
         if !self.contains(&request.hash) {
-            self.queue.push_front(request)
+            match request.sender {
+                Some(_) => self.priority.push_front(request),
+                None => self.queue.push_front(request),
+            }
         }
     }
 
     pub(crate) fn contains(&mut self, block: &BlockHash) -> bool {
-        self.queue.iter().any(|request| request.hash.eq(block))
-            || self
-                .want
-                .as_ref()
-                .map_or(false, |request| request.hash.eq(block))
+        self.priority.iter().any(|request| request.hash.eq(block))
+            || self.queue.iter().any(|request| request.hash.eq(block))
+            || self.in_flight.iter().any(|request| request.hash.eq(block))
     }
 
+    // The next block to request, or a block in flight to request again if the peers are slow to
+    // respond
     pub(crate) fn pop(&mut self) -> Option<BlockHash> {
-        match self.want.as_mut() {
-            Some(request) => {
-                if Instant::now().duration_since(self.last_req).as_secs() < SPAM_LIMIT {
-                    None
-                } else {
-                    self.last_req = Instant::now();
-                    Some(request.hash)
-                }
-            }
-            None => {
+        if self.in_flight.len() < self.max_in_flight {
+            if let Some(mut request) = self.priority.pop_back().or_else(|| self.queue.pop_back()) {
                 self.last_req = Instant::now();
-                let request = self.queue.pop_back();
-                let hash = request.as_ref().map(|request| request.hash);
-                self.want = request;
-                hash
+                request.attempts += 1;
+                let hash = request.hash;
+                self.in_flight.push(request);
+                return Some(hash);
             }
         }
+        if self.in_flight.is_empty()
+            || Instant::now().duration_since(self.last_req).as_secs() < SPAM_LIMIT
+        {
+            return None;
+        }
+        self.last_req = Instant::now();
+        // Move the oldest request to the back, so every request in flight is retried in turn
+        let mut request = self.in_flight.remove(0);
+        if request.sender.is_some() && request.attempts >= MAX_CLIENT_ATTEMPTS {
+            self.unserved.extend(request.sender.take());
+            // A block that also matched a filter is still needed
+            if !request.matched {
+                return None;
+            }
+        }
+        request.attempts += 1;
+        let hash = request.hash;
+        self.in_flight.push(request);
+        Some(hash)
+    }
+
+    // Blocks that matched a filter and have not arrived yet
+    pub(crate) fn matched(&self) -> impl Iterator<Item = &BlockHash> {
+        self.priority
+            .iter()
+            .chain(self.queue.iter())
+            .chain(self.in_flight.iter())
+            .filter(|request| request.matched)
+            .map(|request| &request.hash)
+    }
+
+    // Did the block in flight match a filter, rather than only being requested by the client
+    pub(crate) fn is_matched(&self, block: &BlockHash) -> bool {
+        self.in_flight
+            .iter()
+            .any(|request| request.hash.eq(block) && request.matched)
+    }
+
+    // Client requests that were given up on, as no peer served the block
+    pub(crate) fn take_unserved(&mut self) -> Vec<BlockSender> {
+        std::mem::take(&mut self.unserved)
+    }
+
+    // Are there blocks the client is waiting on
+    pub(crate) fn has_priority(&self) -> bool {
+        !self.priority.is_empty()
+            || self
+                .in_flight
+                .iter()
+                .any(|request| request.sender.is_some())
     }
 
     pub(crate) fn need(&self, block: &BlockHash) -> bool {
-        self.want
-            .as_ref()
-            .map_or(false, |request| request.hash.eq(block))
+        self.in_flight.iter().any(|request| request.hash.eq(block))
     }
 
     pub(crate) fn receive(&mut self, hash: &BlockHash) -> Option<BlockSender> {
-        if let Some(request) = self.want.take() {
-            if request.hash.eq(hash) {
-                self.want = None;
-                return request.sender;
-            } else {
-                self.want = Some(request);
-                return None;
-            }
-        }
-        None
+        let index = self
+            .in_flight
+            .iter()
+            .position(|request| request.hash.eq(hash))?;
+        self.in_flight.remove(index).sender
+    }
+
+    // Blocks that are queued or in flight
+    pub(crate) fn len(&self) -> usize {
+        self.in_flight.len() + self.priority.len() + self.queue.len()
     }
 
     pub(crate) fn complete(&self) -> bool {
-        self.want.is_none() && self.queue.is_empty()
+        self.in_flight.is_empty() && self.priority.is_empty() && self.queue.is_empty()
+    }
+
+    // Drop the blocks queued from filter matches, keeping any explicit requests
+    pub(crate) fn clear_matched(&mut self) {
+        self.queue.clear();
+        self.in_flight.retain(|request| request.sender.is_some());
     }
 
     pub(crate) fn remove(&mut self, hashes: &[BlockHash]) {
+        self.priority
+            .retain(|request| !hashes.contains(&request.hash));
         self.queue.retain(|request| !hashes.contains(&request.hash));
-        if let Some(want) = self.want.as_ref() {
-            if hashes.contains(&want.hash) {
-                self.want = None;
-            }
-        }
+        self.in_flight
+            .retain(|request| !hashes.contains(&request.hash));
     }
 }
 
@@ -97,18 +175,27 @@ impl BlockQueue {
 pub(crate) struct Request {
     hash: BlockHash,
     sender: Option<BlockSender>,
+    // The block matched a filter, so it is needed even if the client gives up on it
+    matched: bool,
+    attempts: u32,
 }
 
 impl Request {
     fn new(hash: BlockHash) -> Self {
-        Self { hash, sender: None }
+        Self {
+            hash,
+            sender: None,
+            matched: true,
+            attempts: 0,
+        }
     }
 
-    #[cfg(feature = "filter-control")]
     fn from_block_request(block_request: BlockRequest) -> Self {
         Self {
             hash: block_request.hash,
             sender: Some(block_request.oneshot),
+            matched: false,
+            attempts: 0,
         }
     }
 }
@@ -119,7 +206,6 @@ impl From<BlockHash> for Request {
     }
 }
 
-#[cfg(feature = "filter-control")]
 impl From<BlockRequest> for Request {
     fn from(value: BlockRequest) -> Self {
         Request::from_block_request(value)
@@ -129,6 +215,7 @@ impl From<BlockRequest> for Request {
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
+    use std::time::Duration;
 
     use super::*;
 
@@ -152,15 +239,15 @@ mod test {
         assert_eq!(queue.pop(), Some(hash_1));
         assert_eq!(queue.pop(), None);
         assert_eq!(
-            queue.want.as_ref().map(|request| request.hash),
+            queue.in_flight.first().map(|request| request.hash),
             Some(hash_1)
         );
         assert!(queue.need(&hash_1));
         queue.receive(&hash_1);
-        assert_eq!(queue.want.as_ref().map(|request| request.hash), None);
+        assert_eq!(queue.in_flight.first().map(|request| request.hash), None);
         assert_eq!(queue.pop(), Some(hash_2));
         assert_eq!(
-            queue.want.as_ref().map(|request| request.hash),
+            queue.in_flight.first().map(|request| request.hash),
             Some(hash_2)
         );
         assert!(queue.need(&hash_2));
@@ -201,16 +288,16 @@ mod test {
         tokio::time::sleep(Duration::from_secs(6)).await;
         assert_eq!(queue.pop(), Some(hash_1));
         assert_eq!(
-            queue.want.as_ref().map(|request| request.hash),
+            queue.in_flight.first().map(|request| request.hash),
             Some(hash_1)
         );
         assert!(queue.need(&hash_1));
         queue.receive(&hash_1);
         assert!(!queue.need(&hash_1));
-        assert_eq!(queue.want.as_ref().map(|request| request.hash), None);
+        assert_eq!(queue.in_flight.first().map(|request| request.hash), None);
         assert_eq!(queue.pop(), Some(hash_2));
         assert_eq!(
-            queue.want.as_ref().map(|request| request.hash),
+            queue.in_flight.first().map(|request| request.hash),
             Some(hash_2)
         );
         assert!(queue.need(&hash_2));
@@ -249,14 +336,144 @@ mod test {
         assert_eq!(queue.queue.len(), 3);
         assert_eq!(queue.pop(), Some(hash_1));
         assert_eq!(
-            queue.want.as_ref().map(|request| request.hash),
+            queue.in_flight.first().map(|request| request.hash),
             Some(hash_1)
         );
         queue.remove(&[hash_1]);
         assert!(!queue.need(&hash_1));
-        assert_eq!(queue.want.as_ref().map(|request| request.hash), None);
+        assert_eq!(queue.in_flight.first().map(|request| request.hash), None);
         queue.remove(&[hash_2]);
         assert_eq!(queue.queue.len(), 1);
         assert_eq!(queue.pop(), Some(hash_3));
     }
+
+    #[test]
+    fn test_matched_blocks_cleared() {
+        let hash_1 =
+            BlockHash::from_str("0000007a93b953158a12aef32eb9cc4366eb1eea5892fb04afbeec421c29319d")
+                .unwrap();
+        let hash_2 =
+            BlockHash::from_str("0000009e41d363546c5126c045bdef80e863324ac87f2bec88927a53662f6c0b")
+                .unwrap();
+        let hash_3 =
+            BlockHash::from_str("000000254633c01d43534d80981c3d1e0f4f3541cce2af68084e7631832d2572")
+                .unwrap();
+        let mut queue = BlockQueue::new();
+        queue.add(hash_1);
+        queue.add(hash_2);
+        assert_eq!(queue.pop(), Some(hash_1));
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_3));
+        queue.clear_matched();
+        assert!(!queue.need(&hash_1));
+        assert!(!queue.contains(&hash_2));
+        assert!(!queue.complete());
+        assert_eq!(queue.pop(), Some(hash_3));
+        queue.receive(&hash_3);
+        assert!(queue.complete());
+    }
+
+    #[test]
+    fn test_unserved_requests_are_dropped() {
+        let hash_1 =
+            BlockHash::from_str("0000007a93b953158a12aef32eb9cc4366eb1eea5892fb04afbeec421c29319d")
+                .unwrap();
+        let hash_2 =
+            BlockHash::from_str("0000009e41d363546c5126c045bdef80e863324ac87f2bec88927a53662f6c0b")
+                .unwrap();
+        let mut queue = BlockQueue::new();
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_1));
+        queue.add(hash_2);
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_2));
+        queue.set_max_in_flight(2);
+        assert_eq!(queue.pop(), Some(hash_1));
+        assert_eq!(queue.pop(), Some(hash_2));
+        let mut retries = Vec::new();
+        for _ in 0..2 * MAX_CLIENT_ATTEMPTS {
+            queue.last_req = Instant::now() - std::time::Duration::from_secs(SPAM_LIMIT);
+            retries.extend(queue.pop());
+        }
+        assert_eq!(queue.take_unserved().len(), 2);
+        // The block that matched a filter is still requested, but not for the client
+        assert!(!queue.need(&hash_1));
+        assert!(queue.need(&hash_2));
+        assert!(!queue.has_priority());
+        assert_eq!(retries.iter().filter(|hash| hash.eq(&&hash_1)).count(), 4);
+    }
+
+    #[test]
+    fn test_requested_blocks_first() {
+        let hash_1 =
+            BlockHash::from_str("0000007a93b953158a12aef32eb9cc4366eb1eea5892fb04afbeec421c29319d")
+                .unwrap();
+        let hash_2 =
+            BlockHash::from_str("0000009e41d363546c5126c045bdef80e863324ac87f2bec88927a53662f6c0b")
+                .unwrap();
+        let hash_3 =
+            BlockHash::from_str("000000254633c01d43534d80981c3d1e0f4f3541cce2af68084e7631832d2572")
+                .unwrap();
+        let mut queue = BlockQueue::new();
+        queue.add(hash_1);
+        queue.add(hash_2);
+        assert!(!queue.has_priority());
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_3));
+        assert!(queue.has_priority());
+        assert_eq!(queue.pop(), Some(hash_3));
+        assert!(queue.receive(&hash_3).is_some());
+        assert!(!queue.has_priority());
+        assert_eq!(queue.pop(), Some(hash_1));
+        // A block in flight is handed to the client
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_1));
+        assert!(queue.has_priority());
+        assert!(queue.receive(&hash_1).is_some());
+        // A queued block is moved ahead
+        let (tx, _) = tokio::sync::oneshot::channel();
+        queue.add(BlockRequest::new(tx, hash_2));
+        assert_eq!(queue.queue.len(), 0);
+        assert_eq!(queue.pop(), Some(hash_2));
+        assert!(queue.receive(&hash_2).is_some());
+        assert!(queue.complete());
+    }
+
+    #[test]
+    fn test_blocks_in_flight() {
+        let stale = || Instant::now() - Duration::from_secs(SPAM_LIMIT);
+        let hash_1 =
+            BlockHash::from_str("0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap();
+        let hash_2 =
+            BlockHash::from_str("0000000000000000000000000000000000000000000000000000000000000002")
+                .unwrap();
+        let hash_3 =
+            BlockHash::from_str("0000000000000000000000000000000000000000000000000000000000000003")
+                .unwrap();
+        let mut queue = BlockQueue::new();
+        queue.set_max_in_flight(2);
+        queue.add(hash_1);
+        queue.add(hash_2);
+        queue.add(hash_3);
+        assert_eq!(queue.pop(), Some(hash_1));
+        assert_eq!(queue.pop(), Some(hash_2));
+        // The limit is reached, and the requests are not yet stale
+        assert_eq!(queue.pop(), None);
+        assert!(queue.need(&hash_1));
+        assert!(queue.need(&hash_2));
+        assert!(!queue.need(&hash_3));
+        // Blocks may arrive in any order
+        assert!(queue.receive(&hash_2).is_none());
+        assert_eq!(queue.pop(), Some(hash_3));
+        // Stale requests are retried oldest first
+        queue.last_req = stale();
+        assert_eq!(queue.pop(), Some(hash_1));
+        assert_eq!(queue.pop(), None);
+        queue.last_req = stale();
+        assert_eq!(queue.pop(), Some(hash_3));
+        queue.remove(&[hash_1]);
+        assert!(queue.receive(&hash_3).is_none());
+        assert!(queue.complete());
+    }
 }
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use bitcoin::{BlockHash, ScriptBuf};
+
+use crate::zeroize::zeroize_scripts;
+
+// Matched blocks in each window over which the false positive rate is measured
+pub(crate) const WINDOW_BLOCKS: u32 = 100;
+// The false positive rate of a window that is considered a spike
+const SPIKE_PERCENT: u32 = 50;
+// A script is reported if it matched at least this share of the false positives of a window
+const CULPRIT_PERCENT: u32 = 10;
+
+// Scripts that matched blocks with no relevant transaction during a spike in false positives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BroadScriptReport {
+    pub(crate) scripts: Vec<ScriptBuf>,
+    pub(crate) false_positives: u32,
+    pub(crate) blocks: u32,
+}
+
+// Attribute the blocks downloaded for a filter match to the scripts that matched, so the scripts
+// responsible for many false positives may be reported. Scripts that are commonly spent by
+// other wallets, such as short or anyone-can-spend scripts, match far more filters than a script
+// owned by the wallet.
+#[derive(Debug, Default)]
+pub(crate) struct BroadScripts {
+    // The scripts that matched the filter of each block waiting to be downloaded
+    matched: HashMap<BlockHash, Vec<ScriptBuf>>,
+    // False positives of each script in the current window
+    false_positives: HashMap<ScriptBuf, u32>,
+    window_blocks: u32,
+    window_false_positives: u32,
+}
+
+impl BroadScripts {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_match(&mut self, block_hash: BlockHash, scripts: Vec<ScriptBuf>) {
+        self.matched.insert(block_hash, scripts);
+    }
+
+    // Record if the block had a relevant transaction, returning a report at the end of a window
+    // with a spike in false positives
+    pub(crate) fn record_block(
+        &mut self,
+        block_hash: &BlockHash,
+        relevant: bool,
+    ) -> Option<BroadScriptReport> {
+        let scripts = self.matched.remove(block_hash)?;
+        self.window_blocks += 1;
+        if !relevant {
+            self.window_false_positives += 1;
+            for script in scripts {
+                *self.false_positives.entry(script).or_default() += 1;
+            }
+        } else {
+            zeroize_scripts(scripts);
+        }
+        if self.window_blocks < WINDOW_BLOCKS {
+            return None;
+        }
+        let blocks = std::mem::take(&mut self.window_blocks);
+        let false_positives = std::mem::take(&mut self.window_false_positives);
+        let counts = std::mem::take(&mut self.false_positives);
+        if false_positives * 100 < blocks * SPIKE_PERCENT {
+            zeroize_scripts(counts.into_keys());
+            return None;
+        }
+        let (mut culprits, innocent): (Vec<_>, Vec<_>) = counts
+            .into_iter()
+            .partition(|(_, count)| count * 100 >= false_positives * CULPRIT_PERCENT);
+        zeroize_scripts(innocent.into_iter().map(|(script, _)| script));
+        if culprits.is_empty() {
+            return None;
+        }
+        culprits.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        Some(BroadScriptReport {
+            scripts: culprits.into_iter().map(|(script, _)| script).collect(),
+            false_positives,
+            blocks,
+        })
+    }
+
+    // Forget the matches of blocks that will not be downloaded
+    pub(crate) fn discard(&mut self, hashes: &[BlockHash]) {
+        for hash in hashes {
+            if let Some(scripts) = self.matched.remove(hash) {
+                zeroize_scripts(scripts);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        zeroize_scripts(self.matched.drain().flat_map(|(_, scripts)| scripts));
+    }
+}
+
+impl Drop for BroadScripts {
+    fn drop(&mut self) {
+        self.clear();
+        zeroize_scripts(self.false_positives.drain().map(|(script, _)| script));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn test_broad_scripts_are_reported() {
+        let broad = ScriptBuf::from_bytes(vec![0x51]);
+        let owned = ScriptBuf::from_bytes(vec![0x00, 0x14]);
+        let mut broad_scripts = BroadScripts::new();
+        let hash = |i: u32| BlockHash::hash(&i.to_le_bytes());
+        // Blocks matched by the broad script have no relevant transaction
+        for i in 0..WINDOW_BLOCKS - 1 {
+            let (script, relevant) = if i % 3 == 0 {
+                (owned.clone(), true)
+            } else {
+                (broad.clone(), false)
+            };
+            broad_scripts.record_match(hash(i), vec![script]);
+            assert!(broad_scripts.record_block(&hash(i), relevant).is_none());
+        }
+        // Blocks that were not matched are not counted
+        assert!(broad_scripts.record_block(&hash(1_000), false).is_none());
+        broad_scripts.record_match(hash(WINDOW_BLOCKS), vec![broad.clone()]);
+        let report = broad_scripts
+            .record_block(&hash(WINDOW_BLOCKS), false)
+            .unwrap();
+        assert_eq!(report.scripts, vec![broad.clone()]);
+        assert_eq!(report.blocks, WINDOW_BLOCKS);
+        assert_eq!(report.false_positives, 67);
+        // A window without a spike is not reported
+        for i in 0..WINDOW_BLOCKS {
+            broad_scripts.record_match(hash(i), vec![owned.clone()]);
+            assert!(broad_scripts.record_block(&hash(i), true).is_none());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use bitcoin::{p2p::message_filter::CFHeaders, BlockHash, FilterHeader};
+use bitcoin::{p2p::message_filter::CFHeaders, BlockHash, FilterHash, FilterHeader};
 
 use crate::chain::FilterCommitment;
 
@@ -43,6 +43,20 @@ impl CFHeaderBatch {
         self.inner.len() as u32
     }
 
+    // The offset of the first filter the batches disagree on, with the filter hash each batch
+    // commits to
+    pub(crate) fn first_conflict(
+        &self,
+        other: &CFHeaderBatch,
+    ) -> Option<(u32, FilterHash, FilterHash)> {
+        self.inner
+            .iter()
+            .zip(other.inner.iter())
+            .enumerate()
+            .find(|(_, (ours, theirs))| ours.filter_hash.ne(&theirs.filter_hash))
+            .map(|(offset, (ours, theirs))| (offset as u32, ours.filter_hash, theirs.filter_hash))
+    }
+
     pub(crate) fn take_inner(&mut self) -> Vec<FilterCommitment> {
         core::mem::take(&mut self.inner)
     }
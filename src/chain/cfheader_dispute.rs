@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{p2p::message_filter::CFilter, Block, BlockHash, FilterHash};
+
+use crate::network::PeerId;
+
+use super::{spot_check::has_outputs, Filter};
+
+// The time the peers and the peer sending the block have to answer before the dispute is dropped
+const DISPUTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Peers that sent conflicting filter headers. Filter headers commit to the hash of each filter, so
+// the first block the peers disagree on is downloaded along with the filter each peer has for it.
+// A peer is lying if its filter does not hash to the filter hash it committed to, or if the filter
+// is missing an output of the block. The block is requested of a peer outside the dispute, so a
+// lying peer cannot hold the dispute open by withholding it.
+#[derive(Debug, Clone)]
+pub(crate) struct FilterHeaderDispute {
+    height: u32,
+    block_hash: BlockHash,
+    // The filter hash each peer committed to for the block
+    claims: Vec<(PeerId, FilterHash)>,
+    filters: HashMap<PeerId, Filter>,
+    block: Option<Block>,
+    started: Instant,
+}
+
+impl FilterHeaderDispute {
+    pub(crate) fn new(
+        height: u32,
+        block_hash: BlockHash,
+        claims: Vec<(PeerId, FilterHash)>,
+    ) -> Self {
+        Self {
+            height,
+            block_hash,
+            claims,
+            filters: HashMap::new(),
+            block: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub(crate) fn block_hash(&self) -> BlockHash {
+        self.block_hash
+    }
+
+    pub(crate) fn peers(&self) -> Vec<PeerId> {
+        self.claims.iter().map(|(peer, _)| *peer).collect()
+    }
+
+    // If the filters and the block did not all arrive in time
+    pub(crate) fn timed_out(&self) -> bool {
+        self.started.elapsed() > DISPUTE_TIMEOUT
+    }
+
+    // The peers in the dispute that have not sent their filter
+    pub(crate) fn unanswered(&self) -> Vec<PeerId> {
+        self.claims
+            .iter()
+            .map(|(peer, _)| *peer)
+            .filter(|peer| !self.filters.contains_key(peer))
+            .collect()
+    }
+
+    // Keep the filter if it was sent by a peer in the dispute for the disputed block
+    pub(crate) fn add_filter(&mut self, peer: PeerId, filter: &CFilter) -> bool {
+        if filter.block_hash.ne(&self.block_hash)
+            || !self.claims.iter().any(|(claimant, _)| claimant.eq(&peer))
+        {
+            return false;
+        }
+        self.filters
+            .insert(peer, Filter::new(filter.filter.clone(), filter.block_hash));
+        true
+    }
+
+    // Keep the block if it is the disputed block
+    pub(crate) fn add_block(&mut self, block: &Block) -> bool {
+        if block.block_hash().ne(&self.block_hash) {
+            return false;
+        }
+        self.block = Some(block.clone());
+        true
+    }
+
+    // The peers found to be lying, once the block and the filter of each peer have arrived. If
+    // every filter matches its commitment and has the outputs of the block, no peer is found to
+    // be lying, as the scripts spent by the block are not known to check the rest of the filter.
+    pub(crate) fn resolve(&self) -> Option<Vec<PeerId>> {
+        let block = self.block.as_ref()?;
+        let mut liars = Vec::new();
+        for (peer, claim) in &self.claims {
+            let filter = self.filters.get(peer)?;
+            if filter.filter_hash().ne(claim) || !has_outputs(filter.block_filter(), block) {
+                liars.push(*peer);
+            }
+        }
+        Some(liars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{bip158::BlockFilter, constants::genesis_block, Network, ScriptBuf};
+
+    use super::*;
+
+    fn script_filter(block: &Block) -> Filter {
+        let filter = BlockFilter::new_script_filter(block, |outpoint| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+        })
+        .unwrap();
+        Filter::new(filter.content, block.block_hash())
+    }
+
+    #[test]
+    fn test_dispute_finds_lying_peer() {
+        let mut block = genesis_block(Network::Regtest);
+        let mut output = block.txdata[0].output[0].clone();
+        output.script_pubkey = ScriptBuf::from_bytes(vec![0x51]);
+        block.txdata[0].output.push(output);
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        let block_hash = block.block_hash();
+        let honest = script_filter(&block);
+        // The lying peer commits to a filter without the second output of the block
+        let mut omitted = block.clone();
+        omitted.txdata[0].output.pop();
+        let lying = script_filter(&omitted);
+        let mut dispute = FilterHeaderDispute::new(
+            1,
+            block_hash,
+            vec![
+                (PeerId(0), *honest.filter_hash()),
+                (PeerId(1), *lying.filter_hash()),
+            ],
+        );
+        let cfilter = |filter: &Filter| CFilter {
+            filter_type: 0x00,
+            block_hash,
+            filter: filter.block_filter().content.clone(),
+        };
+        assert!(dispute.add_filter(PeerId(0), &cfilter(&honest)));
+        assert!(!dispute.add_filter(PeerId(2), &cfilter(&honest)));
+        assert!(dispute.resolve().is_none());
+        assert!(dispute.add_filter(PeerId(1), &cfilter(&lying)));
+        assert!(!dispute.add_block(&genesis_block(Network::Regtest)));
+        assert!(dispute.resolve().is_none());
+        assert!(dispute.add_block(&block));
+        assert_eq!(dispute.resolve(), Some(vec![PeerId(1)]));
+        // A filter that does not match the commitment of the peer is also a lie
+        assert!(dispute.add_filter(PeerId(0), &cfilter(&lying)));
+        assert_eq!(dispute.resolve(), Some(vec![PeerId(0), PeerId(1)]));
+    }
+
+    #[test]
+    fn test_dispute_times_out_on_silent_peer() {
+        let block = genesis_block(Network::Regtest);
+        let block_hash = block.block_hash();
+        let filter = script_filter(&block);
+        let mut dispute = FilterHeaderDispute::new(
+            0,
+            block_hash,
+            vec![
+                (PeerId(0), *filter.filter_hash()),
+                (PeerId(1), *filter.filter_hash()),
+            ],
+        );
+        assert!(!dispute.timed_out());
+        assert_eq!(dispute.unanswered(), vec![PeerId(0), PeerId(1)]);
+        let cfilter = CFilter {
+            filter_type: 0x00,
+            block_hash,
+            filter: filter.block_filter().content.clone(),
+        };
+        assert!(dispute.add_filter(PeerId(1), &cfilter));
+        assert_eq!(dispute.unanswered(), vec![PeerId(0)]);
+        dispute.started = Instant::now() - DISPUTE_TIMEOUT - Duration::from_secs(1);
+        assert!(dispute.timed_out());
+    }
+}
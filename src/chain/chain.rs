@@ -1,56 +1,168 @@
 extern crate alloc;
 use std::{
-    collections::{BTreeMap, HashSet},
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet, VecDeque},
     ops::Range,
     sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+#[cfg(not(feature = "filter-control"))]
+use crate::XpubWatch;
 use bitcoin::{
+    bip158::BlockFilter,
     block::Header,
     p2p::message_filter::{CFHeaders, CFilter, GetCFHeaders, GetCFilters},
-    Block, BlockHash, Network, ScriptBuf,
+    Block, BlockHash, Network, ScriptBuf, Transaction,
+};
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::{Address, OutPoint};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
 };
-use tokio::sync::Mutex;
 
+#[cfg(not(feature = "filter-control"))]
+use super::broad_scripts::BroadScripts;
+#[cfg(not(feature = "filter-control"))]
+use super::outpoints::WatchedOutpoints;
 use super::{
+    block_body::check_block_body,
     block_queue::BlockQueue,
     cfheader_batch::CFHeaderBatch,
+    cfheader_dispute::FilterHeaderDispute,
     checkpoints::{HeaderCheckpoint, HeaderCheckpoints},
     error::{BlockScanError, CFHeaderSyncError, CFilterSyncError, HeaderSyncError},
     graph::{AcceptHeaderChanges, BlockTree, HeaderRejection},
-    CFHeaderChanges, Filter, FilterHeaderRequest, FilterRequest, FilterRequestState, HeightExt,
-    HeightMonitor, PeerId,
+    script_index::ScriptIndex,
+    script_shards::ScriptShards,
+    spot_check::SpotChecks,
+    triggers::Triggers,
+    utxos::UtxoSet,
+    view::ChainView,
+    CFHeaderChanges, Filter, FilterCommitment, FilterHeaderRequest, FilterRequestState, HeightExt,
+    HeightMonitor, IndexedHeader, PeerId,
 };
-#[cfg(feature = "filter-control")]
+#[cfg(not(feature = "filter-control"))]
+use crate::derivation::Derivations;
+#[cfg(not(feature = "filter-control"))]
+use crate::error::AddAddressError;
 use crate::error::FetchBlockError;
-#[cfg(feature = "filter-control")]
+#[cfg(not(feature = "filter-control"))]
+use crate::filter_matcher::FilterMatcher;
 use crate::messages::BlockRequest;
+#[cfg(not(feature = "filter-control"))]
+use crate::zeroize::zeroize_scripts;
 #[cfg(feature = "filter-control")]
 use crate::IndexedFilter;
 use crate::{
     chain::header_batch::HeadersBatch,
-    db::{traits::HeaderStore, BlockHeaderChanges},
+    checkpoint_provider::CheckpointProvider,
+    db::{
+        traits::{DynCFHeaderStore, DynFilterStore, HeaderStore},
+        BlockHeaderChanges, PersistedBroadcast, PersistedFilterHeader,
+    },
     dialog::Dialog,
     error::HeaderPersistenceError,
-    messages::{Event, Warning},
-    IndexedBlock, Info, Progress,
+    export::HeaderAttestation,
+    messages::{BlockSender, ConfirmationTrigger, Event, HeightTrigger, Warning},
+    prelude::{poll_once, YieldBudget},
+    FilterCheckpoint, IndexedBlock, Info, IntegrityFailure, IntegrityIssue, IntegrityReport,
+    Progress, ScanStats, ScriptShardStats, ScriptTx, Subsystem, Utxo,
 };
 
 const REORG_LOOKBACK: u32 = 7;
+// Recent headers reported when the node is synced
+const RECENT_HISTORY_LEN: u32 = 10;
 const FILTER_BASIC: u8 = 0x00;
 const CF_HEADER_BATCH_SIZE: u32 = 1_999;
-const FILTER_BATCH_SIZE: u32 = 999;
+// Headers accepted before yielding to the runtime
+const HEADERS_PER_YIELD: u32 = 250;
+// Stored filters checked before yielding to the runtime
+const STORED_FILTERS_PER_YIELD: u32 = 50;
+// The deepest fork followed below the headers kept in memory, so a header that builds on an old
+// block cannot make the node load much of the chain from the header store
+const MAX_STORED_FORK_DEPTH: u32 = 2_016;
+// Roughly one year of blocks past the newest checkpoint before the checkpoints are considered stale
+const STALE_CHECKPOINT_BLOCKS: u32 = 52_560;
+
+// The first block to pay to our scripts, when it is being detected
+#[derive(Debug, Clone, Copy)]
+enum Birthday {
+    Ignored,
+    Searching,
+    // Found in a scanned block, but not yet written to the header store
+    Unwritten(HeaderCheckpoint),
+    Found(HeaderCheckpoint),
+}
 
 #[derive(Debug)]
 pub(crate) struct Chain<H: HeaderStore> {
     pub(crate) header_chain: BlockTree,
     request_state: FilterRequestState,
     checkpoints: HeaderCheckpoints,
+    checkpoint_provider: Option<Box<dyn CheckpointProvider>>,
+    // Stale checkpoints are only refreshed and reported once
+    stale_checkpoints_checked: bool,
     network: Network,
     db: Arc<Mutex<H>>,
     heights: Arc<Mutex<HeightMonitor>>,
-    scripts: HashSet<ScriptBuf>,
+    scripts: ScriptShards,
+    script_index: Option<ScriptIndex>,
+    utxos: Option<UtxoSet>,
+    filter_store: Option<Box<dyn DynFilterStore>>,
+    // Filters checked since the last write to the filter store
+    unwritten_filters: Vec<(BlockHash, BlockFilter)>,
+    cf_header_store: Option<Box<dyn DynCFHeaderStore>>,
+    // Filter headers agreed on since the last write to the filter header store
+    unwritten_cf_headers: BTreeMap<u32, PersistedFilterHeader>,
+    birthday: Birthday,
+    scan_stats: ScanStats,
+    // Blocks below the anchor loaded on startup, to detect reorganizations while offline
+    reorg_depth: u32,
+    spot_checks: SpotChecks,
+    #[cfg(not(feature = "filter-control"))]
+    filter_matcher: Option<Box<dyn FilterMatcher>>,
+    #[cfg(not(feature = "filter-control"))]
+    derivations: Option<Derivations>,
+    #[cfg(not(feature = "filter-control"))]
+    broad_scripts: BroadScripts,
+    // Stop checking scripts reported to cause many false positives
+    #[cfg(not(feature = "filter-control"))]
+    exclude_broad_scripts: bool,
+    // Report matched blocks in place of downloading them
+    #[cfg(not(feature = "filter-control"))]
+    filter_matches_only: bool,
+    #[cfg(not(feature = "filter-control"))]
+    outpoints: WatchedOutpoints,
     block_queue: BlockQueue,
+    // Matched blocks that arrived before the matched blocks below them, by height, and if the
+    // block was already sent to the client that requested it
+    held_blocks: BTreeMap<u32, (Block, bool)>,
+    block_stream: Option<mpsc::Sender<IndexedBlock>>,
+    // Matched blocks waiting for the client to make room in the block stream
+    unstreamed_blocks: VecDeque<IndexedBlock>,
+    rescan_checked_to: Option<u32>,
+    trust_checkpoints: bool,
+    // Headers accepted without a proof of work check, held back from the store until they link
+    // to a checkpoint
+    unchecked_headers: Vec<BlockHeaderChanges>,
+    // The tip the unchecked headers were built on
+    unchecked_from: Option<HeaderCheckpoint>,
+    // How far header timestamps may be ahead of the network-adjusted time, if they are checked
+    header_time_drift: Option<Duration>,
+    // The median offset of the clocks of peers from the local clock, in seconds
+    time_offset: i64,
+    // Staged headers are discarded if a write fails, so the store may be missing headers
+    write_failed: bool,
+    // Blocks reorganized out of the chain since the last call to `take_disconnected`
+    disconnected: Vec<BlockHash>,
+    // The filter header before the first filter header synced
+    anchor_filter: Option<FilterCheckpoint>,
+    // Headers of the chain of most work shared with the client
+    view: ChainView,
+    // Clients waiting on a height or the confirmations of a transaction
+    triggers: Triggers,
     dialog: Arc<Dialog>,
 }
 
@@ -59,36 +171,248 @@ impl<H: HeaderStore> Chain<H> {
     pub(crate) fn new(
         network: Network,
         scripts: HashSet<ScriptBuf>,
+        script_shard_size: Option<usize>,
         anchor: HeaderCheckpoint,
         checkpoints: HeaderCheckpoints,
         dialog: Arc<Dialog>,
         height_monitor: Arc<Mutex<HeightMonitor>>,
         db: H,
         quorum_required: u8,
+        trust_checkpoints: bool,
     ) -> Self {
         let header_chain = BlockTree::new(anchor, network);
         Chain {
             header_chain,
             checkpoints,
+            checkpoint_provider: None,
+            stale_checkpoints_checked: false,
             request_state: FilterRequestState::new(quorum_required),
             network,
             db: Arc::new(Mutex::new(db)),
             heights: height_monitor,
-            scripts,
+            scripts: ScriptShards::new(scripts, script_shard_size),
+            script_index: None,
+            utxos: None,
+            filter_store: None,
+            unwritten_filters: Vec::new(),
+            cf_header_store: None,
+            unwritten_cf_headers: BTreeMap::new(),
+            birthday: Birthday::Ignored,
+            scan_stats: ScanStats::default(),
+            reorg_depth: REORG_LOOKBACK,
+            spot_checks: SpotChecks::default(),
+            #[cfg(not(feature = "filter-control"))]
+            filter_matcher: None,
+            #[cfg(not(feature = "filter-control"))]
+            derivations: None,
+            #[cfg(not(feature = "filter-control"))]
+            broad_scripts: BroadScripts::new(),
+            #[cfg(not(feature = "filter-control"))]
+            exclude_broad_scripts: false,
+            #[cfg(not(feature = "filter-control"))]
+            filter_matches_only: false,
+            #[cfg(not(feature = "filter-control"))]
+            outpoints: WatchedOutpoints::new(),
             block_queue: BlockQueue::new(),
+            held_blocks: BTreeMap::new(),
+            block_stream: None,
+            unstreamed_blocks: VecDeque::new(),
+            rescan_checked_to: None,
+            trust_checkpoints,
+            unchecked_headers: Vec::new(),
+            unchecked_from: None,
+            header_time_drift: None,
+            time_offset: 0,
+            write_failed: false,
+            disconnected: Vec::new(),
+            anchor_filter: None,
+            view: ChainView::new(),
+            triggers: Triggers::new(),
             dialog,
         }
     }
 
-    // The last ten heights and headers in the chain
-    pub(crate) fn last_ten(&self) -> BTreeMap<u32, Header> {
+    // Share the chain of most work with a client
+    pub(crate) fn with_chain_view(mut self, view: ChainView) -> Self {
+        self.view = view;
+        self
+    }
+
+    // Check filters with a matcher in place of the scripts
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn with_filter_matcher(mut self, matcher: Option<Box<dyn FilterMatcher>>) -> Self {
+        self.filter_matcher = matcher;
+        self
+    }
+
+    // Check filters for the scripts derived from an extended public key
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn with_xpub_watch(mut self, watch: Option<XpubWatch>) -> Self {
+        if let Some(watch) = watch {
+            let mut derivations = Derivations::new(watch);
+            for script in derivations.extend() {
+                self.scripts.insert(script);
+            }
+            self.derivations = Some(derivations);
+        }
+        self
+    }
+
+    // Remove scripts that cause a spike in false positives from the checked scripts
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn with_broad_script_exclusion(mut self, enabled: bool) -> Self {
+        self.exclude_broad_scripts = enabled;
+        self
+    }
+
+    // Send an event for each matched filter without downloading the block
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn with_filter_matches_only(mut self, enabled: bool) -> Self {
+        self.filter_matches_only = enabled;
+        self
+    }
+
+    // Check filters for the spends of the outpoints
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn with_outpoints(mut self, outpoints: HashSet<OutPoint>) -> Self {
+        self.put_outpoints(outpoints);
+        self
+    }
+
+    // Download up to this many blocks at once
+    pub(crate) fn with_block_workers(mut self, workers: usize) -> Self {
+        self.block_queue.set_max_in_flight(workers);
+        self
+    }
+
+    // Detect the first block to pay to our scripts, and skip the filters before it
+    pub(crate) fn with_birthday_detection(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.birthday = Birthday::Searching;
+        }
+        self
+    }
+
+    // Check the outputs of this many randomly sampled blocks against their filter in each range
+    pub(crate) fn with_filter_spot_checks(mut self, samples: u32) -> Self {
+        self.spot_checks = SpotChecks::new(samples);
+        self
+    }
+
+    // Check this many blocks below the anchor for reorganizations on startup
+    pub(crate) fn with_reorg_depth(mut self, depth: Option<u32>) -> Self {
+        if let Some(depth) = depth {
+            self.reorg_depth = depth;
+        }
+        self
+    }
+
+    // Reject headers with timestamps at or below the median time past, or too far in the future
+    pub(crate) fn with_header_time_drift(mut self, drift: Option<Duration>) -> Self {
+        self.header_time_drift = drift;
+        self
+    }
+
+    // Record the transactions of scanned blocks that pay to or spend from our scripts
+    pub(crate) fn with_script_index(mut self, enabled: bool) -> Self {
+        self.script_index = enabled.then(ScriptIndex::new);
+        self
+    }
+
+    // Write checked filters to a store, and check the stored filters before requesting them
+    pub(crate) fn with_filter_store(mut self, store: Option<Box<dyn DynFilterStore>>) -> Self {
+        self.filter_store = store;
+        self
+    }
+
+    // Write agreed filter headers to a store, and load the stored filter headers on start
+    pub(crate) fn with_cf_header_store(mut self, store: Option<Box<dyn DynCFHeaderStore>>) -> Self {
+        self.cf_header_store = store;
+        self
+    }
+
+    // Track the unspent outputs paying to our scripts
+    pub(crate) fn with_utxo_tracking(mut self, enabled: bool) -> Self {
+        self.utxos = enabled.then(UtxoSet::new);
+        self
+    }
+
+    // Ask for newer checkpoints when a peer is far past the newest checkpoint
+    pub(crate) fn with_checkpoint_provider(
+        mut self,
+        provider: Option<Box<dyn CheckpointProvider>>,
+    ) -> Self {
+        self.checkpoint_provider = provider;
+        self
+    }
+
+    // Refresh the checkpoints from the provider if a peer advertises a height far past the newest
+    // checkpoint, and warn if they are still stale
+    pub(crate) fn check_checkpoint_freshness(&mut self, peer_height: u32) {
+        let is_stale = |checkpoints: &HeaderCheckpoints| {
+            peer_height
+                > checkpoints
+                    .last()
+                    .height
+                    .saturating_add(STALE_CHECKPOINT_BLOCKS)
+        };
+        if self.stale_checkpoints_checked || !is_stale(&self.checkpoints) {
+            return;
+        }
+        self.stale_checkpoints_checked = true;
+        if let Some(provider) = self.checkpoint_provider.as_mut() {
+            if self.checkpoints.extend(provider.checkpoints(self.network)) {
+                // Checkpoints our headers have already passed cannot be met again
+                let tip =
+                    HeaderCheckpoint::new(self.header_chain.height(), self.header_chain.tip_hash());
+                self.checkpoints.prune_up_to(tip);
+            }
+            if !is_stale(&self.checkpoints) {
+                return;
+            }
+        }
+        self.dialog.send_warning(Warning::OutdatedCheckpoints {
+            checkpoint: self.checkpoints.last(),
+            peer_height,
+        });
+    }
+
+    // The height the chain of most work is known to have reached, from our headers or checkpoints
+    pub(crate) fn known_height(&self) -> u32 {
+        self.header_chain
+            .height()
+            .max(self.checkpoints.last().height)
+    }
+
+    // The recent heights and headers in the chain, covering at least the reorganization depth
+    pub(crate) fn recent_history(&self) -> BTreeMap<u32, Header> {
         self.header_chain
             .iter_headers()
-            .take(10)
+            .take(RECENT_HISTORY_LEN.max(self.reorg_depth) as usize)
             .map(|index| (index.height, index.header))
             .collect()
     }
 
+    // The filter header of the tip, if the filter headers are synced to it
+    pub(crate) fn tip_filter(&self) -> Option<FilterCheckpoint> {
+        let hash = self.header_chain.tip_hash();
+        let commitment = self.header_chain.filter_commitment(hash)?;
+        Some(FilterCheckpoint::new(
+            HeaderCheckpoint::new(self.header_chain.height(), hash),
+            commitment.header,
+        ))
+    }
+
+    pub(crate) fn anchor_filter(&self) -> Option<FilterCheckpoint> {
+        self.anchor_filter
+    }
+
+    // Are enough peers within one block of our tip to trust it
+    pub(crate) async fn is_tip_agreed(&self, required: u8) -> bool {
+        let height_lock = self.heights.lock().await;
+        height_lock.near(self.header_chain.height()) >= required as usize
+    }
+
     // Do we have best known height and is our height equal to it
     // If our height is greater, we received partial inventory, and
     // the header message contained the rest of the new blocks.
@@ -108,8 +432,8 @@ impl<H: HeaderStore> Chain<H> {
         let scan_height = self.header_chain.height();
         // The header relevant to compute the next adjustment
         let last_adjustment = scan_height.last_epoch_start(self.network);
-        // Seven blocks ago
-        let reorg = scan_height.saturating_sub(REORG_LOOKBACK);
+        // The deepest reorganization to detect, seven blocks ago by default
+        let reorg = scan_height.saturating_sub(self.reorg_depth);
         // To handle adjustments and reorgs, we would have the minimum of each of these heights
         let min_interesting_height = last_adjustment.min(reorg);
         let max_interesting_height = last_adjustment.max(reorg);
@@ -125,89 +449,294 @@ impl<H: HeaderStore> Chain<H> {
         }
         // Now that the block tree is updated to the appropriate start, load in the rest of
         // the history from this point onward. This is either: from the user start height,
-        // from the last difficulty adjustment, or seven blocks ago, depending on what the
+        // from the last difficulty adjustment, or the reorganization depth, depending on what the
         // header store was able to provide.
+        let started = Instant::now();
         let loaded_headers = db
             .load(self.header_chain.height().increment()..)
             .await
             .map_err(HeaderPersistenceError::Database)?;
+        self.dialog.check_database_latency("load headers", started);
+        let mut checked = 0;
+        let mut failure = None;
+        let mut result = Ok(());
+        // Recovering from a stale header store may load a large part of the chain
+        let mut budget = YieldBudget::new(HEADERS_PER_YIELD);
         for (height, header) in loaded_headers {
+            budget.tick().await;
             let apply_header_changes = self.header_chain.accept_header(header);
-            match apply_header_changes {
+            let issue = match apply_header_changes {
                 AcceptHeaderChanges::Accepted { connected_at } => {
+                    let checkpoint = self.checkpoints.next().copied();
                     if height.ne(&connected_at.height) {
                         self.dialog.send_warning(Warning::CorruptedHeaders);
-                        return Err(HeaderPersistenceError::HeadersDoNotLink);
-                    }
-                    if let Some(checkpoint) = self.checkpoints.next() {
+                        result = Err(HeaderPersistenceError::HeadersDoNotLink);
+                        Some(IntegrityIssue::DoesNotLink)
+                    } else if let Some(checkpoint) = checkpoint {
                         if connected_at.header.block_hash().eq(&checkpoint.hash) {
-                            self.checkpoints.advance()
+                            self.checkpoints.advance();
+                            None
+                        } else if height.eq(&checkpoint.height) {
+                            self.dialog.send_warning(Warning::CorruptedHeaders);
+                            result = Err(HeaderPersistenceError::MismatchedCheckpoints);
+                            Some(IntegrityIssue::CheckpointMismatch)
+                        } else {
+                            None
                         }
+                    } else {
+                        None
                     }
                 }
                 AcceptHeaderChanges::Rejected(reject_reason) => match reject_reason {
                     HeaderRejection::UnknownPrevHash(_) => {
-                        return Err(HeaderPersistenceError::CannotLocateHistory);
+                        result = Err(HeaderPersistenceError::CannotLocateHistory);
+                        Some(IntegrityIssue::DoesNotLink)
                     }
                     HeaderRejection::InvalidPow { expected, got } => {
                         crate::log!(
-                            self.dialog,
+                            self.dialog, Subsystem::Chain,
                             format!(
                                 "Unexpected invalid proof of work when importing a block header. expected {}, got: {}",
                                 expected.to_consensus(),
                                 got.to_consensus()
                             )
                         );
+                        Some(IntegrityIssue::InvalidWork)
                     }
                 },
-                _ => (),
+                _ => None,
+            };
+            match issue {
+                Some(issue) => {
+                    failure.get_or_insert(IntegrityFailure { height, issue });
+                }
+                None if failure.is_none() => checked += 1,
+                None => (),
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        self.view.reset(self.header_chain.iter_headers());
+        crate::info!(
+            self.dialog,
+            Subsystem::Database,
+            Info::IntegrityReport(IntegrityReport { checked, failure })
+        );
+        result?;
+        if let Some(index) = self.script_index.as_mut() {
+            let history = db
+                .load_script_history()
+                .await
+                .map_err(HeaderPersistenceError::Database)?;
+            index.load(history);
+        }
+        if let Some(utxos) = self.utxos.as_mut() {
+            let loaded = db
+                .load_utxos()
+                .await
+                .map_err(HeaderPersistenceError::Database)?;
+            utxos.load(loaded);
+        }
+        if let Birthday::Searching = self.birthday {
+            if let Some(birthday) = db
+                .load_birthday()
+                .await
+                .map_err(HeaderPersistenceError::Database)?
+            {
+                self.birthday = Birthday::Found(birthday);
             }
         }
         // Because the user requested a scan after the `scan_height`, the filters below this point
         // may be assumed as checked. Note that in a reorg, filters below this height may still be
         // retrieved, as this only considers the canonical chain as checked.
         self.header_chain.assume_checked_to(scan_height);
+        drop(db);
+        self.assume_checked_to_birthday();
         Ok(())
     }
 
+    // Set the filter headers of the chain from the filter header store, stopping at the first
+    // filter header that is not for a block of the chain of most work or does not extend the
+    // filter header before it. The filter headers after it are requested from peers.
+    pub(crate) async fn load_cf_headers(&mut self) {
+        let mut headers = self
+            .header_chain
+            .iter_headers()
+            .collect::<Vec<IndexedHeader>>();
+        headers.reverse();
+        let first = match headers.first() {
+            Some(first) => *first,
+            None => return,
+        };
+        let store = match self.cf_header_store.as_mut() {
+            Some(store) => store,
+            None => return,
+        };
+        let started = Instant::now();
+        let stored = match store.load_filter_headers(first.height).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not load filter headers from disk: {e}"),
+                });
+                return;
+            }
+        };
+        self.dialog
+            .check_database_latency("load filter headers", started);
+        let mut prev_filter_header = None;
+        let mut loaded = 0;
+        for indexed_header in headers {
+            let block_hash = indexed_header.header.block_hash();
+            let stored = match stored.get(&indexed_header.height) {
+                Some(stored) if stored.block_hash.eq(&block_hash) => stored,
+                _ => break,
+            };
+            let filter_header = stored.filter_header();
+            match prev_filter_header {
+                Some(prev) if stored.prev_filter_header.ne(&prev) => break,
+                Some(_) => (),
+                None => {
+                    self.anchor_filter = match indexed_header.height.checked_sub(1) {
+                        Some(height) => Some(FilterCheckpoint::new(
+                            HeaderCheckpoint::new(height, indexed_header.header.prev_blockhash),
+                            stored.prev_filter_header,
+                        )),
+                        None => Some(FilterCheckpoint::new(
+                            HeaderCheckpoint::new(0, block_hash),
+                            filter_header,
+                        )),
+                    };
+                }
+            }
+            let commitment = FilterCommitment {
+                header: filter_header,
+                filter_hash: stored.filter_hash,
+            };
+            self.header_chain.set_commitment(commitment, block_hash);
+            prev_filter_header = Some(filter_header);
+            loaded += 1;
+        }
+        if loaded > 0 {
+            crate::log!(
+                self.dialog,
+                Subsystem::Database,
+                format!("Loaded {loaded} filter headers from the filter header store")
+            );
+        }
+    }
+
+    // Write the filter headers agreed on since the last write to the filter header store
+    pub(crate) async fn write_cf_headers(&mut self) {
+        if self.unwritten_cf_headers.is_empty() {
+            return;
+        }
+        if let Some(store) = self.cf_header_store.as_mut() {
+            let filter_headers = core::mem::take(&mut self.unwritten_cf_headers);
+            if let Err(e) = store.write_filter_headers(filter_headers).await {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save filter headers to disk: {e}"),
+                });
+            }
+        }
+    }
+
     // Sync the chain with headers from a peer, adjusting to reorgs if needed
     pub(crate) async fn sync_chain(&mut self, message: Vec<Header>) -> Result<(), HeaderSyncError> {
-        let header_batch = HeadersBatch::new(message).map_err(|_| HeaderSyncError::EmptyMessage)?;
+        let mut header_batch =
+            HeadersBatch::new(message).map_err(|_| HeaderSyncError::EmptyMessage)?;
         // If our chain already has the last header in the message there is no new information
         if self.header_chain.contains(header_batch.last().block_hash()) {
             return Ok(());
         }
-        // We check first if the peer is sending us nonsense
-        self.sanity_check(&header_batch)?;
+        // Timestamps are checked against the blocks before the batch and the network-adjusted time.
+        // Headers too far in the future may be valid later, so they are left for a later request.
+        if let Some(max_time) = self.max_header_time() {
+            let prev_times = self
+                .header_chain
+                .ancestor_times(header_batch.first().prev_blockhash);
+            if !header_batch.times_valid(prev_times) {
+                return Err(HeaderSyncError::InvalidHeaderTimes);
+            }
+            if !header_batch.retain_until(max_time) {
+                return Err(HeaderSyncError::FutureHeaders);
+            }
+        }
+        // We check first if the peer is sending us nonsense. Hashing thousands of headers may take
+        // a while on slow devices, so this is done on a blocking thread to keep peer I/O responsive.
+        let network = self.network;
+        // Proof of work may be skipped for headers that must link to a checkpoint
+        let check_pow = !self.trust_checkpoints
+            || self.header_chain.height() + header_batch.len() > self.checkpoints.last().height;
+        let header_batch = crate::runtime::spawn_blocking(move || {
+            header_batch
+                .verify(network, check_pow)
+                .map(|_| header_batch)
+        })
+        .await
+        .ok_or(HeaderSyncError::VerificationAborted)??;
+        let prev_hash = header_batch.first().prev_blockhash;
+        if !self.header_chain.contains(prev_hash) {
+            self.load_fork_ancestors(prev_hash).await;
+        }
         let next_checkpoint = self.checkpoints.next().copied();
         let mut db = self.db.lock().await;
         let mut reorg_occured = false;
+        let mut rows = 0;
+        let mut budget = YieldBudget::new(HEADERS_PER_YIELD);
         for header in header_batch.into_iter() {
+            budget.tick().await;
+            let base =
+                HeaderCheckpoint::new(self.header_chain.height(), self.header_chain.tip_hash());
             let changes = self.header_chain.accept_header(header);
             match changes {
                 AcceptHeaderChanges::Accepted { connected_at } => {
                     crate::log!(
                         self.dialog,
+                        Subsystem::Chain,
                         format!(
                             "Chain updated {} -> {}",
                             connected_at.height,
                             connected_at.header.block_hash()
                         )
                     );
-                    db.stage(BlockHeaderChanges::Connected(connected_at));
+                    let changes = BlockHeaderChanges::Connected(connected_at);
+                    self.view.apply(&changes);
+                    if !check_pow || !self.unchecked_headers.is_empty() {
+                        self.unchecked_from.get_or_insert(base);
+                        self.unchecked_headers.push(changes);
+                    } else {
+                        db.stage(changes);
+                        rows += 1;
+                    }
                     if let Some(checkpoint) = next_checkpoint {
                         if connected_at.height.eq(&checkpoint.height) {
                             if connected_at.header.block_hash().eq(&checkpoint.hash) {
                                 crate::log!(
                                     self.dialog,
+                                    Subsystem::Chain,
                                     format!("Found checkpoint, height: {}", checkpoint.height)
                                 );
                                 self.checkpoints.advance();
+                                // The checkpoint vouches for the headers that link to it
+                                for changes in self.unchecked_headers.drain(..) {
+                                    rows += match &changes {
+                                        BlockHeaderChanges::Connected(_) => 1,
+                                        BlockHeaderChanges::Reorganized { accepted, .. } => {
+                                            accepted.len()
+                                        }
+                                    };
+                                    db.stage(changes);
+                                }
+                                self.unchecked_from = None;
                             } else {
                                 self.dialog
                     .send_warning(
                         Warning::UnexpectedSyncError { warning: "Unmatched checkpoint sent by a peer. Restarting header sync with new peers.".into() }
                     );
+                                drop(db);
+                                self.discard_unchecked_headers().await;
                                 return Err(HeaderSyncError::InvalidCheckpoint);
                             }
                         }
@@ -216,7 +745,11 @@ impl<H: HeaderStore> Chain<H> {
                 AcceptHeaderChanges::Duplicate => (),
                 AcceptHeaderChanges::ExtendedFork { connected_at } => match next_checkpoint {
                     Some(_checkpoint_expected) => {
-                        crate::log!(self.dialog, "Detected fork before known checkpoint");
+                        crate::log!(
+                            self.dialog,
+                            Subsystem::Chain,
+                            "Detected fork before known checkpoint"
+                        );
                         self.dialog.send_warning(Warning::UnexpectedSyncError {
                             warning: "Pre-checkpoint fork".into(),
                         });
@@ -224,6 +757,7 @@ impl<H: HeaderStore> Chain<H> {
                     None => {
                         crate::log!(
                             self.dialog,
+                            Subsystem::Chain,
                             format!("Fork created or extended {}", connected_at.height)
                         )
                     }
@@ -232,17 +766,43 @@ impl<H: HeaderStore> Chain<H> {
                     accepted,
                     disconnected,
                 } => {
-                    crate::log!(self.dialog, "Valid reorganization found");
+                    crate::log!(self.dialog, Subsystem::Chain, "Valid reorganization found");
                     reorg_occured = true;
                     let removed_hashes: Vec<BlockHash> = disconnected
                         .iter()
                         .map(|index| index.header.block_hash())
                         .collect();
                     self.block_queue.remove(&removed_hashes);
-                    db.stage(BlockHeaderChanges::Reorganized {
+                    self.held_blocks
+                        .retain(|_, (block, _)| !removed_hashes.contains(&block.block_hash()));
+                    self.spot_checks.discard(&removed_hashes);
+                    #[cfg(not(feature = "filter-control"))]
+                    self.broad_scripts.discard(&removed_hashes);
+                    if let Some(index) = self.script_index.as_mut() {
+                        index.disconnect(&removed_hashes);
+                    }
+                    if let Some(utxos) = self.utxos.as_mut() {
+                        let (removed, unspent) = utxos.disconnect(&removed_hashes);
+                        if !removed.is_empty() || !unspent.is_empty() {
+                            self.dialog
+                                .send_event(Event::UtxosReorganized { removed, unspent });
+                        }
+                    }
+                    self.triggers.disconnect(&removed_hashes);
+                    self.disconnected.extend(removed_hashes);
+                    let accepted_rows = accepted.len();
+                    let changes = BlockHeaderChanges::Reorganized {
                         accepted,
                         reorganized: disconnected.clone(),
-                    });
+                    };
+                    self.view.apply(&changes);
+                    if !check_pow || !self.unchecked_headers.is_empty() {
+                        self.unchecked_from.get_or_insert(base);
+                        self.unchecked_headers.push(changes);
+                    } else {
+                        db.stage(changes);
+                        rows += accepted_rows;
+                    }
                     let disconnected_event =
                         Event::BlocksDisconnected(disconnected.into_iter().rev().collect());
                     self.dialog.send_event(disconnected_event);
@@ -253,41 +813,119 @@ impl<H: HeaderStore> Chain<H> {
                         got: _,
                     } => return Err(HeaderSyncError::InvalidBits),
                     HeaderRejection::UnknownPrevHash(prev) => {
-                        crate::log!(self.dialog, format!("Unknown prevhash does not link to the current header chain: {prev}"));
+                        // Forks below the headers kept in memory were loaded from the header store,
+                        // unless they are too deep to follow
+                        if let Ok(Some(height)) = db.height_of(&prev).await {
+                            crate::log!(self.dialog, Subsystem::Chain, format!("Header forks from block {prev} at height {height}, deeper than a fork is followed"));
+                        } else {
+                            crate::log!(self.dialog, Subsystem::Chain, format!("Unknown prevhash does not link to the current header chain: {prev}"));
+                        }
                         return Err(HeaderSyncError::FloatingHeaders);
                     }
                 },
             }
         }
-        if let Err(e) = db.write().await {
-            self.dialog.send_warning(Warning::FailedPersistence {
-                warning: format!("Could not save headers to disk: {e}"),
-            });
+        // The header store remains locked, so only the triggers are borrowed
+        if let Some(tip) = self.indexed_tip() {
+            self.triggers.resolve(tip);
+        }
+        let started = Instant::now();
+        match db.write().await {
+            Ok(()) => {
+                if rows > 0 {
+                    crate::info!(
+                        self.dialog,
+                        Subsystem::Database,
+                        Info::DatabaseFlushed { rows }
+                    );
+                }
+            }
+            Err(e) => {
+                self.write_failed = true;
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save headers to disk: {e}"),
+                })
+            }
         }
+        self.dialog.check_database_latency("write headers", started);
         drop(db);
         if reorg_occured {
+            // Held blocks may have been waiting on blocks that were disconnected
+            self.release_blocks();
             self.clear_compact_filter_queue();
         }
         Ok(())
     }
 
-    // These are invariants in all batches of headers we receive
-    fn sanity_check(&mut self, header_batch: &HeadersBatch) -> Result<(), HeaderSyncError> {
-        // All the headers connect with each other and is the difficulty adjustment not absurd
-        if !header_batch.connected() {
-            return Err(HeaderSyncError::HeadersNotConnected);
+    // Follow a fork from a block below the headers kept in memory by loading the chain of most work
+    // above that block from the header store
+    async fn load_fork_ancestors(&mut self, prev_hash: BlockHash) {
+        // The header store is behind the headers in memory until they are written
+        if !self.unchecked_headers.is_empty() || self.write_failed {
+            return;
         }
-
-        // All headers pass their own proof of work and the network minimum
-        if !header_batch.individually_valid_pow() {
-            return Err(HeaderSyncError::InvalidHeaderWork);
+        let ancestor = match self.fetch_header_by_hash(prev_hash).await {
+            Ok(Some(ancestor)) => ancestor,
+            _ => return,
+        };
+        let root = self.header_chain.root_height();
+        if ancestor.height >= root
+            || self.header_chain.height().saturating_sub(ancestor.height) > MAX_STORED_FORK_DEPTH
+        {
+            return;
+        }
+        let stored = match self.fetch_header_range(ancestor.height..root + 1).await {
+            Ok(stored) => stored,
+            Err(_) => return,
+        };
+        let ancestors = stored
+            .into_iter()
+            .map(|(height, header)| IndexedHeader::new(height, header))
+            .collect();
+        if self.header_chain.extend_below(ancestors) {
+            crate::log!(
+                self.dialog,
+                Subsystem::Chain,
+                format!(
+                    "Loaded stored headers from height {} to follow a fork",
+                    ancestor.height
+                )
+            );
+            self.view.reset(self.header_chain.iter_headers());
         }
+    }
 
-        if !header_batch.bits_adhere_transition(self.network) {
-            return Err(HeaderSyncError::InvalidBits);
+    // Forget the headers accepted without a proof of work check, returning to the tip they were
+    // built on
+    async fn discard_unchecked_headers(&mut self) {
+        self.unchecked_headers.clear();
+        if let Some(base) = self.unchecked_from.take() {
+            crate::log!(
+                self.dialog,
+                Subsystem::Chain,
+                format!("Discarding unchecked headers above height {}", base.height)
+            );
+            self.header_chain = BlockTree::new(base, self.network);
+            self.view.reset(self.header_chain.iter_headers());
         }
+    }
 
-        Ok(())
+    // Follow the clocks of peers when checking how far a header is in the future
+    pub(crate) fn set_time_offset(&mut self, offset: i64) {
+        self.time_offset = offset;
+    }
+
+    // The latest timestamp a header may have, if header timestamps are checked
+    fn max_header_time(&self) -> Option<u32> {
+        let drift = self.header_time_drift?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs() as i64)
+            .unwrap_or_default();
+        let max_time = now
+            .saturating_add(self.time_offset)
+            .saturating_add(drift.as_secs() as i64);
+        Some(max_time.clamp(0, u32::MAX as i64) as u32)
     }
 
     // Sync the compact filter headers, possibly encountering conflicts
@@ -297,6 +935,10 @@ impl<H: HeaderStore> Chain<H> {
         cf_headers: CFHeaders,
     ) -> Result<CFHeaderChanges, CFHeaderSyncError> {
         let batch: CFHeaderBatch = cf_headers.into();
+        // Filter headers are requested again once the dispute is resolved
+        if self.request_state.dispute.is_some() {
+            return Ok(CFHeaderChanges::AddedToQueue);
+        }
         let request = self
             .request_state
             .last_filter_header_request
@@ -344,12 +986,24 @@ impl<H: HeaderStore> Chain<H> {
                 if pending.ne(&batch) {
                     self.request_state.pending_batch = None;
                     self.request_state.agreement_state.reset_agreements();
+                    self.request_state.dispute =
+                        pending
+                            .first_conflict(&batch)
+                            .and_then(|(offset, ours, theirs)| {
+                                let height = request.start_height + offset;
+                                let block_hash = self.header_chain.block_hash_at_height(height)?;
+                                Some(FilterHeaderDispute::new(
+                                    height,
+                                    block_hash,
+                                    vec![(id, ours), (peer_id, theirs)],
+                                ))
+                            });
                     Ok(CFHeaderChanges::Conflict)
                 } else {
                     self.request_state.agreement_state.got_agreement();
                     if self.request_state.agreement_state.enough_agree() {
                         self.request_state.agreement_state.reset_agreements();
-                        self.push_cf_header_batch(batch, request.stop_hash);
+                        self.push_cf_header_batch(batch, request);
                         Ok(CFHeaderChanges::Extended)
                     } else {
                         self.request_state.pending_batch = Some((id, batch));
@@ -361,7 +1015,7 @@ impl<H: HeaderStore> Chain<H> {
                 self.request_state.agreement_state.got_agreement();
                 if self.request_state.agreement_state.enough_agree() {
                     self.request_state.agreement_state.reset_agreements();
-                    self.push_cf_header_batch(batch, request.stop_hash);
+                    self.push_cf_header_batch(batch, request);
                     Ok(CFHeaderChanges::Extended)
                 } else {
                     self.request_state.pending_batch = Some((peer_id, batch));
@@ -371,12 +1025,68 @@ impl<H: HeaderStore> Chain<H> {
         }
     }
 
-    fn push_cf_header_batch(&mut self, mut batch: CFHeaderBatch, stop_hash: BlockHash) {
+    // The peers in a filter header dispute, the request for the filter each peer has for the
+    // disputed block, and the disputed block
+    pub(crate) fn dispute_requests(&self) -> Option<(Vec<PeerId>, GetCFilters, BlockHash)> {
+        let dispute = self.request_state.dispute.as_ref()?;
+        let get_filters = GetCFilters {
+            filter_type: FILTER_BASIC,
+            start_height: dispute.height(),
+            stop_hash: dispute.block_hash(),
+        };
+        Some((dispute.peers(), get_filters, dispute.block_hash()))
+    }
+
+    // Keep a filter for the disputed block, returning if the filter was part of the dispute
+    pub(crate) fn dispute_filter(&mut self, peer_id: PeerId, filter: &CFilter) -> bool {
+        self.request_state
+            .dispute
+            .as_mut()
+            .map_or(false, |dispute| dispute.add_filter(peer_id, filter))
+    }
+
+    // Keep the disputed block, returning if the block was part of the dispute
+    pub(crate) fn dispute_block(&mut self, block: &Block) -> Result<bool, BlockScanError> {
+        let dispute = match self.request_state.dispute.as_mut() {
+            Some(dispute) => dispute,
+            None => return Ok(false),
+        };
+        if block.block_hash().ne(&dispute.block_hash()) {
+            return Ok(false);
+        }
+        check_block_body(block)?;
+        Ok(dispute.add_block(block))
+    }
+
+    // The peers that lied about their filter headers, once the dispute can be resolved
+    pub(crate) fn resolve_dispute(&mut self) -> Option<Vec<PeerId>> {
+        let liars = self.request_state.dispute.as_ref()?.resolve()?;
+        self.request_state.dispute = None;
+        Some(liars)
+    }
+
+    // The peers that did not send their filter, once a dispute has not been resolved in time
+    pub(crate) fn expire_dispute(&mut self) -> Option<Vec<PeerId>> {
+        if !self.request_state.dispute.as_ref()?.timed_out() {
+            return None;
+        }
+        self.request_state
+            .dispute
+            .take()
+            .map(|dispute| dispute.unanswered())
+    }
+
+    fn push_cf_header_batch(&mut self, mut batch: CFHeaderBatch, request: FilterHeaderRequest) {
+        let prev_header = *batch.prev_header();
         // Start from the stop hash and work backwards
         let cf_header_iter = batch.take_inner().into_iter().rev();
-        let mut curr = stop_hash;
+        let mut curr = request.stop_hash;
+        let mut first = None;
+        let mut committed = Vec::new();
         for commitment in cf_header_iter {
             self.header_chain.set_commitment(commitment, curr);
+            first = Some((curr, commitment.header));
+            committed.push((curr, commitment));
             match self.header_chain.header_at_hash(curr) {
                 Some(header) => {
                     curr = header.prev_blockhash;
@@ -386,6 +1096,36 @@ impl<H: HeaderStore> Chain<H> {
                 None => break,
             }
         }
+        if self.cf_header_store.is_some() {
+            let mut prev_filter_header = prev_header;
+            for (block_hash, commitment) in committed.into_iter().rev() {
+                if let Some(height) = self.header_chain.height_of_hash(block_hash) {
+                    self.unwritten_cf_headers.insert(
+                        height,
+                        PersistedFilterHeader::new(
+                            block_hash,
+                            commitment.filter_hash,
+                            prev_filter_header,
+                        ),
+                    );
+                }
+                prev_filter_header = commitment.header;
+            }
+        }
+        // The batch starts the filter header chain if the block before it has no filter header
+        if self.header_chain.filter_commitment(curr).is_some() {
+            return;
+        }
+        self.anchor_filter = match request.start_height.checked_sub(1) {
+            Some(height) => Some(FilterCheckpoint::new(
+                HeaderCheckpoint::new(height, curr),
+                prev_header,
+            )),
+            // There is no block before the genesis block, so the genesis block is the anchor
+            None => first.map(|(hash, header)| {
+                FilterCheckpoint::new(HeaderCheckpoint::new(0, hash), header)
+            }),
+        };
     }
 
     // We need to make this public for new peers that connect to us throughout syncing the filter headers
@@ -425,151 +1165,890 @@ impl<H: HeaderStore> Chain<H> {
         self.header_chain.filter_headers_synced()
     }
 
-    // Handle a new filter
+    // Handle a new filter, returning if it was the last filter of a range requested from a peer.
+    // Filters are checked in the order of the chain, so a filter that arrives before the filters
+    // of the blocks below it waits for them.
     pub(crate) fn sync_filter(
         &mut self,
         filter_message: CFilter,
-    ) -> Result<Option<GetCFilters>, CFilterSyncError> {
+    ) -> Result<bool, CFilterSyncError> {
+        let block_hash = filter_message.block_hash;
         if self.is_filters_synced() {
-            return Ok(None);
+            return Ok(self.request_state.filter_batches.complete(block_hash));
         }
-        let filter = Filter::new(filter_message.filter, filter_message.block_hash);
-        let expected_filter_hash = self
+        // The filter of a block announced at the tip may arrive before its filter header
+        if self
             .header_chain
-            .filter_commitment(filter_message.block_hash);
-        // Disallow any filter that we do not have a block hash for
-        match expected_filter_hash {
-            Some(ref_hash) => {
-                if filter.filter_hash().ne(&ref_hash.filter_hash) {
-                    return Err(CFilterSyncError::MisalignedFilterHash);
-                }
+            .filter_commitment(filter_message.block_hash)
+            .is_none()
+            && self
+                .request_state
+                .tip_block
+                .eq(&Some(filter_message.block_hash))
+        {
+            self.request_state.early_filter = Some(filter_message);
+            return Ok(self.request_state.filter_batches.complete(block_hash));
+        }
+        let next = self.next_filter_height();
+        let (height, filter) = match self.verify_filter(filter_message) {
+            Ok(verified) => verified,
+            Err(e) => {
+                // The range is requested again from another peer instead of being completed
+                self.request_state.filter_batches.release(block_hash);
+                return Err(e);
             }
-            None => {
-                return Err(CFilterSyncError::UnknownFilterHash);
+        };
+        let completed = self.request_state.filter_batches.complete(block_hash);
+        match height.cmp(&next) {
+            // Another peer sent the filter already
+            Ordering::Less => (),
+            Ordering::Greater => self.request_state.filter_batches.hold(height, next, filter),
+            Ordering::Equal => {
+                self.check_filter(height, filter, true)?;
+                let mut next = height + 1;
+                while let Some(filter) = self.request_state.filter_batches.take(next) {
+                    self.check_filter(next, filter, true)?;
+                    next += 1;
+                }
             }
         }
+        Ok(completed)
+    }
 
-        #[cfg(feature = "filter-control")]
-        if !self
+    // Check a filter commits to the filter header of its block, returning the height of the block
+    fn verify_filter(&self, filter_message: CFilter) -> Result<(u32, Filter), CFilterSyncError> {
+        // Disallow any filter that we do not have a block hash for
+        let expected_filter_hash = self
             .header_chain
-            .is_filter_checked(&filter_message.block_hash)
-        {
-            let height = self
-                .header_chain
-                .height_of_hash(filter_message.block_hash)
-                .ok_or(CFilterSyncError::UnknownFilterHash)?;
-            let indexed_filter = IndexedFilter::new(height, filter);
-            self.dialog.send_event(Event::IndexedFilter(indexed_filter));
+            .filter_commitment(filter_message.block_hash)
+            .ok_or(CFilterSyncError::UnknownFilterHash)?;
+        let height = self
+            .header_chain
+            .height_of_hash(filter_message.block_hash)
+            .ok_or(CFilterSyncError::UnknownFilterHash)?;
+        let filter = Filter::new(filter_message.filter, filter_message.block_hash);
+        if filter.filter_hash().ne(&expected_filter_hash.filter_hash) {
+            return Err(CFilterSyncError::MisalignedFilterHash);
         }
+        Ok((height, filter))
+    }
 
-        #[cfg(not(feature = "filter-control"))]
-        if !self.block_queue.contains(&filter_message.block_hash)
-            && !self
+    // The height of the next filter to check, which is the lowest block with an unchecked filter
+    fn next_filter_height(&mut self) -> u32 {
+        if let Some(next) = self.request_state.filter_batches.next() {
+            if self
                 .header_chain
-                .is_filter_checked(&filter_message.block_hash)
-            && filter
-                .contains_any(self.scripts.iter())
-                .map_err(CFilterSyncError::Filter)?
-        {
-            // Add to the block queue
-            self.block_queue.add(filter_message.block_hash);
-        }
-
-        self.header_chain.check_filter(filter_message.block_hash);
-        let stop_hash = self
-            .request_state
-            .last_filter_request
-            .ok_or(CFilterSyncError::UnrequestedStophash)?
-            .stop_hash;
-        if filter_message.block_hash.eq(&stop_hash) {
-            if !self.is_filters_synced() {
-                Ok(Some(self.next_filter_message()))
-            } else {
-                Ok(None)
+                .block_hash_at_height(next)
+                .map_or(false, |hash| !self.header_chain.is_filter_checked(&hash))
+            {
+                return next;
             }
-        } else {
-            Ok(None)
         }
-    }
-
-    // Next filter message, if there is one
-    pub(crate) fn next_filter_message(&mut self) -> GetCFilters {
-        let mut last_unchecked_filter = self.header_chain.height();
+        let mut next = self.header_chain.height();
         for block_data in self.header_chain.iter_data() {
             if block_data.filter_checked {
                 break;
             }
-            last_unchecked_filter = block_data.height;
-        }
-        let stop_hash_index = last_unchecked_filter + FILTER_BATCH_SIZE;
-        let stop_hash = self
-            .header_chain
-            .block_hash_at_height(stop_hash_index)
-            .unwrap_or(self.header_chain.tip_hash());
-        self.request_state.last_filter_request = Some(FilterRequest {
-            stop_hash,
-            start_height: last_unchecked_filter,
-        });
-        GetCFilters {
-            filter_type: FILTER_BASIC,
-            start_height: last_unchecked_filter,
-            stop_hash,
+            next = block_data.height;
         }
+        self.request_state.filter_batches.set_next(next);
+        next
     }
 
-    // Are we synced with filters
-    pub(crate) fn is_filters_synced(&self) -> bool {
-        self.header_chain.filters_synced()
-    }
+    // Check the next filter from a peer or the filter store, which is only written to if it is new
+    fn check_filter(
+        &mut self,
+        height: u32,
+        filter: Filter,
+        is_new: bool,
+    ) -> Result<(), CFilterSyncError> {
+        let block_hash = *filter.block_hash();
+        if let Some((start_height, stop_height)) = self
+            .request_state
+            .filter_batches
+            .sample_range(height, self.header_chain.height())
+        {
+            self.spot_checks.sample(start_height, stop_height);
+        }
+        if is_new && self.filter_store.is_some() {
+            self.unwritten_filters
+                .push((block_hash, filter.block_filter().clone()));
+        }
 
-    // Pop a block from the queue of interesting blocks
-    pub(crate) fn next_block(&mut self) -> Option<BlockHash> {
-        self.block_queue.pop()
-    }
+        #[cfg(not(feature = "filter-control"))]
+        if !self.block_queue.contains(&block_hash)
+            && !self.header_chain.is_filter_checked(&block_hash)
+        {
+            self.scan_stats.filters_scanned += 1;
+            if self.filter_matches(&filter)? {
+                self.scan_stats.filters_matched += 1;
+                if self.filter_matches_only {
+                    self.dialog.send_event(Event::FilterMatched {
+                        height,
+                        hash: block_hash,
+                    });
+                } else {
+                    if self.filter_matcher.is_none() {
+                        let scripts = self
+                            .scripts
+                            .matching(&filter)
+                            .map_err(CFilterSyncError::Filter)?;
+                        self.broad_scripts.record_match(block_hash, scripts);
+                    }
+                    self.block_queue.add(block_hash);
+                }
+            }
+        }
 
-    // Are there any blocks left in the queue
-    pub(crate) fn block_queue_empty(&self) -> bool {
-        self.block_queue.complete()
-    }
+        if self.spot_checks.is_sampling()
+            && !self.block_queue.contains(&block_hash)
+            && self
+                .spot_checks
+                .keep(height, block_hash, filter.block_filter())
+        {
+            self.block_queue.add(block_hash);
+        }
 
-    // Make sure we have this hash in our chain, check the merkle root, and pass the block
-    pub(crate) fn check_send_block(&mut self, block: Block) -> Result<(), BlockScanError> {
-        let block_hash = block.block_hash();
-        if !self.block_queue.need(&block_hash) {
-            return Ok(());
+        #[cfg(feature = "filter-control")]
+        if !self.header_chain.is_filter_checked(&block_hash) {
+            self.scan_stats.filters_scanned += 1;
+            let indexed_filter = IndexedFilter::new(height, filter);
+            self.dialog.send_event(Event::IndexedFilter(indexed_filter));
         }
-        let height = self
-            .header_chain
-            .height_of_hash(block_hash)
-            .ok_or(BlockScanError::NoBlockHash)?;
-        if !block.check_merkle_root() {
-            return Err(BlockScanError::InvalidMerkleRoot);
+
+        self.header_chain.check_filter(block_hash);
+        self.request_state.filter_batches.set_next(height + 1);
+        Ok(())
+    }
+
+    // Check a filter with the configured matcher, or for any of the scripts
+    #[cfg(not(feature = "filter-control"))]
+    fn filter_matches(&mut self, filter: &Filter) -> Result<bool, CFilterSyncError> {
+        if self.outpoints_spent_in(filter)? {
+            return Ok(true);
         }
-        let sender = self.block_queue.receive(&block_hash);
-        match sender {
-            Some(sender) => {
-                let send_result = sender.send(Ok(IndexedBlock::new(height, block)));
-                if send_result.is_err() {
-                    self.dialog.send_warning(Warning::ChannelDropped)
-                };
-            }
-            None => {
-                self.dialog
-                    .send_event(Event::Block(IndexedBlock::new(height, block)));
+        match self.filter_matcher.as_mut() {
+            Some(matcher) => {
+                let height = self
+                    .header_chain
+                    .height_of_hash(*filter.block_hash())
+                    .ok_or(CFilterSyncError::UnknownFilterHash)?;
+                Ok(matcher.matches(height, *filter.block_hash(), filter.block_filter()))
             }
+            None => self
+                .scripts
+                .matches(filter)
+                .map_err(CFilterSyncError::Filter),
         }
-        Ok(())
     }
 
-    // Add a script to our list
-    pub(crate) fn put_script(&mut self, script: ScriptBuf) {
-        self.scripts.insert(script);
+    // Check a filter for the scripts of the watched outpoints, which match when an output is spent
+    #[cfg(not(feature = "filter-control"))]
+    fn outpoints_spent_in(&mut self, filter: &Filter) -> Result<bool, CFilterSyncError> {
+        if self.outpoints.is_empty() {
+            return Ok(false);
+        }
+        if let Some(index) = self.script_index.as_ref() {
+            self.outpoints.resolve(|outpoint| index.script_of(outpoint));
+        }
+        filter
+            .contains_any(self.outpoints.scripts())
+            .map_err(CFilterSyncError::Filter)
     }
 
-    // Explicitly request a block
-    #[cfg(feature = "filter-control")]
-    pub(crate) async fn get_block(&mut self, request: BlockRequest) {
-        let height_opt = self.header_chain.height_of_hash(request.hash);
+    // Check the next filters from the filter store, stopping at the first filter the store does
+    // not have. The store is only read before any filters are requested from peers, and may hold
+    // the filters of the whole chain, so the runtime is yielded to between filters.
+    pub(crate) async fn check_stored_filters(&mut self) {
+        if !self.request_state.filter_batches.is_idle() {
+            return;
+        }
+        let mut store = match self.filter_store.take() {
+            Some(store) => store,
+            None => return,
+        };
+        let mut checked = 0;
+        let mut budget = YieldBudget::new(STORED_FILTERS_PER_YIELD);
+        while !self.is_filters_synced() {
+            budget.tick().await;
+            let height = self.next_filter_height();
+            let block_hash = match self.header_chain.block_hash_at_height(height) {
+                Some(block_hash) => block_hash,
+                None => break,
+            };
+            let content = match store.filter(block_hash).await {
+                Ok(Some(filter)) => filter.content,
+                Ok(None) => break,
+                Err(e) => {
+                    self.dialog.send_warning(Warning::FailedPersistence {
+                        warning: format!("Could not load filters from disk: {e}"),
+                    });
+                    break;
+                }
+            };
+            let filter_message = CFilter {
+                filter_type: FILTER_BASIC,
+                block_hash,
+                filter: content,
+            };
+            // A stored filter that does not match its filter header is requested again
+            let checked_filter = self
+                .verify_filter(filter_message)
+                .and_then(|(height, filter)| self.check_filter(height, filter, false));
+            if checked_filter.is_err() {
+                break;
+            }
+            checked += 1;
+        }
+        self.filter_store = Some(store);
+        if checked > 0 {
+            crate::log!(
+                self.dialog,
+                Subsystem::Filters,
+                format!("Checked {checked} filters from the filter store")
+            );
+        }
+    }
+
+    // Write the filters checked since the last write to the filter store
+    pub(crate) async fn write_filters(&mut self) {
+        if self.unwritten_filters.is_empty() {
+            return;
+        }
+        if let Some(store) = self.filter_store.as_mut() {
+            let filters = core::mem::take(&mut self.unwritten_filters);
+            if let Err(e) = store.write_filters(filters).await {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save filters to disk: {e}"),
+                });
+            }
+        }
+    }
+
+    // Split the filters that are not checked yet into ranges, and request a range from each of the
+    // peers that is not sending one already
+    pub(crate) fn filter_requests(&mut self, peers: &[PeerId]) -> Vec<(PeerId, GetCFilters)> {
+        if self.is_filters_synced() {
+            return Vec::new();
+        }
+        let next = self.next_filter_height();
+        let header_chain = &self.header_chain;
+        self.request_state
+            .filter_batches
+            .assign(peers, next, header_chain.height(), |height| {
+                header_chain.block_hash_at_height(height)
+            })
+            .into_iter()
+            .map(|(peer, range)| {
+                let get_filters = GetCFilters {
+                    filter_type: FILTER_BASIC,
+                    start_height: range.start_height,
+                    stop_hash: range.stop_hash,
+                };
+                (peer, get_filters)
+            })
+            .collect()
+    }
+
+    // Are we synced with filters
+    pub(crate) fn is_filters_synced(&self) -> bool {
+        self.header_chain.filters_synced()
+    }
+
+    // Request the filter header and filter for a block announced at the tip in the same round
+    // trip as the header, assuming the block extends the chain of most work.
+    pub(crate) fn tip_filter_messages(
+        &mut self,
+        block: BlockHash,
+    ) -> Option<(GetCFHeaders, GetCFilters)> {
+        if !self.is_cf_headers_synced() || !self.is_filters_synced() {
+            return None;
+        }
+        let prev_header = self
+            .header_chain
+            .iter_data()
+            .next()?
+            .filter_commitment?
+            .header;
+        let start_height = self.header_chain.height() + 1;
+        self.request_state.last_filter_header_request = Some(FilterHeaderRequest {
+            expected_prev_filter_header: Some(prev_header),
+            start_height,
+            stop_hash: block,
+        });
+        self.request_state.tip_block = Some(block);
+        Some((
+            GetCFHeaders {
+                filter_type: FILTER_BASIC,
+                start_height,
+                stop_hash: block,
+            },
+            GetCFilters {
+                filter_type: FILTER_BASIC,
+                start_height,
+                stop_hash: block,
+            },
+        ))
+    }
+
+    // The filter for the tip block, once the filter header it commits to is known
+    pub(crate) fn take_early_filter(&mut self) -> Option<CFilter> {
+        let filter = self.request_state.early_filter.take()?;
+        if self
+            .header_chain
+            .filter_commitment(filter.block_hash)
+            .is_some()
+        {
+            self.request_state.tip_block = None;
+            Some(filter)
+        } else {
+            self.request_state.early_filter = Some(filter);
+            None
+        }
+    }
+
+    // Pop a block from the queue of interesting blocks
+    pub(crate) fn next_block(&mut self) -> Option<BlockHash> {
+        let next = self.block_queue.pop();
+        for sender in self.block_queue.take_unserved() {
+            if sender.send(Err(FetchBlockError::NotServed)).is_err() {
+                self.dialog.send_warning(Warning::ChannelDropped);
+            }
+        }
+        next
+    }
+
+    // Has the client explicitly requested any blocks
+    pub(crate) fn block_requested(&self) -> bool {
+        self.block_queue.has_priority()
+    }
+
+    // Are there any blocks left in the queue
+    pub(crate) fn block_queue_empty(&self) -> bool {
+        self.block_queue.complete() && self.held_blocks.is_empty()
+    }
+
+    // Make sure we have this hash in our chain, check the transactions are committed to by the
+    // header, and pass the block
+    pub(crate) fn check_send_block(&mut self, block: Block) -> Result<(), BlockScanError> {
+        let block_hash = block.block_hash();
+        if !self.block_queue.need(&block_hash) {
+            return Ok(());
+        }
+        let height = self
+            .header_chain
+            .height_of_hash(block_hash)
+            .ok_or(BlockScanError::NoBlockHash)?;
+        check_block_body(&block)?;
+        self.scan_stats.blocks_downloaded += 1;
+        self.triggers.scan(height, &block);
+        self.resolve_triggers();
+        if let Some(check) = self.spot_checks.check(height, &block) {
+            if !check.passed {
+                self.dialog.send_warning(Warning::InvalidFilter(check));
+            }
+            // Sampled blocks did not match a filter, so they are only sent if the client asked
+            if let Some(sender) = self.block_queue.receive(&block_hash) {
+                self.send_requested(sender, IndexedBlock::new(height, block));
+            }
+            self.release_blocks();
+            return Ok(());
+        }
+        let matched = self.block_queue.is_matched(&block_hash);
+        // The client is waiting on a requested block, so it is sent without waiting for the blocks
+        // below it. Blocks are only scanned if they matched a filter, in the order of the chain.
+        let sender = self.block_queue.receive(&block_hash);
+        if !matched {
+            if let Some(sender) = sender {
+                self.send_requested(sender, IndexedBlock::new(height, block));
+            }
+            self.release_blocks();
+            return Ok(());
+        }
+        let delivered = sender.is_some();
+        if let Some(sender) = sender {
+            self.send_requested(sender, IndexedBlock::new(height, block.clone()));
+        }
+        self.held_blocks.insert(height, (block, delivered));
+        self.release_blocks();
+        Ok(())
+    }
+
+    fn send_requested(&self, sender: BlockSender, block: IndexedBlock) {
+        if sender.send(Ok(block)).is_err() {
+            self.dialog.send_warning(Warning::ChannelDropped)
+        }
+    }
+
+    // Scan the held blocks in the order of the chain, up to the lowest matched block that has not
+    // arrived yet
+    fn release_blocks(&mut self) {
+        let lowest_pending = self
+            .block_queue
+            .matched()
+            .filter_map(|hash| self.header_chain.height_of_hash(*hash))
+            .min();
+        while let Some(height) = self.held_blocks.keys().next().copied() {
+            if lowest_pending.map_or(false, |lowest| height > lowest) {
+                break;
+            }
+            if let Some((block, delivered)) = self.held_blocks.remove(&height) {
+                self.scan_block(height, block, delivered);
+            }
+        }
+    }
+
+    // Scan a matched block for the scripts and outpoints, and send it to the client if it was not
+    // already sent to a request for it
+    fn scan_block(&mut self, height: u32, block: Block, delivered: bool) {
+        #[cfg(not(feature = "filter-control"))]
+        self.extend_derivations(height, &block);
+        // Spends are found before the outputs are forgotten, so the block counts as a true positive
+        let mut spends_watched = false;
+        #[cfg(not(feature = "filter-control"))]
+        for (outpoint, txid) in self.outpoints.scan(&block) {
+            spends_watched = true;
+            self.dialog.send_event(Event::OutpointSpent {
+                outpoint,
+                txid,
+                height,
+            });
+        }
+        self.check_birthday(height, &block);
+        if let Some(index) = self.script_index.as_mut() {
+            index.scan(height, &block, |script| self.scripts.contains(script));
+        }
+        if let Some(utxos) = self.utxos.as_mut() {
+            let (created, spent) =
+                utxos.scan(height, &block, |script| self.scripts.contains(script));
+            spends_watched |= !spent.is_empty();
+            for utxo in created {
+                self.dialog.send_event(Event::UtxoCreated(utxo));
+            }
+            for (utxo, txid) in spent {
+                self.dialog.send_event(Event::UtxoSpent { utxo, txid });
+            }
+        }
+        self.count_positive(&block, spends_watched);
+        if !delivered {
+            self.stream_block(IndexedBlock::new(height, block))
+        }
+    }
+
+    // Count a block downloaded for a filter match as a true or false positive. Blocks matched by a
+    // filter matcher are not counted, as the scripts it matches are not known.
+    fn count_positive(&mut self, block: &Block, spends_watched: bool) {
+        #[cfg(not(feature = "filter-control"))]
+        if self.filter_matcher.is_some() {
+            return;
+        }
+        let relevant = spends_watched || block.txdata.iter().any(|tx| self.is_relevant(tx));
+        if relevant {
+            self.scan_stats.true_positives += 1;
+        } else {
+            self.scan_stats.false_positives += 1;
+        }
+        #[cfg(not(feature = "filter-control"))]
+        self.check_broad_scripts(&block.block_hash(), relevant);
+    }
+
+    // Does the transaction pay to the scripts, or spend from an indexed output
+    pub(crate) fn is_relevant(&self, tx: &Transaction) -> bool {
+        tx.output
+            .iter()
+            .any(|output| self.scripts.contains(&output.script_pubkey))
+            || self
+                .script_index
+                .as_ref()
+                .map_or(false, |index| index.spends_indexed(tx))
+    }
+
+    // Report the scripts that matched most of the blocks with no relevant transaction during a
+    // spike in false positives, and stop checking them if configured
+    #[cfg(not(feature = "filter-control"))]
+    fn check_broad_scripts(&mut self, block_hash: &BlockHash, relevant: bool) {
+        let report = match self.broad_scripts.record_block(block_hash, relevant) {
+            Some(report) => report,
+            None => return,
+        };
+        // A block that only spends an output of the wallet is a false positive unless the spent
+        // output is known, so scripts are only excluded if spent outputs are tracked
+        let excluded =
+            self.exclude_broad_scripts && (self.script_index.is_some() || self.utxos.is_some());
+        if excluded {
+            for script in &report.scripts {
+                self.scripts.remove(script);
+            }
+        }
+        self.dialog.send_warning(Warning::BroadScripts {
+            scripts: report.scripts,
+            false_positives: report.false_positives,
+            blocks: report.blocks,
+            excluded,
+        });
+    }
+
+    // Counts of the filters and blocks scanned
+    pub(crate) fn scan_stats(&self) -> ScanStats {
+        self.scan_stats
+    }
+
+    // End the first phase of a recovery scan by deriving every script and checking the filters
+    // again from the first block the wallet was active in. Returns true if filters must be checked.
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn finish_coarse_scan(&mut self) -> bool {
+        let derivations = match self.derivations.as_mut() {
+            Some(derivations) => derivations,
+            None => return false,
+        };
+        let first_active = match derivations.finish_probe() {
+            Some(first_active) => first_active,
+            None => return false,
+        };
+        for script in derivations.extend() {
+            self.scripts.insert(script);
+        }
+        // The wallet was never active, so there is nothing to check again
+        let first_active = match first_active {
+            Some(height) => height,
+            None => return false,
+        };
+        self.header_chain.reset_all_filters();
+        self.header_chain
+            .assume_checked_to(first_active.saturating_sub(1));
+        true
+    }
+
+    #[cfg(feature = "filter-control")]
+    pub(crate) fn finish_coarse_scan(&mut self) -> bool {
+        false
+    }
+
+    // Derive more scripts if the block pays to a derived script past the last used index
+    #[cfg(not(feature = "filter-control"))]
+    fn extend_derivations(&mut self, height: u32, block: &Block) {
+        let derivations = match self.derivations.as_mut() {
+            Some(derivations) => derivations,
+            None => return,
+        };
+        let newly_used = derivations.scan(height, block);
+        if newly_used.is_empty() {
+            return;
+        }
+        for script in derivations.extend() {
+            self.scripts.insert(script);
+        }
+        // The filters above the block were checked without the new scripts
+        if self.header_chain.reset_filters_above(height) {
+            self.request_state.filter_batches.clear();
+        }
+        for (keychain, index) in newly_used {
+            self.dialog
+                .send_event(Event::NewDerivationUsed { keychain, index });
+        }
+    }
+
+    // Send matched blocks to the block stream if a client is listening, otherwise as an event
+    fn stream_block(&mut self, indexed_block: IndexedBlock) {
+        if self.block_stream.is_some() {
+            self.unstreamed_blocks.push_back(indexed_block);
+            self.flush_block_stream();
+        } else {
+            self.dialog.send_event(Event::Block(indexed_block));
+        }
+    }
+
+    // Move as many waiting blocks into the stream as it has room for. Blocks downloaded while the
+    // stream was full wait here rather than skipping the stream.
+    fn flush_block_stream(&mut self) {
+        while let Some(indexed_block) = self.unstreamed_blocks.pop_front() {
+            let stream = match self.block_stream.as_ref() {
+                Some(stream) => stream,
+                None => {
+                    self.dialog.send_event(Event::Block(indexed_block));
+                    continue;
+                }
+            };
+            match stream.try_send(indexed_block) {
+                Ok(_) => (),
+                Err(TrySendError::Full(indexed_block)) => {
+                    self.unstreamed_blocks.push_front(indexed_block);
+                    return;
+                }
+                Err(TrySendError::Closed(indexed_block)) => {
+                    self.block_stream = None;
+                    self.dialog.send_event(Event::Block(indexed_block));
+                }
+            }
+        }
+    }
+
+    // Replace the channel matched blocks are streamed to
+    pub(crate) fn set_block_stream(&mut self, stream: mpsc::Sender<IndexedBlock>) {
+        self.block_stream = Some(stream);
+        self.flush_block_stream();
+    }
+
+    // Hold off on downloading more blocks if the client is not keeping up with the stream
+    pub(crate) fn block_stream_ready(&mut self) -> bool {
+        self.flush_block_stream();
+        match self.block_stream.as_ref() {
+            Some(stream) if stream.is_closed() => {
+                self.block_stream = None;
+                true
+            }
+            Some(stream) => self.unstreamed_blocks.is_empty() && stream.capacity() > 0,
+            None => true,
+        }
+    }
+
+    // Add a script to our list
+    pub(crate) fn put_script(&mut self, script: ScriptBuf) {
+        self.scripts.insert(script);
+    }
+
+    // Stop checking filters for the scripts
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn remove_scripts(&mut self, scripts: HashSet<ScriptBuf>) {
+        for script in &scripts {
+            self.scripts.remove(script);
+        }
+        zeroize_scripts(scripts);
+    }
+
+    // Forget the scripts and everything derived from them, overwriting the memory that held them
+    pub(crate) fn clear_scripts(&mut self) {
+        self.scripts.clear();
+        #[cfg(not(feature = "filter-control"))]
+        {
+            self.derivations = None;
+            self.broad_scripts = BroadScripts::new();
+        }
+    }
+
+    // Watch the outpoints for a spend
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn put_outpoints(&mut self, outpoints: HashSet<OutPoint>) {
+        for outpoint in outpoints {
+            let script = self
+                .script_index
+                .as_ref()
+                .and_then(|index| index.script_of(&outpoint));
+            self.outpoints.insert(outpoint, script);
+        }
+    }
+
+    // Add the scripts of the addresses, unless any address is for another network
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn put_addresses(&mut self, addresses: Vec<Address>) -> Result<(), AddAddressError> {
+        let network = self.network;
+        if let Some(address) = addresses
+            .iter()
+            .find(|address| !address.as_unchecked().is_valid_for_network(network))
+        {
+            return Err(AddAddressError::NetworkMismatch {
+                address: address.clone(),
+                network,
+            });
+        }
+        for address in addresses {
+            self.scripts.insert(address.script_pubkey());
+        }
+        Ok(())
+    }
+
+    // Statistics for each shard of scripts
+    pub(crate) fn script_shard_stats(&self) -> Vec<ScriptShardStats> {
+        self.scripts.stats()
+    }
+
+    // The transactions found for a script, which is empty if scripts are not indexed
+    pub(crate) fn script_history(&self, script: &ScriptBuf) -> Vec<ScriptTx> {
+        self.script_index
+            .as_ref()
+            .map(|index| index.history(script))
+            .unwrap_or_default()
+    }
+
+    // Attest to a header in the chain of most work the node holds in memory
+    pub(crate) fn attest(&self, hash: BlockHash) -> Option<HeaderAttestation> {
+        let height = self.header_chain.height_of_hash(hash)?;
+        let header = self.header_chain.header_at_hash(hash)?;
+        let (canonical, work) = self.header_chain.canonical_work(height)?;
+        if canonical.ne(&hash) {
+            return None;
+        }
+        // Prefer a checkpoint the network agrees on over the block the node started from
+        let root = self.header_chain.root_height();
+        let known = HeaderCheckpoint::closest_checkpoint_below_height(height, self.network);
+        let (checkpoint, root_work) = match self.header_chain.canonical_work(known.height) {
+            Some((hash, root_work)) if known.height >= root && hash.eq(&known.hash) => {
+                (known, root_work)
+            }
+            _ => {
+                let (hash, root_work) = self.header_chain.canonical_work(root)?;
+                (HeaderCheckpoint::new(root, hash), root_work)
+            }
+        };
+        Some(HeaderAttestation {
+            network: self.network,
+            header: IndexedHeader::new(height, header),
+            checkpoint,
+            work: work - root_work,
+            tip_height: self.header_chain.height(),
+        })
+    }
+
+    // Resolve once the tip of the chain is at or above the height
+    pub(crate) fn notify_at_height(&mut self, trigger: HeightTrigger) {
+        self.triggers.at_height(trigger);
+        self.resolve_triggers();
+    }
+
+    // Resolve once the transaction is buried under the number of blocks. A transaction the
+    // script index already found is counted from the block that includes it.
+    pub(crate) fn notify_confirmations(&mut self, trigger: ConfirmationTrigger) {
+        let included = self
+            .script_index
+            .as_ref()
+            .and_then(|index| index.block_of(&trigger.txid))
+            .and_then(|(height, hash)| {
+                self.header_chain
+                    .header_at_hash(hash)
+                    .map(|header| IndexedHeader::new(height, header))
+            });
+        self.triggers.confirmations(trigger, included);
+        self.resolve_triggers();
+    }
+
+    fn resolve_triggers(&mut self) {
+        if let Some(tip) = self.indexed_tip() {
+            self.triggers.resolve(tip);
+        }
+    }
+
+    fn indexed_tip(&self) -> Option<IndexedHeader> {
+        let height = self.header_chain.height();
+        self.header_chain
+            .header_at_height(height)
+            .map(|header| IndexedHeader::new(height, header))
+    }
+
+    // The unspent outputs paying to our scripts, which is empty if they are not tracked
+    pub(crate) fn unspent(&self) -> Vec<Utxo> {
+        self.utxos
+            .as_ref()
+            .map(|utxos| utxos.list())
+            .unwrap_or_default()
+    }
+
+    // Write the transactions found for our scripts and the birthday, if either changed since the
+    // last write, and report the filters checked against their block
+    pub(crate) async fn write_scan_results(&mut self) {
+        for check in self.spot_checks.take_results() {
+            crate::info!(self.dialog, Subsystem::Chain, Info::FilterSpotCheck(check));
+        }
+        let history = self
+            .script_index
+            .as_mut()
+            .map(|index| index.take_pending())
+            .unwrap_or_default();
+        if !history.is_empty() {
+            let mut db = self.db.lock().await;
+            if let Err(e) = db.write_script_history(history).await {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save script history to disk: {e}"),
+                });
+            }
+        }
+        if let Some(utxos) = self.utxos.as_mut().and_then(|utxos| utxos.take_changed()) {
+            let mut db = self.db.lock().await;
+            if let Err(e) = db.write_utxos(utxos).await {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save unspent outputs to disk: {e}"),
+                });
+            }
+        }
+        if let Birthday::Unwritten(birthday) = self.birthday {
+            let mut db = self.db.lock().await;
+            match db.write_birthday(birthday).await {
+                Ok(()) => {
+                    self.birthday = Birthday::Found(birthday);
+                    crate::info!(self.dialog, Subsystem::Chain, Info::BirthdayFound(birthday));
+                }
+                Err(e) => self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save the wallet birthday to disk: {e}"),
+                }),
+            }
+        }
+    }
+
+    // Load the transactions broadcast and not buried before the node last stopped
+    pub(crate) async fn load_broadcasts(&mut self) -> Vec<PersistedBroadcast> {
+        let mut db = self.db.lock().await;
+        match db.load_broadcasts().await {
+            Ok(broadcasts) => broadcasts,
+            Err(e) => {
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not load broadcast transactions from disk: {e}"),
+                });
+                Vec::new()
+            }
+        }
+    }
+
+    pub(crate) async fn write_broadcasts(&mut self, broadcasts: Vec<PersistedBroadcast>) {
+        let mut db = self.db.lock().await;
+        if let Err(e) = db.write_broadcasts(broadcasts).await {
+            self.dialog.send_warning(Warning::FailedPersistence {
+                warning: format!("Could not save broadcast transactions to disk: {e}"),
+            });
+        }
+    }
+
+    // The height of a block the node asked for
+    pub(crate) fn requested_height(&self, block_hash: &BlockHash) -> Option<u32> {
+        if !self.block_queue.need(block_hash) {
+            return None;
+        }
+        self.header_chain.height_of_hash(*block_hash)
+    }
+
+    pub(crate) fn take_disconnected(&mut self) -> Vec<BlockHash> {
+        core::mem::take(&mut self.disconnected)
+    }
+
+    // Record the block as the birthday if it is the earliest to pay to our scripts
+    fn check_birthday(&mut self, height: u32, block: &Block) {
+        let earliest = match self.birthday {
+            Birthday::Ignored => return,
+            Birthday::Searching => u32::MAX,
+            Birthday::Unwritten(birthday) | Birthday::Found(birthday) => birthday.height,
+        };
+        if height >= earliest {
+            return;
+        }
+        let pays_to_scripts = block
+            .txdata
+            .iter()
+            .flat_map(|tx| tx.output.iter())
+            .any(|output| self.scripts.contains(&output.script_pubkey));
+        if pays_to_scripts {
+            self.birthday = Birthday::Unwritten(HeaderCheckpoint::new(height, block.block_hash()));
+        }
+    }
+
+    // Filters before the birthday have nothing to match
+    fn assume_checked_to_birthday(&mut self) {
+        if let Birthday::Unwritten(birthday) | Birthday::Found(birthday) = self.birthday {
+            self.header_chain
+                .assume_checked_to(birthday.height.saturating_sub(1));
+        }
+    }
+
+    // Explicitly request a block
+    pub(crate) async fn get_block(&mut self, request: BlockRequest) {
+        let height_opt = self.header_chain.height_of_hash(request.hash);
         if height_opt.is_none() {
             let err_reponse = request.oneshot.send(Err(FetchBlockError::UnknownHash));
             if err_reponse.is_err() {
@@ -578,7 +2057,8 @@ impl<H: HeaderStore> Chain<H> {
         } else {
             crate::log!(
                 self.dialog,
-                format!("Adding block {} to queue", request.hash)
+                Subsystem::Chain,
+                format!("Adding block {} to queue", self.dialog.redact(request.hash))
             );
             self.block_queue.add(request)
         }
@@ -607,12 +2087,48 @@ impl<H: HeaderStore> Chain<H> {
         }
     }
 
+    // The header of a block in the chain of most work and its height, by the hash of the block
+    pub(crate) async fn fetch_header_by_hash(
+        &mut self,
+        hash: BlockHash,
+    ) -> Result<Option<IndexedHeader>, HeaderPersistenceError<H::Error>> {
+        let height = match self.header_chain.height_of_hash(hash) {
+            // Blocks in memory may be on a fork of the chain of most work
+            Some(height) => match self.header_chain.block_hash_at_height(height) {
+                Some(canonical) if canonical.eq(&hash) => Some(height),
+                _ => None,
+            },
+            None => {
+                let mut db = self.db.lock().await;
+                let height_opt = db.height_of(&hash).await;
+                if height_opt.is_err() {
+                    self.dialog.send_warning(Warning::FailedPersistence {
+                        warning: format!(
+                            "Unexpected error fetching the height of {hash} from the header store"
+                        ),
+                    });
+                }
+                height_opt.map_err(HeaderPersistenceError::Database)?
+            }
+        };
+        let height = match height {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+        let header = self.fetch_header(height).await?;
+        Ok(header
+            .filter(|header| header.block_hash().eq(&hash))
+            .map(|header| IndexedHeader::new(height, header)))
+    }
+
     pub(crate) async fn fetch_header_range(
         &self,
         range: Range<u32>,
     ) -> Result<BTreeMap<u32, Header>, HeaderPersistenceError<H::Error>> {
         let mut db = self.db.lock().await;
+        let started = Instant::now();
         let range_opt = db.load(range).await;
+        self.dialog.check_database_latency("load headers", started);
         if range_opt.is_err() {
             self.dialog.send_warning(Warning::FailedPersistence {
                 warning: "Unexpected error fetching a range of headers from the header store"
@@ -622,21 +2138,67 @@ impl<H: HeaderStore> Chain<H> {
         range_opt.map_err(HeaderPersistenceError::Database)
     }
 
+    // Reclaim unused space in the header store
+    pub(crate) async fn compact_database(&self) -> Result<(), H::Error> {
+        let mut db = self.db.lock().await;
+        db.compact().await
+    }
+
+    // The number of peers that must agree on filter headers before they are accepted
+    pub(crate) fn set_required_peers(&mut self, required_peers: u8) {
+        self.request_state
+            .agreement_state
+            .set_required(required_peers);
+    }
+
     // Reset the compact filter queue because we received a new block
     pub(crate) fn clear_compact_filter_queue(&mut self) {
         self.request_state.agreement_state.reset_agreements();
         self.request_state.last_filter_header_request = None;
         self.request_state.pending_batch = None;
+        self.request_state.tip_block = None;
+        self.request_state.early_filter = None;
+        self.request_state.dispute = None;
+        self.request_state.filter_batches.clear();
     }
 
     // Clear the filter header cache to rescan the filters for new scripts.
     pub(crate) fn clear_filters(&mut self) {
+        // Remember how far the filters were checked if the rescan is cancelled
+        if self.is_filters_synced() {
+            self.rescan_checked_to = Some(self.header_chain.height());
+        }
         self.header_chain.reset_all_filters();
+        self.request_state.filter_batches.clear();
+        self.assume_checked_to_birthday();
+    }
+
+    // Abort an in-progress rescan, returning if there was a rescan to cancel.
+    pub(crate) fn cancel_rescan(&mut self) -> bool {
+        match self.rescan_checked_to.take() {
+            Some(height) => {
+                self.header_chain.assume_checked_to(height);
+                self.request_state.filter_batches.clear();
+                self.block_queue.clear_matched();
+                self.held_blocks.clear();
+                self.spot_checks.clear();
+                #[cfg(not(feature = "filter-control"))]
+                self.broad_scripts.clear();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The rescan, if any, ran to completion
+    pub(crate) fn rescan_complete(&mut self) {
+        self.rescan_checked_to = None;
     }
 
     pub(crate) async fn send_chain_update(&self) {
         crate::info!(
             self.dialog,
+            Subsystem::Filters,
             Info::Progress(Progress::new(
                 self.header_chain.total_filter_headers_synced(),
                 self.header_chain.total_filters_synced(),
@@ -645,6 +2207,7 @@ impl<H: HeaderStore> Chain<H> {
         );
         crate::log!(
             self.dialog,
+            Subsystem::Filters,
             format!(
                 "Headers: ({}/{}) CFHeaders: ({}/{}) CFilters: ({}/{})",
                 self.header_chain.height(),
@@ -660,16 +2223,68 @@ impl<H: HeaderStore> Chain<H> {
             )
         );
     }
+
+    // Write any staged headers, returning the tip if every header was persisted
+    pub(crate) async fn flush(&mut self) -> Option<HeaderCheckpoint> {
+        let mut db = self.db.lock().await;
+        if let Err(e) = db.write().await {
+            self.write_failed = true;
+            self.dialog.send_warning(Warning::FailedPersistence {
+                warning: format!("Could not save headers to disk: {e}"),
+            });
+        }
+        (!self.write_failed).then_some(HeaderCheckpoint::new(
+            self.header_chain.height(),
+            self.header_chain.tip_hash(),
+        ))
+    }
+
+    // Write staged headers without awaiting the header store, for when there is no runtime to
+    // await it. Returns false if the store is in use or could not write immediately.
+    pub(crate) fn try_flush(&mut self) -> bool {
+        let mut db = match self.db.try_lock() {
+            Ok(db) => db,
+            Err(_) => return false,
+        };
+        match poll_once(db.write()) {
+            Some(Ok(())) => true,
+            Some(Err(e)) => {
+                self.write_failed = true;
+                self.dialog.send_warning(Warning::FailedPersistence {
+                    warning: format!("Could not save headers to disk: {e}"),
+                });
+                false
+            }
+            None => false,
+        }
+    }
+
+    // The highest block with a checked filter
+    pub(crate) fn filters_checked_to(&self) -> Option<HeaderCheckpoint> {
+        self.header_chain
+            .iter_data()
+            .find(|data| data.filter_checked)
+            .map(|data| HeaderCheckpoint::new(data.height, data.header.block_hash()))
+    }
+
+    // Blocks that are queued or in flight
+    pub(crate) fn blocks_pending(&self) -> usize {
+        self.block_queue.len() + self.held_blocks.len()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    use std::{collections::HashSet, str::FromStr};
+    use std::{
+        collections::{HashMap, HashSet},
+        str::FromStr,
+    };
 
     use bitcoin::hashes::sha256d;
     use bitcoin::hashes::Hash;
     use bitcoin::{
+        bip158::BlockFilter,
         block::Header,
         consensus::deserialize,
         p2p::message_filter::{CFHeaders, CFilter},
@@ -679,19 +2294,57 @@ mod tests {
 
     use crate::{
         chain::checkpoints::{HeaderCheckpoint, HeaderCheckpoints},
+        db::traits::HeaderStore,
+        IndexedBlock,
         {
             dialog::Dialog,
-            messages::{Event, Info, Warning},
+            messages::{BlockRequest, Event, Info, Warning},
         },
     };
 
-    use super::{CFHeaderChanges, Chain, HeightMonitor};
+    use super::{
+        Birthday, BlockTree, CFHeaderChanges, Chain, HeaderSyncError, HeightMonitor, PeerId,
+        STALE_CHECKPOINT_BLOCKS,
+    };
+
+    // Headers with a valid proof of work on regtest, each building on the last
+    fn mine_headers(prev_blockhash: BlockHash, time: u32, count: u32, tag: u8) -> Vec<Header> {
+        use bitcoin::{block::Version, pow::CompactTarget, TxMerkleNode};
+
+        let mut prev_blockhash = prev_blockhash;
+        (0..count)
+            .map(|i| {
+                let mut header = Header {
+                    version: Version::TWO,
+                    prev_blockhash,
+                    merkle_root: TxMerkleNode::from_byte_array([tag; 32]),
+                    time: time + i,
+                    bits: CompactTarget::from_consensus(0x207fffff),
+                    nonce: 0,
+                };
+                while header.validate_pow(header.target()).is_err() {
+                    header.nonce += 1;
+                }
+                prev_blockhash = header.block_hash();
+                header
+            })
+            .collect()
+    }
 
     fn new_regtest(
         anchor: HeaderCheckpoint,
         height_monitor: Arc<Mutex<HeightMonitor>>,
         peers: u8,
     ) -> Chain<()> {
+        new_regtest_with_db(anchor, height_monitor, peers, ())
+    }
+
+    fn new_regtest_with_db<H: HeaderStore>(
+        anchor: HeaderCheckpoint,
+        height_monitor: Arc<Mutex<HeightMonitor>>,
+        peers: u8,
+        db: H,
+    ) -> Chain<H> {
         let (log_tx, _) = tokio::sync::mpsc::channel::<String>(1);
         let (info_tx, _) = tokio::sync::mpsc::channel::<Info>(1);
         let (warn_tx, _) = tokio::sync::mpsc::unbounded_channel::<Warning>();
@@ -701,21 +2354,217 @@ mod tests {
         Chain::new(
             bitcoin::Network::Regtest,
             HashSet::new(),
+            None,
             anchor,
             checkpoints,
             Arc::new(Dialog::new(
                 crate::LogLevel::Debug,
+                HashMap::new(),
                 log_tx,
                 info_tx,
                 warn_tx,
                 event_tx,
             )),
             height_monitor,
-            (),
+            db,
             peers,
+            false,
         )
     }
 
+    #[tokio::test]
+    async fn test_block_stream() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<IndexedBlock>(1);
+        chain.set_block_stream(tx);
+        assert!(chain.block_stream_ready());
+        chain.block_queue.add(genesis.block_hash());
+        assert_eq!(chain.next_block(), Some(genesis.block_hash()));
+        chain.check_send_block(genesis.clone()).unwrap();
+        // The client has not made room for another block
+        assert!(!chain.block_stream_ready());
+        let indexed_block = rx.recv().await.unwrap();
+        assert_eq!(indexed_block.height, 0);
+        assert_eq!(indexed_block.block.block_hash(), genesis.block_hash());
+        // Copies share the block, and the block is only copied out if it is shared
+        let copy = indexed_block.clone();
+        assert!(Arc::ptr_eq(&copy.block, &indexed_block.block));
+        assert_eq!(copy.into_block(), genesis);
+        assert_eq!(indexed_block.into_block(), genesis);
+        assert!(chain.block_stream_ready());
+        // Dropping the stream falls back to events
+        drop(rx);
+        assert!(chain.block_stream_ready());
+        assert!(chain.block_stream.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blocks_stream_in_height_order() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        chain.block_queue.set_max_in_flight(3);
+        let mut blocks = vec![genesis.clone()];
+        for time in 1..3 {
+            let mut block = genesis.clone();
+            block.header.prev_blockhash = blocks.last().unwrap().block_hash();
+            block.header.time += time;
+            chain.header_chain.accept_header(block.header);
+            blocks.push(block);
+        }
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<IndexedBlock>(3);
+        chain.set_block_stream(tx);
+        for block in &blocks {
+            chain.block_queue.add(block.block_hash());
+        }
+        while chain.next_block().is_some() {}
+        // Blocks that arrive ahead of the blocks below them are held
+        chain.check_send_block(blocks[2].clone()).unwrap();
+        chain.check_send_block(blocks[1].clone()).unwrap();
+        assert!(rx.try_recv().is_err());
+        assert!(!chain.block_queue_empty());
+        chain.check_send_block(blocks[0].clone()).unwrap();
+        for height in 0..3 {
+            assert_eq!(rx.recv().await.unwrap().height, height);
+        }
+        assert!(chain.block_queue_empty());
+    }
+
+    #[tokio::test]
+    async fn test_full_block_stream_holds_blocks() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        chain.block_queue.set_max_in_flight(2);
+        let mut block = genesis.clone();
+        block.header.prev_blockhash = genesis.block_hash();
+        block.header.time += 1;
+        chain.header_chain.accept_header(block.header);
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<IndexedBlock>(1);
+        chain.set_block_stream(tx);
+        chain.block_queue.add(genesis.block_hash());
+        chain.block_queue.add(block.block_hash());
+        while chain.next_block().is_some() {}
+        // Both blocks were in flight when the stream filled up
+        chain.check_send_block(genesis.clone()).unwrap();
+        chain.check_send_block(block.clone()).unwrap();
+        assert_eq!(chain.unstreamed_blocks.len(), 1);
+        assert!(!chain.block_stream_ready());
+        assert_eq!(rx.recv().await.unwrap().height, 0);
+        // The waiting block moves into the stream once there is room
+        assert!(!chain.block_stream_ready());
+        assert!(chain.unstreamed_blocks.is_empty());
+        assert_eq!(rx.recv().await.unwrap().height, 1);
+        assert!(chain.block_stream_ready());
+    }
+
+    #[tokio::test]
+    async fn test_only_matched_blocks_are_scanned() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1).with_utxo_tracking(true);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        chain.put_script(genesis.txdata[0].output[0].script_pubkey.clone());
+        // A block the client requested is sent without being scanned
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        chain
+            .block_queue
+            .add(BlockRequest::new(tx, genesis.block_hash()));
+        chain.next_block();
+        chain.check_send_block(genesis.clone()).unwrap();
+        assert_eq!(rx.await.unwrap().unwrap().height, 0);
+        assert!(chain.unspent().is_empty());
+        // A matched block the client also requested is sent to the client and scanned
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        chain.block_queue.add(genesis.block_hash());
+        chain
+            .block_queue
+            .add(BlockRequest::new(tx, genesis.block_hash()));
+        chain.next_block();
+        chain.check_send_block(genesis.clone()).unwrap();
+        assert_eq!(rx.await.unwrap().unwrap().height, 0);
+        assert_eq!(chain.unspent().len(), 1);
+        assert!(chain.block_queue_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "filter-control"))]
+    fn test_addresses_match_network() {
+        use bitcoin::{address::NetworkUnchecked, Address};
+
+        use crate::error::AddAddressError;
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let mut chain = new_regtest(gen, Arc::new(Mutex::new(HeightMonitor::new())), 1);
+        let regtest =
+            Address::<NetworkUnchecked>::from_str("bcrt1q6rhpng9evdsfnn833a4f4vej0asu6dk5srld6x")
+                .unwrap()
+                .assume_checked();
+        let mainnet =
+            Address::<NetworkUnchecked>::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+                .unwrap()
+                .assume_checked();
+        let result = chain.put_addresses(vec![regtest.clone(), mainnet.clone()]);
+        assert!(
+            matches!(result, Err(AddAddressError::NetworkMismatch { address, network: bitcoin::Network::Regtest }) if address == mainnet)
+        );
+        assert_eq!(chain.script_shard_stats()[0].scripts, 0);
+        chain.put_addresses(vec![regtest.clone()]).unwrap();
+        assert!(chain.scripts.contains(&regtest.script_pubkey()));
+    }
+
+    #[tokio::test]
+    async fn test_birthday_is_first_paying_block() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1).with_birthday_detection(true);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        chain.block_queue.add(genesis.block_hash());
+        chain.next_block();
+        chain.check_send_block(genesis.clone()).unwrap();
+        // The block does not pay to any of our scripts
+        assert!(matches!(chain.birthday, Birthday::Searching));
+        chain.put_script(genesis.txdata[0].output[0].script_pubkey.clone());
+        chain.block_queue.add(genesis.block_hash());
+        chain.next_block();
+        chain.check_send_block(genesis.clone()).unwrap();
+        assert!(matches!(chain.birthday, Birthday::Unwritten(birthday) if birthday.height == 0));
+        chain.write_scan_results().await;
+        assert!(
+            matches!(chain.birthday, Birthday::Found(birthday) if birthday.hash == genesis.block_hash())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_rescan() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1);
+        chain.header_chain = BlockTree::from_genesis(bitcoin::Network::Regtest);
+        chain.header_chain.assume_checked_to(0);
+        assert!(chain.is_filters_synced());
+        assert!(!chain.cancel_rescan());
+        chain.clear_filters();
+        assert!(!chain.is_filters_synced());
+        chain.block_queue.add(genesis.block_hash());
+        assert!(chain.cancel_rescan());
+        assert!(chain.is_filters_synced());
+        assert!(chain.block_queue_empty());
+        assert!(!chain.cancel_rescan());
+    }
+
     #[tokio::test]
     async fn test_fork_includes_old_vals() {
         let gen = HeaderCheckpoint::new(
@@ -754,6 +2603,122 @@ mod tests {
             );
             index += 1;
         }
+        // Only headers in the chain of most work are attested
+        assert!(chain.attest(block_3.block_hash()).is_none());
+        let attestation = chain.attest(block_2.block_hash()).unwrap();
+        assert_eq!(attestation.header.height, 2);
+        assert_eq!(attestation.checkpoint, gen);
+        assert_eq!(attestation.confirmations(), 3);
+        assert_eq!(attestation.work, block_1.work() + block_2.work());
+    }
+
+    #[tokio::test]
+    async fn test_future_headers_deferred() {
+        let gen = HeaderCheckpoint::new(
+            0,
+            BlockHash::from_str("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1)
+            .with_header_time_drift(Some(std::time::Duration::ZERO));
+        let block_1: Header = deserialize(&hex::decode("0000002006226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f047eb4d0fe76345e307d0e020a079cedfa37101ee7ac84575cf829a611b0f84bc4805e66ffff7f2001000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("00000020299e41732deb76d869fcdb5f72518d3784e99482f572afb73068d52134f1f75e1f20f5da8d18661d0f13aa3db8fff0f53598f7d61f56988a6d66573394b2c6ffc5805e66ffff7f2001000000").unwrap()).unwrap();
+        let block_3: Header = deserialize(&hex::decode("00000020b96feaa82716f11befeb608724acee4743e0920639a70f35f1637a88b8b6ea3471f1dbedc283ce6a43a87ed3c8e6326dae8d3dbacce1b2daba08e508054ffdb697815e66ffff7f2001000000").unwrap()).unwrap();
+        // The clocks of peers are behind, so only the first block is in the past
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        chain.set_time_offset(block_1.time as i64 - now);
+        let chain_sync = chain.sync_chain(vec![block_1, block_2, block_3]).await;
+        assert!(chain_sync.is_ok());
+        assert_eq!(chain.header_chain.height(), 1);
+        let chain_sync = chain.sync_chain(vec![block_2, block_3]).await;
+        assert_eq!(chain_sync, Err(HeaderSyncError::FutureHeaders));
+        assert_eq!(chain.header_chain.height(), 1);
+        // The deferred headers are accepted once the time passes
+        chain.set_time_offset(0);
+        let chain_sync = chain.sync_chain(vec![block_2, block_3]).await;
+        assert!(chain_sync.is_ok());
+        assert_eq!(chain.header_chain.height(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unchecked_headers_discarded_on_bad_checkpoint() {
+        let gen = HeaderCheckpoint::new(
+            0,
+            BlockHash::from_str("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1);
+        let block_1: Header = deserialize(&hex::decode("0000002006226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f047eb4d0fe76345e307d0e020a079cedfa37101ee7ac84575cf829a611b0f84bc4805e66ffff7f2001000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("00000020299e41732deb76d869fcdb5f72518d3784e99482f572afb73068d52134f1f75e1f20f5da8d18661d0f13aa3db8fff0f53598f7d61f56988a6d66573394b2c6ffc5805e66ffff7f2001000000").unwrap()).unwrap();
+        let fake_block_2 = Header {
+            nonce: block_2.nonce + 1,
+            ..block_2
+        };
+        chain.trust_checkpoints = true;
+        assert!(chain
+            .checkpoints
+            .extend(vec![HeaderCheckpoint::new(2, block_2.block_hash())]));
+        // Headers below the last checkpoint are accepted without checking their work
+        let chain_sync = chain.sync_chain(vec![block_1, fake_block_2]).await;
+        assert_eq!(chain_sync, Err(HeaderSyncError::InvalidCheckpoint));
+        assert_eq!(chain.header_chain.height(), 0);
+        assert_eq!(chain.header_chain.tip_hash(), gen.hash);
+        assert!(chain.unchecked_headers.is_empty());
+        assert!(chain.unchecked_from.is_none());
+        // The branch that links to the checkpoint is accepted
+        let chain_sync = chain.sync_chain(vec![block_1, block_2]).await;
+        assert!(chain_sync.is_ok());
+        assert_eq!(chain.header_chain.height(), 2);
+        assert_eq!(chain.header_chain.tip_hash(), block_2.block_hash());
+        assert!(chain.unchecked_headers.is_empty());
+        assert!(chain.checkpoints.next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fork_below_headers_in_memory() {
+        use crate::db::memory::MemoryHeaderDb;
+
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest_with_db(gen, height_monitor, 1, MemoryHeaderDb::new());
+        let time = genesis.header.time + 600;
+        let main = mine_headers(gen.hash, time, 10, 1);
+        chain.sync_chain(main.clone()).await.unwrap();
+        assert_eq!(chain.header_chain.height(), 10);
+        // Keep only the headers above height 6 in memory, as if the node loaded them on start
+        chain.header_chain = BlockTree::from_header(6_u32, main[5], bitcoin::Network::Regtest);
+        for header in &main[6..] {
+            chain.header_chain.accept_header(*header);
+        }
+        assert_eq!(chain.header_chain.root_height(), 6);
+        // A fork from height 3 with more work reorganizes the chain
+        let fork = mine_headers(main[2].block_hash(), time + 100, 8, 2);
+        chain.sync_chain(fork.clone()).await.unwrap();
+        assert_eq!(chain.header_chain.height(), 11);
+        assert_eq!(chain.header_chain.tip_hash(), fork[7].block_hash());
+        assert_eq!(
+            chain.header_chain.block_hash_at_height(3),
+            Some(main[2].block_hash())
+        );
+        assert_eq!(
+            chain.header_chain.block_hash_at_height(4),
+            Some(fork[0].block_hash())
+        );
+        let stored = chain.fetch_header_range(4..12).await.unwrap();
+        assert_eq!(stored.get(&4), Some(&fork[0]));
+        assert_eq!(stored.get(&11), Some(&fork[7]));
+        // A header that does not link to a stored block is still rejected
+        let floating = mine_headers(BlockHash::all_zeros(), time, 1, 3);
+        assert_eq!(
+            chain.sync_chain(floating).await,
+            Err(HeaderSyncError::FloatingHeaders)
+        );
     }
 
     #[tokio::test]
@@ -764,7 +2729,9 @@ mod tests {
                 .unwrap(),
         );
         let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
-        let mut chain = new_regtest(gen, height_monitor.clone(), 1);
+        let store: HashMap<BlockHash, BlockFilter> = HashMap::new();
+        let mut chain =
+            new_regtest(gen, height_monitor.clone(), 1).with_filter_store(Some(Box::new(store)));
         let block_1: Header = deserialize(&hex::decode("000000206a7cb0df73f2a05fd8eb63de4c9c0fda70d8848f3581b601338b530088474f4bbe54a272e64276a49cf98359a6e43563b6527cce7c9434c0c2ca21b4710b84593362c266ffff7f2000000000").unwrap()).unwrap();
         let block_2: Header = deserialize(&hex::decode("000000204326468f18d82108c98e5a328192770c8cb8d4e3322a4df708fe3232b3f0797dcd9468dd32ad9d68cfd49048378ec2caae965e4998200e4f83cba92f396f0b373462c266ffff7f2001000000").unwrap()).unwrap();
         let block_3: Header = deserialize(&hex::decode("00000020a860ab5e9320ad1e0318e154ea31cab1e030a1f4e1bcf89c63bfdf3055852d01053e4b600cfa947ce54315cc62b23e706dbfca5566f3156b272bf1f8971d930b3462c266ffff7f2001000000").unwrap()).unwrap();
@@ -773,6 +2740,18 @@ mod tests {
         let chain_sync = chain.sync_chain(header_batch).await;
         assert!(chain_sync.is_ok());
         assert_eq!(chain.header_chain.height(), 2500);
+        let indexed_header = chain
+            .fetch_header_by_hash(block_2.block_hash())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(indexed_header.height, 2498);
+        assert_eq!(indexed_header.header, block_2);
+        assert!(chain
+            .fetch_header_by_hash(BlockHash::all_zeros())
+            .await
+            .unwrap()
+            .is_none());
         height_monitor.lock().await.insert(1.into(), 2500);
         assert!(chain.is_synced().await);
         let filter_1 = hex::decode("018976c0").unwrap();
@@ -787,15 +2766,17 @@ mod tests {
         let filter_hash_2 = FilterHash::from_raw_hash(filter_hash_2);
         let filter_hash_3 = FilterHash::from_raw_hash(filter_hash_3);
         let filter_hash_4 = FilterHash::from_raw_hash(filter_hash_4);
+        assert!(chain.tip_filter().is_none());
         chain.next_cf_header_message();
+        let prev_filter_header = FilterHeader::from_slice(
+            &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
+                .unwrap(),
+        )
+        .unwrap();
         let cf_headers = CFHeaders {
             filter_type: 0x00,
             stop_hash: block_4.block_hash(),
-            previous_filter_header: FilterHeader::from_slice(
-                &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
-                    .unwrap(),
-            )
-            .unwrap(),
+            previous_filter_header: prev_filter_header,
             filter_hashes: vec![filter_hash_1, filter_hash_2, filter_hash_3, filter_hash_4],
         };
         let cf_header_sync_res = chain.sync_cf_headers(0.into(), cf_headers);
@@ -803,7 +2784,22 @@ mod tests {
         let append_attempt = cf_header_sync_res.unwrap();
         assert_eq!(CFHeaderChanges::Extended, append_attempt);
         assert!(chain.is_cf_headers_synced());
-        chain.next_filter_message();
+        // The filter headers are pinned from the anchor to the tip
+        let anchor = chain.anchor_filter().unwrap();
+        assert_eq!(anchor.block.height, gen.height);
+        assert_eq!(anchor.block.hash, gen.hash);
+        assert_eq!(anchor.filter_header, prev_filter_header);
+        let tip = chain.tip_filter().unwrap();
+        assert_eq!(tip.block.hash, block_4.block_hash());
+        assert_eq!(
+            tip.filter_header,
+            chain
+                .header_chain
+                .filter_commitment(block_4.block_hash())
+                .unwrap()
+                .header
+        );
+        chain.filter_requests(&[PeerId(0)]);
         let sync_filter_1 = chain.sync_filter(CFilter {
             filter_type: 0x00,
             block_hash: block_1.block_hash(),
@@ -829,6 +2825,569 @@ mod tests {
         });
         assert!(sync_filter_4.is_ok());
         assert!(chain.is_filters_synced());
+        // A rescan checks the filters written to the store without requesting them
+        chain.write_filters().await;
+        chain.clear_filters();
+        assert!(!chain.is_filters_synced());
+        chain.check_stored_filters().await;
+        assert!(chain.is_filters_synced());
+    }
+
+    #[tokio::test]
+    async fn test_cf_headers_survive_restart() {
+        let gen = HeaderCheckpoint::new(
+            2496,
+            BlockHash::from_str("4b4f478800538b3301b681358f84d870da0f9c4cde63ebd85fa0f273dfb07c6a")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let store = crate::MemoryCFHeaderDb::new();
+        let mut chain =
+            new_regtest(gen, height_monitor.clone(), 1).with_cf_header_store(Some(Box::new(store)));
+        let block_1: Header = deserialize(&hex::decode("000000206a7cb0df73f2a05fd8eb63de4c9c0fda70d8848f3581b601338b530088474f4bbe54a272e64276a49cf98359a6e43563b6527cce7c9434c0c2ca21b4710b84593362c266ffff7f2000000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("000000204326468f18d82108c98e5a328192770c8cb8d4e3322a4df708fe3232b3f0797dcd9468dd32ad9d68cfd49048378ec2caae965e4998200e4f83cba92f396f0b373462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_3: Header = deserialize(&hex::decode("00000020a860ab5e9320ad1e0318e154ea31cab1e030a1f4e1bcf89c63bfdf3055852d01053e4b600cfa947ce54315cc62b23e706dbfca5566f3156b272bf1f8971d930b3462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_4: Header = deserialize(&hex::decode("0000002004a138485264fdcec8abcd044e26a97b501649f941b9eed342ae26c51bfde134f84b9962adfb060e7b251a52d0ad0bc13eb6a69d35900860e9e0e027ff2bb86a3462c266ffff7f2001000000").unwrap()).unwrap();
+        let header_batch = vec![block_1, block_2, block_3, block_4];
+        assert!(chain.sync_chain(header_batch.clone()).await.is_ok());
+        let filter_hashes = ["018976c0", "018b1f28", "01117310", "0107dda0"]
+            .iter()
+            .map(|filter| {
+                FilterHash::from_raw_hash(sha256d::Hash::hash(&hex::decode(filter).unwrap()))
+            })
+            .collect::<Vec<FilterHash>>();
+        let prev_filter_header = FilterHeader::from_slice(
+            &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
+                .unwrap(),
+        )
+        .unwrap();
+        chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_4.block_hash(),
+            previous_filter_header: prev_filter_header,
+            filter_hashes,
+        };
+        assert_eq!(
+            chain.sync_cf_headers(0.into(), cf_headers).unwrap(),
+            CFHeaderChanges::Extended
+        );
+        chain.write_cf_headers().await;
+        assert!(chain.unwritten_cf_headers.is_empty());
+        // The node starts again with the same filter header store
+        let store = chain.cf_header_store.take();
+        let mut restarted = new_regtest(gen, height_monitor, 1).with_cf_header_store(store);
+        assert!(restarted.sync_chain(header_batch).await.is_ok());
+        assert!(!restarted.is_cf_headers_synced());
+        restarted.load_cf_headers().await;
+        assert!(restarted.is_cf_headers_synced());
+        let anchor = restarted.anchor_filter().unwrap();
+        assert_eq!(anchor.block.hash, gen.hash);
+        assert_eq!(anchor.filter_header, prev_filter_header);
+        assert_eq!(
+            restarted.tip_filter().unwrap().filter_header,
+            chain.tip_filter().unwrap().filter_header
+        );
+    }
+
+    #[test]
+    fn test_stale_checkpoints_are_refreshed_once() {
+        let anchor = HeaderCheckpoint::most_recent(bitcoin::Network::Regtest);
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let calls = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let provider_calls = Arc::clone(&calls);
+        let provider = move |_: bitcoin::Network| {
+            provider_calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            vec![HeaderCheckpoint::new(60_000, BlockHash::all_zeros())]
+        };
+        let mut chain = new_regtest(anchor, height_monitor, 1)
+            .with_checkpoint_provider(Some(Box::new(provider)));
+        // A peer within a year of the newest checkpoint does not refresh them
+        chain.check_checkpoint_freshness(STALE_CHECKPOINT_BLOCKS);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 0);
+        chain.check_checkpoint_freshness(STALE_CHECKPOINT_BLOCKS + 1);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(chain.checkpoints.last().height, 60_000);
+        assert_eq!(chain.known_height(), 60_000);
+        chain.check_checkpoint_freshness(200_000);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "filter-control"))]
+    async fn test_filter_matcher_selects_blocks() {
+        let gen = HeaderCheckpoint::new(
+            2496,
+            BlockHash::from_str("4b4f478800538b3301b681358f84d870da0f9c4cde63ebd85fa0f273dfb07c6a")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let matcher = |height: u32, _: BlockHash, _: &bitcoin::bip158::BlockFilter| height % 2 == 0;
+        let mut chain = new_regtest(gen, height_monitor.clone(), 1)
+            .with_filter_matcher(Some(Box::new(matcher)));
+        let block_1: Header = deserialize(&hex::decode("000000206a7cb0df73f2a05fd8eb63de4c9c0fda70d8848f3581b601338b530088474f4bbe54a272e64276a49cf98359a6e43563b6527cce7c9434c0c2ca21b4710b84593362c266ffff7f2000000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("000000204326468f18d82108c98e5a328192770c8cb8d4e3322a4df708fe3232b3f0797dcd9468dd32ad9d68cfd49048378ec2caae965e4998200e4f83cba92f396f0b373462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_3: Header = deserialize(&hex::decode("00000020a860ab5e9320ad1e0318e154ea31cab1e030a1f4e1bcf89c63bfdf3055852d01053e4b600cfa947ce54315cc62b23e706dbfca5566f3156b272bf1f8971d930b3462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_4: Header = deserialize(&hex::decode("0000002004a138485264fdcec8abcd044e26a97b501649f941b9eed342ae26c51bfde134f84b9962adfb060e7b251a52d0ad0bc13eb6a69d35900860e9e0e027ff2bb86a3462c266ffff7f2001000000").unwrap()).unwrap();
+        let headers = [block_1, block_2, block_3, block_4];
+        assert!(chain.sync_chain(headers.to_vec()).await.is_ok());
+        height_monitor.lock().await.insert(1.into(), 2500);
+        let filters = [
+            hex::decode("018976c0").unwrap(),
+            hex::decode("018b1f28").unwrap(),
+            hex::decode("01117310").unwrap(),
+            hex::decode("0107dda0").unwrap(),
+        ];
+        chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_4.block_hash(),
+            previous_filter_header: FilterHeader::from_slice(
+                &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
+                    .unwrap(),
+            )
+            .unwrap(),
+            filter_hashes: filters
+                .iter()
+                .map(|filter| FilterHash::from_raw_hash(sha256d::Hash::hash(filter)))
+                .collect(),
+        };
+        assert!(chain.sync_cf_headers(0.into(), cf_headers).is_ok());
+        chain.filter_requests(&[PeerId(0)]);
+        for (header, filter) in headers.iter().zip(filters) {
+            let sync_filter = chain.sync_filter(CFilter {
+                filter_type: 0x00,
+                block_hash: header.block_hash(),
+                filter,
+            });
+            assert!(sync_filter.is_ok());
+        }
+        assert!(chain.is_filters_synced());
+        assert!(!chain.block_queue.contains(&block_1.block_hash()));
+        assert!(chain.block_queue.contains(&block_2.block_hash()));
+        assert!(!chain.block_queue.contains(&block_3.block_hash()));
+        assert!(chain.block_queue.contains(&block_4.block_hash()));
+        let stats = chain.scan_stats();
+        assert_eq!(stats.filters_scanned, 4);
+        assert_eq!(stats.filters_matched, 2);
+        assert_eq!(stats.blocks_downloaded, 0);
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "filter-control"))]
+    async fn test_filter_matches_only() {
+        let gen = HeaderCheckpoint::new(
+            2496,
+            BlockHash::from_str("4b4f478800538b3301b681358f84d870da0f9c4cde63ebd85fa0f273dfb07c6a")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let matcher = |height: u32, _: BlockHash, _: &bitcoin::bip158::BlockFilter| height % 2 == 0;
+        let mut chain = new_regtest(gen, height_monitor.clone(), 1)
+            .with_filter_matcher(Some(Box::new(matcher)))
+            .with_filter_matches_only(true);
+        let (log_tx, _) = tokio::sync::mpsc::channel::<String>(1);
+        let (info_tx, _) = tokio::sync::mpsc::channel::<Info>(1);
+        let (warn_tx, _) = tokio::sync::mpsc::unbounded_channel::<Warning>();
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+        chain.dialog = Arc::new(Dialog::new(
+            crate::LogLevel::Debug,
+            HashMap::new(),
+            log_tx,
+            info_tx,
+            warn_tx,
+            event_tx,
+        ));
+        let block_1: Header = deserialize(&hex::decode("000000206a7cb0df73f2a05fd8eb63de4c9c0fda70d8848f3581b601338b530088474f4bbe54a272e64276a49cf98359a6e43563b6527cce7c9434c0c2ca21b4710b84593362c266ffff7f2000000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("000000204326468f18d82108c98e5a328192770c8cb8d4e3322a4df708fe3232b3f0797dcd9468dd32ad9d68cfd49048378ec2caae965e4998200e4f83cba92f396f0b373462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_3: Header = deserialize(&hex::decode("00000020a860ab5e9320ad1e0318e154ea31cab1e030a1f4e1bcf89c63bfdf3055852d01053e4b600cfa947ce54315cc62b23e706dbfca5566f3156b272bf1f8971d930b3462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_4: Header = deserialize(&hex::decode("0000002004a138485264fdcec8abcd044e26a97b501649f941b9eed342ae26c51bfde134f84b9962adfb060e7b251a52d0ad0bc13eb6a69d35900860e9e0e027ff2bb86a3462c266ffff7f2001000000").unwrap()).unwrap();
+        let headers = [block_1, block_2, block_3, block_4];
+        assert!(chain.sync_chain(headers.to_vec()).await.is_ok());
+        height_monitor.lock().await.insert(1.into(), 2500);
+        let filters = [
+            hex::decode("018976c0").unwrap(),
+            hex::decode("018b1f28").unwrap(),
+            hex::decode("01117310").unwrap(),
+            hex::decode("0107dda0").unwrap(),
+        ];
+        chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_4.block_hash(),
+            previous_filter_header: FilterHeader::from_slice(
+                &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
+                    .unwrap(),
+            )
+            .unwrap(),
+            filter_hashes: filters
+                .iter()
+                .map(|filter| FilterHash::from_raw_hash(sha256d::Hash::hash(filter)))
+                .collect(),
+        };
+        assert!(chain.sync_cf_headers(0.into(), cf_headers).is_ok());
+        chain.filter_requests(&[PeerId(0)]);
+        for (header, filter) in headers.iter().zip(filters) {
+            let sync_filter = chain.sync_filter(CFilter {
+                filter_type: 0x00,
+                block_hash: header.block_hash(),
+                filter,
+            });
+            assert!(sync_filter.is_ok());
+        }
+        assert!(chain.is_filters_synced());
+        // Matched blocks are reported without being downloaded
+        assert!(chain.block_queue_empty());
+        let mut matched = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            if let Event::FilterMatched { height, hash } = event {
+                matched.push((height, hash));
+            }
+        }
+        assert_eq!(
+            matched,
+            vec![(2498, block_2.block_hash()), (2500, block_4.block_hash())]
+        );
+        let stats = chain.scan_stats();
+        assert_eq!(stats.filters_scanned, 4);
+        assert_eq!(stats.filters_matched, 2);
+        assert_eq!(stats.blocks_downloaded, 0);
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "filter-control"))]
+    async fn test_derived_scripts_check_filters_again() {
+        use bitcoin::{
+            bip158,
+            bip32::{DerivationPath, Xpriv, Xpub},
+            block::Version,
+            constants::genesis_block,
+            pow::CompactTarget,
+            secp256k1::Secp256k1,
+            transaction, Amount, Block, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+            Witness,
+        };
+
+        use crate::derivation::{Derivations, DerivedScriptKind, XpubWatch};
+
+        // Mine a block with a coinbase paying to the script
+        fn mine_block(prev_blockhash: BlockHash, time: u32, script_pubkey: ScriptBuf) -> Block {
+            let coinbase = Transaction {
+                version: transaction::Version::TWO,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::from_bytes(time.to_le_bytes().to_vec()),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(50_000),
+                    script_pubkey,
+                }],
+            };
+            let mut block = Block {
+                header: Header {
+                    version: Version::TWO,
+                    prev_blockhash,
+                    merkle_root: coinbase.compute_txid().into(),
+                    time,
+                    bits: CompactTarget::from_consensus(0x207fffff),
+                    nonce: 0,
+                },
+                txdata: vec![coinbase],
+            };
+            while block.header.validate_pow(block.header.target()).is_err() {
+                block.header.nonce += 1;
+            }
+            block
+        }
+
+        let secp = Secp256k1::new();
+        let xpub = Xpub::from_priv(
+            &secp,
+            &Xpriv::new_master(bitcoin::Network::Regtest, &[7; 32]).unwrap(),
+        );
+        let watch = XpubWatch::new(
+            xpub,
+            DerivedScriptKind::P2wpkh,
+            &DerivationPath::from_str("m/0").unwrap(),
+            &DerivationPath::from_str("m/1").unwrap(),
+        )
+        .unwrap()
+        .gap_limit(2);
+        // The first three external scripts, of which only two are watched at first
+        let external = Derivations::new(watch.clone().gap_limit(3)).extend();
+        let genesis = genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1).with_xpub_watch(Some(watch));
+        // The last watched script is paid, and the next script is paid a block later
+        let block_1 = mine_block(
+            genesis.block_hash(),
+            genesis.header.time + 1,
+            external[1].clone(),
+        );
+        let block_2 = mine_block(
+            block_1.block_hash(),
+            genesis.header.time + 2,
+            external[2].clone(),
+        );
+        assert!(chain
+            .sync_chain(vec![block_1.header, block_2.header])
+            .await
+            .is_ok());
+        let filters: Vec<Vec<u8>> = [&block_1, &block_2]
+            .iter()
+            .map(|block| {
+                BlockFilter::new_script_filter(block, |outpoint| {
+                    Err::<ScriptBuf, _>(bip158::Error::UtxoMissing(*outpoint))
+                })
+                .unwrap()
+                .content
+            })
+            .collect();
+        chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_2.block_hash(),
+            previous_filter_header: FilterHeader::all_zeros(),
+            filter_hashes: filters
+                .iter()
+                .map(|filter| FilterHash::from_raw_hash(sha256d::Hash::hash(filter)))
+                .collect(),
+        };
+        assert!(chain.sync_cf_headers(0.into(), cf_headers).is_ok());
+        chain.filter_requests(&[PeerId(0)]);
+        for (block, filter) in [&block_1, &block_2].iter().zip(filters.clone()) {
+            let sync_filter = chain.sync_filter(CFilter {
+                filter_type: 0x00,
+                block_hash: block.block_hash(),
+                filter,
+            });
+            assert!(sync_filter.is_ok());
+        }
+        assert!(chain.is_filters_synced());
+        assert!(chain.block_queue.contains(&block_1.block_hash()));
+        assert!(!chain.block_queue.contains(&block_2.block_hash()));
+        assert_eq!(chain.next_block(), Some(block_1.block_hash()));
+        assert!(chain.check_send_block(block_1).is_ok());
+        // The filter after the block is checked again with the newly derived scripts
+        assert!(!chain.is_filters_synced());
+        let requests = chain.filter_requests(&[PeerId(0)]);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].1.start_height, 2);
+        let sync_filter = chain.sync_filter(CFilter {
+            filter_type: 0x00,
+            block_hash: block_2.block_hash(),
+            filter: filters[1].clone(),
+        });
+        assert!(sync_filter.is_ok());
+        assert!(chain.is_filters_synced());
+        assert!(chain.block_queue.contains(&block_2.block_hash()));
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "filter-control"))]
+    async fn test_watched_spends_are_true_positives() {
+        use bitcoin::{
+            absolute::LockTime, constants::genesis_block, transaction, Amount, OutPoint, ScriptBuf,
+            Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        use crate::chain::broad_scripts::WINDOW_BLOCKS;
+
+        let mut block = genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, block.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor, 1).with_broad_script_exclusion(true);
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        chain.put_script(script.clone());
+        let watched = OutPoint::new(block.txdata[0].compute_txid(), 0);
+        chain.put_outpoints([watched].into());
+        // The block only spends the watched output
+        block.txdata.push(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: watched,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new_op_return([1; 4]),
+            }],
+        });
+        chain
+            .broad_scripts
+            .record_match(block.block_hash(), vec![script.clone()]);
+        chain.scan_block(1, block, false);
+        let stats = chain.scan_stats();
+        assert_eq!(stats.true_positives, 1);
+        assert_eq!(stats.false_positives, 0);
+        // Spends of other outputs are not known without a script index or UTXO set, so broad
+        // scripts are only reported
+        for i in 0..WINDOW_BLOCKS {
+            let hash = BlockHash::hash(&i.to_le_bytes());
+            chain.broad_scripts.record_match(hash, vec![script.clone()]);
+            chain.check_broad_scripts(&hash, false);
+        }
+        assert!(chain.scripts.contains(&script));
+    }
+
+    #[tokio::test]
+    async fn test_tip_filter_arrives_early() {
+        let gen = HeaderCheckpoint::new(
+            2496,
+            BlockHash::from_str("4b4f478800538b3301b681358f84d870da0f9c4cde63ebd85fa0f273dfb07c6a")
+                .unwrap(),
+        );
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor.clone(), 1);
+        let block_1: Header = deserialize(&hex::decode("000000206a7cb0df73f2a05fd8eb63de4c9c0fda70d8848f3581b601338b530088474f4bbe54a272e64276a49cf98359a6e43563b6527cce7c9434c0c2ca21b4710b84593362c266ffff7f2000000000").unwrap()).unwrap();
+        let block_2: Header = deserialize(&hex::decode("000000204326468f18d82108c98e5a328192770c8cb8d4e3322a4df708fe3232b3f0797dcd9468dd32ad9d68cfd49048378ec2caae965e4998200e4f83cba92f396f0b373462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_3: Header = deserialize(&hex::decode("00000020a860ab5e9320ad1e0318e154ea31cab1e030a1f4e1bcf89c63bfdf3055852d01053e4b600cfa947ce54315cc62b23e706dbfca5566f3156b272bf1f8971d930b3462c266ffff7f2001000000").unwrap()).unwrap();
+        let block_4: Header = deserialize(&hex::decode("0000002004a138485264fdcec8abcd044e26a97b501649f941b9eed342ae26c51bfde134f84b9962adfb060e7b251a52d0ad0bc13eb6a69d35900860e9e0e027ff2bb86a3462c266ffff7f2001000000").unwrap()).unwrap();
+        chain
+            .sync_chain(vec![block_1, block_2, block_3])
+            .await
+            .unwrap();
+        height_monitor.lock().await.insert(1.into(), 2499);
+        let filters = [
+            hex::decode("018976c0").unwrap(),
+            hex::decode("018b1f28").unwrap(),
+            hex::decode("01117310").unwrap(),
+        ];
+        let filter_4 = hex::decode("0107dda0").unwrap();
+        let filter_hash = |filter: &Vec<u8>| FilterHash::from_raw_hash(sha256d::Hash::hash(filter));
+        chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_3.block_hash(),
+            previous_filter_header: FilterHeader::from_slice(
+                &hex::decode("12c10339861d7ca367696b8c92a4c5acb609e66e5bf2d352376225ead1f78011")
+                    .unwrap(),
+            )
+            .unwrap(),
+            filter_hashes: filters.iter().map(filter_hash).collect(),
+        };
+        assert_eq!(
+            chain.sync_cf_headers(0.into(), cf_headers).unwrap(),
+            CFHeaderChanges::Extended
+        );
+        chain.filter_requests(&[PeerId(0)]);
+        for (block, filter) in [block_1, block_2, block_3].iter().zip(filters) {
+            chain
+                .sync_filter(CFilter {
+                    filter_type: 0x00,
+                    block_hash: block.block_hash(),
+                    filter,
+                })
+                .unwrap();
+        }
+        assert!(chain.is_filters_synced());
+        // The filter header and filter for the announced block are requested with its header
+        let (get_cf_headers, get_filters) =
+            chain.tip_filter_messages(block_4.block_hash()).unwrap();
+        assert_eq!(get_cf_headers.start_height, 2500);
+        assert_eq!(get_filters.stop_hash, block_4.block_hash());
+        chain.sync_chain(vec![block_4]).await.unwrap();
+        // The filter is held until the filter header commits to it
+        assert!(!chain
+            .sync_filter(CFilter {
+                filter_type: 0x00,
+                block_hash: block_4.block_hash(),
+                filter: filter_4.clone(),
+            })
+            .unwrap());
+        assert!(chain.take_early_filter().is_none());
+        let prev_header = chain
+            .header_chain
+            .filter_commitment(block_3.block_hash())
+            .unwrap()
+            .header;
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: block_4.block_hash(),
+            previous_filter_header: prev_header,
+            filter_hashes: vec![filter_hash(&filter_4)],
+        };
+        assert_eq!(
+            chain.sync_cf_headers(0.into(), cf_headers).unwrap(),
+            CFHeaderChanges::Extended
+        );
+        let early_filter = chain.take_early_filter().unwrap();
+        chain.sync_filter(early_filter).unwrap();
+        assert!(chain.is_filters_synced());
+    }
+
+    #[tokio::test]
+    async fn test_filter_ranges_out_of_order() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let gen = HeaderCheckpoint::new(0, genesis.block_hash());
+        let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
+        let mut chain = new_regtest(gen, height_monitor.clone(), 1);
+        let headers = mine_headers(gen.hash, genesis.header.time + 600, 1_500, 1);
+        chain.sync_chain(headers.clone()).await.unwrap();
+        height_monitor.lock().await.insert(1.into(), 1_500);
+        assert!(chain.is_synced().await);
+        // Every block has an empty filter
+        let filter = vec![0x00];
+        let filter_hash = FilterHash::from_raw_hash(sha256d::Hash::hash(&filter));
+        let get_cf_headers = chain.next_cf_header_message();
+        let cf_headers = CFHeaders {
+            filter_type: 0x00,
+            stop_hash: get_cf_headers.stop_hash,
+            previous_filter_header: FilterHeader::all_zeros(),
+            filter_hashes: vec![filter_hash; (1_501 - get_cf_headers.start_height) as usize],
+        };
+        assert_eq!(
+            chain.sync_cf_headers(0.into(), cf_headers).unwrap(),
+            CFHeaderChanges::Extended
+        );
+        let cfilter = |height: u32, filter: Vec<u8>| CFilter {
+            filter_type: 0x00,
+            block_hash: headers[height as usize - 1].block_hash(),
+            filter,
+        };
+        let requests = chain.filter_requests(&[PeerId(0), PeerId(1)]);
+        assert_eq!(requests.len(), 2);
+        let first = requests[0].1.start_height;
+        let second = requests[1].1.start_height;
+        assert!(first < second);
+        // The last filter of the second range does not match its filter header, so the range is
+        // requested from another peer
+        let invalid = cfilter(1_500, vec![0x01, 0x00]);
+        assert!(chain.sync_filter(invalid).is_err());
+        let requests = chain.filter_requests(&[PeerId(0), PeerId(2)]);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, PeerId(2));
+        assert_eq!(requests[0].1.start_height, second);
+        // The second range arrives first, and its filters wait for the first range
+        for height in second..1_500 {
+            assert!(!chain.sync_filter(cfilter(height, filter.clone())).unwrap());
+        }
+        assert!(chain.sync_filter(cfilter(1_500, filter.clone())).unwrap());
+        let checked = |chain: &Chain<()>, height: u32| {
+            let hash = chain.header_chain.block_hash_at_height(height).unwrap();
+            chain.header_chain.is_filter_checked(&hash)
+        };
+        assert!(!checked(&chain, first));
+        assert!(!checked(&chain, second));
+        assert!(!chain.is_filters_synced());
+        // Each filter of the first range is checked as it arrives, then the filters held after it
+        for height in first..second - 1 {
+            assert!(!chain.sync_filter(cfilter(height, filter.clone())).unwrap());
+            assert!(checked(&chain, height));
+            assert!(!checked(&chain, height + 1));
+        }
+        assert!(chain
+            .sync_filter(cfilter(second - 1, filter.clone()))
+            .unwrap());
+        assert!(checked(&chain, 1_500));
+        assert!(chain.is_filters_synced());
     }
 
     #[tokio::test]
@@ -878,7 +3437,7 @@ mod tests {
         let append_attempt = cf_header_sync_res.unwrap();
         assert_eq!(CFHeaderChanges::Extended, append_attempt);
         assert!(chain.is_cf_headers_synced());
-        chain.next_filter_message();
+        chain.filter_requests(&[PeerId(0)]);
         let sync_filter_1 = chain.sync_filter(CFilter {
             filter_type: 0x00,
             block_hash: block_1.block_hash(),
@@ -955,7 +3514,7 @@ mod tests {
         let append_attempt = cf_header_sync_res.unwrap();
         assert_eq!(CFHeaderChanges::Extended, append_attempt);
         assert!(chain.is_cf_headers_synced());
-        chain.next_filter_message();
+        chain.filter_requests(&[PeerId(0)]);
         let sync_filter_1 = chain.sync_filter(CFilter {
             filter_type: 0x00,
             block_hash: block_2.block_hash(),
@@ -1029,6 +3588,15 @@ mod tests {
         assert!(cf_header_sync_res.is_ok());
         assert_eq!(cf_header_sync_res.unwrap(), CFHeaderChanges::Conflict);
         assert!(chain.request_state.pending_batch.is_none());
+        // The peers disagree on the filter of the last block
+        let (peers, get_filters, block_hash) = chain.dispute_requests().unwrap();
+        assert_eq!(peers, vec![PeerId(0), PeerId(1)]);
+        assert_eq!(get_filters.start_height, 2500);
+        assert_eq!(block_hash, block_4.block_hash());
+        assert!(chain.resolve_dispute().is_none());
+        assert!(chain.expire_dispute().is_none());
+        // The filter headers are requested again once the dispute is settled
+        chain.clear_compact_filter_queue();
         chain.next_cf_header_message();
         let cf_headers = CFHeaders {
             filter_type: 0x00,
@@ -1186,10 +3754,16 @@ mod tests {
         let filter_hash_5 = FilterHash::from_raw_hash(filter_hash_5);
         chain.next_cf_header_message();
         // Reorganize the blocks
+        let view = chain.view.clone();
+        assert_eq!(view.height_of(&block_4.block_hash()), Some(2500));
         let header_batch = vec![new_block_4, block_5];
         let chain_sync = chain.sync_chain(header_batch).await;
         assert!(chain_sync.is_ok());
         assert_eq!(chain.header_chain.height(), 2501);
+        // The view of the chain follows the reorganization
+        assert_eq!(view.height_of(&block_4.block_hash()), None);
+        assert_eq!(view.header_at(2500), Some(new_block_4));
+        assert_eq!(view.height(), Some(2501));
         chain.next_cf_header_message();
         let cf_headers = CFHeaders {
             filter_type: 0x00,
@@ -1241,7 +3815,7 @@ mod tests {
         let cf_header_sync_res = chain.sync_cf_headers(2.into(), cf_headers);
         assert!(cf_header_sync_res.is_ok());
         assert_eq!(cf_header_sync_res.unwrap(), CFHeaderChanges::Extended);
-        chain.next_filter_message();
+        chain.filter_requests(&[PeerId(0)]);
         let sync_filter_1 = chain.sync_filter(CFilter {
             filter_type: 0x00,
             block_hash: block_1.block_hash(),
@@ -1427,7 +4001,7 @@ mod tests {
         let cf_header_sync_res = chain.sync_cf_headers(0.into(), cf_headers);
         assert!(cf_header_sync_res.is_ok());
         assert_eq!(cf_header_sync_res.unwrap(), CFHeaderChanges::Extended);
-        chain.next_filter_message();
+        chain.filter_requests(&[PeerId(0)]);
         let sync_filter_1 = chain.sync_filter(CFilter {
             filter_type: 0x00,
             block_hash: block_1.block_hash(),
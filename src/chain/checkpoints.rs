@@ -518,7 +518,7 @@ pub const MAINNET_HEADER_CP: &[(Height, &str)] = &[
 ];
 
 /// A known block hash in the chain of most work.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HeaderCheckpoint {
     /// The index of the block hash.
     pub height: Height,
@@ -629,6 +629,20 @@ impl HeaderCheckpoints {
         self.last
     }
 
+    // Add checkpoints past the last known checkpoint, returning true if any were added
+    pub fn extend(&mut self, mut checkpoints: Vec<HeaderCheckpoint>) -> bool {
+        checkpoints.sort_by_key(|checkpoint| checkpoint.height);
+        let mut extended = false;
+        for checkpoint in checkpoints {
+            if checkpoint.height > self.last.height {
+                self.checkpoints.push_back(checkpoint);
+                self.last = checkpoint;
+                extended = true;
+            }
+        }
+        extended
+    }
+
     pub fn prune_up_to(&mut self, checkpoint: HeaderCheckpoint) {
         while let Some(header_checkpoint) = self.next() {
             if header_checkpoint.height.le(&checkpoint.height) {
@@ -667,6 +681,7 @@ impl TryFrom<(u32, &str)> for HeaderCheckpoint {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::hashes::Hash;
     use bitcoin::Network;
 
     #[test]
@@ -697,4 +712,20 @@ mod tests {
         let checkpoint = HeaderCheckpoint::most_recent(Network::Signet);
         assert!(checkpoint.height > 200_000);
     }
+
+    #[test]
+    fn test_checkpoints_are_extended() {
+        let mut checkpoints = HeaderCheckpoints::new(&Network::Signet);
+        let last = checkpoints.last();
+        let older = HeaderCheckpoint::new(last.height - 1, BlockHash::all_zeros());
+        assert!(!checkpoints.extend(vec![older]));
+        let next = HeaderCheckpoint::new(last.height + 2_000, BlockHash::all_zeros());
+        let newer = HeaderCheckpoint::new(last.height + 4_000, BlockHash::all_zeros());
+        assert!(checkpoints.extend(vec![newer, older, next]));
+        assert_eq!(checkpoints.last().height, newer.height);
+        checkpoints.prune_up_to(last);
+        assert_eq!(checkpoints.next().unwrap().height, next.height);
+        checkpoints.advance();
+        assert_eq!(checkpoints.next().unwrap().height, newer.height);
+    }
 }
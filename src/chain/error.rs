@@ -8,11 +8,13 @@ pub(crate) enum HeaderSyncError {
     HeadersNotConnected,
     InvalidHeaderWork,
     InvalidHeaderTimes,
+    FutureHeaders,
     InvalidCheckpoint,
     MiscalculatedDifficulty,
     InvalidBits,
     FloatingHeaders,
     DbError,
+    VerificationAborted,
 }
 
 impl Display for HeaderSyncError {
@@ -28,6 +30,12 @@ impl Display for HeaderSyncError {
             HeaderSyncError::InvalidHeaderTimes => {
                 write!(f, "one or more headers does not have a valid block time.")
             }
+            HeaderSyncError::FutureHeaders => {
+                write!(
+                    f,
+                    "the headers are too far ahead of the network-adjusted time."
+                )
+            }
             HeaderSyncError::InvalidCheckpoint => {
                 write!(f, "a checkpoint in the chain did not match.")
             }
@@ -39,6 +47,9 @@ impl Display for HeaderSyncError {
                 "the peer sent us a chain that does not connect to any header of ours."
             ),
             HeaderSyncError::DbError => write!(f, "the database could not load a fork."),
+            HeaderSyncError::VerificationAborted => {
+                write!(f, "the header verification task did not complete.")
+            }
             HeaderSyncError::InvalidBits => write!(
                 f,
                 "the target work does not adhere to basic transition requirements."
@@ -148,6 +159,8 @@ impl_sourceless_error!(FilterError);
 pub(crate) enum BlockScanError {
     NoBlockHash,
     InvalidMerkleRoot,
+    DuplicateTransactions,
+    InvalidWitnessCommitment,
 }
 
 impl Display for BlockScanError {
@@ -159,6 +172,15 @@ impl Display for BlockScanError {
             BlockScanError::InvalidMerkleRoot => {
                 write!(f, "the block sent to us does not have a merkle root that matches its header commitment.")
             }
+            BlockScanError::DuplicateTransactions => {
+                write!(
+                    f,
+                    "the block sent to us repeats transactions of its merkle tree."
+                )
+            }
+            BlockScanError::InvalidWitnessCommitment => {
+                write!(f, "the block sent to us does not have witness data that matches its coinbase commitment.")
+            }
         }
     }
 }
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+use bitcoin::BlockHash;
+
+use crate::network::PeerId;
+
+use super::Filter;
+
+// The most filters requested in one message
+pub(crate) const FILTER_BATCH_SIZE: u32 = 999;
+// Ranges requested past the next filter to check, which bounds the filters held in memory while
+// the filters before them arrive
+const MAX_RANGES_AHEAD: u32 = 8;
+
+// A range of filters requested from a single peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FilterRange {
+    pub(crate) start_height: u32,
+    pub(crate) stop_height: u32,
+    pub(crate) stop_hash: BlockHash,
+}
+
+// Ranges of compact block filters requested from different peers at the same time. Peers answer
+// their ranges concurrently, and filters that arrive ahead of the filters before them are held
+// until they may be checked in the order of the chain.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FilterBatches {
+    // The peer each range was requested from, by the height the range starts at. A range without
+    // a peer is requested again from the next idle peer.
+    requested: BTreeMap<u32, (Option<PeerId>, FilterRange)>,
+    // Verified filters waiting on the filters of the blocks below them
+    waiting: BTreeMap<u32, Filter>,
+    // The height of the next filter to check
+    next: Option<u32>,
+    // The last height sampled for spot checks
+    sampled_to: Option<u32>,
+}
+
+impl FilterBatches {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    // No ranges are requested and no filters are waiting to be checked
+    pub(crate) fn is_idle(&self) -> bool {
+        self.requested.is_empty() && self.waiting.is_empty()
+    }
+
+    pub(crate) fn next(&self) -> Option<u32> {
+        self.next
+    }
+
+    pub(crate) fn set_next(&mut self, height: u32) {
+        self.next = Some(height);
+    }
+
+    // Request a range from each peer that is not answering one. Released ranges and the ranges of
+    // peers that are no longer connected are requested again before any new range.
+    pub(crate) fn assign(
+        &mut self,
+        peers: &[PeerId],
+        next: u32,
+        tip: u32,
+        hash_at: impl Fn(u32) -> Option<BlockHash>,
+    ) -> Vec<(PeerId, FilterRange)> {
+        self.requested
+            .retain(|_, (_, range)| range.stop_height >= next);
+        let busy = self
+            .requested
+            .values()
+            .filter_map(|(peer, _)| *peer)
+            .collect::<Vec<PeerId>>();
+        let mut idle = peers.iter().filter(|peer| !busy.contains(peer)).copied();
+        let mut assigned = Vec::new();
+        for (peer, range) in self.requested.values_mut() {
+            if peer.map_or(false, |peer| peers.contains(&peer)) {
+                continue;
+            }
+            match idle.next() {
+                Some(replacement) => {
+                    *peer = Some(replacement);
+                    assigned.push((replacement, *range));
+                }
+                None => return assigned,
+            }
+        }
+        let mut start = self
+            .requested
+            .values()
+            .map(|(_, range)| range.stop_height + 1)
+            .max()
+            .unwrap_or(next)
+            .max(next);
+        let limit = next.saturating_add(MAX_RANGES_AHEAD * (FILTER_BATCH_SIZE + 1));
+        for peer in idle {
+            if start > tip || start >= limit {
+                break;
+            }
+            let stop_height = start.saturating_add(FILTER_BATCH_SIZE).min(tip);
+            let stop_hash = match hash_at(stop_height) {
+                Some(hash) => hash,
+                None => break,
+            };
+            let range = FilterRange {
+                start_height: start,
+                stop_height,
+                stop_hash,
+            };
+            self.requested.insert(start, (Some(peer), range));
+            assigned.push((peer, range));
+            start = stop_height + 1;
+        }
+        assigned
+    }
+
+    // Remove the range the filter completes, returning if there was one
+    pub(crate) fn complete(&mut self, block_hash: BlockHash) -> bool {
+        let start = self
+            .requested
+            .iter()
+            .find(|(_, (_, range))| range.stop_hash.eq(&block_hash))
+            .map(|(start, _)| *start);
+        match start {
+            Some(start) => self.requested.remove(&start).is_some(),
+            None => false,
+        }
+    }
+
+    // Request the range ending at the block again from another peer, returning if there was one
+    pub(crate) fn release(&mut self, block_hash: BlockHash) -> bool {
+        match self
+            .requested
+            .values_mut()
+            .find(|(_, range)| range.stop_hash.eq(&block_hash))
+        {
+            Some((peer, _)) => {
+                *peer = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Hold a filter until the filters below it are checked. Filters too far ahead of the next
+    // filter are requested again later.
+    pub(crate) fn hold(&mut self, height: u32, next: u32, filter: Filter) {
+        let limit = next.saturating_add(MAX_RANGES_AHEAD * (FILTER_BATCH_SIZE + 1));
+        if height > next && height < limit {
+            self.waiting.insert(height, filter);
+        }
+    }
+
+    pub(crate) fn take(&mut self, height: u32) -> Option<Filter> {
+        self.waiting.remove(&height)
+    }
+
+    // The range of blocks to sample for spot checks, once the filters reach the end of the last
+    // sampled range
+    pub(crate) fn sample_range(&mut self, height: u32, tip: u32) -> Option<(u32, u32)> {
+        if self
+            .sampled_to
+            .map_or(false, |sampled_to| height <= sampled_to)
+        {
+            return None;
+        }
+        let stop_height = height.saturating_add(FILTER_BATCH_SIZE).min(tip);
+        self.sampled_to = Some(stop_height);
+        Some((height, stop_height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    fn hash_at(height: u32) -> Option<BlockHash> {
+        let mut bytes = [0; 32];
+        bytes[..4].copy_from_slice(&height.to_le_bytes());
+        Some(BlockHash::from_byte_array(bytes))
+    }
+
+    #[test]
+    fn test_ranges_are_split_across_peers() {
+        let mut batches = FilterBatches::new();
+        let peers = [PeerId(0), PeerId(1), PeerId(2)];
+        let assigned = batches.assign(&peers, 1, 2_500, hash_at);
+        let ranges = assigned
+            .iter()
+            .map(|(_, range)| (range.start_height, range.stop_height))
+            .collect::<Vec<(u32, u32)>>();
+        assert_eq!(ranges, vec![(1, 1_000), (1_001, 2_000), (2_001, 2_500)]);
+        // Peers answering a range are not asked for another
+        assert!(batches.assign(&peers, 1, 2_500, hash_at).is_empty());
+        // The range of a peer that disconnected is requested from the next idle peer
+        assert!(batches.complete(hash_at(2_000).unwrap()));
+        let assigned = batches.assign(&[PeerId(1), PeerId(2)], 1, 2_500, hash_at);
+        assert_eq!(assigned.len(), 1);
+        assert_eq!(assigned[0].0, PeerId(1));
+        assert_eq!(assigned[0].1.start_height, 1);
+        assert!(!batches.is_idle());
+        // A released range is requested again before its peer disconnects
+        assert!(batches.release(hash_at(2_500).unwrap()));
+        assert!(!batches.release(hash_at(2_499).unwrap()));
+        let assigned = batches.assign(&[PeerId(1), PeerId(3)], 1, 2_500, hash_at);
+        assert_eq!(assigned.len(), 1);
+        assert_eq!(assigned[0].0, PeerId(3));
+        assert_eq!(assigned[0].1.start_height, 2_001);
+        // Ranges are only requested a bounded distance past the next filter
+        let peers = (0..20).map(PeerId).collect::<Vec<PeerId>>();
+        let mut batches = FilterBatches::new();
+        let assigned = batches.assign(&peers, 0, 100_000, hash_at);
+        assert_eq!(assigned.len(), MAX_RANGES_AHEAD as usize);
+    }
+}
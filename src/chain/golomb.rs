@@ -0,0 +1,61 @@
+// Golomb-Rice coded sets as defined in BIP 158
+
+// Parameters of the basic filter type
+pub(crate) const P: u8 = 19;
+pub(crate) const M: u64 = 784_931;
+
+// The most bits that are guaranteed to be valid in a peeked word, as the word may start at any
+// bit of its first byte
+const PEEK_BITS: u32 = 56;
+
+// Reads bits most significant first, a machine word at a time
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    // The next 64 bits, padded with zeros past the end of the data
+    fn peek(&self) -> u64 {
+        let byte = self.pos / 8;
+        let mut word = [0; 8];
+        if byte < self.data.len() {
+            let end = (byte + 8).min(self.data.len());
+            word[..end - byte].copy_from_slice(&self.data[byte..end]);
+        }
+        u64::from_be_bytes(word) << (self.pos % 8)
+    }
+
+    fn consume(&mut self, bits: u32) -> Option<()> {
+        self.pos += bits as usize;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+}
+
+// Decode `n_elements` from a set of Golomb-Rice coded differences. Rather than reading one bit
+// at a time, the unary quotient of each element is counted with a single instruction on a
+// peeked word, and the remainder is read from the same or the following word.
+pub(crate) fn decode(data: &[u8], n_elements: u64) -> Option<Vec<u64>> {
+    let mut reader = BitReader { data, pos: 0 };
+    let mut elements = Vec::with_capacity(n_elements.min(data.len() as u64 * 8) as usize);
+    let mut element: u64 = 0;
+    for _ in 0..n_elements {
+        let mut quotient: u64 = 0;
+        loop {
+            let ones = reader.peek().leading_ones();
+            if ones < PEEK_BITS {
+                quotient += u64::from(ones);
+                // Consume the terminating zero as well
+                reader.consume(ones + 1)?;
+                break;
+            }
+            quotient += u64::from(PEEK_BITS);
+            reader.consume(PEEK_BITS)?;
+        }
+        let remainder = reader.peek() >> (64 - P);
+        reader.consume(u32::from(P))?;
+        element = element.checked_add((quotient << P) + remainder)?;
+        elements.push(element);
+    }
+    Some(elements)
+}
@@ -1,6 +1,9 @@
 use std::collections::{BTreeMap, HashMap};
 
-use crate::{prelude::ZerolikeExt, HeaderCheckpoint};
+use crate::{
+    prelude::{ZerolikeExt, MEDIAN_TIME_PAST},
+    HeaderCheckpoint,
+};
 
 use bitcoin::{
     block::Header, constants::genesis_block, BlockHash, CompactTarget, FilterHash, Network, Work,
@@ -41,16 +44,11 @@ pub(crate) enum HeaderRejection {
 pub(crate) struct Tip {
     pub hash: BlockHash,
     pub height: Height,
-    pub next_work_required: Option<CompactTarget>,
 }
 
 impl Tip {
     pub(crate) fn from_checkpoint(height: Height, hash: BlockHash) -> Self {
-        Self {
-            hash,
-            height,
-            next_work_required: None,
-        }
+        Self { hash, height }
     }
 }
 
@@ -107,11 +105,7 @@ impl BlockTree {
         let genesis = genesis_block(network);
         let height = 0;
         let hash = genesis.block_hash();
-        let tip = Tip {
-            hash,
-            height,
-            next_work_required: Some(genesis.header.bits),
-        };
+        let tip = Tip { hash, height };
         let mut headers = HashMap::with_capacity(20_000);
         let block_node = BlockNode::new(height, genesis.header, genesis.header.work());
         headers.insert(hash, block_node);
@@ -129,11 +123,7 @@ impl BlockTree {
     pub(crate) fn from_header(height: impl Into<Height>, header: Header, network: Network) -> Self {
         let height = height.into();
         let hash = header.block_hash();
-        let tip = Tip {
-            hash,
-            height,
-            next_work_required: Some(header.bits),
-        };
+        let tip = Tip { hash, height };
         let mut headers = HashMap::with_capacity(20_000);
         let block_node = BlockNode::new(height, header, header.work());
         headers.insert(hash, block_node);
@@ -152,16 +142,7 @@ impl BlockTree {
 
         if self.active_tip.hash.eq(&prev_hash) {
             let new_height = self.active_tip.height.increment();
-            let params = self.network.params();
-            let next_work = if !params.no_pow_retargeting
-                && !params.allow_min_difficulty_blocks
-                && new_height.is_adjustment_multiple(self.network)
-            {
-                self.compute_next_work_required(new_height)
-            } else {
-                self.active_tip.next_work_required
-            };
-            if let Some(work) = next_work {
+            if let Some(work) = self.next_work_required(prev_hash, new_height, &new_header) {
                 if new_header.bits.ne(&work) {
                     return AcceptHeaderChanges::Rejected(HeaderRejection::InvalidPow {
                         expected: work,
@@ -172,7 +153,6 @@ impl BlockTree {
             let new_tip = Tip {
                 hash: new_hash,
                 height: new_height,
-                next_work_required: next_work,
             };
             let prev_work = self
                 .headers
@@ -201,16 +181,7 @@ impl BlockTree {
             let fork = self.candidate_forks.swap_remove(fork_index);
             if let Some(node) = self.headers.get(&fork.hash) {
                 let new_height = node.height.increment();
-                let params = self.network.params();
-                let next_work = if !params.no_pow_retargeting
-                    && !params.allow_min_difficulty_blocks
-                    && new_height.is_adjustment_multiple(self.network)
-                {
-                    self.compute_next_work_required(new_height)
-                } else {
-                    fork.next_work_required
-                };
-                if let Some(work) = next_work {
+                if let Some(work) = self.next_work_required(prev_hash, new_height, &new_header) {
                     if new_header.bits.ne(&work) {
                         return AcceptHeaderChanges::Rejected(HeaderRejection::InvalidPow {
                             expected: work,
@@ -222,7 +193,6 @@ impl BlockTree {
                 let new_tip = Tip {
                     hash: new_hash,
                     height: new_height,
-                    next_work_required: next_work,
                 };
                 let new_block_node = BlockNode::new(new_height, new_header, acc_work);
                 self.headers.insert(new_hash, new_block_node);
@@ -253,16 +223,7 @@ impl BlockTree {
             // A new fork was detected
             Some(node) => {
                 let new_height = node.height.increment();
-                let params = self.network.params();
-                let next_work = if !params.no_pow_retargeting
-                    && !params.allow_min_difficulty_blocks
-                    && new_height.is_adjustment_multiple(self.network)
-                {
-                    self.compute_next_work_required(new_height)
-                } else {
-                    Some(node.header.bits)
-                };
-                if let Some(work) = next_work {
+                if let Some(work) = self.next_work_required(prev_hash, new_height, &new_header) {
                     if new_header.bits.ne(&work) {
                         return AcceptHeaderChanges::Rejected(HeaderRejection::InvalidPow {
                             expected: work,
@@ -274,7 +235,6 @@ impl BlockTree {
                 let new_tip = Tip {
                     hash: new_hash,
                     height: new_height,
-                    next_work_required: next_work,
                 };
                 self.candidate_forks.push(new_tip);
                 let new_block_node = BlockNode::new(new_height, new_header, acc_work);
@@ -323,21 +283,59 @@ impl BlockTree {
         }
     }
 
-    fn compute_next_work_required(&self, new_height: Height) -> Option<CompactTarget> {
-        let adjustment_period =
-            Height::from_u64_checked(self.network.params().difficulty_adjustment_interval())?;
-        let epoch_start = new_height.checked_sub(adjustment_period)?;
-        let epoch_end = new_height.checked_sub(1)?;
-        let epoch_start_hash = self.canonical_hashes.get(&epoch_start)?;
-        let epoch_end_hash = self.canonical_hashes.get(&epoch_end)?;
-        let epoch_start_header = self.headers.get(epoch_start_hash).map(|node| node.header)?;
-        let epoch_end_header = self.headers.get(epoch_end_hash).map(|node| node.header)?;
-        let new_target = CompactTarget::from_header_difficulty_adjustment(
-            epoch_start_header,
-            epoch_end_header,
-            self.network,
-        );
-        Some(new_target)
+    // The bits a header at the height must have to build on the parent, following the difficulty
+    // adjustment of Bitcoin Core. Networks that allow blocks of minimum difficulty, such as the test
+    // networks, use the minimum difficulty for a block more than twenty minutes after its parent,
+    // and the difficulty of the last regular block otherwise. The bits are not known if the headers
+    // needed to compute them are not held, such as the headers before the anchor.
+    fn next_work_required(
+        &self,
+        parent_hash: BlockHash,
+        new_height: Height,
+        new_header: &Header,
+    ) -> Option<CompactTarget> {
+        let params = self.network.params();
+        let parent = self.headers.get(&parent_hash)?;
+        if !new_height.is_adjustment_multiple(self.network) {
+            if !params.allow_min_difficulty_blocks {
+                return Some(parent.header.bits);
+            }
+            let pow_limit = params.max_attainable_target.to_compact_lossy();
+            let min_difficulty_after = parent
+                .header
+                .time
+                .saturating_add((params.pow_target_spacing * 2) as u32);
+            if new_header.time > min_difficulty_after {
+                return Some(pow_limit);
+            }
+            let mut last_regular = parent;
+            while !last_regular.height.is_adjustment_multiple(self.network)
+                && last_regular.header.bits.eq(&pow_limit)
+            {
+                last_regular = self.headers.get(&last_regular.header.prev_blockhash)?;
+            }
+            return Some(last_regular.header.bits);
+        }
+        if params.no_pow_retargeting {
+            return Some(parent.header.bits);
+        }
+        let adjustment_period = Height::from_u64_checked(params.difficulty_adjustment_interval())?;
+        let mut epoch_start = parent;
+        for _ in 1..adjustment_period {
+            epoch_start = self.headers.get(&epoch_start.header.prev_blockhash)?;
+        }
+        // Testnet4 adjusts from the first block of the period, so a block of minimum difficulty
+        // at the end of the period does not reset the difficulty (BIP 94)
+        let last_bits = match self.network {
+            Network::Testnet4 => epoch_start.header.bits,
+            _ => parent.header.bits,
+        };
+        let timespan = parent.header.time.saturating_sub(epoch_start.header.time);
+        Some(CompactTarget::from_next_work_required(
+            last_bits,
+            timespan.into(),
+            params,
+        ))
     }
 
     pub(crate) fn block_hash_at_height(&self, height: Height) -> Option<BlockHash> {
@@ -352,6 +350,105 @@ impl BlockTree {
         self.headers.get(hash).map(|node| node.header)
     }
 
+    // The hash of a block in the chain of most work and the work accumulated since the tree was
+    // built. The block the tree was built on may not be held, and has no work of its own.
+    pub(crate) fn canonical_work(&self, height: Height) -> Option<(BlockHash, Work)> {
+        let hash = match self.block_hash_at_height(height) {
+            Some(hash) => hash,
+            None => self.header_at_height(height + 1)?.prev_blockhash,
+        };
+        let work = self
+            .headers
+            .get(&hash)
+            .map(|node| node.acc_work)
+            .unwrap_or(Work::zero());
+        Some((hash, work))
+    }
+
+    // The height of the block the tree was built on
+    pub(crate) fn root_height(&self) -> Height {
+        match self.canonical_hashes.keys().next() {
+            Some(lowest) => lowest.saturating_sub(1),
+            None => self.active_tip.height,
+        }
+    }
+
+    // Extend the tree below the lowest header held with headers of the chain of most work, in
+    // ascending order, so a fork from a block deeper than the tree may be followed. Headers that are
+    // already held are skipped. Returns false if the headers do not link to the tree.
+    pub(crate) fn extend_below(&mut self, ancestors: Vec<IndexedHeader>) -> bool {
+        let ancestors: Vec<IndexedHeader> = ancestors
+            .into_iter()
+            .filter(|ancestor| !self.headers.contains_key(&ancestor.header.block_hash()))
+            .collect();
+        let last = match ancestors.last() {
+            Some(last) => *last,
+            None => return false,
+        };
+        let lowest = self
+            .iter_data()
+            .last()
+            .map(|node| (node.height, node.header, node.filter_checked));
+        let links = match lowest {
+            Some((height, header, _)) => {
+                last.header.block_hash().eq(&header.prev_blockhash)
+                    && last.height.increment() == height
+            }
+            None => last.header.block_hash().eq(&self.active_tip.hash),
+        };
+        let contiguous = ancestors.windows(2).all(|pair| {
+            pair[1]
+                .header
+                .prev_blockhash
+                .eq(&pair[0].header.block_hash())
+                && pair[1].height == pair[0].height.increment()
+        });
+        if !links || !contiguous {
+            return false;
+        }
+        // Work is accumulated from the lowest header held, so the work of the new headers is added
+        // to every header already in the tree
+        let added_work = ancestors
+            .iter()
+            .fold(Work::zero(), |work, ancestor| work + ancestor.header.work());
+        for node in self.headers.values_mut() {
+            node.acc_work = node.acc_work + added_work;
+        }
+        // Filters below a block with a checked filter were checked when the tree was built
+        let mut filter_checked = false;
+        if let Some((height, header, checked)) = lowest {
+            self.canonical_hashes.insert(height, header.block_hash());
+            filter_checked = checked;
+        }
+        let mut acc_work = Work::zero();
+        for ancestor in ancestors {
+            acc_work = acc_work + ancestor.header.work();
+            let hash = ancestor.header.block_hash();
+            let mut node = BlockNode::new(ancestor.height, ancestor.header, acc_work);
+            node.filter_checked = filter_checked;
+            self.headers.insert(hash, node);
+            self.canonical_hashes.insert(ancestor.height, hash);
+        }
+        true
+    }
+
+    // The timestamps of up to the last eleven blocks ending at the hash, oldest first
+    pub(crate) fn ancestor_times(&self, hash: BlockHash) -> Vec<u32> {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_PAST);
+        let mut current = hash;
+        while times.len() < MEDIAN_TIME_PAST {
+            match self.headers.get(&current) {
+                Some(node) => {
+                    times.push(node.header.time);
+                    current = node.header.prev_blockhash;
+                }
+                None => break,
+            }
+        }
+        times.reverse();
+        times
+    }
+
     pub(crate) fn height_of_hash(&self, hash: BlockHash) -> Option<Height> {
         self.headers.get(&hash).map(|node| node.height)
     }
@@ -413,6 +510,21 @@ impl BlockTree {
         }
     }
 
+    // Returns if any filter above the height was checked
+    pub(crate) fn reset_filters_above(&mut self, height: Height) -> bool {
+        let mut reset = false;
+        let mut curr = self.tip_hash();
+        while let Some(node) = self.headers.get_mut(&curr) {
+            if node.height <= height {
+                break;
+            }
+            reset |= node.filter_checked;
+            node.filter_checked = false;
+            curr = node.header.prev_blockhash;
+        }
+        reset
+    }
+
     pub(crate) fn is_filter_checked(&self, hash: &BlockHash) -> bool {
         if let Some(node) = self.headers.get(hash) {
             return node.filter_checked;
@@ -740,4 +852,49 @@ mod tests {
         chain.assume_checked_to(4);
         assert!(chain.filters_synced());
     }
+
+    #[test]
+    fn test_min_difficulty_blocks() {
+        use bitcoin::{block::Version, hashes::Hash, TxMerkleNode};
+
+        let header = |prev_blockhash: BlockHash, time: u32, bits: u32| Header {
+            version: Version::ONE,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(bits),
+            nonce: 0,
+        };
+        let regular = 0x1c00ffff;
+        let min_difficulty = 0x1d00ffff;
+        let block_1 = header(BlockHash::all_zeros(), 1_000_000, regular);
+        let mut chain = BlockTree::from_header(1_u32, block_1, Network::Testnet4);
+        let block_2 = header(block_1.block_hash(), 1_000_600, regular);
+        assert!(matches!(
+            chain.accept_header(block_2),
+            AcceptHeaderChanges::Accepted { connected_at: _ }
+        ));
+        // A block may have the minimum difficulty if it is more than twenty minutes after its parent
+        let early = header(block_2.block_hash(), 1_001_800, min_difficulty);
+        assert!(matches!(
+            chain.accept_header(early),
+            AcceptHeaderChanges::Rejected(HeaderRejection::InvalidPow { .. })
+        ));
+        let block_3 = header(block_2.block_hash(), 1_001_801, min_difficulty);
+        assert!(matches!(
+            chain.accept_header(block_3),
+            AcceptHeaderChanges::Accepted { connected_at: _ }
+        ));
+        // The next block returns to the difficulty of the last regular block
+        let block_4 = header(block_3.block_hash(), 1_002_401, min_difficulty);
+        assert!(matches!(
+            chain.accept_header(block_4),
+            AcceptHeaderChanges::Rejected(HeaderRejection::InvalidPow { .. })
+        ));
+        let block_4 = header(block_3.block_hash(), 1_002_401, regular);
+        assert!(matches!(
+            chain.accept_header(block_4),
+            AcceptHeaderChanges::Accepted { connected_at: _ }
+        ));
+    }
 }
@@ -1,6 +1,9 @@
-use bitcoin::{block::Header, params::Params, Target};
+use bitcoin::{block::Header, params::Params, Network, Target};
 
 use crate::impl_sourceless_error;
+use crate::prelude::{Median, MEDIAN_TIME_PAST};
+
+use super::error::HeaderSyncError;
 
 pub(crate) struct HeadersBatch {
     batch: Vec<Header>,
@@ -16,6 +19,24 @@ impl HeadersBatch {
         Ok(HeadersBatch { batch })
     }
 
+    // Check the batch is consistent with itself, stopping at the first check that fails.
+    // Proof of work is the most expensive check, so it is only done for connected headers,
+    // and may be skipped for headers committed to by a checkpoint.
+    pub(crate) fn verify(&self, network: Network, check_pow: bool) -> Result<(), HeaderSyncError> {
+        // All the headers connect with each other and is the difficulty adjustment not absurd
+        if !self.connected() {
+            return Err(HeaderSyncError::HeadersNotConnected);
+        }
+        // All headers pass their own proof of work and the network minimum
+        if check_pow && !self.individually_valid_pow() {
+            return Err(HeaderSyncError::InvalidHeaderWork);
+        }
+        if !self.bits_adhere_transition(network) {
+            return Err(HeaderSyncError::InvalidBits);
+        }
+        Ok(())
+    }
+
     // Are they all logically connected?
     pub(crate) fn connected(&self) -> bool {
         self.batch
@@ -48,6 +69,45 @@ impl HeadersBatch {
             })
     }
 
+    // Is each timestamp above the median time past of the blocks before it? The median time past
+    // is only checked once the times of enough blocks are known, so the first headers after an
+    // anchor without stored headers are not checked.
+    pub(crate) fn times_valid(&self, mut prev_times: Vec<u32>) -> bool {
+        for header in &self.batch {
+            if prev_times.len() >= MEDIAN_TIME_PAST {
+                let mut window = prev_times[prev_times.len() - MEDIAN_TIME_PAST..].to_vec();
+                if header.time <= window.median() {
+                    return false;
+                }
+            }
+            prev_times.push(header.time);
+        }
+        true
+    }
+
+    // Remove the headers from the first header later than the maximum time, as they may be valid
+    // once the time passes. Returns false if every header is too late.
+    pub(crate) fn retain_until(&mut self, max_time: u32) -> bool {
+        match self.batch.iter().position(|header| header.time > max_time) {
+            Some(0) => false,
+            Some(index) => {
+                self.batch.truncate(index);
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u32 {
+        self.batch.len() as u32
+    }
+
+    pub(crate) fn first(&self) -> &Header {
+        self.batch
+            .first()
+            .expect("headers have at least one element by construction")
+    }
+
     // The tip of the list
     pub(crate) fn last(&self) -> &Header {
         self.batch
@@ -76,3 +136,50 @@ impl core::fmt::Display for HeadersBatchError {
 }
 
 impl_sourceless_error!(HeadersBatchError);
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::constants::genesis_block;
+
+    use super::*;
+
+    #[test]
+    fn test_skip_pow_check() {
+        let mut header = genesis_block(Network::Bitcoin).header;
+        header.nonce += 1;
+        let batch = HeadersBatch::new(vec![header]).unwrap();
+        assert!(matches!(
+            batch.verify(Network::Bitcoin, true),
+            Err(HeaderSyncError::InvalidHeaderWork)
+        ));
+        assert!(batch.verify(Network::Bitcoin, false).is_ok());
+    }
+
+    #[test]
+    fn test_header_times() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let headers = (0..3)
+            .map(|i| {
+                let mut header = genesis;
+                header.time = 1_000 + i;
+                header
+            })
+            .collect::<Vec<Header>>();
+        let mut batch = HeadersBatch::new(headers).unwrap();
+        let prev_times = (0..MEDIAN_TIME_PAST as u32).map(|i| 990 + i).collect();
+        assert!(batch.times_valid(prev_times));
+        // The first header is at the median time past
+        let prev_times = (0..MEDIAN_TIME_PAST as u32).map(|i| 995 + i).collect();
+        assert!(!batch.times_valid(prev_times));
+        // Too few times are known to find the median
+        assert!(batch.times_valid(vec![2_000]));
+        // The last header is too far ahead of the maximum time, so it is left for later
+        assert!(batch.retain_until(1_002));
+        assert_eq!(batch.len(), 3);
+        assert!(batch.retain_until(1_001));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.last().time, 1_001);
+        assert!(!batch.retain_until(999));
+        assert_eq!(batch.len(), 2);
+    }
+}
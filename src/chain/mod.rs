@@ -1,8 +1,14 @@
 //! Structures and checkpoints related to the blockchain.
 //!
 //! Notably, [`checkpoints`] contains known Bitcoin block hashes and heights with significant work, so Kyoto nodes do not have to sync from genesis.
+#[cfg(not(feature = "filter-control"))]
+mod bitmap_filter;
+mod block_body;
 pub(crate) mod block_queue;
+#[cfg(not(feature = "filter-control"))]
+mod broad_scripts;
 mod cfheader_batch;
+mod cfheader_dispute;
 #[allow(clippy::module_inception)]
 pub(crate) mod chain;
 /// Expected block header checkpoints and corresponding structure.
@@ -10,21 +16,34 @@ pub mod checkpoints;
 /// Errors associated with the blockchain representation.
 #[allow(dead_code)]
 pub(crate) mod error;
+mod filter_batches;
+#[cfg(not(feature = "filter-control"))]
+pub(crate) mod golomb;
 pub(crate) mod graph;
 pub(crate) mod header_batch;
+#[cfg(not(feature = "filter-control"))]
+mod outpoints;
+pub(crate) mod script_index;
+pub(crate) mod script_shards;
+mod spot_check;
+mod triggers;
+mod utxos;
+pub(crate) mod view;
 
 use std::collections::HashMap;
 
 use bitcoin::hashes::{sha256d, Hash};
 use bitcoin::{
-    bip158::BlockFilter, block::Header, params::Params, BlockHash, FilterHash, FilterHeader,
-    ScriptBuf,
+    bip158::BlockFilter, block::Header, p2p::message_filter::CFilter, params::Params, BlockHash,
+    FilterHash, FilterHeader, ScriptBuf,
 };
 
 use crate::network::PeerId;
 
 use cfheader_batch::CFHeaderBatch;
+use cfheader_dispute::FilterHeaderDispute;
 use error::FilterError;
+use filter_batches::FilterBatches;
 
 type Height = u32;
 
@@ -51,30 +70,32 @@ pub(crate) struct FilterCommitment {
 
 #[derive(Debug, Clone)]
 pub(crate) struct FilterRequestState {
-    pub last_filter_request: Option<FilterRequest>,
+    pub filter_batches: FilterBatches,
     pub last_filter_header_request: Option<FilterHeaderRequest>,
     pub pending_batch: Option<(PeerId, CFHeaderBatch)>,
     pub agreement_state: FilterHeaderAgreements,
+    // A block announced at the tip whose filter was requested alongside its header
+    pub tip_block: Option<BlockHash>,
+    // The filter for the tip block, if it arrived before the filter headers were agreed on
+    pub early_filter: Option<CFilter>,
+    // Peers that sent conflicting filter headers, until the peer that lied is found
+    pub dispute: Option<FilterHeaderDispute>,
 }
 
 impl FilterRequestState {
     pub(crate) fn new(required: u8) -> Self {
         Self {
-            last_filter_request: None,
+            filter_batches: FilterBatches::new(),
             last_filter_header_request: None,
             pending_batch: None,
             agreement_state: FilterHeaderAgreements::new(required),
+            tip_block: None,
+            early_filter: None,
+            dispute: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct FilterRequest {
-    #[allow(unused)]
-    pub start_height: u32,
-    pub stop_hash: BlockHash,
-}
-
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct FilterHeaderRequest {
     pub start_height: u32,
@@ -107,14 +128,18 @@ impl FilterHeaderAgreements {
     pub(crate) fn reset_agreements(&mut self) {
         self.current = 0;
     }
+
+    pub(crate) fn set_required(&mut self, required: u8) {
+        self.required = required;
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum CFHeaderChanges {
     AddedToQueue,
     Extended,
-    // Unfortunately, auditing each peer by reconstruction the filter would be costly in network
-    // and compute. Instead it is easier to disconnect from all peers and try again.
+    // Peers sent different filter headers. The first block they disagree on and the filter each
+    // peer has for it are downloaded to find the peer that lied.
     Conflict,
 }
 
@@ -155,6 +180,10 @@ impl Filter {
             .map_err(|_| FilterError::IORead)
     }
 
+    pub fn block_filter(&self) -> &BlockFilter {
+        &self.block_filter
+    }
+
     pub fn contents(self) -> Vec<u8> {
         self.block_filter.content
     }
@@ -176,6 +205,14 @@ impl HeightMonitor {
         self.map.values().copied().max()
     }
 
+    // The number of peers within one block of the height
+    pub(crate) fn near(&self, height: Height) -> usize {
+        self.map
+            .values()
+            .filter(|peer_height| peer_height.abs_diff(height) <= 1)
+            .count()
+    }
+
     pub(crate) fn retain(&mut self, peers: &[PeerId]) {
         self.map.retain(|peer_id, _| peers.contains(peer_id));
     }
@@ -252,6 +289,9 @@ mod tests {
         // peer one is now at 12
         height_monitor.increment(peer_one);
         assert!(height_monitor.max().unwrap().eq(&12));
+        assert_eq!(height_monitor.near(12), 2);
+        assert_eq!(height_monitor.near(13), 1);
+        assert_eq!(height_monitor.near(10), 1);
     }
 
     #[test]
@@ -0,0 +1,181 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use bitcoin::{Block, OutPoint, ScriptBuf, Txid};
+
+// Outputs watched for a spend. Filters commit to the scripts of the outputs a block spends, not to
+// the outpoints, so the filters are checked for the script of each outpoint once it is known. The
+// script of an outpoint is known from the script index, or from a block that creates the output.
+#[derive(Debug, Default)]
+pub(crate) struct WatchedOutpoints {
+    outpoints: HashMap<OutPoint, Option<ScriptBuf>>,
+    // The outpoints with a script that is not known yet
+    unresolved: HashSet<OutPoint>,
+    // The number of watched outpoints locked to each known script
+    scripts: HashMap<ScriptBuf, usize>,
+}
+
+impl WatchedOutpoints {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn insert(&mut self, outpoint: OutPoint, script: Option<ScriptBuf>) {
+        match script {
+            Some(script) => self.learn(outpoint, script),
+            None => {
+                if let Entry::Vacant(entry) = self.outpoints.entry(outpoint) {
+                    entry.insert(None);
+                    self.unresolved.insert(outpoint);
+                }
+            }
+        }
+    }
+
+    // Record the script of an outpoint, unless it is already known
+    fn learn(&mut self, outpoint: OutPoint, script: ScriptBuf) {
+        let known = self.outpoints.entry(outpoint).or_default();
+        if known.is_some() {
+            return;
+        }
+        *self.scripts.entry(script.clone()).or_default() += 1;
+        *known = Some(script);
+        self.unresolved.remove(&outpoint);
+    }
+
+    // Stop watching an outpoint, returning if it was watched
+    fn remove(&mut self, outpoint: &OutPoint) -> bool {
+        let script = match self.outpoints.remove(outpoint) {
+            Some(Some(script)) => script,
+            Some(None) => return self.unresolved.remove(outpoint),
+            None => return false,
+        };
+        if let Some(count) = self.scripts.get_mut(&script) {
+            *count -= 1;
+            if *count == 0 {
+                self.scripts.remove(&script);
+            }
+        }
+        true
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.outpoints.is_empty()
+    }
+
+    // Look up the scripts that are not known yet
+    pub(crate) fn resolve(&mut self, lookup: impl Fn(&OutPoint) -> Option<ScriptBuf>) {
+        if self.unresolved.is_empty() {
+            return;
+        }
+        let resolved = self
+            .unresolved
+            .iter()
+            .filter_map(|outpoint| lookup(outpoint).map(|script| (*outpoint, script)))
+            .collect::<Vec<_>>();
+        for (outpoint, script) in resolved {
+            self.learn(outpoint, script);
+        }
+    }
+
+    // The scripts to check filters for, once for any number of outpoints locked to a script
+    pub(crate) fn scripts(&self) -> impl Iterator<Item = &ScriptBuf> {
+        self.scripts.keys()
+    }
+
+    // Learn the scripts of watched outputs created in the block, and return the watched outputs it
+    // spends with the spending transaction. Spent outputs are no longer watched.
+    pub(crate) fn scan(&mut self, block: &Block) -> Vec<(OutPoint, Txid)> {
+        let mut spent = Vec::new();
+        if self.outpoints.is_empty() {
+            return spent;
+        }
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if self.unresolved.contains(&outpoint) {
+                    self.learn(outpoint, output.script_pubkey.clone());
+                }
+            }
+            for input in &tx.input {
+                if self.remove(&input.previous_output) {
+                    spent.push((input.previous_output, txid));
+                }
+            }
+        }
+        spent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, constants::genesis_block, hashes::Hash, transaction::Version, Amount,
+        Network, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_outpoint_spends_are_found() {
+        let mut block = genesis_block(Network::Regtest);
+        let coinbase = block.txdata[0].compute_txid();
+        let script = block.txdata[0].output[0].script_pubkey.clone();
+        let watched = OutPoint::new(coinbase, 0);
+        let mut outpoints = WatchedOutpoints::new();
+        outpoints.insert(watched, None);
+        assert_eq!(outpoints.scripts().count(), 0);
+        outpoints.resolve(|_| None);
+        assert_eq!(outpoints.scripts().count(), 0);
+        // The block that creates the output reveals the script to check filters for
+        assert!(outpoints.scan(&block).is_empty());
+        assert_eq!(outpoints.scripts().collect::<Vec<_>>(), vec![&script]);
+        let spend = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: watched,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txid = spend.compute_txid();
+        block.txdata.push(spend);
+        assert_eq!(outpoints.scan(&block), vec![(watched, txid)]);
+        assert!(outpoints.is_empty());
+        assert_eq!(outpoints.scripts().count(), 0);
+        assert!(outpoints.scan(&block).is_empty());
+    }
+
+    #[test]
+    fn test_outpoints_are_indexed_by_script() {
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        let first = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let second = OutPoint::new(Txid::from_byte_array([2; 32]), 0);
+        let unknown = OutPoint::new(Txid::from_byte_array([3; 32]), 0);
+        let mut outpoints = WatchedOutpoints::new();
+        outpoints.insert(first, Some(script.clone()));
+        outpoints.insert(second, None);
+        outpoints.insert(unknown, None);
+        // Only the outpoints without a script are looked up
+        outpoints.resolve(|outpoint| {
+            assert_ne!(outpoint, &first);
+            (outpoint == &second).then(|| script.clone())
+        });
+        assert_eq!(outpoints.unresolved, HashSet::from([unknown]));
+        // A script shared by outpoints is checked once, until every outpoint locked to it is spent
+        assert_eq!(outpoints.scripts().collect::<Vec<_>>(), vec![&script]);
+        assert!(outpoints.remove(&first));
+        assert_eq!(outpoints.scripts().collect::<Vec<_>>(), vec![&script]);
+        assert!(outpoints.remove(&second));
+        assert_eq!(outpoints.scripts().count(), 0);
+        assert!(outpoints.remove(&unknown));
+        assert!(outpoints.is_empty());
+        assert!(!outpoints.remove(&unknown));
+    }
+}
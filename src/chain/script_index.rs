@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use bitcoin::{Block, BlockHash, OutPoint, ScriptBuf, Transaction, Txid};
+
+use crate::ScriptTx;
+
+// The transactions found for each script in the blocks the node has scanned. Outputs paying to a
+// script are recorded by their outpoint, so a later transaction spending the output is recorded
+// as well, even though the script does not appear in the spending transaction.
+#[derive(Debug, Default)]
+pub(crate) struct ScriptIndex {
+    history: HashMap<ScriptBuf, Vec<ScriptTx>>,
+    outputs: HashMap<OutPoint, ScriptBuf>,
+    // Found since the index was last persisted
+    pending: Vec<(ScriptBuf, ScriptTx)>,
+}
+
+impl ScriptIndex {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Rebuild the index from the history in the header store
+    pub(crate) fn load(&mut self, history: Vec<(ScriptBuf, ScriptTx)>) {
+        for (script, script_tx) in history {
+            self.insert(script, script_tx);
+        }
+        self.pending.clear();
+    }
+
+    // Record the transactions in the block that pay to a script, or spend an output that does
+    pub(crate) fn scan(
+        &mut self,
+        height: u32,
+        block: &Block,
+        is_indexed: impl Fn(&ScriptBuf) -> bool,
+    ) {
+        let block_hash = block.block_hash();
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            for input in &tx.input {
+                if let Some(script) = self.outputs.get(&input.previous_output).cloned() {
+                    let spend = ScriptTx {
+                        txid,
+                        height,
+                        block_hash,
+                        vout: None,
+                    };
+                    self.insert(script, spend);
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                if is_indexed(&output.script_pubkey) {
+                    let receive = ScriptTx {
+                        txid,
+                        height,
+                        block_hash,
+                        vout: Some(vout as u32),
+                    };
+                    self.insert(output.script_pubkey.clone(), receive);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, script: ScriptBuf, script_tx: ScriptTx) {
+        let history = self.history.entry(script.clone()).or_default();
+        if history.contains(&script_tx) {
+            return;
+        }
+        if let Some(vout) = script_tx.vout {
+            self.outputs
+                .insert(OutPoint::new(script_tx.txid, vout), script.clone());
+        }
+        history.push(script_tx);
+        history.sort_by_key(|script_tx| script_tx.height);
+        self.pending.push((script, script_tx));
+    }
+
+    // Remove the transactions of blocks that are no longer in the chain of most work. Outputs are
+    // kept, as the transaction that created them may be confirmed again in the new chain.
+    pub(crate) fn disconnect(&mut self, removed: &[BlockHash]) {
+        for history in self.history.values_mut() {
+            history.retain(|script_tx| !removed.contains(&script_tx.block_hash));
+        }
+        self.history.retain(|_, history| !history.is_empty());
+        self.pending
+            .retain(|(_, script_tx)| !removed.contains(&script_tx.block_hash));
+    }
+
+    // Does the transaction spend an output paying to an indexed script
+    pub(crate) fn spends_indexed(&self, tx: &Transaction) -> bool {
+        tx.input
+            .iter()
+            .any(|input| self.outputs.contains_key(&input.previous_output))
+    }
+
+    // The script of an output paying to an indexed script
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn script_of(&self, outpoint: &OutPoint) -> Option<ScriptBuf> {
+        self.outputs.get(outpoint).cloned()
+    }
+
+    // The height and hash of the block that includes the transaction, if it was found
+    pub(crate) fn block_of(&self, txid: &Txid) -> Option<(u32, BlockHash)> {
+        self.history
+            .values()
+            .flatten()
+            .find(|script_tx| script_tx.txid.eq(txid))
+            .map(|script_tx| (script_tx.height, script_tx.block_hash))
+    }
+
+    // The transactions found for the script, ordered by height
+    pub(crate) fn history(&self, script: &ScriptBuf) -> Vec<ScriptTx> {
+        self.history.get(script).cloned().unwrap_or_default()
+    }
+
+    // The transactions found since the last call, to be written to the header store
+    pub(crate) fn take_pending(&mut self) -> Vec<(ScriptBuf, ScriptTx)> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, constants::genesis_block, transaction::Version, Amount, Network,
+        Sequence, TxIn, TxOut, Witness,
+    };
+
+    use super::*;
+
+    fn spend(previous_output: OutPoint, script_pubkey: ScriptBuf) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_script_history() {
+        let mut block = genesis_block(Network::Regtest);
+        let ours = block.txdata[0].output[0].script_pubkey.clone();
+        let coinbase = block.txdata[0].compute_txid();
+        let theirs = ScriptBuf::from_bytes(vec![0x51]);
+        let mut index = ScriptIndex::new();
+        index.scan(0, &block, |script| script.eq(&ours));
+        let history = index.history(&ours);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].txid, coinbase);
+        assert_eq!(history[0].vout, Some(0));
+        assert!(index.history(&theirs).is_empty());
+        // Scanning the same block twice does not duplicate the history
+        index.scan(0, &block, |script| script.eq(&ours));
+        assert_eq!(index.history(&ours).len(), 1);
+        assert_eq!(index.take_pending().len(), 1);
+        // A spend of our output is found without our script in the transaction
+        block.header.nonce += 1;
+        block.txdata = vec![spend(OutPoint::new(coinbase, 0), theirs.clone())];
+        index.scan(1, &block, |script| script.eq(&ours));
+        let history = index.history(&ours);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].height, 1);
+        assert_eq!(history[1].vout, None);
+        assert!(index.history(&theirs).is_empty());
+        let pending = index.take_pending();
+        assert_eq!(pending.len(), 1);
+        assert!(index.spends_indexed(&block.txdata[0]));
+        #[cfg(not(feature = "filter-control"))]
+        assert_eq!(
+            index.script_of(&OutPoint::new(coinbase, 0)),
+            Some(ours.clone())
+        );
+        // The spend is removed if the block is reorganized, and the history may be reloaded
+        index.disconnect(&[block.block_hash()]);
+        assert_eq!(index.history(&ours).len(), 1);
+        let mut reloaded = ScriptIndex::new();
+        reloaded.load(pending);
+        assert_eq!(reloaded.history(&ours).len(), 1);
+        assert!(reloaded.take_pending().is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use bitcoin::ScriptBuf;
+
+#[cfg(not(feature = "filter-control"))]
+use super::{bitmap_filter::BitmapFilter, error::FilterError, Filter};
+use crate::{zeroize::zeroize_scripts, ScriptShardStats};
+
+// Decoding every element of a filter up front pays off once there are this many scripts
+#[cfg(not(feature = "filter-control"))]
+const BITMAP_MIN_SCRIPTS: usize = 1_000;
+// Spawning a thread for each shard costs more than it saves below this many scripts
+#[cfg(not(feature = "filter-control"))]
+const PARALLEL_MIN_SCRIPTS: usize = 20_000;
+
+// A subset of the scripts with its own statistics
+#[derive(Debug, Default)]
+struct Shard {
+    scripts: HashSet<ScriptBuf>,
+    stats: ScriptShardStats,
+}
+
+// The scripts to check filters for, split into shards of a bounded size, so the hashes computed to
+// query a filter are bounded by the size of a shard. Large sets check each shard on its own thread.
+#[derive(Debug)]
+pub(crate) struct ScriptShards {
+    shards: Vec<Shard>,
+    shard_size: Option<usize>,
+}
+
+impl ScriptShards {
+    pub(crate) fn new(scripts: HashSet<ScriptBuf>, shard_size: Option<usize>) -> Self {
+        let mut shards = Self {
+            shards: vec![Shard::default()],
+            shard_size: shard_size.map(|size| size.max(1)),
+        };
+        for script in scripts {
+            shards.insert(script);
+        }
+        shards
+    }
+
+    pub(crate) fn insert(&mut self, script: ScriptBuf) {
+        if self.contains(&script) {
+            return;
+        }
+        let shard_size = self.shard_size;
+        let last = self.shards.last_mut().expect("there is always one shard");
+        match shard_size {
+            Some(size) if last.scripts.len() >= size => {
+                let mut shard = Shard::default();
+                shard.scripts.insert(script);
+                self.shards.push(shard);
+            }
+            _ => {
+                last.scripts.insert(script);
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, script: &ScriptBuf) -> bool {
+        self.shards
+            .iter()
+            .any(|shard| shard.scripts.contains(script))
+    }
+
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn remove(&mut self, script: &ScriptBuf) {
+        for shard in self.shards.iter_mut() {
+            zeroize_scripts(shard.scripts.take(script));
+        }
+    }
+
+    // Remove every script, overwriting the memory that held them
+    pub(crate) fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            zeroize_scripts(shard.scripts.drain());
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<ScriptShardStats> {
+        self.shards
+            .iter()
+            .map(|shard| ScriptShardStats {
+                scripts: shard.scripts.len(),
+                ..shard.stats
+            })
+            .collect()
+    }
+
+    // Check the filter for the scripts of every shard, in parallel if there are enough scripts
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn matches(&mut self, filter: &Filter) -> Result<bool, FilterError> {
+        let num_scripts: usize = self.shards.iter().map(|shard| shard.scripts.len()).sum();
+        let decoded = if num_scripts >= BITMAP_MIN_SCRIPTS {
+            Some(BitmapFilter::decode(
+                filter.block_hash(),
+                &filter.block_filter().content,
+            )?)
+        } else {
+            None
+        };
+        let check = |shard: &Shard| {
+            let start = std::time::Instant::now();
+            let matched = match decoded.as_ref() {
+                Some(decoded) => Ok(decoded.contains_any(shard.scripts.iter())),
+                None => filter.contains_any(shard.scripts.iter()),
+            };
+            (matched, start.elapsed())
+        };
+        let results = if self.shards.len() > 1 && num_scripts >= PARALLEL_MIN_SCRIPTS {
+            std::thread::scope(|scope| {
+                let handles = self
+                    .shards
+                    .iter()
+                    .map(|shard| scope.spawn(move || check(shard)))
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("matching a filter does not panic"))
+                    .collect::<Vec<_>>()
+            })
+        } else {
+            self.shards.iter().map(check).collect::<Vec<_>>()
+        };
+        let mut any_match = false;
+        for (shard, (matched, elapsed)) in self.shards.iter_mut().zip(results) {
+            let matched = matched?;
+            shard.stats.filters_checked += 1;
+            shard.stats.time_matching += elapsed;
+            if matched {
+                shard.stats.matches += 1;
+                any_match = true;
+            }
+        }
+        Ok(any_match)
+    }
+
+    // The scripts found in a filter, to attribute a match to the scripts that caused it
+    #[cfg(not(feature = "filter-control"))]
+    pub(crate) fn matching(&self, filter: &Filter) -> Result<Vec<ScriptBuf>, FilterError> {
+        let decoded = BitmapFilter::decode(filter.block_hash(), &filter.block_filter().content)?;
+        Ok(self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.scripts.iter())
+            .filter(|script| decoded.contains_any(std::iter::once(*script)))
+            .cloned()
+            .collect())
+    }
+}
+
+impl Drop for ScriptShards {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(i: u8) -> ScriptBuf {
+        ScriptBuf::from_bytes(vec![0x51, i])
+    }
+
+    fn sizes(shards: &ScriptShards) -> Vec<usize> {
+        shards.stats().iter().map(|stats| stats.scripts).collect()
+    }
+
+    #[test]
+    fn test_scripts_are_sharded() {
+        let scripts = (0..5).map(script).collect::<HashSet<ScriptBuf>>();
+        let mut shards = ScriptShards::new(scripts.clone(), Some(2));
+        assert_eq!(sizes(&shards), vec![2, 2, 1]);
+        shards.insert(script(0));
+        assert_eq!(sizes(&shards), vec![2, 2, 1]);
+        shards.insert(script(5));
+        assert_eq!(sizes(&shards), vec![2, 2, 2]);
+        let unbounded = ScriptShards::new(scripts, None);
+        assert_eq!(unbounded.stats().len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "filter-control"))]
+    fn test_shards_are_matched() {
+        use bitcoin::{bip158::BlockFilter, constants::genesis_block, Network};
+
+        let block = genesis_block(Network::Regtest);
+        // The genesis block has no inputs to look up
+        let block_filter = BlockFilter::new_script_filter(&block, |outpoint| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+        })
+        .unwrap();
+        let filter = Filter::new(block_filter.content, block.block_hash());
+        let mut scripts = (0..4).map(script).collect::<HashSet<ScriptBuf>>();
+        let mut shards = ScriptShards::new(scripts.clone(), Some(2));
+        assert!(!shards.matches(&filter).unwrap());
+        scripts.insert(block.txdata[0].output[0].script_pubkey.clone());
+        let mut shards = ScriptShards::new(scripts, Some(2));
+        assert!(shards.matches(&filter).unwrap());
+        let stats = shards.stats();
+        assert_eq!(stats.len(), 3);
+        assert!(stats.iter().all(|stats| stats.filters_checked == 1));
+        assert_eq!(stats.iter().map(|stats| stats.matches).sum::<u64>(), 1);
+        let coinbase = block.txdata[0].output[0].script_pubkey.clone();
+        assert_eq!(shards.matching(&filter).unwrap(), vec![coinbase.clone()]);
+        shards.remove(&coinbase);
+        assert!(!shards.matches(&filter).unwrap());
+    }
+}
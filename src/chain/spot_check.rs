@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::{
+    bip158::BlockFilter,
+    key::rand::{thread_rng, Rng},
+    Block, BlockHash,
+};
+
+use crate::FilterSpotCheck;
+
+// Filters sampled from each range of synced filters, checked against their block once it is
+// downloaded.
+//
+// A filter cannot be rebuilt from its block alone, as it commits to the scripts spent by the
+// block, which are not known to a light client. Every output script of the block must be in the
+// filter, however, so a filter that is missing an output of its block is certain to be invalid.
+#[derive(Debug, Default)]
+pub(crate) struct SpotChecks {
+    samples: u32,
+    // Heights sampled from the range of filters being synced
+    sampled: HashSet<u32>,
+    // Sampled filters waiting on their block
+    pending: HashMap<BlockHash, BlockFilter>,
+    // Checked since the results were last taken
+    results: Vec<FilterSpotCheck>,
+}
+
+impl SpotChecks {
+    pub(crate) fn new(samples: u32) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    // Choose heights to check from a newly requested range of filters
+    pub(crate) fn sample(&mut self, start_height: u32, stop_height: u32) {
+        self.sampled.clear();
+        if stop_height < start_height {
+            return;
+        }
+        let mut rng = thread_rng();
+        for _ in 0..self.samples {
+            self.sampled
+                .insert(rng.gen_range(start_height..=stop_height));
+        }
+    }
+
+    pub(crate) fn is_sampling(&self) -> bool {
+        !self.sampled.is_empty()
+    }
+
+    // Keep the filter if its height was sampled, returning true if the block should be downloaded
+    pub(crate) fn keep(
+        &mut self,
+        height: u32,
+        block_hash: BlockHash,
+        filter: &BlockFilter,
+    ) -> bool {
+        if !self.sampled.remove(&height) {
+            return false;
+        }
+        self.pending.insert(block_hash, filter.clone());
+        true
+    }
+
+    // Check that every output of the block is in its sampled filter, returning `None` if the
+    // block was not sampled
+    pub(crate) fn check(&mut self, height: u32, block: &Block) -> Option<FilterSpotCheck> {
+        let block_hash = block.block_hash();
+        let filter = self.pending.remove(&block_hash)?;
+        let passed = has_outputs(&filter, block);
+        let check = FilterSpotCheck {
+            height,
+            block_hash,
+            passed,
+        };
+        self.results.push(check);
+        Some(check)
+    }
+
+    // Forget the samples of blocks that will not be downloaded
+    pub(crate) fn discard(&mut self, hashes: &[BlockHash]) {
+        self.pending.retain(|hash, _| !hashes.contains(hash));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.sampled.clear();
+        self.pending.clear();
+    }
+
+    // The checks completed since the last call, to be reported to the client
+    pub(crate) fn take_results(&mut self) -> Vec<FilterSpotCheck> {
+        std::mem::take(&mut self.results)
+    }
+}
+
+// Is every output script of the block in the filter
+pub(crate) fn has_outputs(filter: &BlockFilter, block: &Block) -> bool {
+    let scripts = block
+        .txdata
+        .iter()
+        .flat_map(|tx| tx.output.iter())
+        .map(|output| output.script_pubkey.as_script())
+        .filter(|script| !script.is_empty() && !script.is_op_return())
+        .map(|script| script.as_bytes());
+    filter
+        .match_all(&block.block_hash(), scripts)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, Network, ScriptBuf};
+
+    use super::*;
+
+    #[test]
+    fn test_spot_check_finds_missing_outputs() {
+        let mut block = genesis_block(Network::Regtest);
+        let mut output = block.txdata[0].output[0].clone();
+        output.script_pubkey = ScriptBuf::from_bytes(vec![0x51]);
+        block.txdata[0].output.push(output);
+        let block_hash = block.block_hash();
+        let filter = BlockFilter::new_script_filter(&block, |outpoint| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+        })
+        .unwrap();
+        let mut spot_checks = SpotChecks::new(3);
+        assert!(!spot_checks.is_sampling());
+        spot_checks.sample(7, 7);
+        assert!(spot_checks.is_sampling());
+        assert!(!spot_checks.keep(8, block_hash, &filter));
+        assert!(spot_checks.keep(7, block_hash, &filter));
+        assert!(!spot_checks.is_sampling());
+        assert!(spot_checks.check(7, &block).unwrap().passed);
+        assert!(spot_checks.check(7, &block).is_none());
+        // A filter that does not commit to the second output fails the check
+        let mut honest = block.clone();
+        honest.txdata[0].output.pop();
+        let omitting = BlockFilter::new_script_filter(&honest, |outpoint| {
+            Err::<ScriptBuf, _>(bitcoin::bip158::Error::UtxoMissing(*outpoint))
+        })
+        .unwrap();
+        spot_checks.sample(7, 7);
+        assert!(spot_checks.keep(7, block_hash, &omitting));
+        assert!(!spot_checks.check(7, &block).unwrap().passed);
+        assert_eq!(spot_checks.take_results().len(), 2);
+        assert!(spot_checks.take_results().is_empty());
+    }
+}
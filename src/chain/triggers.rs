@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use bitcoin::{Block, BlockHash, Txid};
+
+use crate::messages::{ConfirmationTrigger, HeightTrigger, IndexedHeaderSender};
+
+use super::IndexedHeader;
+
+// A transaction the client is waiting on, and the block that includes it once it is found
+#[derive(Debug)]
+struct Confirmation {
+    txid: Txid,
+    depth: u32,
+    included: Option<IndexedHeader>,
+    oneshot: IndexedHeaderSender,
+}
+
+impl Confirmation {
+    fn is_buried(&self, tip: &IndexedHeader) -> bool {
+        match self.included {
+            // The block that includes the transaction is the first confirmation
+            Some(included) => included.height + self.depth.max(1) <= tip.height + 1,
+            None => false,
+        }
+    }
+}
+
+// Clients waiting for the chain to reach a height, or for a transaction to be buried under a
+// number of blocks. Triggers are resolved as the tip of the chain advances, and a trigger the
+// client stopped waiting on is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Triggers {
+    heights: Vec<HeightTrigger>,
+    confirmations: Vec<Confirmation>,
+}
+
+impl Triggers {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn at_height(&mut self, trigger: HeightTrigger) {
+        self.heights.push(trigger);
+    }
+
+    // Wait for the transaction, which may be known to be included in a block already
+    pub(crate) fn confirmations(
+        &mut self,
+        trigger: ConfirmationTrigger,
+        included: Option<IndexedHeader>,
+    ) {
+        self.confirmations.push(Confirmation {
+            txid: trigger.txid,
+            depth: trigger.depth,
+            included,
+            oneshot: trigger.oneshot,
+        });
+    }
+
+    // Record the block that includes a transaction a client is waiting on
+    pub(crate) fn scan(&mut self, height: u32, block: &Block) {
+        if self
+            .confirmations
+            .iter()
+            .all(|conf| conf.included.is_some())
+        {
+            return;
+        }
+        let txids = block
+            .txdata
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<HashSet<Txid>>();
+        for conf in self.confirmations.iter_mut() {
+            if conf.included.is_none() && txids.contains(&conf.txid) {
+                conf.included = Some(IndexedHeader::new(height, block.header));
+            }
+        }
+    }
+
+    // Transactions in blocks reorganized out of the chain are unconfirmed until found again
+    pub(crate) fn disconnect(&mut self, removed: &[BlockHash]) {
+        for conf in self.confirmations.iter_mut() {
+            if conf
+                .included
+                .map_or(false, |index| removed.contains(&index.header.block_hash()))
+            {
+                conf.included = None;
+            }
+        }
+    }
+
+    // Resolve the triggers reached by the tip of the chain. Height triggers receive the tip, and
+    // confirmation triggers receive the block that includes the transaction.
+    pub(crate) fn resolve(&mut self, tip: IndexedHeader) {
+        let (reached, waiting): (Vec<HeightTrigger>, Vec<HeightTrigger>) =
+            std::mem::take(&mut self.heights)
+                .into_iter()
+                .partition(|trigger| trigger.height <= tip.height);
+        for trigger in reached {
+            // The client stopped waiting
+            let _ = trigger.oneshot.send(tip);
+        }
+        self.heights = waiting
+            .into_iter()
+            .filter(|trigger| !trigger.oneshot.is_closed())
+            .collect();
+        let (buried, waiting): (Vec<Confirmation>, Vec<Confirmation>) =
+            std::mem::take(&mut self.confirmations)
+                .into_iter()
+                .partition(|conf| conf.is_buried(&tip));
+        for conf in buried {
+            if let Some(included) = conf.included {
+                let _ = conf.oneshot.send(included);
+            }
+        }
+        self.confirmations = waiting
+            .into_iter()
+            .filter(|conf| !conf.oneshot.is_closed())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, Network};
+
+    use super::*;
+
+    #[test]
+    fn test_triggers_follow_the_tip() {
+        let block = genesis_block(Network::Regtest);
+        let txid = block.txdata[0].compute_txid();
+        let tip_at = |height: u32| IndexedHeader::new(height, block.header);
+        let mut triggers = Triggers::new();
+        let (tx, mut at_height) = tokio::sync::oneshot::channel();
+        triggers.at_height(HeightTrigger::new(tx, 10));
+        let (tx, mut confirmed) = tokio::sync::oneshot::channel();
+        triggers.confirmations(ConfirmationTrigger::new(tx, txid, 3), None);
+        triggers.resolve(tip_at(9));
+        assert!(at_height.try_recv().is_err());
+        triggers.resolve(tip_at(10));
+        assert_eq!(at_height.try_recv().unwrap().height, 10);
+        // The transaction is found at height 11, and a reorganization unconfirms it
+        triggers.scan(11, &block);
+        triggers.resolve(tip_at(12));
+        assert!(confirmed.try_recv().is_err());
+        triggers.disconnect(&[block.block_hash()]);
+        triggers.resolve(tip_at(14));
+        assert!(confirmed.try_recv().is_err());
+        triggers.scan(12, &block);
+        triggers.resolve(tip_at(14));
+        assert_eq!(confirmed.try_recv().unwrap().height, 12);
+        // Abandoned triggers are dropped
+        let (tx, at_height) = tokio::sync::oneshot::channel();
+        triggers.at_height(HeightTrigger::new(tx, 100));
+        drop(at_height);
+        triggers.resolve(tip_at(15));
+        assert!(triggers.heights.is_empty());
+        assert!(triggers.confirmations.is_empty());
+    }
+}
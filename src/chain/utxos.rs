@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use bitcoin::{Block, BlockHash, OutPoint, ScriptBuf, Txid};
+
+use crate::{db::PersistedUtxo, HeaderCheckpoint, Utxo};
+
+// Spent outputs are kept for this many blocks, so they may be restored if the block that spent
+// them is reorganized out of the chain
+const SPENT_DEPTH: u32 = 100;
+
+// The outputs paying to the scripts of the node that are not spent, over the blocks the node has
+// scanned. Outputs created in a block that is reorganized out of the chain are removed, and the
+// outputs it spent are unspent again.
+#[derive(Debug, Default)]
+pub(crate) struct UtxoSet {
+    unspent: HashMap<OutPoint, Utxo>,
+    // The outputs spent in each block and the height of the block
+    spent: HashMap<BlockHash, (u32, Vec<Utxo>)>,
+    // The set changed since it was last persisted
+    changed: bool,
+}
+
+impl UtxoSet {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Rebuild the set from the outputs in the header store
+    pub(crate) fn load(&mut self, utxos: Vec<PersistedUtxo>) {
+        for PersistedUtxo { utxo, spent } in utxos {
+            match spent {
+                Some(block) => self
+                    .spent
+                    .entry(block.hash)
+                    .or_insert_with(|| (block.height, Vec::new()))
+                    .1
+                    .push(utxo),
+                None => {
+                    self.unspent.insert(utxo.outpoint, utxo);
+                }
+            }
+        }
+    }
+
+    // Record the outputs of the block that pay to a script, and the outputs it spends, returning
+    // the outputs that were created and those that were spent with the spending transaction
+    pub(crate) fn scan(
+        &mut self,
+        height: u32,
+        block: &Block,
+        is_watched: impl Fn(&ScriptBuf) -> bool,
+    ) -> (Vec<Utxo>, Vec<(Utxo, Txid)>) {
+        let block_hash = block.block_hash();
+        let mut created = Vec::new();
+        let mut spent = Vec::new();
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            for input in &tx.input {
+                if let Some(utxo) = self.unspent.remove(&input.previous_output) {
+                    spent.push((utxo, txid));
+                }
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                if !is_watched(&output.script_pubkey) {
+                    continue;
+                }
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if self.unspent.contains_key(&outpoint) || self.is_spent(&outpoint) {
+                    continue;
+                }
+                let utxo = Utxo {
+                    outpoint,
+                    txout: output.clone(),
+                    height,
+                    block_hash,
+                };
+                self.unspent.insert(outpoint, utxo.clone());
+                created.push(utxo);
+            }
+        }
+        if !spent.is_empty() {
+            let utxos = spent.iter().map(|(utxo, _)| utxo.clone());
+            self.spent
+                .entry(block_hash)
+                .or_insert_with(|| (height, Vec::new()))
+                .1
+                .extend(utxos);
+        }
+        let spent_len = self.spent.len();
+        self.spent
+            .retain(|_, (spent_at, _)| spent_at.saturating_add(SPENT_DEPTH) > height);
+        self.changed |= !created.is_empty() || !spent.is_empty() || spent_len != self.spent.len();
+        (created, spent)
+    }
+
+    fn is_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent
+            .values()
+            .any(|(_, utxos)| utxos.iter().any(|utxo| utxo.outpoint.eq(outpoint)))
+    }
+
+    // Remove the outputs created in blocks that are no longer in the chain of most work, and
+    // restore the outputs those blocks spent, returning the outputs that were removed and those
+    // that are unspent again
+    pub(crate) fn disconnect(&mut self, removed: &[BlockHash]) -> (Vec<Utxo>, Vec<Utxo>) {
+        let mut removed_utxos = Vec::new();
+        self.unspent.retain(|_, utxo| {
+            let keep = !removed.contains(&utxo.block_hash);
+            if !keep {
+                removed_utxos.push(utxo.clone());
+            }
+            keep
+        });
+        let mut unspent = Vec::new();
+        for block_hash in removed {
+            if let Some((_, utxos)) = self.spent.remove(block_hash) {
+                for utxo in utxos {
+                    if !removed.contains(&utxo.block_hash) {
+                        self.unspent.insert(utxo.outpoint, utxo.clone());
+                        unspent.push(utxo);
+                    }
+                }
+            }
+        }
+        self.changed |= !removed_utxos.is_empty() || !unspent.is_empty();
+        (removed_utxos, unspent)
+    }
+
+    // The unspent and recently spent outputs, if the set changed since the last call, to be
+    // written to the header store
+    pub(crate) fn take_changed(&mut self) -> Option<Vec<PersistedUtxo>> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        let unspent = self
+            .unspent
+            .values()
+            .map(|utxo| PersistedUtxo::new(utxo.clone(), None));
+        let spent = self.spent.iter().flat_map(|(block_hash, (height, utxos))| {
+            let block = HeaderCheckpoint::new(*height, *block_hash);
+            utxos
+                .iter()
+                .map(move |utxo| PersistedUtxo::new(utxo.clone(), Some(block)))
+        });
+        Some(unspent.chain(spent).collect())
+    }
+
+    // The unspent outputs, ordered by height
+    pub(crate) fn list(&self) -> Vec<Utxo> {
+        let mut utxos = self.unspent.values().cloned().collect::<Vec<Utxo>>();
+        utxos.sort_by_key(|utxo| (utxo.height, utxo.outpoint));
+        utxos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, constants::genesis_block, transaction::Version, Amount, Network,
+        Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_utxos_follow_reorganizations() {
+        let funding = genesis_block(Network::Regtest);
+        let ours = funding.txdata[0].output[0].script_pubkey.clone();
+        let outpoint = OutPoint::new(funding.txdata[0].compute_txid(), 0);
+        let mut utxos = UtxoSet::new();
+        let (created, spent) = utxos.scan(0, &funding, |script| script.eq(&ours));
+        assert_eq!(created.len(), 1);
+        assert!(spent.is_empty());
+        // Scanning the block again does not create the output twice
+        assert!(utxos
+            .scan(0, &funding, |script| script.eq(&ours))
+            .0
+            .is_empty());
+        let mut spending = funding.clone();
+        spending.header.nonce += 1;
+        spending.txdata = vec![Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }];
+        let (created, spent) = utxos.scan(1, &spending, |script| script.eq(&ours));
+        assert!(created.is_empty());
+        assert_eq!(spent.len(), 1);
+        assert_eq!(spent[0].0.outpoint, outpoint);
+        assert_eq!(spent[0].1, spending.txdata[0].compute_txid());
+        assert!(utxos.list().is_empty());
+        // The spent output is persisted with its spending block, and may be reloaded
+        let persisted = utxos.take_changed().unwrap();
+        assert!(utxos.take_changed().is_none());
+        let mut reloaded = UtxoSet::new();
+        reloaded.load(persisted);
+        assert!(reloaded.list().is_empty());
+        assert_eq!(reloaded.disconnect(&[spending.block_hash()]).1.len(), 1);
+        // A spent output is not created again by a rescan
+        assert!(utxos
+            .scan(0, &funding, |script| script.eq(&ours))
+            .0
+            .is_empty());
+        // The output is unspent if the spending block is reorganized out of the chain
+        let (removed, unspent) = utxos.disconnect(&[spending.block_hash()]);
+        assert!(removed.is_empty());
+        assert_eq!(unspent[0].outpoint, outpoint);
+        assert_eq!(utxos.list().len(), 1);
+        assert_eq!(utxos.list()[0].outpoint, outpoint);
+        let (removed, unspent) = utxos.disconnect(&[funding.block_hash()]);
+        assert_eq!(removed[0].outpoint, outpoint);
+        assert!(unspent.is_empty());
+        assert!(utxos.list().is_empty());
+        assert!(utxos.take_changed().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bitcoin::{absolute, block::Header, relative, BlockHash, Sequence, Transaction};
+
+use crate::db::BlockHeaderChanges;
+use crate::prelude::{Median, MEDIAN_TIME_PAST};
+
+use super::IndexedHeader;
+
+/// A read-only view of the chain of most work, shared with a running node.
+///
+/// Queries are answered from headers the node keeps in memory, without sending a message to the
+/// node, so a [`ChainView`] may be read from many application threads at once. Reads do not wait
+/// on the node to sync, and the view is updated as the node connects blocks and follows
+/// reorganizations. Cloning a view only copies a handle to the same headers.
+///
+/// ```
+/// fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+/// assert_shareable::<kyoto::ChainView>();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChainView {
+    inner: Arc<RwLock<Snapshot>>,
+}
+
+#[derive(Debug, Default)]
+struct Snapshot {
+    headers: BTreeMap<u32, Header>,
+    heights: HashMap<BlockHash, u32>,
+}
+
+impl Snapshot {
+    fn insert(&mut self, indexed_header: IndexedHeader) {
+        if let Some(replaced) = self
+            .headers
+            .insert(indexed_header.height, indexed_header.header)
+        {
+            self.heights.remove(&replaced.block_hash());
+        }
+        self.heights
+            .insert(indexed_header.header.block_hash(), indexed_header.height);
+    }
+}
+
+impl ChainView {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // A poisoned lock only means a writer panicked, and the headers remain usable
+    fn read(&self) -> RwLockReadGuard<'_, Snapshot> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Snapshot> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Replace the headers in the view, such as after loading headers from the header store
+    pub(crate) fn reset(&self, headers: impl IntoIterator<Item = IndexedHeader>) {
+        let mut snapshot = Snapshot::default();
+        for indexed_header in headers {
+            snapshot.insert(indexed_header);
+        }
+        *self.write() = snapshot;
+    }
+
+    pub(crate) fn apply(&self, changes: &BlockHeaderChanges) {
+        let mut snapshot = self.write();
+        match changes {
+            BlockHeaderChanges::Connected(indexed_header) => snapshot.insert(*indexed_header),
+            BlockHeaderChanges::Reorganized {
+                accepted,
+                reorganized,
+            } => {
+                for indexed_header in reorganized {
+                    if let Some(height) =
+                        snapshot.heights.remove(&indexed_header.header.block_hash())
+                    {
+                        snapshot.headers.remove(&height);
+                    }
+                }
+                for indexed_header in accepted {
+                    snapshot.insert(*indexed_header);
+                }
+            }
+        }
+    }
+
+    /// The tip of the chain of most work, if the node has loaded any headers.
+    pub fn tip(&self) -> Option<IndexedHeader> {
+        self.read()
+            .headers
+            .iter()
+            .next_back()
+            .map(|(height, header)| IndexedHeader::new(*height, *header))
+    }
+
+    /// The height of the tip of the chain of most work, if the node has loaded any headers.
+    pub fn height(&self) -> Option<u32> {
+        self.read().headers.keys().next_back().copied()
+    }
+
+    /// The header at a height in the chain of most work. Headers below the checkpoint the node
+    /// started from are not in the view.
+    pub fn header_at(&self, height: u32) -> Option<Header> {
+        self.read().headers.get(&height).copied()
+    }
+
+    /// The header and height of a block in the chain of most work, by the hash of the block.
+    pub fn header_by_hash(&self, hash: &BlockHash) -> Option<IndexedHeader> {
+        let snapshot = self.read();
+        let height = snapshot.heights.get(hash)?;
+        let header = snapshot.headers.get(height)?;
+        Some(IndexedHeader::new(*height, *header))
+    }
+
+    /// The height of a block in the chain of most work, by the hash of the block.
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.read().heights.get(hash).copied()
+    }
+
+    /// The median time past of the block at a height, the median timestamp of the block and the
+    /// ten blocks before it. Returns `None` if any of these blocks are not in the view.
+    pub fn median_time_past(&self, height: u32) -> Option<u32> {
+        let snapshot = self.read();
+        let start = height.saturating_sub(MEDIAN_TIME_PAST as u32 - 1);
+        let mut times = Vec::with_capacity(MEDIAN_TIME_PAST);
+        for height in start..=height {
+            times.push(snapshot.headers.get(&height)?.time);
+        }
+        Some(times.median())
+    }
+
+    /// Whether the locktime of the transaction allows it to be included in the next block. A
+    /// locktime in blocks is compared to the height of the next block, and a locktime in seconds
+    /// to the median time past of the tip, following
+    /// [BIP-113](https://github.com/bitcoin/bips/blob/master/bip-0113.mediawiki).
+    ///
+    /// Returns `None` if the view does not have the headers to evaluate the locktime.
+    pub fn is_final(&self, tx: &Transaction) -> Option<bool> {
+        if !tx.is_lock_time_enabled() {
+            return Some(true);
+        }
+        let tip = self.height()?;
+        match tx.lock_time {
+            absolute::LockTime::Blocks(height) => {
+                Some(height.to_consensus_u32() < tip.saturating_add(1))
+            }
+            absolute::LockTime::Seconds(time) => {
+                let median_time_past = self.median_time_past(tip)?;
+                Some(time.to_consensus_u32() < median_time_past)
+            }
+        }
+    }
+
+    /// The number of blocks to wait before an input with this sequence may spend an output
+    /// confirmed at a height, following the relative locktimes of
+    /// [BIP-68](https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki). Zero means the
+    /// input may be included in the next block. Relative locktimes are only enforced for
+    /// transactions with a version of two or greater.
+    ///
+    /// A locktime in units of 512 seconds is measured with the median time past, which does not
+    /// advance by a fixed time each block, so `None` is returned while such a lock is not
+    /// satisfied. `None` is also returned if the view does not have the headers to evaluate the
+    /// locktime.
+    pub fn blocks_until_spendable(&self, confirmed_at: u32, sequence: Sequence) -> Option<u32> {
+        let tip = self.height()?;
+        match sequence.to_relative_lock_time() {
+            None => Some(0),
+            Some(relative::LockTime::Blocks(blocks)) => {
+                let spendable_at = confirmed_at.saturating_add(blocks.value().into());
+                Some(spendable_at.saturating_sub(tip.saturating_add(1)))
+            }
+            Some(relative::LockTime::Time(time)) => {
+                // Measured from the median time past of the block before the output was confirmed
+                let confirmed_time = self.median_time_past(confirmed_at.saturating_sub(1))?;
+                let median_time_past = self.median_time_past(tip)?;
+                let spendable_time = confirmed_time.saturating_add(time.value() as u32 * 512);
+                (spendable_time <= median_time_past).then_some(0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, Network};
+
+    use super::*;
+
+    fn chain_of(len: u32) -> Vec<IndexedHeader> {
+        let mut header = genesis_block(Network::Regtest).header;
+        let mut headers = vec![IndexedHeader::new(0, header)];
+        for height in 1..len {
+            header.prev_blockhash = header.block_hash();
+            header.time = 100 * height;
+            headers.push(IndexedHeader::new(height, header));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_view_follows_reorganizations() {
+        let view = ChainView::new();
+        assert!(view.tip().is_none());
+        let headers = chain_of(12);
+        view.reset(headers.clone());
+        // The view is shared by its clones
+        let reader = view.clone();
+        assert_eq!(reader.height(), Some(11));
+        assert_eq!(reader.header_at(5), Some(headers[5].header));
+        let hash = headers[5].header.block_hash();
+        assert_eq!(reader.height_of(&hash), Some(5));
+        assert_eq!(reader.header_by_hash(&hash), Some(headers[5]));
+        // The timestamps of heights 1 through 11, with a median at height 6
+        assert_eq!(reader.median_time_past(11), Some(600));
+        assert!(reader.median_time_past(12).is_none());
+        let mut fork = headers[11];
+        fork.header.nonce += 1;
+        view.apply(&BlockHeaderChanges::Reorganized {
+            accepted: vec![fork],
+            reorganized: vec![headers[11]],
+        });
+        assert_eq!(reader.height_of(&headers[11].header.block_hash()), None);
+        assert_eq!(reader.tip(), Some(fork));
+        let mut next = fork;
+        next.height = 12;
+        next.header.prev_blockhash = fork.header.block_hash();
+        view.apply(&BlockHeaderChanges::Connected(next));
+        assert_eq!(reader.height(), Some(12));
+    }
+
+    #[test]
+    fn test_locktimes_follow_the_tip() {
+        use bitcoin::{transaction::Version, Amount, OutPoint, ScriptBuf, TxIn, TxOut, Witness};
+
+        let view = ChainView::new();
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::from_consensus(20),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        assert!(view.is_final(&tx).is_none());
+        view.reset(chain_of(20));
+        // The next block is at height 20
+        assert_eq!(view.is_final(&tx), Some(false));
+        tx.lock_time = absolute::LockTime::from_consensus(19);
+        assert_eq!(view.is_final(&tx), Some(true));
+        tx.input[0].sequence = Sequence::MAX;
+        tx.lock_time = absolute::LockTime::from_consensus(100);
+        assert_eq!(view.is_final(&tx), Some(true));
+        assert_eq!(view.blocks_until_spendable(18, Sequence::MAX), Some(0));
+        assert_eq!(
+            view.blocks_until_spendable(18, Sequence::from_height(3)),
+            Some(1)
+        );
+        assert_eq!(
+            view.blocks_until_spendable(17, Sequence::from_height(3)),
+            Some(0)
+        );
+        // The median time past at the tip is 1400, and 800 at height 13
+        let one_interval = Sequence::from_512_second_intervals(1);
+        assert_eq!(view.blocks_until_spendable(14, one_interval), Some(0));
+        assert_eq!(view.blocks_until_spendable(16, one_interval), None);
+    }
+}
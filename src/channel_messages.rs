@@ -2,6 +2,7 @@ use bitcoin::{
     block::Header,
     p2p::{
         address::AddrV2,
+        message_blockdata::Inventory,
         message_filter::{CFHeaders, CFilter, GetCFHeaders, GetCFilters},
         message_network::VersionMessage,
         ServiceFlags,
@@ -9,19 +10,25 @@ use bitcoin::{
     Block, BlockHash, FeeRate, Transaction, Wtxid,
 };
 
-use crate::{messages::RejectPayload, network::PeerId};
+use crate::{
+    messages::{Misbehavior, RejectPayload},
+    network::PeerId,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) enum MainThreadMessage {
     GetAddr,
     GetAddrV2,
     WtxidRelay,
+    SendHeaders,
+    SendCompactBlocks,
     GetHeaders(GetHeaderConfig),
     GetFilterHeaders(GetCFHeaders),
     GetFilters(GetCFilters),
     GetBlock(GetBlockConfig),
     Disconnect,
     BroadcastTx(Transaction),
+    GetTransactions(Vec<Inventory>),
     Verack,
 }
 
@@ -51,6 +58,9 @@ pub(crate) enum PeerMessage {
     Block(Block),
     NewBlocks(Vec<BlockHash>),
     FeeFilter(FeeRate),
+    TxAnnounced(Vec<Inventory>),
+    Transaction(Transaction),
+    Misbehaved(Misbehavior),
 }
 
 #[derive(Debug)]
@@ -62,6 +72,8 @@ pub(crate) enum ReaderMessage {
     Filter(CFilter),
     Block(Block),
     NewBlocks(Vec<BlockHash>),
+    TxAnnounced(Vec<Inventory>),
+    Transaction(Transaction),
     Reject(RejectPayload),
     Disconnect,
     Verack,
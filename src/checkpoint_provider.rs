@@ -0,0 +1,34 @@
+use std::fmt::Debug;
+
+use bitcoin::Network;
+
+use crate::HeaderCheckpoint;
+
+/// Supply checkpoints in addition to those compiled into the crate, such as from a configuration
+/// file or a trusted server, so an application may follow the chain without upgrading the crate.
+///
+/// The node asks for checkpoints when it is built, and again if a peer advertises a height far
+/// past the newest checkpoint. Checkpoints at or below the newest known checkpoint are ignored.
+/// The chain of most work must include every checkpoint, so checkpoints should only be provided
+/// from a trusted source.
+///
+/// A provider may be a closure over the network.
+pub trait CheckpointProvider: Send + Sync {
+    /// Checkpoints for the `network`, in any order.
+    fn checkpoints(&mut self, network: Network) -> Vec<HeaderCheckpoint>;
+}
+
+impl<F> CheckpointProvider for F
+where
+    F: FnMut(Network) -> Vec<HeaderCheckpoint> + Send + Sync,
+{
+    fn checkpoints(&mut self, network: Network) -> Vec<HeaderCheckpoint> {
+        self(network)
+    }
+}
+
+impl Debug for dyn CheckpointProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CheckpointProvider")
+    }
+}
@@ -1,22 +1,38 @@
-#[cfg(feature = "filter-control")]
+use bitcoin::p2p::address::AddrV2;
 use bitcoin::BlockHash;
-#[cfg(not(feature = "filter-control"))]
-use bitcoin::ScriptBuf;
-use bitcoin::Transaction;
 use bitcoin::{block::Header, FeeRate};
-use std::{collections::BTreeMap, ops::Range, time::Duration};
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::{Address, OutPoint};
+use bitcoin::{ScriptBuf, Transaction, Txid};
+#[cfg(not(feature = "filter-control"))]
+use std::collections::HashSet;
+use std::{collections::BTreeMap, ops::Range, path::Path, sync::Arc, time::Duration};
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
 
-use crate::{Event, Info, TrustedPeer, TxBroadcast, Warning};
+use crate::{
+    chain::IndexedHeader, config::ConfigDelta, dialog::Dialog, export, BlockStream, ChainView,
+    ConnectivityCheck, Event, HeaderAttestation, HeaderExportFormat, IndexedBlock, Info,
+    PeerDiversity, PeerInfo, PeerState, PendingRequest, ScanStats, ScriptShardStats, ScriptTx,
+    ShutdownReport, StateTransition, SyncReport, TrustedPeer, TxBroadcast, Utxo, Warning,
+};
 
-#[cfg(feature = "filter-control")]
-use super::{error::FetchBlockError, messages::BlockRequest, BlockReceiver, IndexedBlock};
+#[cfg(not(feature = "filter-control"))]
+use super::{error::AddAddressError, messages::AddressRequest};
+use super::{error::FetchBlockError, messages::BlockRequest, BlockReceiver};
 use super::{
-    error::{ClientError, FetchFeeRateError, FetchHeaderError},
-    messages::{BatchHeaderRequest, ClientMessage, HeaderRequest},
+    error::{
+        ClientError, ExportHeadersError, FetchFeeRateError, FetchHeaderError, UpdateConfigError,
+    },
+    messages::{
+        AttestationRequest, BatchHeaderRequest, ClientMessage, ConfigRequest, ConfirmationTrigger,
+        HashHeaderRequest, HeaderRequest, HeightTrigger, ScriptHistoryRequest,
+    },
 };
 
+const BLOCK_STREAM_CAPACITY: usize = 10;
+
 /// A [`Client`] allows for communication with a running node.
 #[derive(Debug)]
 pub struct Client {
@@ -50,7 +66,29 @@ impl Client {
     }
 }
 
+/// The ends of the channels to a [`Client`] held by a [`Node`](crate::Node).
+///
+/// After a node shuts down, the channels may be taken with
+/// [`Node::into_client_channels`](crate::Node::into_client_channels) and passed to
+/// [`NodeBuilder::rebuild`](crate::NodeBuilder::rebuild) to start another node for the same
+/// [`Client`]. The [`Requester`] and receivers of the client keep working across the restart, and
+/// messages sent with the [`Requester`] in between are handled once the new node is running.
+#[derive(Debug)]
+pub struct ClientChannels {
+    pub(crate) dialog: Dialog,
+    pub(crate) client_recv: Arc<Mutex<UnboundedReceiver<ClientMessage>>>,
+    pub(crate) view: ChainView,
+}
+
 /// Send messages to a node that is running so the node may complete a task.
+///
+/// A [`Requester`] is `Clone`, `Send`, and `Sync`, and cloning it only copies a handle to a
+/// channel, so it may be stored in the state of an application and shared between threads.
+///
+/// ```
+/// fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+/// assert_shareable::<kyoto::Requester>();
+/// ```
 #[derive(Debug, Clone)]
 pub struct Requester {
     ntx: UnboundedSender<ClientMessage>,
@@ -61,14 +99,30 @@ impl Requester {
         Self { ntx }
     }
 
-    /// Tell the node to shut down.
+    /// Tell the node to shut down. The node disconnects from its peers and writes any remaining
+    /// block headers to the header store before the [`ShutdownReport`] is returned, after which it
+    /// is safe to terminate the process.
+    ///
+    /// # Errors
+    ///
+    /// If the node has already stopped running.
+    pub async fn shutdown(&self) -> Result<ShutdownReport, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<ShutdownReport>();
+        self.ntx
+            .send(ClientMessage::Shutdown(Some(tx)))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Tell the node to shut down without waiting for it to stop, for callers that cannot await.
+    /// Use [`Requester::shutdown`] to know when it is safe to terminate the process.
     ///
     /// # Errors
     ///
     /// If the node has already stopped running.
-    pub fn shutdown(&self) -> Result<(), ClientError> {
+    pub fn try_shutdown(&self) -> Result<(), ClientError> {
         self.ntx
-            .send(ClientMessage::Shutdown)
+            .send(ClientMessage::Shutdown(None))
             .map_err(|_| ClientError::SendError)
     }
 
@@ -83,6 +137,9 @@ impl Requester {
     ///
     /// For more information, see BIP-431 and BIP-331.
     ///
+    /// The transaction is written to the [`HeaderStore`](crate::HeaderStore) until it is found in
+    /// a block downloaded by the node, and is broadcast again if the node restarts before then.
+    ///
     /// # Errors
     ///
     /// If the node has stopped running.
@@ -134,6 +191,76 @@ impl Requester {
             .map_err(|_| ClientError::SendError)
     }
 
+    /// Stop watching for Bitcoin [`ScriptBuf`], such as the scripts of addresses that were swept.
+    /// Filters that are checked after the scripts are removed no longer match them, but blocks
+    /// already queued for download are still sent. Scripts the node was not watching are ignored.
+    /// The node overwrites the buffers of the removed scripts with zeros before freeing them, and
+    /// does the same for every watched script when it shuts down. This is best effort: copies
+    /// the node made earlier, such as when a collection of scripts grew, are not overwritten.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn remove_scripts(
+        &self,
+        scripts: impl IntoIterator<Item = impl Into<ScriptBuf>>,
+    ) -> Result<(), ClientError> {
+        let scripts = scripts.into_iter().map(Into::into).collect();
+        self.ntx
+            .send(ClientMessage::RemoveScripts(scripts))
+            .map_err(|_| ClientError::SendError)
+    }
+
+    /// Watch for the spends of Bitcoin [`OutPoint`], such as the coins of a wallet. The node sends
+    /// [`Event::OutpointSpent`](crate::Event::OutpointSpent) when a block spends an outpoint. Does
+    /// not rescan the filters.
+    ///
+    /// Filters commit to the scripts of the outputs a block spends, so the node must know the
+    /// script of an outpoint to find its spend. The script is known if the node indexes scripts
+    /// and the outpoint pays to an indexed script, or if the node scans the block that creates
+    /// the outpoint. Otherwise, watch the script the outpoint pays to as well.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    #[cfg(not(feature = "filter-control"))]
+    pub fn add_outpoints(&self, outpoints: HashSet<OutPoint>) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::AddOutpoints(outpoints))
+            .map_err(|_| ClientError::SendError)
+    }
+
+    /// Add the scripts of Bitcoin [`Address`] to watch for. Does not rescan the filters.
+    ///
+    /// # Errors
+    ///
+    /// If any address is not valid for the network of the node, in which case no addresses are
+    /// added, or if the node has stopped running.
+    #[cfg(not(feature = "filter-control"))]
+    pub async fn add_addresses(
+        &self,
+        addresses: impl IntoIterator<Item = Address>,
+    ) -> Result<(), AddAddressError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), AddAddressError>>();
+        let message = AddressRequest::new(tx, addresses.into_iter().collect());
+        self.ntx
+            .send(ClientMessage::AddAddresses(message))
+            .map_err(|_| AddAddressError::SendError)?;
+        rx.await.map_err(|_| AddAddressError::RecvError)?
+    }
+
+    /// Add the script of a Bitcoin [`Address`] to watch for. Does not rescan the filters.
+    ///
+    /// # Errors
+    ///
+    /// If the address is not valid for the network of the node, or if the node has stopped
+    /// running.
+    #[cfg(not(feature = "filter-control"))]
+    pub async fn add_address(&self, address: Address) -> Result<(), AddAddressError> {
+        self.add_addresses([address]).await
+    }
+
     /// Get a header at the specified height, if it exists.
     ///
     /// # Note
@@ -153,6 +280,36 @@ impl Requester {
         rx.await.map_err(|_| FetchHeaderError::RecvError)?
     }
 
+    /// Get the header of a block in the chain of most work by its hash, as blocks are referenced
+    /// by protocols such as the Lightning Network.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running, or the block is not in the chain of most work.
+    pub async fn get_header_by_hash(&self, hash: BlockHash) -> Result<Header, FetchHeaderError> {
+        let indexed_header = self.get_indexed_header(hash).await?;
+        Ok(indexed_header.header)
+    }
+
+    /// Get the height of a block in the chain of most work by its hash.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running, or the block is not in the chain of most work.
+    pub async fn get_height(&self, hash: BlockHash) -> Result<u32, FetchHeaderError> {
+        let indexed_header = self.get_indexed_header(hash).await?;
+        Ok(indexed_header.height)
+    }
+
+    async fn get_indexed_header(&self, hash: BlockHash) -> Result<IndexedHeader, FetchHeaderError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Result<IndexedHeader, FetchHeaderError>>();
+        let message = HashHeaderRequest::new(tx, hash);
+        self.ntx
+            .send(ClientMessage::GetHeaderByHash(message))
+            .map_err(|_| FetchHeaderError::SendError)?;
+        rx.await.map_err(|_| FetchHeaderError::RecvError)?
+    }
+
     /// Get a range of headers by the specified range.
     ///
     /// # Errors
@@ -171,14 +328,60 @@ impl Requester {
         rx.await.map_err(|_| FetchHeaderError::RecvError)?
     }
 
-    /// Request a block be fetched. Note that this method will request a block
-    /// from a connected peer's inventory, and may take an indefinite amount of
-    /// time, until a peer responds.
+    /// Write a range of headers to a file in the specified [`HeaderExportFormat`]. The height,
+    /// hash, time, difficulty bits and nonce of each header are exported.
     ///
     /// # Errors
     ///
-    /// If the node has stopped running.
-    #[cfg(feature = "filter-control")]
+    /// If the node has stopped running, the headers could not be loaded from the header store,
+    /// or the file could not be written.
+    pub async fn export_headers(
+        &self,
+        range: Range<u32>,
+        format: HeaderExportFormat,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ExportHeadersError> {
+        let headers = self.get_header_range(range).await?;
+        let path = path.as_ref().to_path_buf();
+        // Rendering and writing a long range of headers would otherwise block the runtime
+        tokio::task::spawn_blocking(move || std::fs::write(path, export::render(&headers, format)))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??;
+        Ok(())
+    }
+
+    /// Attest to a block header in the chain of most work, with its height, the work of the chain
+    /// since a checkpoint, and the height of the tip. The attestation may be serialized with
+    /// [`HeaderAttestation::to_bytes`] to refer to the chain the node saw in a signed statement.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running, or if the header is not in the chain of most work the
+    /// node holds in memory, such as a header below the checkpoint the node started from.
+    pub async fn attest_header(
+        &self,
+        hash: BlockHash,
+    ) -> Result<HeaderAttestation, FetchHeaderError> {
+        let (tx, rx) =
+            tokio::sync::oneshot::channel::<Result<HeaderAttestation, FetchHeaderError>>();
+        self.ntx
+            .send(ClientMessage::AttestHeader(AttestationRequest::new(
+                tx, hash,
+            )))
+            .map_err(|_| FetchHeaderError::SendError)?;
+        rx.await.map_err(|_| FetchHeaderError::RecvError)?
+    }
+
+    /// Request any block in the chain of most work from connected peers, whether or not it
+    /// matched the scripts, for instance a block containing a transaction found out of band.
+    ///
+    /// Requested blocks are downloaded ahead of any blocks queued by filter matches,
+    /// including while the node is still syncing.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running, if the block is not in the chain of most work, or if no
+    /// peer served the block after it was requested five times.
     pub async fn get_block(&self, block_hash: BlockHash) -> Result<IndexedBlock, FetchBlockError> {
         let (tx, rx) = tokio::sync::oneshot::channel::<Result<IndexedBlock, FetchBlockError>>();
         let message = BlockRequest::new(tx, block_hash);
@@ -194,7 +397,6 @@ impl Requester {
     /// # Errors
     ///
     /// If the node has stopped running.
-    #[cfg(feature = "filter-control")]
     pub fn request_block(&self, block_hash: BlockHash) -> Result<BlockReceiver, FetchBlockError> {
         let (tx, rx) = tokio::sync::oneshot::channel::<Result<IndexedBlock, FetchBlockError>>();
         let message = BlockRequest::new(tx, block_hash);
@@ -204,6 +406,26 @@ impl Requester {
         Ok(rx)
     }
 
+    /// Receive blocks that match the scripts over a dedicated [`BlockStream`] instead of as an
+    /// [`Event::Block`]. The stream holds a limited number of blocks, and the node will wait to
+    /// download more blocks until the stream has room. Blocks that were already downloaded wait
+    /// for the stream rather than being sent as events. This allows expensive block processing to
+    /// happen at its own pace without blocking the event channel.
+    ///
+    /// If the stream is dropped, blocks are sent as events again. Calling this method again replaces
+    /// the previous stream.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub fn block_stream(&self) -> Result<BlockStream, ClientError> {
+        let (tx, rx) = mpsc::channel::<IndexedBlock>(BLOCK_STREAM_CAPACITY);
+        self.ntx
+            .send(ClientMessage::BlockStream(tx))
+            .map_err(|_| ClientError::SendError)?;
+        Ok(BlockStream::new(rx))
+    }
+
     /// Starting after the configured checkpoint, look for block inclusions with newly added scripts.
     ///
     /// # Errors
@@ -215,6 +437,296 @@ impl Requester {
             .map_err(|_| ClientError::SendError)
     }
 
+    /// Stop a rescan that is in progress. Filters that have not yet been checked for the rescan are
+    /// skipped, and blocks that have not yet been downloaded for the rescan are dropped. The node
+    /// will emit [`Info::RescanCancelled`] and return to following the tip of the chain.
+    ///
+    /// If no rescan is in progress, this has no effect.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub fn cancel_rescan(&self) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::CancelRescan)
+            .map_err(|_| ClientError::SendError)
+    }
+
+    /// Reclaim unused space in the header and peer stores, and remove peers beyond the size
+    /// configured with [`NodeBuilder::peer_db_size`](crate::NodeBuilder::peer_db_size).
+    /// Compaction waits until the node is synced to the tip of the chain, after which the node
+    /// will emit [`Info::DatabaseCompacted`].
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub fn compact_database(&self) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::CompactDatabase)
+            .map_err(|_| ClientError::SendError)
+    }
+
+    /// Get the time and bandwidth spent in each phase of syncing. This may be used to
+    /// benchmark changes to the node configuration.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn sync_report(&self) -> Result<SyncReport, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<SyncReport>();
+        self.ntx
+            .send(ClientMessage::GetSyncReport(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the most recent changes in [`NodeState`](crate::NodeState), oldest first, with the
+    /// time each state was entered. This is useful to diagnose which step of the sync is stalled.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn state_history(&self) -> Result<Vec<StateTransition>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<StateTransition>>();
+        self.ntx
+            .send(ClientMessage::GetStateHistory(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the protocol version, services, user agent, and starting height advertised by each
+    /// connected peer. This is useful to find peers that claim to serve compact block filters but
+    /// fail to respond to requests.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn connected_peers(&self) -> Result<Vec<PeerInfo>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<PeerInfo>>();
+        self.ntx
+            .send(ClientMessage::GetConnectedPeers(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the requests sent to peers that have not been answered yet, with the peer and the time
+    /// since each request was sent. When the sync appears stuck, this shows exactly what the node
+    /// is waiting on.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn pending_requests(&self) -> Result<Vec<PendingRequest>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<PendingRequest>>();
+        self.ntx
+            .send(ClientMessage::GetPendingRequests(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get what each connected peer is doing, such as completing the handshake, serving headers
+    /// or filters, or waiting idle. Changes are also sent as [`Info::PeerState`].
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn peer_states(&self) -> Result<Vec<PeerState>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<PeerState>>();
+        self.ntx
+            .send(ClientMessage::GetPeerStates(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get a summary of the connected peers by transport, network, netgroup and software, to
+    /// check the node is not concentrated on one provider or implementation.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn peer_diversity(&self) -> Result<PeerDiversity, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<PeerDiversity>();
+        self.ntx
+            .send(ClientMessage::GetPeerDiversity(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get statistics for each shard of the scripts that compact block filters are checked for,
+    /// including the number of scripts, filters checked, matches, and time spent matching.
+    /// There is a single shard unless the scripts were sharded when building the node.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn script_shard_stats(&self) -> Result<Vec<ScriptShardStats>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<ScriptShardStats>>();
+        self.ntx
+            .send(ClientMessage::GetScriptShardStats(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the offset of the network-adjusted time from the local clock in seconds, which is the
+    /// median offset of the clocks reported by peers when they connected. A positive offset means
+    /// the peers are ahead of the local clock. The offset is zero until five peers have connected,
+    /// or if the peers are more than seventy minutes from the local clock, in which case
+    /// [`Warning::ClockSkew`](crate::Warning::ClockSkew) is sent.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn time_offset(&self) -> Result<i64, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<i64>();
+        self.ntx
+            .send(ClientMessage::GetTimeOffset(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Open test connections to a few peers with the transport the node was built with, and
+    /// report if each peer was reached and how long the connection took to open. Connected and
+    /// configured peers are tested first, then a peer from the database. An empty list means the
+    /// node has no peer to test, for instance before the first peers are found over DNS.
+    ///
+    /// Connections are opened with the same timeout as connections to peers, and are closed
+    /// before any message is sent.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn test_connectivity(&self) -> Result<Vec<ConnectivityCheck>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<ConnectivityCheck>>();
+        self.ntx
+            .send(ClientMessage::TestConnectivity(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the number of filters scanned and matched, and the blocks downloaded, since the node
+    /// started. Blocks downloaded for a filter match are split into true and false positives, which
+    /// may be used to tune the scripts of the node and estimate the bandwidth spent on blocks with
+    /// no relevant transaction.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn scan_stats(&self) -> Result<ScanStats, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<ScanStats>();
+        self.ntx
+            .send(ClientMessage::GetScanStats(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the transactions that paid to or spent from a script, ordered by height, over every
+    /// block the node has scanned. The history is empty unless the node was built with
+    /// [`NodeBuilder::index_scripts`](crate::NodeBuilder::index_scripts).
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn history_for_script(
+        &self,
+        script: impl Into<ScriptBuf>,
+    ) -> Result<Vec<ScriptTx>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<ScriptTx>>();
+        let message = ScriptHistoryRequest::new(tx, script.into());
+        self.ntx
+            .send(ClientMessage::GetScriptHistory(message))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get the unspent outputs that pay to the scripts of the node, ordered by height. The set is
+    /// empty unless the node was built with
+    /// [`NodeBuilder::track_utxos`](crate::NodeBuilder::track_utxos).
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn list_unspent(&self) -> Result<Vec<Utxo>, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<Utxo>>();
+        self.ntx
+            .send(ClientMessage::ListUnspent(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Get a read-only [`ChainView`] of the chain of most work. Once the view is returned, headers,
+    /// heights, and the median time past are read from it without sending a message to the node,
+    /// so the view may be queried often and from many threads. The view remains updated if the
+    /// node is rebuilt with [`NodeBuilder::rebuild`](crate::NodeBuilder::rebuild).
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn chain_view(&self) -> Result<ChainView, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<ChainView>();
+        self.ntx
+            .send(ClientMessage::ChainView(tx))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Wait for the chain of most work to reach a height, for instance the height a timelock
+    /// expires. The tip of the chain is returned once it is at or above the height, which is
+    /// immediately if the height was already reached.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn notify_at_height(&self, height: u32) -> Result<IndexedHeader, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<IndexedHeader>();
+        self.ntx
+            .send(ClientMessage::NotifyAtHeight(HeightTrigger::new(
+                tx, height,
+            )))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Wait for a transaction to be buried under a number of blocks, counting the block that
+    /// includes it as the first confirmation. The header of the block that includes the
+    /// transaction is returned.
+    ///
+    /// The transaction is found in the blocks the node downloads, which are the blocks that match
+    /// the scripts of the node and the blocks requested with
+    /// [`Requester::get_block`]. With [`NodeBuilder::index_scripts`](crate::NodeBuilder::index_scripts),
+    /// transactions found before this call are known as well. If the block that includes the
+    /// transaction is reorganized out of the chain, the count starts again once the transaction is
+    /// found in another block.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub async fn notify_confirmations(
+        &self,
+        txid: Txid,
+        confirmations: u32,
+    ) -> Result<IndexedHeader, ClientError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<IndexedHeader>();
+        let trigger = ConfirmationTrigger::new(tx, txid, confirmations);
+        self.ntx
+            .send(ClientMessage::NotifyConfirmations(trigger))
+            .map_err(|_| ClientError::SendError)?;
+        rx.await.map_err(|_| ClientError::RecvError)
+    }
+
+    /// Send the events of a [`SimulatedReorg`](crate::SimulatedReorg) to the client, in the order
+    /// the node sends them for a reorganization found over the network. The chain of the node is
+    /// not changed, so this is only meant to test how an application handles reorganizations.
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    #[cfg(feature = "testing")]
+    pub fn simulate_reorg(&self, reorg: crate::SimulatedReorg) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::SimulateReorg(reorg))
+            .map_err(|_| ClientError::SendError)
+    }
+
     /// Set a new connection timeout for peers to respond to messages.
     ///
     /// # Errors
@@ -226,6 +738,37 @@ impl Requester {
             .map_err(|_| ClientError::SendError)
     }
 
+    /// Change a subset of the configuration of the running node, such as timeouts, log levels
+    /// and the number of connections, without restarting it. See [`ConfigDelta`] for when each
+    /// setting takes effect.
+    ///
+    /// # Errors
+    ///
+    /// If more peers would be required to agree on the tip than the node maintains connections
+    /// to, in which case no changes are applied, or if the node has stopped running.
+    pub async fn update_config(&self, delta: ConfigDelta) -> Result<(), UpdateConfigError> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Result<(), UpdateConfigError>>();
+        let message = ConfigRequest::new(tx, delta);
+        self.ntx
+            .send(ClientMessage::UpdateConfig(message))
+            .map_err(|_| UpdateConfigError::SendError)?;
+        rx.await.map_err(|_| UpdateConfigError::RecvError)?
+    }
+
+    /// Acknowledge that the client finished processing the next `count` events, in the order they
+    /// were received. Events that are not acknowledged are sent again when the node starts, if it
+    /// was built with [`NodeBuilder::journal_events`](crate::NodeBuilder::journal_events).
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    #[cfg(feature = "journal")]
+    pub fn acknowledge_events(&self, count: usize) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::AcknowledgeEvents(count))
+            .map_err(|_| ClientError::SendError)
+    }
+
     /// Add another known peer to connect to.
     ///
     /// # Errors
@@ -237,6 +780,21 @@ impl Requester {
             .map_err(|_| ClientError::SendError)
     }
 
+    /// Disconnect from a peer and ban it, so the node does not connect to it again. The ban is
+    /// saved to the peer store, and the peer is removed from the configured peers.
+    ///
+    /// Peers are also banned by the node once they misbehave often enough, which is reported as a
+    /// [`Warning::PeerBanned`].
+    ///
+    /// # Errors
+    ///
+    /// If the node has stopped running.
+    pub fn ban_peer(&self, addr: AddrV2) -> Result<(), ClientError> {
+        self.ntx
+            .send(ClientMessage::BanPeer(addr))
+            .map_err(|_| ClientError::SendError)
+    }
+
     /// Check if the node is running.
     pub fn is_running(&self) -> bool {
         self.ntx.send(ClientMessage::NoOp).is_ok()
@@ -286,7 +844,18 @@ mod tests {
         ));
         assert!(broadcast.is_ok());
         drop(crx);
-        let broadcast = requester.shutdown();
+        let broadcast = requester.shutdown().await;
         assert!(broadcast.is_err());
+        assert!(requester.try_shutdown().is_err());
+    }
+
+    #[test]
+    fn test_requester_is_shareable() {
+        fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+        assert_shareable::<Requester>();
+        assert_eq!(
+            std::mem::size_of::<Requester>(),
+            std::mem::size_of::<UnboundedSender<ClientMessage>>()
+        );
     }
 }
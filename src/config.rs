@@ -1,26 +1,161 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
 
 use bitcoin::ScriptBuf;
 
+#[cfg(feature = "rest")]
+use crate::block_source::BlockSource;
+#[cfg(feature = "journal")]
+use crate::journal::EventJournal;
 use crate::{
     chain::checkpoints::HeaderCheckpoint,
-    network::{dns::DnsResolver, ConnectionType},
-    LogLevel, PeerStoreSizeConfig, PeerTimeoutConfig, TrustedPeer,
+    checkpoint_provider::CheckpointProvider,
+    db::traits::{DynCFHeaderStore, DynFilterStore},
+    network::{dns::DnsResolver, ConnectionType, STALL_TIMEOUT_SECS, TIP_POLL_INTERVAL_SECS},
+    peer_selector::{PeerSelector, RandomPeerSelector},
+    EventMask, LogLevel, MissingFiltersPolicy, PeerStoreSizeConfig, PeerTimeoutConfig, Subsystem,
+    TrustedPeer,
 };
+#[cfg(not(feature = "filter-control"))]
+use crate::{filter_matcher::FilterMatcher, XpubWatch};
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::OutPoint;
 
 const REQUIRED_PEERS: u8 = 1;
+pub(crate) const MAX_PEERS: u8 = 15;
+
+// More peers cannot agree on the tip than the node maintains connections to, so the sync would
+// never complete
+pub(crate) fn tip_agreement_reachable(tip_agreement: u8, required_peers: u8) -> bool {
+    tip_agreement <= required_peers.max(1)
+}
 
 pub(crate) struct NodeConfig {
     pub required_peers: u8,
     pub white_list: Vec<TrustedPeer>,
     pub dns_resolver: DnsResolver,
+    pub dns_seeds: Option<Vec<String>>,
     pub addresses: HashSet<ScriptBuf>,
+    pub script_shard_size: Option<usize>,
     pub data_path: Option<PathBuf>,
     pub header_checkpoint: Option<HeaderCheckpoint>,
+    pub checkpoint_provider: Option<Box<dyn CheckpointProvider>>,
+    pub reorg_depth: Option<u32>,
     pub connection_type: ConnectionType,
+    #[cfg(feature = "i2p")]
+    pub i2p_sam: Option<std::net::SocketAddr>,
     pub target_peer_size: PeerStoreSizeConfig,
     pub peer_timeout_config: PeerTimeoutConfig,
     pub log_level: LogLevel,
+    pub subsystem_log_levels: HashMap<Subsystem, LogLevel>,
+    pub log_sensitive_data: bool,
+    #[cfg(feature = "rest")]
+    pub block_source: Option<Box<dyn BlockSource>>,
+    pub filter_store: Option<Box<dyn DynFilterStore>>,
+    pub cf_header_store: Option<Box<dyn DynCFHeaderStore>>,
+    pub peer_selector: Box<dyn PeerSelector>,
+    #[cfg(not(feature = "filter-control"))]
+    pub filter_matcher: Option<Box<dyn FilterMatcher>>,
+    #[cfg(not(feature = "filter-control"))]
+    pub xpub_watch: Option<XpubWatch>,
+    #[cfg(not(feature = "filter-control"))]
+    pub exclude_broad_scripts: bool,
+    #[cfg(not(feature = "filter-control"))]
+    pub filter_matches_only: bool,
+    #[cfg(not(feature = "filter-control"))]
+    pub outpoints: HashSet<OutPoint>,
+    pub trust_checkpoints: bool,
+    pub header_time_drift: Option<Duration>,
+    pub tip_poll_interval: Duration,
+    pub compact_block_announcements: bool,
+    pub max_peer_lag: Option<u32>,
+    pub tip_agreement: u8,
+    pub stall_timeout: Duration,
+    pub missing_filters: MissingFiltersPolicy,
+    pub headers_only: bool,
+    pub monitor_mempool: bool,
+    pub block_workers: usize,
+    pub event_mask: EventMask,
+    pub index_scripts: bool,
+    pub track_utxos: bool,
+    pub detect_birthday: bool,
+    pub filter_spot_checks: u32,
+    #[cfg(feature = "journal")]
+    pub journal_events: bool,
+    #[cfg(feature = "journal")]
+    pub event_journal: Option<EventJournal>,
+}
+
+/// Changes to the configuration of a running node, applied with
+/// [`Requester::update_config`](crate::Requester::update_config). Settings that are not set are
+/// left as they are.
+///
+/// Timeouts apply to connections opened after the change, so existing peers keep the timeouts
+/// they were connected with until they are replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDelta {
+    pub(crate) response_timeout: Option<Duration>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) max_connection_time: Option<Duration>,
+    pub(crate) ping_interval: Option<Duration>,
+    pub(crate) log_level: Option<LogLevel>,
+    pub(crate) subsystem_log_levels: HashMap<Subsystem, LogLevel>,
+    pub(crate) required_peers: Option<u8>,
+}
+
+impl ConfigDelta {
+    /// An empty set of changes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the time a peer has to respond to a message from the local node.
+    pub fn response_timeout(mut self, response_timeout: impl Into<Duration>) -> Self {
+        self.response_timeout = Some(response_timeout.into());
+        self
+    }
+
+    /// Set the time a peer has to complete the initial TCP handshake.
+    pub fn handshake_timeout(mut self, handshake_timeout: impl Into<Duration>) -> Self {
+        self.handshake_timeout = Some(handshake_timeout.into());
+        self
+    }
+
+    /// Set the maximum time a connection with a remote peer is maintained.
+    pub fn maximum_connection_time(mut self, max_connection_time: impl Into<Duration>) -> Self {
+        self.max_connection_time = Some(max_connection_time.into());
+        self
+    }
+
+    /// Set how long a connected peer may go without sending a message before it is sent a ping.
+    pub fn ping_interval(mut self, ping_interval: impl Into<Duration>) -> Self {
+        self.ping_interval = Some(ping_interval.into());
+        self
+    }
+
+    /// Set the [`LogLevel`] of the node. Levels set for a single [`Subsystem`] still override
+    /// this level.
+    pub fn log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    /// Set the [`LogLevel`] of a single [`Subsystem`].
+    pub fn subsystem_log_level(mut self, subsystem: Subsystem, log_level: LogLevel) -> Self {
+        self.subsystem_log_levels.insert(subsystem, log_level);
+        self
+    }
+
+    /// Set the number of peer connections the node maintains. The number of connections will be
+    /// clamped to a range of 1 to 15. Connections beyond the new requirement are not closed, but
+    /// are not replaced when they end.
+    pub fn required_peers(mut self, num_peers: u8) -> Self {
+        self.required_peers = Some(num_peers.clamp(1, MAX_PEERS));
+        self
+    }
 }
 
 impl Default for NodeConfig {
@@ -29,13 +164,58 @@ impl Default for NodeConfig {
             required_peers: REQUIRED_PEERS,
             white_list: Default::default(),
             dns_resolver: DnsResolver::default(),
+            dns_seeds: Default::default(),
             addresses: Default::default(),
+            script_shard_size: Default::default(),
             data_path: Default::default(),
             header_checkpoint: Default::default(),
+            checkpoint_provider: Default::default(),
+            reorg_depth: Default::default(),
             connection_type: Default::default(),
+            #[cfg(feature = "i2p")]
+            i2p_sam: Default::default(),
             target_peer_size: PeerStoreSizeConfig::default(),
             peer_timeout_config: PeerTimeoutConfig::default(),
             log_level: Default::default(),
+            subsystem_log_levels: Default::default(),
+            log_sensitive_data: Default::default(),
+            #[cfg(feature = "rest")]
+            block_source: Default::default(),
+            filter_store: Default::default(),
+            cf_header_store: Default::default(),
+            peer_selector: Box::new(RandomPeerSelector::new()),
+            #[cfg(not(feature = "filter-control"))]
+            filter_matcher: Default::default(),
+            #[cfg(not(feature = "filter-control"))]
+            xpub_watch: Default::default(),
+            #[cfg(not(feature = "filter-control"))]
+            exclude_broad_scripts: Default::default(),
+            #[cfg(not(feature = "filter-control"))]
+            filter_matches_only: Default::default(),
+            #[cfg(not(feature = "filter-control"))]
+            outpoints: Default::default(),
+            trust_checkpoints: Default::default(),
+            header_time_drift: Default::default(),
+            tip_poll_interval: Duration::from_secs(TIP_POLL_INTERVAL_SECS),
+            compact_block_announcements: Default::default(),
+            max_peer_lag: Default::default(),
+            tip_agreement: Default::default(),
+            stall_timeout: Duration::from_secs(STALL_TIMEOUT_SECS),
+            missing_filters: Default::default(),
+            headers_only: Default::default(),
+            monitor_mempool: Default::default(),
+            block_workers: std::thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(1),
+            event_mask: EventMask::default(),
+            index_scripts: Default::default(),
+            track_utxos: Default::default(),
+            detect_birthday: Default::default(),
+            filter_spot_checks: Default::default(),
+            #[cfg(feature = "journal")]
+            journal_events: Default::default(),
+            #[cfg(feature = "journal")]
+            event_journal: Default::default(),
         }
     }
 }
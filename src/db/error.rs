@@ -6,6 +6,8 @@ pub enum SqlInitializationError {
     IO(std::io::Error),
     /// An error occured performing a SQL operation.
     SQL(rusqlite::Error),
+    /// Another instance of the node is using the data directory.
+    Locked,
 }
 
 #[cfg(feature = "rusqlite")]
@@ -18,6 +20,12 @@ impl core::fmt::Display for SqlInitializationError {
             SqlInitializationError::SQL(e) => {
                 write!(f, "reading or writing from the database failed: {e}")
             }
+            SqlInitializationError::Locked => {
+                write!(
+                    f,
+                    "another instance of the node is using the data directory."
+                )
+            }
         }
     }
 }
@@ -28,6 +36,7 @@ impl std::error::Error for SqlInitializationError {
         match self {
             SqlInitializationError::IO(error) => Some(error),
             SqlInitializationError::SQL(error) => Some(error),
+            SqlInitializationError::Locked => None,
         }
     }
 }
@@ -46,6 +55,23 @@ impl From<std::io::Error> for SqlInitializationError {
     }
 }
 
+/// Errors while reading from the in-memory peer backend.
+#[derive(Debug)]
+pub enum MemoryPeerStoreError {
+    /// There are no known peers in the store.
+    Empty,
+}
+
+impl core::fmt::Display for MemoryPeerStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryPeerStoreError::Empty => write!(f, "there are no known peers in the store."),
+        }
+    }
+}
+
+impl std::error::Error for MemoryPeerStoreError {}
+
 /// Errors while reading or writing to and from a SQL-based peer backend.
 #[cfg(feature = "rusqlite")]
 #[derive(Debug)]
@@ -103,6 +129,92 @@ impl From<bitcoin::consensus::encode::Error> for SqlPeerStoreError {
     }
 }
 
+/// Errors while reading or writing to and from a SQL-based compact filter backend.
+#[cfg(feature = "rusqlite")]
+#[derive(Debug)]
+pub enum SqlFilterStoreError {
+    /// An error occured performing a SQL operation.
+    SQL(rusqlite::Error),
+}
+
+#[cfg(feature = "rusqlite")]
+impl core::fmt::Display for SqlFilterStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlFilterStoreError::SQL(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl std::error::Error for SqlFilterStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SqlFilterStoreError::SQL(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl From<rusqlite::Error> for SqlFilterStoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::SQL(value)
+    }
+}
+
+/// Errors while reading or writing to and from a SQL-based compact filter header backend.
+#[cfg(feature = "rusqlite")]
+#[derive(Debug)]
+pub enum SqlCFHeaderStoreError {
+    /// A consensus critical data structure is malformed.
+    Deserialize(bitcoin::consensus::encode::Error),
+    /// An error occured performing a SQL operation.
+    SQL(rusqlite::Error),
+}
+
+#[cfg(feature = "rusqlite")]
+impl core::fmt::Display for SqlCFHeaderStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlCFHeaderStoreError::Deserialize(e) => {
+                write!(
+                    f,
+                    "a byte array could not be deserialized into a known datatype: {e}"
+                )
+            }
+            SqlCFHeaderStoreError::SQL(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl std::error::Error for SqlCFHeaderStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SqlCFHeaderStoreError::Deserialize(error) => Some(error),
+            SqlCFHeaderStoreError::SQL(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl From<rusqlite::Error> for SqlCFHeaderStoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::SQL(value)
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl From<bitcoin::consensus::encode::Error> for SqlCFHeaderStoreError {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Deserialize(value)
+    }
+}
+
 /// Errors while reading or writing to and from a SQL-based block header backend.
 #[cfg(feature = "rusqlite")]
 #[derive(Debug)]
@@ -156,3 +268,222 @@ impl From<bitcoin::consensus::encode::Error> for SqlHeaderStoreError {
         Self::Deserialize(value)
     }
 }
+
+/// Errors when initializing a redb backend.
+#[cfg(feature = "redb")]
+#[derive(Debug)]
+pub enum RedbInitializationError {
+    /// A file or directory could not be opened or created.
+    IO(std::io::Error),
+    /// An error occured performing a database operation.
+    Redb(redb::Error),
+    /// Another instance of the node is using the data directory.
+    Locked,
+}
+
+#[cfg(feature = "redb")]
+impl core::fmt::Display for RedbInitializationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedbInitializationError::IO(e) => {
+                write!(f, "a file or directory could not be opened or created: {e}")
+            }
+            RedbInitializationError::Redb(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+            RedbInitializationError::Locked => {
+                write!(
+                    f,
+                    "another instance of the node is using the data directory."
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl std::error::Error for RedbInitializationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RedbInitializationError::IO(error) => Some(error),
+            RedbInitializationError::Redb(error) => Some(error),
+            RedbInitializationError::Locked => None,
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<redb::Error> for RedbInitializationError {
+    fn from(value: redb::Error) -> Self {
+        Self::Redb(value)
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<std::io::Error> for RedbInitializationError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+/// Errors while reading or writing to and from a redb peer backend.
+#[cfg(feature = "redb")]
+#[derive(Debug)]
+pub enum RedbPeerStoreError {
+    /// A consensus critical data structure is malformed.
+    Deserialize(bitcoin::consensus::encode::Error),
+    /// There are no known peers in the database.
+    Empty,
+    /// An error occured performing a database operation.
+    Redb(redb::Error),
+}
+
+#[cfg(feature = "redb")]
+impl core::fmt::Display for RedbPeerStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedbPeerStoreError::Deserialize(e) => {
+                write!(
+                    f,
+                    "a byte array could not be deserialized into a known datatype: {e}"
+                )
+            }
+            RedbPeerStoreError::Empty => {
+                write!(f, "there are no known peers in the database.")
+            }
+            RedbPeerStoreError::Redb(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl std::error::Error for RedbPeerStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RedbPeerStoreError::Deserialize(error) => Some(error),
+            RedbPeerStoreError::Empty => None,
+            RedbPeerStoreError::Redb(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<redb::Error> for RedbPeerStoreError {
+    fn from(value: redb::Error) -> Self {
+        Self::Redb(value)
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<bitcoin::consensus::encode::Error> for RedbPeerStoreError {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Deserialize(value)
+    }
+}
+
+/// Errors while reading or writing to and from a redb compact filter header backend.
+#[cfg(feature = "redb")]
+#[derive(Debug)]
+pub enum RedbCFHeaderStoreError {
+    /// A consensus critical data structure is malformed.
+    Deserialize(bitcoin::consensus::encode::Error),
+    /// An error occured performing a database operation.
+    Redb(redb::Error),
+}
+
+#[cfg(feature = "redb")]
+impl core::fmt::Display for RedbCFHeaderStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedbCFHeaderStoreError::Deserialize(e) => {
+                write!(
+                    f,
+                    "a byte array could not be deserialized into a known datatype: {e}"
+                )
+            }
+            RedbCFHeaderStoreError::Redb(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl std::error::Error for RedbCFHeaderStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RedbCFHeaderStoreError::Deserialize(error) => Some(error),
+            RedbCFHeaderStoreError::Redb(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<redb::Error> for RedbCFHeaderStoreError {
+    fn from(value: redb::Error) -> Self {
+        Self::Redb(value)
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<bitcoin::consensus::encode::Error> for RedbCFHeaderStoreError {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Deserialize(value)
+    }
+}
+
+/// Errors while reading or writing to and from a redb block header backend.
+#[cfg(feature = "redb")]
+#[derive(Debug)]
+pub enum RedbHeaderStoreError {
+    /// The headers do not link together.
+    Corruption,
+    /// Consensus deserialization failed.
+    Deserialize(bitcoin::consensus::encode::Error),
+    /// An error occured performing a database operation.
+    Redb(redb::Error),
+}
+
+#[cfg(feature = "redb")]
+impl core::fmt::Display for RedbHeaderStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedbHeaderStoreError::Redb(e) => {
+                write!(f, "reading or writing from the database failed: {e}")
+            }
+            RedbHeaderStoreError::Deserialize(e) => {
+                write!(f, "consensus decoding failed {e}")
+            }
+            RedbHeaderStoreError::Corruption => {
+                write!(f, "a consensus critical data structure is malformed.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl std::error::Error for RedbHeaderStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RedbHeaderStoreError::Corruption => None,
+            RedbHeaderStoreError::Redb(error) => Some(error),
+            RedbHeaderStoreError::Deserialize(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<redb::Error> for RedbHeaderStoreError {
+    fn from(value: redb::Error) -> Self {
+        Self::Redb(value)
+    }
+}
+
+#[cfg(feature = "redb")]
+impl From<bitcoin::consensus::encode::Error> for RedbHeaderStoreError {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Deserialize(value)
+    }
+}
@@ -0,0 +1,214 @@
+use std::{ops::Range, path::PathBuf, time::Duration};
+
+use bitcoin::{consensus, BlockHash, Network};
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension};
+
+use super::error::{SqlHeaderStoreError, SqlInitializationError, SqlPeerStoreError};
+use super::sqlite::{
+    headers::FILE_NAME as HEADER_FILE_NAME, peers::FILE_NAME as PEER_FILE_NAME, DATA_DIR,
+    DEFAULT_CWD,
+};
+use crate::chain::checkpoints::HeaderCheckpoint;
+
+/// Open the databases of an existing data directory without running a node.
+///
+/// The databases are opened read-only, so a data directory in use by a running node cannot be
+/// inspected.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kyoto::db::inspect::DataDirInspector;
+/// use kyoto::Network;
+///
+/// let inspector = DataDirInspector::open(Network::Signet, None).unwrap();
+/// let tip = inspector.tip().unwrap();
+/// let gaps = inspector.gaps().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct DataDirInspector {
+    headers: Connection,
+    peers: Connection,
+}
+
+/// The number of peers in the peer database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCounts {
+    /// Every peer in the database.
+    pub total: u32,
+    /// Peers the node has connected to.
+    pub tried: u32,
+    /// Peers the node has banned.
+    pub banned: u32,
+}
+
+impl DataDirInspector {
+    /// Open the header and peer databases for a network with an optional directory path. If
+    /// no path is provided, the databases in a `data` subdirectory where the program is ran
+    /// will be opened.
+    ///
+    /// # Errors
+    ///
+    /// If either database does not exist or is being used by a running node.
+    pub fn open(network: Network, path: Option<PathBuf>) -> Result<Self, SqlInitializationError> {
+        let mut path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CWD));
+        path.push(DATA_DIR);
+        path.push(network.to_string());
+        let headers = Self::open_read_only(path.join(HEADER_FILE_NAME))?;
+        let peers = Self::open_read_only(path.join(PEER_FILE_NAME))?;
+        Ok(Self { headers, peers })
+    }
+
+    fn open_read_only(path: PathBuf) -> Result<Connection, SqlInitializationError> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(Duration::ZERO)?;
+        // A running node holds an exclusive lock, so any read will fail
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+            row.get::<_, u32>(0)
+        })
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) => {
+                SqlInitializationError::Locked
+            }
+            _ => SqlInitializationError::SQL(e),
+        })?;
+        Ok(conn)
+    }
+
+    fn checkpoint_at(&self, stmt: &str) -> Result<Option<HeaderCheckpoint>, SqlHeaderStoreError> {
+        let row: Option<(u32, [u8; 32])> = self
+            .headers
+            .query_row(stmt, [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        match row {
+            Some((height, hash)) => {
+                let hash: BlockHash = consensus::deserialize(&hash)?;
+                Ok(Some(HeaderCheckpoint::new(height, hash)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The highest header in the database, if any headers are stored.
+    pub fn tip(&self) -> Result<Option<HeaderCheckpoint>, SqlHeaderStoreError> {
+        self.checkpoint_at("SELECT height, block_hash FROM headers ORDER BY height DESC LIMIT 1")
+    }
+
+    /// The lowest header in the database, which is the first header stored after the anchor
+    /// checkpoint of the node.
+    pub fn anchor(&self) -> Result<Option<HeaderCheckpoint>, SqlHeaderStoreError> {
+        self.checkpoint_at("SELECT height, block_hash FROM headers ORDER BY height ASC LIMIT 1")
+    }
+
+    /// Ranges of heights between the anchor and the tip that have no stored header.
+    pub fn gaps(&self) -> Result<Vec<Range<u32>>, SqlHeaderStoreError> {
+        let mut stmt = self
+            .headers
+            .prepare("SELECT height FROM headers ORDER BY height")?;
+        let mut rows = stmt.query([])?;
+        let mut gaps = Vec::new();
+        let mut last: Option<u32> = None;
+        while let Some(row) = rows.next()? {
+            let height: u32 = row.get(0)?;
+            if let Some(prev) = last {
+                if height > prev + 1 {
+                    gaps.push(prev + 1..height);
+                }
+            }
+            last = Some(height);
+        }
+        Ok(gaps)
+    }
+
+    /// The number of headers in the database.
+    pub fn header_count(&self) -> Result<u32, SqlHeaderStoreError> {
+        let count = self
+            .headers
+            .query_row("SELECT COUNT(*) FROM headers", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// The number of peers in the database, including those tried and banned.
+    pub fn peer_counts(&self) -> Result<PeerCounts, SqlPeerStoreError> {
+        let counts = self.peers.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(tried), 0), COALESCE(SUM(banned), 0) FROM peers",
+            [],
+            |row| {
+                Ok(PeerCounts {
+                    total: row.get(0)?,
+                    tried: row.get(1)?,
+                    banned: row.get(2)?,
+                })
+            },
+        )?;
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{block::Header, consensus::deserialize};
+
+    use crate::{
+        chain::IndexedHeader,
+        db::{
+            sqlite::{headers::SqliteHeaderDb, peers::SqlitePeerDb},
+            traits::{HeaderStore, PeerStore},
+            BlockHeaderChanges, PeerStatus, PersistedPeer,
+        },
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_inspect_data_dir() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut header_db = SqliteHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let mut peer_db = SqlitePeerDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let block_8: Header = deserialize(&hex::decode("0000002016fe292517eecbbd63227d126a6b1db30ebc5262c61f8f3a4a529206388fc262dfd043cef8454f71f30b5bbb9eb1a4c9aea87390f429721e435cf3f8aa6e2a9171375166ffff7f2000000000").unwrap()).unwrap();
+        let block_10: Header = deserialize(&hex::decode("000000201d062f2162835787db536c55317e08df17c58078c7610328bdced198574093790c9f554a7780a6043a19619d2a4697364bb62abf6336c0568c31f1eedca3c3e171375166ffff7f2000000000").unwrap()).unwrap();
+        header_db.stage(BlockHeaderChanges::Connected(IndexedHeader::new(
+            8, block_8,
+        )));
+        header_db.stage(BlockHeaderChanges::Connected(IndexedHeader::new(
+            10, block_10,
+        )));
+        header_db.write().await.unwrap();
+        let peer = PersistedPeer::new(
+            bitcoin::p2p::address::AddrV2::Ipv4(std::net::Ipv4Addr::LOCALHOST),
+            0,
+            bitcoin::p2p::ServiceFlags::NONE,
+            PeerStatus::Tried,
+        );
+        peer_db.update(peer).await.unwrap();
+        // The node holds the lock on the data directory
+        assert!(matches!(
+            DataDirInspector::open(Network::Regtest, Some(path.into())),
+            Err(SqlInitializationError::Locked)
+        ));
+        drop(header_db);
+        drop(peer_db);
+        let inspector = DataDirInspector::open(Network::Regtest, Some(path.into())).unwrap();
+        let tip = inspector.tip().unwrap().unwrap();
+        assert_eq!(tip.height, 10);
+        assert_eq!(tip.hash, block_10.block_hash());
+        let anchor = inspector.anchor().unwrap().unwrap();
+        assert_eq!(anchor.height, 8);
+        assert_eq!(inspector.gaps().unwrap(), vec![9..10]);
+        assert_eq!(inspector.header_count().unwrap(), 2);
+        assert_eq!(
+            inspector.peer_counts().unwrap(),
+            PeerCounts {
+                total: 1,
+                tried: 1,
+                banned: 0
+            }
+        );
+        drop(inspector);
+        binding.close().unwrap();
+    }
+}
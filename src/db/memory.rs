@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::ops::RangeBounds;
+
+use bitcoin::key::rand::{thread_rng, Rng};
+use bitcoin::p2p::address::AddrV2;
+use bitcoin::{block::Header, BlockHash};
+
+use crate::prelude::FutureResult;
+use crate::PeerStoreSizeConfig;
+
+use super::error::MemoryPeerStoreError;
+use super::traits::{CFHeaderStore, HeaderStore, PeerStore};
+use super::{BlockHeaderChanges, PeerStatus, PersistedFilterHeader, PersistedPeer};
+
+/// A [`HeaderStore`] that keeps the chain of block headers in memory. Headers are lost when the
+/// node stops, so the node syncs from its checkpoint every time it starts.
+#[derive(Debug, Default)]
+pub struct MemoryHeaderDb {
+    headers: BTreeMap<u32, Header>,
+    heights: HashMap<BlockHash, u32>,
+}
+
+impl MemoryHeaderDb {
+    /// Create an empty header store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, height: u32, header: Header) {
+        if let Some(replaced) = self.headers.insert(height, header) {
+            self.heights.remove(&replaced.block_hash());
+        }
+        self.heights.insert(header.block_hash(), height);
+    }
+}
+
+impl HeaderStore for MemoryHeaderDb {
+    type Error = Infallible;
+
+    fn load<'a>(
+        &'a mut self,
+        range: impl RangeBounds<u32> + Send + Sync + 'a,
+    ) -> FutureResult<'a, BTreeMap<u32, Header>, Self::Error> {
+        let headers = self
+            .headers
+            .range(range)
+            .map(|(height, header)| (*height, *header))
+            .collect();
+        Box::pin(async move { Ok(headers) })
+    }
+
+    fn stage(&mut self, changes: BlockHeaderChanges) {
+        match changes {
+            BlockHeaderChanges::Connected(indexed_header) => {
+                self.insert(indexed_header.height, indexed_header.header);
+            }
+            BlockHeaderChanges::Reorganized {
+                accepted,
+                reorganized,
+            } => {
+                for indexed_header in reorganized {
+                    if let Some(height) = self.heights.remove(&indexed_header.header.block_hash()) {
+                        self.headers.remove(&height);
+                    }
+                }
+                for indexed_header in accepted {
+                    self.insert(indexed_header.height, indexed_header.header);
+                }
+            }
+        }
+    }
+
+    fn write(&mut self) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn height_of<'a>(
+        &'a mut self,
+        hash: &'a BlockHash,
+    ) -> FutureResult<'a, Option<u32>, Self::Error> {
+        let height = self.heights.get(hash).copied();
+        Box::pin(async move { Ok(height) })
+    }
+
+    fn hash_at(&mut self, height: u32) -> FutureResult<'_, Option<BlockHash>, Self::Error> {
+        let hash = self.headers.get(&height).map(|header| header.block_hash());
+        Box::pin(async move { Ok(hash) })
+    }
+
+    fn header_at(&mut self, height: u32) -> FutureResult<'_, Option<Header>, Self::Error> {
+        let header = self.headers.get(&height).copied();
+        Box::pin(async move { Ok(header) })
+    }
+}
+
+/// A [`CFHeaderStore`] that keeps the chain of compact filter headers in memory. Filter headers are
+/// lost when the node stops, so the node downloads the filter headers from its checkpoint every
+/// time it starts.
+#[derive(Debug, Default)]
+pub struct MemoryCFHeaderDb {
+    filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+}
+
+impl MemoryCFHeaderDb {
+    /// Create an empty filter header store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CFHeaderStore for MemoryCFHeaderDb {
+    type Error = Infallible;
+
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<'_, (), Self::Error> {
+        self.filter_headers.extend(filter_headers);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<'_, BTreeMap<u32, PersistedFilterHeader>, Self::Error> {
+        let filter_headers = self
+            .filter_headers
+            .range(start_height..)
+            .map(|(height, filter_header)| (*height, *filter_header))
+            .collect();
+        Box::pin(async move { Ok(filter_headers) })
+    }
+}
+
+/// A [`PeerStore`] that keeps the peers of the network in memory. Peers are lost when the node
+/// stops, so the node bootstraps new peers from DNS every time it starts.
+#[derive(Debug, Default)]
+pub struct MemoryPeerDb {
+    peers: HashMap<AddrV2, PersistedPeer>,
+}
+
+impl MemoryPeerDb {
+    /// Create an empty peer store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn unbanned(&self) -> impl Iterator<Item = &PersistedPeer> {
+        self.peers
+            .values()
+            .filter(|peer| peer.status.ne(&PeerStatus::Ban))
+    }
+}
+
+impl PeerStore for MemoryPeerDb {
+    type Error = MemoryPeerStoreError;
+
+    fn update(&mut self, peer: PersistedPeer) -> FutureResult<'_, (), Self::Error> {
+        match self.peers.get_mut(&peer.addr) {
+            // A gossiped peer does not change the status of a peer that is already known
+            Some(known) if peer.status.eq(&PeerStatus::Gossiped) => {
+                known.port = peer.port;
+                known.services = peer.services;
+            }
+            _ => {
+                self.peers.insert(peer.addr.clone(), peer);
+            }
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn random(&mut self) -> FutureResult<'_, PersistedPeer, Self::Error> {
+        let count = self.unbanned().count();
+        let peer = if count == 0 {
+            None
+        } else {
+            let index = thread_rng().gen_range(0..count);
+            self.unbanned().nth(index).cloned()
+        };
+        Box::pin(async move { peer.ok_or(MemoryPeerStoreError::Empty) })
+    }
+
+    fn num_unbanned(&mut self) -> FutureResult<'_, u32, Self::Error> {
+        let count = self.unbanned().count() as u32;
+        Box::pin(async move { Ok(count) })
+    }
+
+    fn compact(&mut self, target: PeerStoreSizeConfig) -> FutureResult<'_, (), Self::Error> {
+        if let PeerStoreSizeConfig::Limit(limit) = target {
+            let mut excess = (self.unbanned().count() as u32).saturating_sub(limit);
+            // Peers that were never tried are removed first
+            let mut removable = self
+                .unbanned()
+                .map(|peer| (peer.status.eq(&PeerStatus::Tried), peer.addr.clone()))
+                .collect::<Vec<(bool, AddrV2)>>();
+            removable.sort_by_key(|(tried, _)| *tried);
+            for (_, addr) in removable {
+                if excess == 0 {
+                    break;
+                }
+                self.peers.remove(&addr);
+                excess -= 1;
+            }
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bitcoin::{
+        constants::genesis_block, hashes::Hash, p2p::ServiceFlags, FilterHash, FilterHeader,
+        Network,
+    };
+
+    use crate::chain::IndexedHeader;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_stores() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut next = genesis;
+        next.prev_blockhash = genesis.block_hash();
+        let mut headers = MemoryHeaderDb::new();
+        headers.stage(BlockHeaderChanges::Connected(IndexedHeader::new(
+            0, genesis,
+        )));
+        headers.stage(BlockHeaderChanges::Connected(IndexedHeader::new(1, next)));
+        headers.write().await.unwrap();
+        assert_eq!(headers.load(0..).await.unwrap().len(), 2);
+        assert_eq!(
+            headers.height_of(&next.block_hash()).await.unwrap(),
+            Some(1)
+        );
+        let mut fork = next;
+        fork.nonce += 1;
+        headers.stage(BlockHeaderChanges::Reorganized {
+            accepted: vec![IndexedHeader::new(1, fork)],
+            reorganized: vec![IndexedHeader::new(1, next)],
+        });
+        assert_eq!(headers.height_of(&next.block_hash()).await.unwrap(), None);
+        assert_eq!(headers.hash_at(1).await.unwrap(), Some(fork.block_hash()));
+
+        let mut filter_headers = MemoryCFHeaderDb::new();
+        let first = PersistedFilterHeader::new(
+            genesis.block_hash(),
+            FilterHash::from_byte_array([1; 32]),
+            FilterHeader::all_zeros(),
+        );
+        let second = PersistedFilterHeader::new(
+            next.block_hash(),
+            FilterHash::from_byte_array([2; 32]),
+            first.filter_header(),
+        );
+        filter_headers
+            .write_filter_headers([(0, first), (1, second)].into())
+            .await
+            .unwrap();
+        let reorganized = PersistedFilterHeader {
+            block_hash: fork.block_hash(),
+            ..second
+        };
+        filter_headers
+            .write_filter_headers([(1, reorganized)].into())
+            .await
+            .unwrap();
+        let loaded = filter_headers.load_filter_headers(1).await.unwrap();
+        assert_eq!(
+            loaded.into_iter().collect::<Vec<_>>(),
+            vec![(1, reorganized)]
+        );
+
+        let mut peers = MemoryPeerDb::new();
+        assert!(peers.random().await.is_err());
+        let addr = AddrV2::Ipv4(Ipv4Addr::new(1, 1, 1, 1));
+        let tried = PersistedPeer::new(addr.clone(), 8333, ServiceFlags::NONE, PeerStatus::Tried);
+        peers.update(tried).await.unwrap();
+        let gossiped = PersistedPeer::new(addr, 8333, ServiceFlags::NONE, PeerStatus::Gossiped);
+        peers.update(gossiped).await.unwrap();
+        assert_eq!(peers.random().await.unwrap().status, PeerStatus::Tried);
+        let other = PersistedPeer::new(
+            AddrV2::Ipv4(Ipv4Addr::new(2, 2, 2, 2)),
+            8333,
+            ServiceFlags::NONE,
+            PeerStatus::Gossiped,
+        );
+        peers.update(other).await.unwrap();
+        assert_eq!(peers.num_unbanned().await.unwrap(), 2);
+        peers.compact(PeerStoreSizeConfig::Limit(1)).await.unwrap();
+        assert_eq!(peers.num_unbanned().await.unwrap(), 1);
+        assert_eq!(peers.random().await.unwrap().status, PeerStatus::Tried);
+    }
+}
@@ -6,14 +6,30 @@
 
 use bitcoin::key::rand::distributions::Standard;
 use bitcoin::key::rand::prelude::Distribution;
-use bitcoin::key::rand::{thread_rng, Rng};
 use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::ServiceFlags;
+use bitcoin::{BlockHash, FilterHash, FilterHeader};
 
 use crate::chain::IndexedHeader;
+use crate::{HeaderCheckpoint, TxBroadcast, Utxo};
+
+// The data directory is used by the databases and the event journal
+#[cfg(any(feature = "rusqlite", feature = "redb", feature = "journal"))]
+pub(crate) const DEFAULT_CWD: &str = ".";
+#[cfg(any(feature = "rusqlite", feature = "redb", feature = "journal"))]
+pub(crate) const DATA_DIR: &str = "light_client_data";
 
 /// Errors a database backend may produce.
 pub mod error;
+/// Read-only inspection of the SQL Lite databases in a data directory.
+#[cfg(feature = "rusqlite")]
+pub mod inspect;
+/// Stores that keep headers and peers in memory, for applications that do not persist data.
+pub mod memory;
+/// Persistence traits defined with redb, a pure Rust alternative to SQL Lite.
+#[cfg(feature = "redb")]
+#[allow(clippy::result_large_err)]
+pub mod redb;
 /// Persistence traits defined with SQL Lite to store data between sessions.
 #[cfg(feature = "rusqlite")]
 pub mod sqlite;
@@ -71,13 +87,6 @@ impl Distribution<PeerStatus> for Standard {
     }
 }
 
-impl PeerStatus {
-    pub(crate) fn random() -> PeerStatus {
-        let mut rng = thread_rng();
-        rng.gen()
-    }
-}
-
 /// Changes applied to the chain of block headers.
 #[derive(Debug, Clone)]
 pub enum BlockHeaderChanges {
@@ -91,3 +100,78 @@ pub enum BlockHeaderChanges {
         reorganized: Vec<IndexedHeader>,
     },
 }
+
+/// A compact filter header that will be saved to the [`traits::CFHeaderStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PersistedFilterHeader {
+    /// The block the filter header commits to.
+    pub block_hash: BlockHash,
+    /// The hash of the filter of the block.
+    pub filter_hash: FilterHash,
+    /// The filter header of the block before, which the filter header of this block extends.
+    pub prev_filter_header: FilterHeader,
+}
+
+impl PersistedFilterHeader {
+    /// Build a new filter header with known fields
+    pub fn new(
+        block_hash: BlockHash,
+        filter_hash: FilterHash,
+        prev_filter_header: FilterHeader,
+    ) -> Self {
+        Self {
+            block_hash,
+            filter_hash,
+            prev_filter_header,
+        }
+    }
+
+    /// The filter header of the block, which commits to the filter of the block and every block
+    /// before it.
+    pub fn filter_header(&self) -> FilterHeader {
+        self.filter_hash.filter_header(&self.prev_filter_header)
+    }
+}
+
+/// A transaction broadcast by the node that will be saved to the [`traits::HeaderStore`] until it
+/// is buried deep enough in the chain.
+#[derive(Debug, Clone)]
+pub struct PersistedBroadcast {
+    /// The transaction and how it is shared with peers.
+    pub broadcast: TxBroadcast,
+    /// The number of times the transaction was sent to peers. The transaction is no longer sent
+    /// after a number of attempts.
+    pub attempts: u32,
+    /// The block the transaction was confirmed in, if any. The transaction is sent again if the
+    /// block is reorganized out of the chain.
+    pub confirmed: Option<HeaderCheckpoint>,
+}
+
+impl PersistedBroadcast {
+    /// Build a new broadcast with known fields
+    pub fn new(broadcast: TxBroadcast, attempts: u32, confirmed: Option<HeaderCheckpoint>) -> Self {
+        Self {
+            broadcast,
+            attempts,
+            confirmed,
+        }
+    }
+}
+
+/// An output paying to a script of the node that will be saved to the [`traits::HeaderStore`]
+/// while it is unspent, or until the block that spent it is buried deep enough in the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedUtxo {
+    /// The output and the block it was created in.
+    pub utxo: Utxo,
+    /// The block the output was spent in, if any. The output is unspent again if the block is
+    /// reorganized out of the chain.
+    pub spent: Option<HeaderCheckpoint>,
+}
+
+impl PersistedUtxo {
+    /// Build a new output with known fields
+    pub fn new(utxo: Utxo, spent: Option<HeaderCheckpoint>) -> Self {
+        Self { utxo, spent }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use bitcoin::{consensus, Network};
+use redb::{Database, TableDefinition};
+
+use crate::db::error::{RedbCFHeaderStoreError, RedbInitializationError};
+use crate::db::traits::CFHeaderStore;
+use crate::db::PersistedFilterHeader;
+use crate::prelude::FutureResult;
+
+pub(crate) const FILE_NAME: &str = "cfheaders.redb";
+// The block hash, filter hash and previous filter header by height
+const CFHEADERS: TableDefinition<u32, [u8; 96]> = TableDefinition::new("cfheaders");
+
+/// Compact filter header storage implementation with redb, a database written in Rust.
+#[derive(Debug)]
+pub struct RedbCFHeaderDb {
+    db: Database,
+}
+
+impl RedbCFHeaderDb {
+    /// Create a new [`RedbCFHeaderDb`] with an optional file path. If no path is provided,
+    /// the file will be stored in a `data` subdirectory where the program is ran.
+    pub fn new(network: Network, path: Option<PathBuf>) -> Result<Self, RedbInitializationError> {
+        let db = super::open(network, path, FILE_NAME)?;
+        // Build the table if it doesn't exist, so it may always be opened for reading
+        let tx = db.begin_write().map_err(redb::Error::from)?;
+        tx.open_table(CFHEADERS).map_err(redb::Error::from)?;
+        tx.commit().map_err(redb::Error::from)?;
+        Ok(Self { db })
+    }
+
+    fn write_rows(
+        &self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> Result<(), redb::Error> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut table = tx.open_table(CFHEADERS)?;
+            for (height, filter_header) in filter_headers {
+                let mut bytes = [0; 96];
+                bytes[..32].copy_from_slice(&consensus::serialize(&filter_header.block_hash));
+                bytes[32..64].copy_from_slice(&consensus::serialize(&filter_header.filter_hash));
+                bytes[64..]
+                    .copy_from_slice(&consensus::serialize(&filter_header.prev_filter_header));
+                // A reorganized block replaces the filter header at its height
+                table.insert(height, bytes)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> Result<(), RedbCFHeaderStoreError> {
+        Ok(self.write_rows(filter_headers)?)
+    }
+
+    fn read_rows(&self, start_height: u32) -> Result<Vec<(u32, [u8; 96])>, redb::Error> {
+        let tx = self.db.begin_read()?;
+        let table = tx.open_table(CFHEADERS)?;
+        let mut rows = Vec::new();
+        for row in table.range(start_height..)? {
+            let (height, filter_header) = row?;
+            rows.push((height.value(), filter_header.value()));
+        }
+        Ok(rows)
+    }
+
+    async fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> Result<BTreeMap<u32, PersistedFilterHeader>, RedbCFHeaderStoreError> {
+        let mut filter_headers = BTreeMap::new();
+        for (height, bytes) in self.read_rows(start_height)? {
+            let filter_header = PersistedFilterHeader {
+                block_hash: consensus::deserialize(&bytes[..32])?,
+                filter_hash: consensus::deserialize(&bytes[32..64])?,
+                prev_filter_header: consensus::deserialize(&bytes[64..])?,
+            };
+            filter_headers.insert(height, filter_header);
+        }
+        Ok(filter_headers)
+    }
+}
+
+impl CFHeaderStore for RedbCFHeaderDb {
+    type Error = RedbCFHeaderStoreError;
+
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.write_filter_headers(filter_headers))
+    }
+
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<'_, BTreeMap<u32, PersistedFilterHeader>, Self::Error> {
+        Box::pin(self.load_filter_headers(start_height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, BlockHash, FilterHash, FilterHeader};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_redb_cfheader_store() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut db = RedbCFHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        assert!(db.load_filter_headers(0).await.unwrap().is_empty());
+        let first = PersistedFilterHeader::new(
+            BlockHash::from_byte_array([1; 32]),
+            FilterHash::from_byte_array([1; 32]),
+            FilterHeader::all_zeros(),
+        );
+        let second = PersistedFilterHeader::new(
+            BlockHash::from_byte_array([2; 32]),
+            FilterHash::from_byte_array([2; 32]),
+            first.filter_header(),
+        );
+        db.write_filter_headers([(10, first), (11, second)].into())
+            .await
+            .unwrap();
+        // The filter headers remain after the store is opened again
+        drop(db);
+        let mut db = RedbCFHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let loaded = db.load_filter_headers(0).await.unwrap();
+        assert_eq!(loaded.get(&10), Some(&first));
+        assert_eq!(loaded.get(&11), Some(&second));
+        // A reorganized block replaces the filter header at its height
+        let reorganized = PersistedFilterHeader {
+            block_hash: BlockHash::from_byte_array([3; 32]),
+            ..second
+        };
+        db.write_filter_headers([(11, reorganized)].into())
+            .await
+            .unwrap();
+        let loaded = db.load_filter_headers(11).await.unwrap();
+        assert_eq!(
+            loaded.into_iter().collect::<Vec<_>>(),
+            vec![(11, reorganized)]
+        );
+        drop(db);
+        binding.close().unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::RangeBounds;
+use std::path::PathBuf;
+
+use bitcoin::block::Header;
+use bitcoin::hashes::Hash;
+use bitcoin::{consensus, BlockHash, Network};
+use redb::{Database, TableDefinition};
+
+use crate::db::error::{RedbHeaderStoreError, RedbInitializationError};
+use crate::db::traits::HeaderStore;
+use crate::db::BlockHeaderChanges;
+use crate::prelude::FutureResult;
+
+pub(crate) const FILE_NAME: &str = "headers.redb";
+// Serialized headers by height
+const HEADERS: TableDefinition<u32, [u8; 80]> = TableDefinition::new("headers");
+// Find the height of a block hash without scanning every header
+const HEIGHTS: TableDefinition<[u8; 32], u32> = TableDefinition::new("heights");
+
+/// Header storage implementation with redb, a database written in Rust.
+///
+/// Only the chain of block headers is persisted. Script history, the wallet birthday and
+/// unconfirmed broadcasts are not saved between sessions.
+#[derive(Debug)]
+pub struct RedbHeaderDb {
+    db: Database,
+    accepted: BTreeMap<u32, Header>,
+    disconnected: HashSet<BlockHash>,
+}
+
+impl RedbHeaderDb {
+    /// Create a new [`RedbHeaderDb`] with an optional file path. If no path is provided,
+    /// the file will be stored in a `data` subdirectory where the program is ran.
+    pub fn new(network: Network, path: Option<PathBuf>) -> Result<Self, RedbInitializationError> {
+        let db = super::open(network, path, FILE_NAME)?;
+        // Build the tables if they don't exist, so they may always be opened for reading
+        let tx = db.begin_write().map_err(redb::Error::from)?;
+        tx.open_table(HEADERS).map_err(redb::Error::from)?;
+        tx.open_table(HEIGHTS).map_err(redb::Error::from)?;
+        tx.commit().map_err(redb::Error::from)?;
+        Ok(Self {
+            db,
+            accepted: BTreeMap::new(),
+            disconnected: HashSet::new(),
+        })
+    }
+
+    fn read_range(
+        &self,
+        range: impl RangeBounds<u32>,
+    ) -> Result<Vec<(u32, [u8; 80])>, redb::Error> {
+        let tx = self.db.begin_read()?;
+        let table = tx.open_table(HEADERS)?;
+        let mut headers = Vec::new();
+        for row in table.range(range)? {
+            let (height, header) = row?;
+            headers.push((height.value(), header.value()));
+        }
+        Ok(headers)
+    }
+
+    async fn load<'a>(
+        &mut self,
+        range: impl RangeBounds<u32> + Send + Sync + 'a,
+    ) -> Result<BTreeMap<u32, Header>, RedbHeaderStoreError> {
+        let mut headers = BTreeMap::<u32, Header>::new();
+        for (height, header) in self.read_range(range)? {
+            let next_header: Header = consensus::deserialize(&header)?;
+            if let Some(header) = headers.values().last() {
+                if header.block_hash().ne(&next_header.prev_blockhash) {
+                    return Err(RedbHeaderStoreError::Corruption);
+                }
+            }
+            headers.insert(height, next_header);
+        }
+        Ok(headers)
+    }
+
+    fn stage(&mut self, changes: BlockHeaderChanges) {
+        match changes {
+            BlockHeaderChanges::Connected(indexed_header) => {
+                self.accepted
+                    .insert(indexed_header.height, indexed_header.header);
+            }
+            BlockHeaderChanges::Reorganized {
+                accepted,
+                reorganized,
+            } => {
+                for indexed_header in reorganized {
+                    let removed_hash = indexed_header.header.block_hash();
+                    self.accepted
+                        .retain(|_, header| header.block_hash().ne(&removed_hash));
+                    self.disconnected.insert(removed_hash);
+                }
+                for indexed_header in accepted {
+                    self.accepted
+                        .insert(indexed_header.height, indexed_header.header);
+                }
+            }
+        }
+    }
+
+    fn write_staged(&mut self) -> Result<(), redb::Error> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut headers = tx.open_table(HEADERS)?;
+            let mut heights = tx.open_table(HEIGHTS)?;
+            for removed in core::mem::take(&mut self.disconnected) {
+                let height = heights
+                    .remove(removed.to_byte_array())?
+                    .map(|height| height.value());
+                if let Some(height) = height {
+                    headers.remove(height)?;
+                }
+            }
+            for (height, header) in core::mem::take(&mut self.accepted) {
+                let mut bytes = [0; 80];
+                bytes.copy_from_slice(&consensus::serialize(&header));
+                let replaced = headers.insert(height, bytes)?.map(|header| header.value());
+                // The header this one replaces may no longer be found by its hash
+                if let Some(replaced) = replaced {
+                    if let Ok(replaced) = consensus::deserialize::<Header>(&replaced) {
+                        heights.remove(replaced.block_hash().to_byte_array())?;
+                    }
+                }
+                heights.insert(header.block_hash().to_byte_array(), height)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn write(&mut self) -> Result<(), RedbHeaderStoreError> {
+        Ok(self.write_staged()?)
+    }
+
+    fn read_height(&self, block_hash: &BlockHash) -> Result<Option<u32>, redb::Error> {
+        let tx = self.db.begin_read()?;
+        let table = tx.open_table(HEIGHTS)?;
+        let height = table.get(block_hash.to_byte_array())?;
+        Ok(height.map(|height| height.value()))
+    }
+
+    async fn height_of(
+        &mut self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<u32>, RedbHeaderStoreError> {
+        Ok(self.read_height(block_hash)?)
+    }
+
+    fn read_header(&self, height: u32) -> Result<Option<[u8; 80]>, redb::Error> {
+        let tx = self.db.begin_read()?;
+        let table = tx.open_table(HEADERS)?;
+        let header = table.get(height)?;
+        Ok(header.map(|header| header.value()))
+    }
+
+    async fn hash_at(&mut self, height: u32) -> Result<Option<BlockHash>, RedbHeaderStoreError> {
+        let header = self.header_at(height).await?;
+        Ok(header.map(|header| header.block_hash()))
+    }
+
+    async fn header_at(&mut self, height: u32) -> Result<Option<Header>, RedbHeaderStoreError> {
+        match self.read_header(height)? {
+            Some(header) => Ok(Some(consensus::deserialize(&header)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl HeaderStore for RedbHeaderDb {
+    type Error = RedbHeaderStoreError;
+
+    fn load<'a>(
+        &'a mut self,
+        range: impl RangeBounds<u32> + Send + Sync + 'a,
+    ) -> FutureResult<'a, BTreeMap<u32, Header>, Self::Error> {
+        Box::pin(self.load(range))
+    }
+
+    fn stage(&mut self, changes: BlockHeaderChanges) {
+        self.stage(changes)
+    }
+
+    fn write(&mut self) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.write())
+    }
+
+    fn height_of<'a>(
+        &'a mut self,
+        block_hash: &'a BlockHash,
+    ) -> FutureResult<'a, Option<u32>, Self::Error> {
+        Box::pin(self.height_of(block_hash))
+    }
+
+    fn hash_at(&mut self, height: u32) -> FutureResult<'_, Option<BlockHash>, Self::Error> {
+        Box::pin(self.hash_at(height))
+    }
+
+    fn header_at(&mut self, height: u32) -> FutureResult<'_, Option<Header>, Self::Error> {
+        Box::pin(self.header_at(height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::constants::genesis_block;
+
+    use crate::chain::IndexedHeader;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_redb_header_store() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut db = RedbHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut next = genesis;
+        next.prev_blockhash = genesis.block_hash();
+        db.stage(BlockHeaderChanges::Connected(IndexedHeader::new(
+            0, genesis,
+        )));
+        db.stage(BlockHeaderChanges::Connected(IndexedHeader::new(1, next)));
+        db.write().await.unwrap();
+        assert_eq!(db.load(0..).await.unwrap().len(), 2);
+        assert_eq!(db.load(1..).await.unwrap().get(&1), Some(&next));
+        assert_eq!(db.height_of(&next.block_hash()).await.unwrap(), Some(1));
+        // A header is no longer found by its hash once it is reorganized
+        let mut fork = next;
+        fork.nonce += 1;
+        db.stage(BlockHeaderChanges::Reorganized {
+            accepted: vec![IndexedHeader::new(1, fork)],
+            reorganized: vec![IndexedHeader::new(1, next)],
+        });
+        db.write().await.unwrap();
+        assert_eq!(db.height_of(&next.block_hash()).await.unwrap(), None);
+        assert_eq!(db.height_of(&fork.block_hash()).await.unwrap(), Some(1));
+        // The headers remain after the store is opened again
+        drop(db);
+        let mut db = RedbHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        assert_eq!(db.hash_at(1).await.unwrap(), Some(fork.block_hash()));
+        assert_eq!(db.header_at(0).await.unwrap(), Some(genesis));
+        assert_eq!(db.header_at(2).await.unwrap(), None);
+        drop(db);
+        binding.close().unwrap();
+    }
+}
@@ -0,0 +1,34 @@
+/// redb compact filter header storage.
+pub mod cfheaders;
+/// redb block header storage.
+pub mod headers;
+/// redb peer storage.
+pub mod peers;
+
+use std::fs;
+use std::path::PathBuf;
+
+use bitcoin::Network;
+use redb::{Database, DatabaseError};
+
+use super::error::RedbInitializationError;
+use super::{DATA_DIR, DEFAULT_CWD};
+
+// Open the database file in the data directory of the network. The file is locked for as long as
+// the database is open, so another instance of the node cannot write to the same data directory.
+pub(crate) fn open(
+    network: Network,
+    path: Option<PathBuf>,
+    file_name: &str,
+) -> Result<Database, RedbInitializationError> {
+    let mut path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CWD));
+    path.push(DATA_DIR);
+    path.push(network.to_string());
+    if !path.exists() {
+        fs::create_dir_all(&path)?;
+    }
+    Database::create(path.join(file_name)).map_err(|e| match e {
+        DatabaseError::DatabaseAlreadyOpen => RedbInitializationError::Locked,
+        _ => RedbInitializationError::Redb(e.into()),
+    })
+}
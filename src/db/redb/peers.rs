@@ -0,0 +1,236 @@
+use std::path::PathBuf;
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::key::rand::{thread_rng, Rng};
+use bitcoin::p2p::ServiceFlags;
+use bitcoin::Network;
+use redb::{Database, ReadableTable, TableDefinition};
+
+use crate::db::error::{RedbInitializationError, RedbPeerStoreError};
+use crate::db::traits::PeerStore;
+use crate::db::{PeerStatus, PersistedPeer};
+use crate::prelude::FutureResult;
+use crate::PeerStoreSizeConfig;
+
+pub(crate) const FILE_NAME: &str = "peers.redb";
+// Peers by their serialized address. The record is the port, service flags, and if the peer was
+// tried or banned.
+const PEERS: TableDefinition<&[u8], [u8; 12]> = TableDefinition::new("peers");
+
+type PeerRecord = (Vec<u8>, [u8; 12]);
+
+fn encode_record(peer: &PersistedPeer) -> [u8; 12] {
+    let (tried, banned) = match peer.status {
+        PeerStatus::Gossiped => (false, false),
+        PeerStatus::Tried => (true, false),
+        PeerStatus::Ban => (true, true),
+    };
+    let mut record = [0; 12];
+    record[..2].copy_from_slice(&peer.port.to_le_bytes());
+    record[2..10].copy_from_slice(&peer.services.to_u64().to_le_bytes());
+    record[10] = tried as u8;
+    record[11] = banned as u8;
+    record
+}
+
+fn is_banned(record: &[u8; 12]) -> bool {
+    record[11] != 0
+}
+
+/// Structure to create a redb backend to store peers.
+#[derive(Debug)]
+pub struct RedbPeerDb {
+    db: Database,
+}
+
+impl RedbPeerDb {
+    /// Create a new peer storage with an optional directory path. If no path is provided,
+    /// the file will be stored in a `data` subdirectory where the program is ran.
+    pub fn new(network: Network, path: Option<PathBuf>) -> Result<Self, RedbInitializationError> {
+        let db = super::open(network, path, FILE_NAME)?;
+        // Build the table if it doesn't exist, so it may always be opened for reading
+        let tx = db.begin_write().map_err(redb::Error::from)?;
+        tx.open_table(PEERS).map_err(redb::Error::from)?;
+        tx.commit().map_err(redb::Error::from)?;
+        Ok(Self { db })
+    }
+
+    fn write_peer(&mut self, peer: PersistedPeer) -> Result<(), redb::Error> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut table = tx.open_table(PEERS)?;
+            let address_blob = serialize(&peer.addr);
+            let mut record = encode_record(&peer);
+            // A gossiped peer does not change the status of a peer that is already known
+            if peer.status.eq(&PeerStatus::Gossiped) {
+                let known = table
+                    .get(address_blob.as_slice())?
+                    .map(|known| known.value());
+                if let Some(known) = known {
+                    record[10..].copy_from_slice(&known[10..]);
+                }
+            }
+            table.insert(address_blob.as_slice(), record)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn update(&mut self, peer: PersistedPeer) -> Result<(), RedbPeerStoreError> {
+        Ok(self.write_peer(peer)?)
+    }
+
+    // The addresses and records of the peers that are not banned
+    fn unbanned(&self) -> Result<Vec<PeerRecord>, redb::Error> {
+        let tx = self.db.begin_read()?;
+        let table = tx.open_table(PEERS)?;
+        let mut peers = Vec::new();
+        for row in table.iter()? {
+            let (addr, record) = row?;
+            let record = record.value();
+            if !is_banned(&record) {
+                peers.push((addr.value().to_vec(), record));
+            }
+        }
+        Ok(peers)
+    }
+
+    async fn random(&mut self) -> Result<PersistedPeer, RedbPeerStoreError> {
+        let mut peers = self.unbanned()?;
+        if peers.is_empty() {
+            return Err(RedbPeerStoreError::Empty);
+        }
+        let index = thread_rng().gen_range(0..peers.len());
+        let (ip_addr, record) = peers.swap_remove(index);
+        let port = u16::from_le_bytes([record[0], record[1]]);
+        let mut service_blob = [0; 8];
+        service_blob.copy_from_slice(&record[2..10]);
+        let services = ServiceFlags::from(u64::from_le_bytes(service_blob));
+        let status = if record[10] != 0 {
+            PeerStatus::Tried
+        } else {
+            PeerStatus::Gossiped
+        };
+        let ip = deserialize(&ip_addr)?;
+        Ok(PersistedPeer::new(ip, port, services, status))
+    }
+
+    async fn num_unbanned(&mut self) -> Result<u32, RedbPeerStoreError> {
+        Ok(self.unbanned()?.len() as u32)
+    }
+
+    fn remove_excess(&mut self, limit: u32) -> Result<(), redb::Error> {
+        let mut peers = self.unbanned()?;
+        let excess = (peers.len() as u32).saturating_sub(limit) as usize;
+        // Peers that were never tried are removed first
+        peers.sort_by_key(|(_, record)| record[10]);
+        let tx = self.db.begin_write()?;
+        {
+            let mut table = tx.open_table(PEERS)?;
+            for (addr, _) in peers.iter().take(excess) {
+                table.remove(addr.as_slice())?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn compact(&mut self, target: PeerStoreSizeConfig) -> Result<(), RedbPeerStoreError> {
+        if let PeerStoreSizeConfig::Limit(limit) = target {
+            self.remove_excess(limit)?;
+        }
+        self.db.compact().map_err(redb::Error::from)?;
+        Ok(())
+    }
+}
+
+impl PeerStore for RedbPeerDb {
+    type Error = RedbPeerStoreError;
+
+    fn update(&mut self, peer: PersistedPeer) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.update(peer))
+    }
+
+    fn random(&mut self) -> FutureResult<'_, PersistedPeer, Self::Error> {
+        Box::pin(self.random())
+    }
+
+    fn num_unbanned(&mut self) -> FutureResult<'_, u32, Self::Error> {
+        Box::pin(self.num_unbanned())
+    }
+
+    fn compact(&mut self, target: PeerStoreSizeConfig) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.compact(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bitcoin::p2p::address::AddrV2;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_redb_peer_store() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut peer_store = RedbPeerDb::new(Network::Testnet, Some(path.into())).unwrap();
+        assert!(peer_store.random().await.is_err());
+        let ip_1 = Ipv4Addr::new(1, 1, 1, 1);
+        let ip_2 = Ipv4Addr::new(2, 2, 2, 2);
+        let tor = AddrV2::TorV2([8; 10]);
+        let peer_1 = PersistedPeer::new(
+            AddrV2::Ipv4(ip_1),
+            0,
+            ServiceFlags::NONE,
+            PeerStatus::Gossiped,
+        );
+        let peer_2 = PersistedPeer::new(
+            AddrV2::Ipv4(ip_2),
+            0,
+            ServiceFlags::NONE,
+            PeerStatus::Gossiped,
+        );
+        let peer_3 = PersistedPeer::new(tor, 0, ServiceFlags::NONE, PeerStatus::Gossiped);
+        let try_peer_2 =
+            PersistedPeer::new(AddrV2::Ipv4(ip_2), 0, ServiceFlags::NONE, PeerStatus::Tried);
+        let ban_peer_1 =
+            PersistedPeer::new(AddrV2::Ipv4(ip_1), 0, ServiceFlags::NONE, PeerStatus::Ban);
+        peer_store.update(peer_1).await.unwrap();
+        peer_store.update(peer_2).await.unwrap();
+        peer_store.update(peer_3).await.unwrap();
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 3);
+        peer_store.update(try_peer_2).await.unwrap();
+        peer_store.update(ban_peer_1.clone()).await.unwrap();
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 2);
+        for _ in 0..5 {
+            let random = peer_store.random().await.unwrap();
+            assert_ne!(ban_peer_1.addr, random.addr);
+        }
+        // A gossiped peer keeps the status it is known by
+        let gossip_peer_2 = PersistedPeer::new(
+            AddrV2::Ipv4(ip_2),
+            2,
+            ServiceFlags::NETWORK_LIMITED,
+            PeerStatus::Gossiped,
+        );
+        peer_store.update(gossip_peer_2).await.unwrap();
+        // The untried peer is removed first, and the peers remain after the store is opened again
+        peer_store
+            .compact(PeerStoreSizeConfig::Limit(1))
+            .await
+            .unwrap();
+        drop(peer_store);
+        let mut peer_store = RedbPeerDb::new(Network::Testnet, Some(path.into())).unwrap();
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 1);
+        let peer = peer_store.random().await.unwrap();
+        assert_eq!(peer.addr, AddrV2::Ipv4(ip_2));
+        assert!(matches!(peer.status, PeerStatus::Tried));
+        assert_eq!(peer.port, 2);
+        assert_eq!(peer.services, ServiceFlags::NETWORK_LIMITED);
+        drop(peer_store);
+        binding.close().unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bitcoin::{consensus, Network};
+use rusqlite::{params, Connection, Result};
+use tokio::sync::Mutex;
+
+use crate::db::error::{SqlCFHeaderStoreError, SqlInitializationError};
+use crate::db::traits::CFHeaderStore;
+use crate::db::PersistedFilterHeader;
+use crate::prelude::FutureResult;
+
+use super::{lock_exclusive, DATA_DIR, DEFAULT_CWD};
+
+pub(crate) const FILE_NAME: &str = "cfheaders.db";
+// Labels for the schema table
+const SCHEMA_TABLE_NAME: &str = "cfheader_schema_versions";
+const SCHEMA_COLUMN: &str = "schema_key";
+const VERSION_COLUMN: &str = "version";
+const SCHEMA_KEY: &str = "current_version";
+// Update this in the case of schema changes
+const SCHEMA_VERSION: u8 = 0;
+// Always execute this query and adjust the schema with migrations
+const INITIAL_CFHEADER_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS cfheaders (
+    height INTEGER PRIMARY KEY,
+    block_hash BLOB NOT NULL,
+    filter_hash BLOB NOT NULL,
+    prev_filter_header BLOB NOT NULL
+) STRICT";
+
+/// Compact filter header storage implementation with SQL Lite.
+#[derive(Debug)]
+pub struct SqliteCFHeaderDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteCFHeaderDb {
+    /// Create a new [`SqliteCFHeaderDb`] with an optional file path. If no path is provided,
+    /// the file will be stored in a `data` subdirectory where the program is ran.
+    pub fn new(network: Network, path: Option<PathBuf>) -> Result<Self, SqlInitializationError> {
+        let mut path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CWD));
+        path.push(DATA_DIR);
+        path.push(network.to_string());
+        if !path.exists() {
+            fs::create_dir_all(&path)?;
+        }
+        let conn = Connection::open(path.join(FILE_NAME))?;
+        lock_exclusive(&conn)?;
+        // Create the schema version
+        let schema_table_query = format!(
+            "CREATE TABLE IF NOT EXISTS {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN} TEXT PRIMARY KEY, {VERSION_COLUMN} INTEGER NOT NULL)");
+        // Update the schema version
+        conn.execute(&schema_table_query, [])?;
+        let schema_init_version = format!(
+            "INSERT OR REPLACE INTO {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN}, {VERSION_COLUMN}) VALUES (?1, ?2)");
+        conn.execute(&schema_init_version, params![SCHEMA_KEY, SCHEMA_VERSION])?;
+        // Build the table if it doesn't exist
+        conn.execute(INITIAL_CFHEADER_SCHEMA, [])?;
+        // Migrate to any new schema versions
+        Self::migrate(&conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // This function currently does nothing, but if new columns are required this may be used to alter the tables
+    // without breaking older tables.
+    fn migrate(conn: &Connection) -> Result<(), SqlInitializationError> {
+        let version_query =
+            format!("SELECT {VERSION_COLUMN} FROM {SCHEMA_TABLE_NAME} WHERE {SCHEMA_COLUMN} = ?1");
+        let _current_version: u8 =
+            conn.query_row(&version_query, [SCHEMA_KEY], |row| row.get(0))?;
+        // Match on the version and migrate to new schemas in the future
+        Ok(())
+    }
+
+    async fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> Result<(), SqlCFHeaderStoreError> {
+        let mut write_lock = self.conn.lock().await;
+        let tx = write_lock.transaction()?;
+        for (height, filter_header) in filter_headers {
+            let block_hash: Vec<u8> = consensus::serialize(&filter_header.block_hash);
+            let filter_hash: Vec<u8> = consensus::serialize(&filter_header.filter_hash);
+            let prev_filter_header: Vec<u8> =
+                consensus::serialize(&filter_header.prev_filter_header);
+            let stmt = "INSERT OR REPLACE INTO cfheaders (height, block_hash, filter_hash, prev_filter_header) VALUES (?1, ?2, ?3, ?4)";
+            tx.execute(
+                stmt,
+                params![height, block_hash, filter_hash, prev_filter_header],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> Result<BTreeMap<u32, PersistedFilterHeader>, SqlCFHeaderStoreError> {
+        let read_lock = self.conn.lock().await;
+        let stmt = "SELECT height, block_hash, filter_hash, prev_filter_header FROM cfheaders WHERE height >= ?1 ORDER BY height";
+        let mut query = read_lock.prepare(stmt)?;
+        let mut rows = query.query(params![start_height])?;
+        let mut filter_headers = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let height: u32 = row.get(0)?;
+            let block_hash: [u8; 32] = row.get(1)?;
+            let filter_hash: [u8; 32] = row.get(2)?;
+            let prev_filter_header: [u8; 32] = row.get(3)?;
+            let filter_header = PersistedFilterHeader {
+                block_hash: consensus::deserialize(&block_hash)?,
+                filter_hash: consensus::deserialize(&filter_hash)?,
+                prev_filter_header: consensus::deserialize(&prev_filter_header)?,
+            };
+            filter_headers.insert(height, filter_header);
+        }
+        Ok(filter_headers)
+    }
+}
+
+impl CFHeaderStore for SqliteCFHeaderDb {
+    type Error = SqlCFHeaderStoreError;
+
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.write_filter_headers(filter_headers))
+    }
+
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<'_, BTreeMap<u32, PersistedFilterHeader>, Self::Error> {
+        Box::pin(self.load_filter_headers(start_height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, BlockHash, FilterHash, FilterHeader};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sql_cfheader_store() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut db = SqliteCFHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        assert!(db.load_filter_headers(0).await.unwrap().is_empty());
+        let first = PersistedFilterHeader::new(
+            BlockHash::from_byte_array([1; 32]),
+            FilterHash::from_byte_array([1; 32]),
+            FilterHeader::all_zeros(),
+        );
+        let second = PersistedFilterHeader::new(
+            BlockHash::from_byte_array([2; 32]),
+            FilterHash::from_byte_array([2; 32]),
+            first.filter_header(),
+        );
+        db.write_filter_headers([(10, first), (11, second)].into())
+            .await
+            .unwrap();
+        // The filter headers remain after the store is opened again
+        drop(db);
+        let mut db = SqliteCFHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let loaded = db.load_filter_headers(0).await.unwrap();
+        assert_eq!(loaded.get(&10), Some(&first));
+        assert_eq!(loaded.get(&11), Some(&second));
+        // A reorganized block replaces the filter header at its height
+        let reorganized = PersistedFilterHeader {
+            block_hash: BlockHash::from_byte_array([3; 32]),
+            ..second
+        };
+        db.write_filter_headers([(11, reorganized)].into())
+            .await
+            .unwrap();
+        let loaded = db.load_filter_headers(11).await.unwrap();
+        assert_eq!(
+            loaded.into_iter().collect::<Vec<_>>(),
+            vec![(11, reorganized)]
+        );
+    }
+}
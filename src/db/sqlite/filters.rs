@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bitcoin::bip158::BlockFilter;
+use bitcoin::{consensus, BlockHash, Network};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tokio::sync::Mutex;
+
+use crate::db::error::{SqlFilterStoreError, SqlInitializationError};
+use crate::db::traits::FilterStore;
+use crate::prelude::FutureResult;
+
+use super::{lock_exclusive, DATA_DIR, DEFAULT_CWD};
+
+pub(crate) const FILE_NAME: &str = "filters.db";
+// Labels for the schema table
+const SCHEMA_TABLE_NAME: &str = "filter_schema_versions";
+const SCHEMA_COLUMN: &str = "schema_key";
+const VERSION_COLUMN: &str = "version";
+const SCHEMA_KEY: &str = "current_version";
+// Update this in the case of schema changes
+const SCHEMA_VERSION: u8 = 0;
+// Always execute this query and adjust the schema with migrations
+const INITIAL_FILTER_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS filters (
+    block_hash BLOB PRIMARY KEY,
+    filter BLOB NOT NULL
+) STRICT";
+
+/// Compact block filter storage implementation with SQL Lite.
+#[derive(Debug)]
+pub struct SqliteFilterDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteFilterDb {
+    /// Create a new [`SqliteFilterDb`] with an optional file path. If no path is provided,
+    /// the file will be stored in a `data` subdirectory where the program is ran.
+    pub fn new(network: Network, path: Option<PathBuf>) -> Result<Self, SqlInitializationError> {
+        let mut path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CWD));
+        path.push(DATA_DIR);
+        path.push(network.to_string());
+        if !path.exists() {
+            fs::create_dir_all(&path)?;
+        }
+        let conn = Connection::open(path.join(FILE_NAME))?;
+        lock_exclusive(&conn)?;
+        // Create the schema version
+        let schema_table_query = format!(
+            "CREATE TABLE IF NOT EXISTS {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN} TEXT PRIMARY KEY, {VERSION_COLUMN} INTEGER NOT NULL)");
+        // Update the schema version
+        conn.execute(&schema_table_query, [])?;
+        let schema_init_version = format!(
+            "INSERT OR REPLACE INTO {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN}, {VERSION_COLUMN}) VALUES (?1, ?2)");
+        conn.execute(&schema_init_version, params![SCHEMA_KEY, SCHEMA_VERSION])?;
+        // Build the table if it doesn't exist
+        conn.execute(INITIAL_FILTER_SCHEMA, [])?;
+        // Migrate to any new schema versions
+        Self::migrate(&conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // This function currently does nothing, but if new columns are required this may be used to alter the tables
+    // without breaking older tables.
+    fn migrate(conn: &Connection) -> Result<(), SqlInitializationError> {
+        let version_query =
+            format!("SELECT {VERSION_COLUMN} FROM {SCHEMA_TABLE_NAME} WHERE {SCHEMA_COLUMN} = ?1");
+        let _current_version: u8 =
+            conn.query_row(&version_query, [SCHEMA_KEY], |row| row.get(0))?;
+        // Match on the version and migrate to new schemas in the future
+        Ok(())
+    }
+
+    async fn write_filters(
+        &mut self,
+        filters: Vec<(BlockHash, BlockFilter)>,
+    ) -> Result<(), SqlFilterStoreError> {
+        let mut write_lock = self.conn.lock().await;
+        let tx = write_lock.transaction()?;
+        for (block_hash, filter) in filters {
+            let hash: Vec<u8> = consensus::serialize(&block_hash);
+            let stmt = "INSERT OR REPLACE INTO filters (block_hash, filter) VALUES (?1, ?2)";
+            tx.execute(stmt, params![hash, filter.content])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn filter(
+        &mut self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockFilter>, SqlFilterStoreError> {
+        let hash: Vec<u8> = consensus::serialize(&block_hash);
+        let read_lock = self.conn.lock().await;
+        let stmt = "SELECT filter FROM filters WHERE block_hash = ?1";
+        let content: Option<Vec<u8>> = read_lock
+            .query_row(stmt, params![hash], |row| row.get(0))
+            .optional()?;
+        Ok(content.map(|content| BlockFilter { content }))
+    }
+}
+
+impl FilterStore for SqliteFilterDb {
+    type Error = SqlFilterStoreError;
+
+    fn write_filters(
+        &mut self,
+        filters: Vec<(BlockHash, BlockFilter)>,
+    ) -> FutureResult<'_, (), Self::Error> {
+        Box::pin(self.write_filters(filters))
+    }
+
+    fn filter(
+        &mut self,
+        block_hash: BlockHash,
+    ) -> FutureResult<'_, Option<BlockFilter>, Self::Error> {
+        Box::pin(self.filter(block_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sql_filter_store() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut db = SqliteFilterDb::new(Network::Regtest, Some(path.into())).unwrap();
+        let block_hash = BlockHash::from_byte_array([1; 32]);
+        assert!(db.filter(block_hash).await.unwrap().is_none());
+        let filter = BlockFilter::new(&[0x01, 0x02, 0x03]);
+        db.write_filters(vec![(block_hash, filter.clone())])
+            .await
+            .unwrap();
+        assert_eq!(db.filter(block_hash).await.unwrap(), Some(filter));
+        // The filters remain after the store is opened again
+        drop(db);
+        let mut db = SqliteFilterDb::new(Network::Regtest, Some(path.into())).unwrap();
+        assert!(db.filter(block_hash).await.unwrap().is_some());
+        let other = BlockHash::from_byte_array([2; 32]);
+        assert!(db.filter(other).await.unwrap().is_none());
+    }
+}
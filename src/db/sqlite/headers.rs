@@ -5,18 +5,19 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use bitcoin::block::Header;
-use bitcoin::{consensus, BlockHash, Network};
-use rusqlite::{params, params_from_iter, Connection, Result};
+use bitcoin::{consensus, BlockHash, Network, ScriptBuf, Txid};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
 use tokio::sync::Mutex;
 
 use crate::db::error::{SqlHeaderStoreError, SqlInitializationError};
 use crate::db::traits::HeaderStore;
-use crate::db::BlockHeaderChanges;
+use crate::db::{BlockHeaderChanges, PersistedBroadcast, PersistedUtxo};
 use crate::prelude::FutureResult;
+use crate::{HeaderCheckpoint, ScriptTx, TxBroadcast, TxBroadcastPolicy, Utxo};
 
-use super::{DATA_DIR, DEFAULT_CWD};
+use super::{lock_exclusive, DATA_DIR, DEFAULT_CWD};
 
-const FILE_NAME: &str = "headers.db";
+pub(crate) const FILE_NAME: &str = "headers.db";
 // Labels for the schema table
 const SCHEMA_TABLE_NAME: &str = "header_schema_versions";
 const SCHEMA_COLUMN: &str = "schema_key";
@@ -30,6 +31,53 @@ const INITIAL_HEADER_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS headers (
     block_hash BLOB NOT NULL,
     header BLOB NOT NULL
 ) STRICT";
+// Transactions found for scripts, removed with the header of the block they were found in
+const SCRIPT_HISTORY_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS script_history (
+    script BLOB NOT NULL,
+    txid BLOB NOT NULL,
+    height INTEGER NOT NULL,
+    block_hash BLOB NOT NULL,
+    vout INTEGER
+) STRICT";
+
+// The first block found to pay to a script, in a single row
+const BIRTHDAY_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS birthday (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    height INTEGER NOT NULL,
+    block_hash BLOB NOT NULL
+) STRICT";
+
+// Transactions broadcast that have not been seen in a block, replaced on every write
+const UNCONFIRMED_BROADCASTS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS unconfirmed_broadcasts (
+    txid BLOB PRIMARY KEY,
+    tx BLOB NOT NULL,
+    all_peers INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1
+) STRICT";
+
+// Transactions broadcast that were seen in a block recently, replaced on every write
+const CONFIRMED_BROADCASTS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS confirmed_broadcasts (
+    txid BLOB PRIMARY KEY,
+    tx BLOB NOT NULL,
+    all_peers INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    block_hash BLOB NOT NULL
+) STRICT";
+
+// Outputs paying to scripts that are unspent or spent recently, replaced on every write
+const UTXOS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS utxos (
+    outpoint BLOB PRIMARY KEY,
+    txout BLOB NOT NULL,
+    height INTEGER NOT NULL,
+    block_hash BLOB NOT NULL,
+    spent_height INTEGER,
+    spent_block_hash BLOB
+) STRICT";
+
+// Find the height of a block hash without scanning every header
+const HEADER_HASH_INDEX: &str =
+    "CREATE INDEX IF NOT EXISTS headers_by_block_hash ON headers (block_hash)";
 
 const LOAD_QUERY_SELECT_PREFIX: &str = "SELECT * FROM headers ";
 const LOAD_QUERY_ORDERBY_SUFFIX: &str = "ORDER BY height";
@@ -53,6 +101,7 @@ impl SqliteHeaderDb {
             fs::create_dir_all(&path)?;
         }
         let conn = Connection::open(path.join(FILE_NAME))?;
+        lock_exclusive(&conn)?;
         // Create the schema version
         let schema_table_query = format!(
             "CREATE TABLE IF NOT EXISTS {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN} TEXT PRIMARY KEY, {VERSION_COLUMN} INTEGER NOT NULL)");
@@ -63,6 +112,12 @@ impl SqliteHeaderDb {
         conn.execute(&schema_init_version, params![SCHEMA_KEY, SCHEMA_VERSION])?;
         // Build the table if it doesn't exist
         conn.execute(INITIAL_HEADER_SCHEMA, [])?;
+        conn.execute(HEADER_HASH_INDEX, [])?;
+        conn.execute(SCRIPT_HISTORY_SCHEMA, [])?;
+        conn.execute(BIRTHDAY_SCHEMA, [])?;
+        conn.execute(UNCONFIRMED_BROADCASTS_SCHEMA, [])?;
+        conn.execute(CONFIRMED_BROADCASTS_SCHEMA, [])?;
+        conn.execute(UTXOS_SCHEMA, [])?;
         // Migrate to any new schema versions
         Self::migrate(&conn)?;
 
@@ -73,14 +128,24 @@ impl SqliteHeaderDb {
         })
     }
 
-    // This function currently does nothing, but if new columns are required this may be used to alter the tables
-    // without breaking older tables.
+    // If new columns are required this may be used to alter the tables without breaking older tables.
     fn migrate(conn: &Connection) -> Result<(), SqlInitializationError> {
         let version_query =
             format!("SELECT {VERSION_COLUMN} FROM {SCHEMA_TABLE_NAME} WHERE {SCHEMA_COLUMN} = ?1");
         let _current_version: u8 =
             conn.query_row(&version_query, [SCHEMA_KEY], |row| row.get(0))?;
-        // Match on the version and migrate to new schemas in the future
+        // Unconfirmed broadcasts were first written without the number of attempts
+        let has_attempts: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('unconfirmed_broadcasts') WHERE name = 'attempts'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_attempts {
+            conn.execute(
+                "ALTER TABLE unconfirmed_broadcasts ADD COLUMN attempts INTEGER NOT NULL DEFAULT 1",
+                [],
+            )?;
+        }
         Ok(())
     }
 
@@ -168,6 +233,8 @@ impl SqliteHeaderDb {
             let hash: Vec<u8> = consensus::serialize(&removed);
             let stmt = "DELETE FROM headers WHERE block_hash = ?1";
             tx.execute(stmt, params![hash])?;
+            let stmt = "DELETE FROM script_history WHERE block_hash = ?1";
+            tx.execute(stmt, params![hash])?;
         }
         for (height, header) in core::mem::take(&mut self.accepted) {
             let hash: Vec<u8> = consensus::serialize(&header.block_hash());
@@ -187,7 +254,9 @@ impl SqliteHeaderDb {
         let write_lock = self.conn.lock().await;
         let stmt = "SELECT height FROM headers WHERE block_hash = ?1";
         let hash: Vec<u8> = consensus::serialize(&block_hash);
-        let row: Option<u32> = write_lock.query_row(stmt, params![hash], |row| row.get(0))?;
+        let row: Option<u32> = write_lock
+            .query_row(stmt, params![hash], |row| row.get(0))
+            .optional()?;
         Ok(row)
     }
 
@@ -220,6 +289,225 @@ impl SqliteHeaderDb {
     }
 }
 
+impl SqliteHeaderDb {
+    async fn compact(&mut self) -> Result<(), SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        lock.execute_batch("VACUUM")?;
+        Ok(())
+    }
+
+    async fn write_script_history(
+        &mut self,
+        history: Vec<(ScriptBuf, ScriptTx)>,
+    ) -> Result<(), SqlHeaderStoreError> {
+        let mut write_lock = self.conn.lock().await;
+        let tx = write_lock.transaction()?;
+        for (script, script_tx) in history {
+            let txid: Vec<u8> = consensus::serialize(&script_tx.txid);
+            let hash: Vec<u8> = consensus::serialize(&script_tx.block_hash);
+            let stmt = "INSERT INTO script_history (script, txid, height, block_hash, vout) VALUES (?1, ?2, ?3, ?4, ?5)";
+            tx.execute(
+                stmt,
+                params![
+                    script.as_bytes(),
+                    txid,
+                    script_tx.height,
+                    hash,
+                    script_tx.vout
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn load_script_history(
+        &mut self,
+    ) -> Result<Vec<(ScriptBuf, ScriptTx)>, SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        let stmt =
+            "SELECT script, txid, height, block_hash, vout FROM script_history ORDER BY height";
+        let mut query = lock.prepare(stmt)?;
+        let mut rows = query.query([])?;
+        let mut history = Vec::new();
+        while let Some(row) = rows.next()? {
+            let script: Vec<u8> = row.get(0)?;
+            let txid: [u8; 32] = row.get(1)?;
+            let block_hash: [u8; 32] = row.get(3)?;
+            let script_tx = ScriptTx {
+                txid: consensus::deserialize::<Txid>(&txid)?,
+                height: row.get(2)?,
+                block_hash: consensus::deserialize(&block_hash)?,
+                vout: row.get(4)?,
+            };
+            history.push((ScriptBuf::from_bytes(script), script_tx));
+        }
+        Ok(history)
+    }
+
+    async fn write_birthday(
+        &mut self,
+        birthday: HeaderCheckpoint,
+    ) -> Result<(), SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        let hash: Vec<u8> = consensus::serialize(&birthday.hash);
+        let stmt = "INSERT OR REPLACE INTO birthday (id, height, block_hash) VALUES (0, ?1, ?2)";
+        lock.execute(stmt, params![birthday.height, hash])?;
+        Ok(())
+    }
+
+    async fn load_birthday(&mut self) -> Result<Option<HeaderCheckpoint>, SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        let stmt = "SELECT height, block_hash FROM birthday WHERE id = 0";
+        let row: Option<(u32, [u8; 32])> = lock
+            .query_row(stmt, [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        match row {
+            Some((height, hash)) => Ok(Some(HeaderCheckpoint::new(
+                height,
+                consensus::deserialize(&hash)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    async fn write_broadcasts(
+        &mut self,
+        broadcasts: Vec<PersistedBroadcast>,
+    ) -> Result<(), SqlHeaderStoreError> {
+        let mut write_lock = self.conn.lock().await;
+        let tx = write_lock.transaction()?;
+        tx.execute("DELETE FROM unconfirmed_broadcasts", [])?;
+        tx.execute("DELETE FROM confirmed_broadcasts", [])?;
+        for PersistedBroadcast {
+            broadcast,
+            attempts,
+            confirmed,
+        } in broadcasts
+        {
+            let txid: Vec<u8> = consensus::serialize(&broadcast.tx.compute_txid());
+            let transaction: Vec<u8> = consensus::serialize(&broadcast.tx);
+            let all_peers = matches!(broadcast.broadcast_policy, TxBroadcastPolicy::AllPeers);
+            match confirmed {
+                Some(block) => {
+                    let block_hash: Vec<u8> = consensus::serialize(&block.hash);
+                    let stmt = "INSERT INTO confirmed_broadcasts (txid, tx, all_peers, attempts, height, block_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+                    tx.execute(
+                        stmt,
+                        params![
+                            txid,
+                            transaction,
+                            all_peers,
+                            attempts,
+                            block.height,
+                            block_hash
+                        ],
+                    )?;
+                }
+                None => {
+                    let stmt = "INSERT INTO unconfirmed_broadcasts (txid, tx, all_peers, attempts) VALUES (?1, ?2, ?3, ?4)";
+                    tx.execute(stmt, params![txid, transaction, all_peers, attempts])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn load_broadcasts(&mut self) -> Result<Vec<PersistedBroadcast>, SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        let mut query = lock.prepare(
+            "SELECT tx, all_peers, attempts, NULL, NULL FROM unconfirmed_broadcasts
+            UNION ALL SELECT tx, all_peers, attempts, height, block_hash FROM confirmed_broadcasts",
+        )?;
+        let mut rows = query.query([])?;
+        let mut broadcasts = Vec::new();
+        while let Some(row) = rows.next()? {
+            let transaction: Vec<u8> = row.get(0)?;
+            let all_peers: bool = row.get(1)?;
+            let attempts: u32 = row.get(2)?;
+            let height: Option<u32> = row.get(3)?;
+            let block_hash: Option<Vec<u8>> = row.get(4)?;
+            let broadcast_policy = if all_peers {
+                TxBroadcastPolicy::AllPeers
+            } else {
+                TxBroadcastPolicy::RandomPeer
+            };
+            let broadcast =
+                TxBroadcast::new(consensus::deserialize(&transaction)?, broadcast_policy);
+            let confirmed = match (height, block_hash) {
+                (Some(height), Some(block_hash)) => Some(HeaderCheckpoint::new(
+                    height,
+                    consensus::deserialize(&block_hash)?,
+                )),
+                _ => None,
+            };
+            broadcasts.push(PersistedBroadcast::new(broadcast, attempts, confirmed));
+        }
+        Ok(broadcasts)
+    }
+
+    async fn write_utxos(&mut self, utxos: Vec<PersistedUtxo>) -> Result<(), SqlHeaderStoreError> {
+        let mut write_lock = self.conn.lock().await;
+        let tx = write_lock.transaction()?;
+        tx.execute("DELETE FROM utxos", [])?;
+        for PersistedUtxo { utxo, spent } in utxos {
+            let outpoint: Vec<u8> = consensus::serialize(&utxo.outpoint);
+            let txout: Vec<u8> = consensus::serialize(&utxo.txout);
+            let block_hash: Vec<u8> = consensus::serialize(&utxo.block_hash);
+            let spent_height = spent.map(|block| block.height);
+            let spent_block_hash: Option<Vec<u8>> =
+                spent.map(|block| consensus::serialize(&block.hash));
+            let stmt = "INSERT INTO utxos (outpoint, txout, height, block_hash, spent_height, spent_block_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+            tx.execute(
+                stmt,
+                params![
+                    outpoint,
+                    txout,
+                    utxo.height,
+                    block_hash,
+                    spent_height,
+                    spent_block_hash
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn load_utxos(&mut self) -> Result<Vec<PersistedUtxo>, SqlHeaderStoreError> {
+        let lock = self.conn.lock().await;
+        let mut query = lock.prepare(
+            "SELECT outpoint, txout, height, block_hash, spent_height, spent_block_hash FROM utxos",
+        )?;
+        let mut rows = query.query([])?;
+        let mut utxos = Vec::new();
+        while let Some(row) = rows.next()? {
+            let outpoint: Vec<u8> = row.get(0)?;
+            let txout: Vec<u8> = row.get(1)?;
+            let height: u32 = row.get(2)?;
+            let block_hash: Vec<u8> = row.get(3)?;
+            let spent_height: Option<u32> = row.get(4)?;
+            let spent_block_hash: Option<Vec<u8>> = row.get(5)?;
+            let utxo = Utxo {
+                outpoint: consensus::deserialize(&outpoint)?,
+                txout: consensus::deserialize(&txout)?,
+                height,
+                block_hash: consensus::deserialize(&block_hash)?,
+            };
+            let spent = match (spent_height, spent_block_hash) {
+                (Some(height), Some(block_hash)) => Some(HeaderCheckpoint::new(
+                    height,
+                    consensus::deserialize(&block_hash)?,
+                )),
+                _ => None,
+            };
+            utxos.push(PersistedUtxo::new(utxo, spent));
+        }
+        Ok(utxos)
+    }
+}
+
 impl HeaderStore for SqliteHeaderDb {
     type Error = SqlHeaderStoreError;
 
@@ -252,6 +540,48 @@ impl HeaderStore for SqliteHeaderDb {
     fn header_at(&mut self, height: u32) -> FutureResult<Option<Header>, Self::Error> {
         Box::pin(self.header_at(height))
     }
+
+    fn compact(&mut self) -> FutureResult<(), Self::Error> {
+        Box::pin(self.compact())
+    }
+
+    fn write_script_history(
+        &mut self,
+        history: Vec<(ScriptBuf, ScriptTx)>,
+    ) -> FutureResult<(), Self::Error> {
+        Box::pin(self.write_script_history(history))
+    }
+
+    fn load_script_history(&mut self) -> FutureResult<Vec<(ScriptBuf, ScriptTx)>, Self::Error> {
+        Box::pin(self.load_script_history())
+    }
+
+    fn write_birthday(&mut self, birthday: HeaderCheckpoint) -> FutureResult<(), Self::Error> {
+        Box::pin(self.write_birthday(birthday))
+    }
+
+    fn load_birthday(&mut self) -> FutureResult<Option<HeaderCheckpoint>, Self::Error> {
+        Box::pin(self.load_birthday())
+    }
+
+    fn write_broadcasts(
+        &mut self,
+        broadcasts: Vec<PersistedBroadcast>,
+    ) -> FutureResult<(), Self::Error> {
+        Box::pin(self.write_broadcasts(broadcasts))
+    }
+
+    fn load_broadcasts(&mut self) -> FutureResult<Vec<PersistedBroadcast>, Self::Error> {
+        Box::pin(self.load_broadcasts())
+    }
+
+    fn write_utxos(&mut self, utxos: Vec<PersistedUtxo>) -> FutureResult<(), Self::Error> {
+        Box::pin(self.write_utxos(utxos))
+    }
+
+    fn load_utxos(&mut self) -> FutureResult<Vec<PersistedUtxo>, Self::Error> {
+        Box::pin(self.load_utxos())
+    }
 }
 
 #[cfg(test)]
@@ -260,6 +590,7 @@ mod tests {
 
     use super::*;
     use bitcoin::consensus::deserialize;
+    use bitcoin::hashes::Hash;
 
     #[tokio::test]
     async fn test_sql_header_store_normal_use() {
@@ -287,6 +618,11 @@ mod tests {
         assert_eq!(get_hash_9, block_hash_9);
         let get_height_8 = db.height_of(&block_hash_8).await.unwrap().unwrap();
         assert_eq!(get_height_8, 8);
+        assert!(db
+            .height_of(&BlockHash::all_zeros())
+            .await
+            .unwrap()
+            .is_none());
         let load = db.load(7..).await.unwrap();
 
         assert_eq!(map, load);
@@ -322,6 +658,19 @@ mod tests {
         assert!(w.is_ok());
         let get_height_10 = db.header_at(10).await.unwrap().unwrap();
         assert_eq!(block_10, get_height_10);
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        let found = |height: u32, block_hash: BlockHash| ScriptTx {
+            txid: Txid::from_byte_array([height as u8; 32]),
+            height,
+            block_hash,
+            vout: (height == 9).then_some(1),
+        };
+        let history = vec![
+            (script.clone(), found(9, block_9.block_hash())),
+            (script.clone(), found(10, block_10.block_hash())),
+        ];
+        db.write_script_history(history.clone()).await.unwrap();
+        assert_eq!(db.load_script_history().await.unwrap(), history);
         let new_block_10: Header = deserialize(&hex::decode("000000201d062f2162835787db536c55317e08df17c58078c7610328bdced198574093792151c0e9ce4e4c789ca98427d7740cc7acf30d2ca0c08baef266bf152289d814567e5e66ffff7f2001000000").unwrap()).unwrap();
         let block_11: Header = deserialize(&hex::decode("00000020efcf8b12221fccc735b9b0b657ce15b31b9c50aff530ce96a5b4cfe02d8c0068496c1b8a89cf5dec22e46c35ea1035f80f5b666a1b3aa7f3d6f0880d0061adcc567e5e66ffff7f2001000000").unwrap()).unwrap();
         let mut map = BTreeMap::new();
@@ -355,6 +704,57 @@ mod tests {
         map.insert(11, block_11);
         let load = db.load(7..).await.unwrap();
         assert_eq!(map, load);
+        // Transactions in the block that was reorganized are removed
+        assert_eq!(db.load_script_history().await.unwrap(), history[..1]);
+        assert!(db.load_birthday().await.unwrap().is_none());
+        let birthday = HeaderCheckpoint::new(9, block_9.block_hash());
+        db.write_birthday(birthday).await.unwrap();
+        db.write_birthday(birthday).await.unwrap();
+        let loaded = db.load_birthday().await.unwrap().unwrap();
+        assert_eq!(loaded.height, 9);
+        assert_eq!(loaded.hash, block_9.block_hash());
+        assert!(db.load_broadcasts().await.unwrap().is_empty());
+        let coinbase = bitcoin::constants::genesis_block(Network::Regtest).txdata[0].clone();
+        let broadcast = TxBroadcast::new(coinbase.clone(), TxBroadcastPolicy::AllPeers);
+        let block_9 = HeaderCheckpoint::new(9, block_9.block_hash());
+        db.write_broadcasts(vec![
+            PersistedBroadcast::new(broadcast.clone(), 3, None),
+            PersistedBroadcast::new(broadcast, 1, Some(block_9)),
+        ])
+        .await
+        .unwrap();
+        let mut loaded = db.load_broadcasts().await.unwrap();
+        assert_eq!(loaded.len(), 2);
+        loaded.sort_by_key(|broadcast| broadcast.confirmed.is_some());
+        assert_eq!(loaded[1].confirmed, Some(block_9));
+        assert_eq!(loaded[0].broadcast.tx, coinbase);
+        assert!(matches!(
+            loaded[0].broadcast.broadcast_policy,
+            TxBroadcastPolicy::AllPeers
+        ));
+        assert_eq!(loaded[0].attempts, 3);
+        db.write_broadcasts(Vec::new()).await.unwrap();
+        assert!(db.load_broadcasts().await.unwrap().is_empty());
+        assert!(db.load_utxos().await.unwrap().is_empty());
+        let utxo = Utxo {
+            outpoint: bitcoin::OutPoint::new(coinbase.compute_txid(), 0),
+            txout: coinbase.output[0].clone(),
+            height: 8,
+            block_hash: block_8.block_hash(),
+        };
+        let spent = Utxo {
+            outpoint: bitcoin::OutPoint::new(coinbase.compute_txid(), 1),
+            ..utxo.clone()
+        };
+        let utxos = vec![
+            PersistedUtxo::new(utxo, None),
+            PersistedUtxo::new(spent, Some(block_9)),
+        ];
+        db.write_utxos(utxos[1..].to_vec()).await.unwrap();
+        db.write_utxos(utxos.clone()).await.unwrap();
+        let mut loaded = db.load_utxos().await.unwrap();
+        loaded.sort_by_key(|utxo| utxo.spent.is_some());
+        assert_eq!(loaded, utxos);
         drop(db);
         binding.close().unwrap();
     }
@@ -391,4 +791,20 @@ mod tests {
         drop(db);
         binding.close().unwrap();
     }
+
+    #[test]
+    fn test_data_dir_is_locked() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let db = SqliteHeaderDb::new(Network::Regtest, Some(path.into())).unwrap();
+        assert!(matches!(
+            SqliteHeaderDb::new(Network::Regtest, Some(path.into())),
+            Err(SqlInitializationError::Locked)
+        ));
+        drop(db);
+        let db = SqliteHeaderDb::new(Network::Regtest, Some(path.into()));
+        assert!(db.is_ok());
+        drop(db);
+        binding.close().unwrap();
+    }
 }
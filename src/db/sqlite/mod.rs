@@ -1,7 +1,30 @@
+/// SQL compact filter header storage.
+pub mod cfheaders;
+/// SQL compact block filter storage.
+pub mod filters;
 /// SQL block header storage.
 pub mod headers;
 /// SQL peer storage.
 pub mod peers;
 
-pub(crate) const DEFAULT_CWD: &str = ".";
-pub(crate) const DATA_DIR: &str = "light_client_data";
+pub(crate) use super::{DATA_DIR, DEFAULT_CWD};
+
+use std::time::Duration;
+
+use rusqlite::{Connection, ErrorCode};
+
+use super::error::SqlInitializationError;
+
+// Hold an exclusive lock on the database file for the lifetime of the connection, so another
+// instance of the node cannot write to the same data directory.
+pub(crate) fn lock_exclusive(conn: &Connection) -> Result<(), SqlInitializationError> {
+    conn.busy_timeout(Duration::ZERO)?;
+    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")?;
+    conn.execute_batch("BEGIN EXCLUSIVE; COMMIT;")
+        .map_err(|e| match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) => {
+                SqlInitializationError::Locked
+            }
+            _ => SqlInitializationError::SQL(e),
+        })
+}
@@ -12,10 +12,11 @@ use crate::db::error::{SqlInitializationError, SqlPeerStoreError};
 use crate::db::traits::PeerStore;
 use crate::db::{PeerStatus, PersistedPeer};
 use crate::prelude::FutureResult;
+use crate::PeerStoreSizeConfig;
 
-use super::{DATA_DIR, DEFAULT_CWD};
+use super::{lock_exclusive, DATA_DIR, DEFAULT_CWD};
 
-const FILE_NAME: &str = "peers.db";
+pub(crate) const FILE_NAME: &str = "peers.db";
 // Labels for the schema table
 const SCHEMA_TABLE_NAME: &str = "peer_schema_versions";
 const SCHEMA_COLUMN: &str = "schema_key";
@@ -50,6 +51,7 @@ impl SqlitePeerDb {
             fs::create_dir_all(&path)?
         }
         let conn = Connection::open(path.join(FILE_NAME))?;
+        lock_exclusive(&conn)?;
         // Create the schema version
         let schema_table_query = format!("CREATE TABLE IF NOT EXISTS {SCHEMA_TABLE_NAME} ({SCHEMA_COLUMN} TEXT PRIMARY KEY, {VERSION_COLUMN} INTEGER NOT NULL)");
         // Update the schema version
@@ -127,6 +129,21 @@ impl SqlitePeerDb {
         let count: u32 = stmt.query_row([], |row| row.get(0))?;
         Ok(count)
     }
+
+    async fn compact(&mut self, target: PeerStoreSizeConfig) -> Result<(), SqlPeerStoreError> {
+        if let PeerStoreSizeConfig::Limit(limit) = target {
+            let excess = self.num_unbanned().await?.saturating_sub(limit);
+            // Peers that were never tried are removed first
+            let lock = self.conn.lock().await;
+            lock.execute(
+                "DELETE FROM peers WHERE ip_addr IN (SELECT ip_addr FROM peers WHERE banned = false ORDER BY tried, RANDOM() LIMIT ?1)",
+                params![excess],
+            )?;
+        }
+        let lock = self.conn.lock().await;
+        lock.execute_batch("VACUUM")?;
+        Ok(())
+    }
 }
 
 impl PeerStore for SqlitePeerDb {
@@ -142,6 +159,10 @@ impl PeerStore for SqlitePeerDb {
     fn num_unbanned(&mut self) -> FutureResult<u32, Self::Error> {
         Box::pin(self.num_unbanned())
     }
+
+    fn compact(&mut self, target: PeerStoreSizeConfig) -> FutureResult<(), Self::Error> {
+        Box::pin(self.compact(target))
+    }
 }
 
 #[cfg(test)]
@@ -238,4 +259,50 @@ mod tests {
         drop(peer_store);
         binding.close().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_compact_to_target_size() {
+        let binding = tempfile::tempdir().unwrap();
+        let path = binding.path();
+        let mut peer_store =
+            SqlitePeerDb::new(bitcoin::Network::Testnet, Some(path.into())).unwrap();
+        let tried = PersistedPeer::new(
+            AddrV2::Ipv4(Ipv4Addr::new(1, 1, 1, 1)),
+            0,
+            ServiceFlags::NONE,
+            PeerStatus::Tried,
+        );
+        let banned = PersistedPeer::new(
+            AddrV2::Ipv4(Ipv4Addr::new(2, 2, 2, 2)),
+            0,
+            ServiceFlags::NONE,
+            PeerStatus::Ban,
+        );
+        peer_store.update(tried.clone()).await.unwrap();
+        peer_store.update(banned).await.unwrap();
+        for i in 3..10 {
+            let gossiped = PersistedPeer::new(
+                AddrV2::Ipv4(Ipv4Addr::new(i, i, i, i)),
+                0,
+                ServiceFlags::NONE,
+                PeerStatus::Gossiped,
+            );
+            peer_store.update(gossiped).await.unwrap();
+        }
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 8);
+        peer_store
+            .compact(PeerStoreSizeConfig::Unbounded)
+            .await
+            .unwrap();
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 8);
+        peer_store
+            .compact(PeerStoreSizeConfig::Limit(1))
+            .await
+            .unwrap();
+        assert_eq!(peer_store.num_unbanned().await.unwrap(), 1);
+        let random = peer_store.random().await.unwrap();
+        assert_eq!(random.addr, tried.addr);
+        drop(peer_store);
+        binding.close().unwrap();
+    }
 }
@@ -2,11 +2,13 @@ use std::fmt::Debug;
 use std::ops::RangeBounds;
 use std::{collections::BTreeMap, fmt::Display};
 
-use bitcoin::{block::Header, BlockHash};
+use bitcoin::{bip158::BlockFilter, block::Header, BlockHash, ScriptBuf};
 
-use crate::prelude::FutureResult;
+use crate::{prelude::FutureResult, HeaderCheckpoint, PeerStoreSizeConfig, ScriptTx};
 
-use super::{BlockHeaderChanges, PersistedPeer};
+use super::{
+    BlockHeaderChanges, PersistedBroadcast, PersistedFilterHeader, PersistedPeer, PersistedUtxo,
+};
 
 /// Methods required to persist the chain of block headers.
 pub trait HeaderStore: Debug + Send + Sync {
@@ -35,6 +37,93 @@ pub trait HeaderStore: Debug + Send + Sync {
 
     /// Return the header at the height in the database, if it exists.
     fn header_at(&mut self, height: u32) -> FutureResult<Option<Header>, Self::Error>;
+
+    /// Reclaim unused space in the store. By default, this does nothing.
+    fn compact(&mut self) -> FutureResult<(), Self::Error> {
+        async fn do_compact<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_compact())
+    }
+
+    /// Write transactions found for scripts, when the node is configured to index scripts.
+    /// Transactions confirmed in a block removed by [`BlockHeaderChanges::Reorganized`] should
+    /// no longer be loaded. By default, the history is not persisted.
+    fn write_script_history(
+        &mut self,
+        _history: Vec<(ScriptBuf, ScriptTx)>,
+    ) -> FutureResult<(), Self::Error> {
+        async fn do_write_script_history<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_write_script_history())
+    }
+
+    /// Load every transaction found for scripts. By default, there is no history.
+    fn load_script_history(&mut self) -> FutureResult<Vec<(ScriptBuf, ScriptTx)>, Self::Error> {
+        async fn do_load_script_history<E>() -> Result<Vec<(ScriptBuf, ScriptTx)>, E> {
+            Ok(Vec::new())
+        }
+        Box::pin(do_load_script_history())
+    }
+
+    /// Write the first block found to pay to a script of the node, when the node is configured
+    /// to detect it. By default, the birthday is not persisted.
+    fn write_birthday(&mut self, _birthday: HeaderCheckpoint) -> FutureResult<(), Self::Error> {
+        async fn do_write_birthday<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_write_birthday())
+    }
+
+    /// Load the first block found to pay to a script of the node. By default, there is none.
+    fn load_birthday(&mut self) -> FutureResult<Option<HeaderCheckpoint>, Self::Error> {
+        async fn do_load_birthday<E>() -> Result<Option<HeaderCheckpoint>, E> {
+            Ok(None)
+        }
+        Box::pin(do_load_birthday())
+    }
+
+    /// Write the transactions broadcast by the node that have not been seen in a block, or were
+    /// confirmed recently, replacing the transactions written before. By default, the
+    /// transactions are not persisted.
+    fn write_broadcasts(
+        &mut self,
+        _broadcasts: Vec<PersistedBroadcast>,
+    ) -> FutureResult<(), Self::Error> {
+        async fn do_write_broadcasts<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_write_broadcasts())
+    }
+
+    /// Load the transactions broadcast by the node that had not been seen in a block, or were
+    /// confirmed recently, when it last stopped. By default, there are none.
+    fn load_broadcasts(&mut self) -> FutureResult<Vec<PersistedBroadcast>, Self::Error> {
+        async fn do_load_broadcasts<E>() -> Result<Vec<PersistedBroadcast>, E> {
+            Ok(Vec::new())
+        }
+        Box::pin(do_load_broadcasts())
+    }
+
+    /// Write the outputs paying to the scripts of the node that are unspent, or were spent
+    /// recently, replacing the outputs written before, when the node is configured to track
+    /// unspent outputs. By default, the outputs are not persisted.
+    fn write_utxos(&mut self, _utxos: Vec<PersistedUtxo>) -> FutureResult<(), Self::Error> {
+        async fn do_write_utxos<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_write_utxos())
+    }
+
+    /// Load the outputs paying to the scripts of the node that were unspent, or spent recently,
+    /// when it last stopped. By default, there are none.
+    fn load_utxos(&mut self) -> FutureResult<Vec<PersistedUtxo>, Self::Error> {
+        async fn do_load_utxos<E>() -> Result<Vec<PersistedUtxo>, E> {
+            Ok(Vec::new())
+        }
+        Box::pin(do_load_utxos())
+    }
 }
 
 /// Methods that define a list of peers on the Bitcoin P2P network.
@@ -49,13 +138,144 @@ pub trait PeerStore: Debug + Send + Sync {
 
     /// The number of peers in the database that are not marked as banned.
     fn num_unbanned(&mut self) -> FutureResult<u32, Self::Error>;
+
+    /// Remove peers beyond the target size of the store and reclaim unused space.
+    /// By default, this does nothing.
+    fn compact(&mut self, _target: PeerStoreSizeConfig) -> FutureResult<(), Self::Error> {
+        async fn do_compact<E>() -> Result<(), E> {
+            Ok(())
+        }
+        Box::pin(do_compact())
+    }
+}
+
+/// Methods required to persist compact block filters, so the filters checked by the node may be
+/// checked again for new scripts without downloading them from peers.
+pub trait FilterStore: Debug + Send + Sync {
+    /// Errors that may occur within a [`FilterStore`].
+    type Error: Debug + Display;
+    /// Write filters checked by the node, keyed by the hash of their block. Filters loaded from
+    /// the store are checked against the filter headers of the chain of most work, so the
+    /// filters of blocks removed by a reorganization do not have to be removed.
+    fn write_filters(
+        &mut self,
+        filters: Vec<(BlockHash, BlockFilter)>,
+    ) -> FutureResult<(), Self::Error>;
+
+    /// Load the filter of a block, if it was written.
+    fn filter(&mut self, block_hash: BlockHash) -> FutureResult<Option<BlockFilter>, Self::Error>;
+}
+
+/// Methods required to persist the chain of compact filter headers, so the filter headers
+/// verified by the node are not downloaded from peers again when the node restarts.
+pub trait CFHeaderStore: Debug + Send + Sync {
+    /// Errors that may occur within a [`CFHeaderStore`].
+    type Error: Debug + Display;
+    /// Write filter headers agreed on by peers, keyed by the height of their block. Filter headers
+    /// at heights written before are replaced, as the block at the height may have been
+    /// reorganized.
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<(), Self::Error>;
+
+    /// Load the filter headers at the height and every height above it. Filter headers loaded from
+    /// the store are checked against the block hashes of the chain of most work, and must extend
+    /// each other.
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<BTreeMap<u32, PersistedFilterHeader>, Self::Error>;
+}
+
+// A filter store with the errors written as messages, so a store may be configured without
+// another type parameter on the node
+pub(crate) trait DynFilterStore: Debug + Send + Sync {
+    fn write_filters(&mut self, filters: Vec<(BlockHash, BlockFilter)>)
+        -> FutureResult<(), String>;
+
+    fn filter(&mut self, block_hash: BlockHash) -> FutureResult<Option<BlockFilter>, String>;
+}
+
+impl<F: FilterStore> DynFilterStore for F {
+    fn write_filters(
+        &mut self,
+        filters: Vec<(BlockHash, BlockFilter)>,
+    ) -> FutureResult<(), String> {
+        let write = FilterStore::write_filters(self, filters);
+        Box::pin(async move { write.await.map_err(|e| e.to_string()) })
+    }
+
+    fn filter(&mut self, block_hash: BlockHash) -> FutureResult<Option<BlockFilter>, String> {
+        let load = FilterStore::filter(self, block_hash);
+        Box::pin(async move { load.await.map_err(|e| e.to_string()) })
+    }
+}
+
+// A filter header store with the errors written as messages, like the filter store
+pub(crate) trait DynCFHeaderStore: Debug + Send + Sync {
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<(), String>;
+
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<BTreeMap<u32, PersistedFilterHeader>, String>;
+}
+
+impl<C: CFHeaderStore> DynCFHeaderStore for C {
+    fn write_filter_headers(
+        &mut self,
+        filter_headers: BTreeMap<u32, PersistedFilterHeader>,
+    ) -> FutureResult<(), String> {
+        let write = CFHeaderStore::write_filter_headers(self, filter_headers);
+        Box::pin(async move { write.await.map_err(|e| e.to_string()) })
+    }
+
+    fn load_filter_headers(
+        &mut self,
+        start_height: u32,
+    ) -> FutureResult<BTreeMap<u32, PersistedFilterHeader>, String> {
+        let load = CFHeaderStore::load_filter_headers(self, start_height);
+        Box::pin(async move { load.await.map_err(|e| e.to_string()) })
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::collections::HashMap;
     use std::convert::Infallible;
 
+    impl FilterStore for HashMap<BlockHash, BlockFilter> {
+        type Error = Infallible;
+        fn write_filters(
+            &mut self,
+            filters: Vec<(BlockHash, BlockFilter)>,
+        ) -> FutureResult<(), Self::Error> {
+            self.extend(filters);
+            async fn do_write_filters() -> Result<(), Infallible> {
+                Ok(())
+            }
+            Box::pin(do_write_filters())
+        }
+
+        fn filter(
+            &mut self,
+            block_hash: BlockHash,
+        ) -> FutureResult<Option<BlockFilter>, Self::Error> {
+            let filter = self.get(&block_hash).cloned();
+            async fn do_filter(
+                filter: Option<BlockFilter>,
+            ) -> Result<Option<BlockFilter>, Infallible> {
+                Ok(filter)
+            }
+            Box::pin(do_filter(filter))
+        }
+    }
+
     /// Errors for the [`PeerStore`](crate) of unit type.
     #[derive(Debug)]
     pub enum UnitPeerStoreError {
@@ -0,0 +1,557 @@
+use std::{collections::HashMap, str::FromStr};
+
+use bitcoin::{
+    bip32::{self, ChildNumber, DerivationPath, Xpub},
+    key::Secp256k1,
+    secp256k1::VerifyOnly,
+    Block, CompressedPublicKey, NetworkKind, ScriptBuf,
+};
+
+use crate::{
+    error::DescriptorError,
+    zeroize::{zeroize_chain_code, zeroize_scripts},
+};
+
+const DEFAULT_GAP_LIMIT: u32 = 20;
+// The characters of a descriptor and of its checksum, as described in BIP 380
+const DESCRIPTOR_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_GENERATOR: [u64; 5] = [
+    0xf5dee51989,
+    0xa9fdca3312,
+    0x1bab10e32d,
+    0x3706b1677a,
+    0x644d626ffd,
+];
+
+/// A chain of scripts derived from an extended public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keychain {
+    /// Scripts given out to receive payments.
+    External,
+    /// Scripts used for change.
+    Internal,
+}
+
+/// The kind of script derived from each public key of a [`Keychain`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DerivedScriptKind {
+    /// Pay to witness public key hash, as described in BIP 84.
+    #[default]
+    P2wpkh,
+    /// Pay to taproot with no script path, as described in BIP 86.
+    P2tr,
+}
+
+/// Watch the scripts derived from an extended public key, such as the account key of a wallet.
+///
+/// The node derives scripts up to the gap limit past the last used index of each [`Keychain`].
+/// When a block pays to a script past the last used index, the node sends
+/// [`Event::NewDerivationUsed`](crate::Event::NewDerivationUsed) and derives more scripts to
+/// keep the gap. The filters after the block are checked again for the scripts derived during a
+/// sync.
+///
+/// The node does not persist the used indexes, so they should be recorded from the events and
+/// provided with [`XpubWatch::last_used`] when the node is built again.
+#[derive(Debug, Clone)]
+pub struct XpubWatch {
+    external: Xpub,
+    internal: Xpub,
+    kind: DerivedScriptKind,
+    gap_limit: u32,
+    last_used: HashMap<Keychain, u32>,
+    probe_scripts: Option<u32>,
+}
+
+impl XpubWatch {
+    /// Watch the scripts of `kind` derived from `xpub` at the `external` and `internal` paths,
+    /// which are usually `0` and `1` for an account key.
+    ///
+    /// # Errors
+    ///
+    /// If a path has a hardened step, which cannot be derived from a public key.
+    pub fn new(
+        xpub: Xpub,
+        kind: DerivedScriptKind,
+        external: &DerivationPath,
+        internal: &DerivationPath,
+    ) -> Result<Self, bip32::Error> {
+        let secp = Secp256k1::verification_only();
+        Ok(Self {
+            external: xpub.derive_pub(&secp, external)?,
+            internal: xpub.derive_pub(&secp, internal)?,
+            kind,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            last_used: HashMap::new(),
+            probe_scripts: None,
+        })
+    }
+
+    /// Watch the scripts of an output descriptor with a path for each keychain, as described in
+    /// BIP 389, such as `wpkh([d34db33f/84h/0h/0h]xpub.../<0;1>/*)`. The first path of the
+    /// multipath step is the external keychain, and the second is the internal keychain.
+    ///
+    /// Only `wpkh` and `tr` descriptors of a single extended public key are supported. A key
+    /// origin is ignored, and a checksum is verified if present.
+    ///
+    /// # Errors
+    ///
+    /// If the descriptor is not supported or not valid, or does not have exactly two paths.
+    pub fn from_descriptor(descriptor: &str) -> Result<Self, DescriptorError> {
+        let descriptor = ParsedDescriptor::parse(descriptor)?;
+        match descriptor.paths.as_slice() {
+            [external, internal] => descriptor.watch(external, internal),
+            _ => Err(DescriptorError::KeychainMismatch),
+        }
+    }
+
+    /// Watch the scripts of an output descriptor for each keychain, such as
+    /// `wpkh(xpub.../0/*)` and `wpkh(xpub.../1/*)`. Both descriptors must have the same key and
+    /// script kind.
+    ///
+    /// # Errors
+    ///
+    /// If either descriptor is not supported or not valid, or the descriptors do not share a key
+    /// and script kind.
+    pub fn from_descriptors(external: &str, internal: &str) -> Result<Self, DescriptorError> {
+        let external = ParsedDescriptor::parse(external)?;
+        let internal = ParsedDescriptor::parse(internal)?;
+        if external.xpub != internal.xpub || external.kind != internal.kind {
+            return Err(DescriptorError::KeychainMismatch);
+        }
+        match (external.paths.as_slice(), internal.paths.as_slice()) {
+            ([external_path], [internal_path]) => external.watch(external_path, internal_path),
+            _ => Err(DescriptorError::KeychainMismatch),
+        }
+    }
+
+    // The kind of network the extended public key is for
+    pub(crate) fn network_kind(&self) -> NetworkKind {
+        self.external.network
+    }
+
+    /// The number of unused scripts to derive past the last used index. If none is provided, the
+    /// gap limit is 20. A value of zero is treated as one.
+    pub fn gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = gap_limit.max(1);
+        self
+    }
+
+    /// The last index of the keychain known to be used, from a previous run of the node.
+    pub fn last_used(mut self, keychain: Keychain, index: u32) -> Self {
+        self.last_used.insert(keychain, index);
+        self
+    }
+
+    /// Recover the wallet in two phases. Filters are first checked for only the first
+    /// `probe_scripts` scripts of each keychain, which finds the first block the wallet was
+    /// active in with far less matching work. The filters from that block to the tip are then
+    /// checked again with every derived script, and the filters before it are not checked again.
+    ///
+    /// Wallets that received to a later index before the first of the probed scripts are not
+    /// fully recovered, so the number of probed scripts should cover the first addresses the
+    /// wallet gave out. A value of zero is treated as one.
+    pub fn two_phase_recovery(mut self, probe_scripts: u32) -> Self {
+        self.probe_scripts = Some(probe_scripts.max(1));
+        self
+    }
+}
+
+// A descriptor of the scripts derived from one extended public key, with a path to the wildcard
+// step for each multipath value
+#[derive(Debug)]
+struct ParsedDescriptor {
+    kind: DerivedScriptKind,
+    xpub: Xpub,
+    paths: Vec<DerivationPath>,
+}
+
+impl ParsedDescriptor {
+    fn parse(descriptor: &str) -> Result<Self, DescriptorError> {
+        let descriptor = match descriptor.split_once('#') {
+            Some((descriptor, checksum)) => {
+                if descriptor_checksum(descriptor).as_deref() != Some(checksum) {
+                    return Err(DescriptorError::Checksum);
+                }
+                descriptor
+            }
+            None => descriptor,
+        };
+        let (kind, inner) = if let Some(inner) = descriptor.strip_prefix("wpkh(") {
+            (DerivedScriptKind::P2wpkh, inner)
+        } else if let Some(inner) = descriptor.strip_prefix("tr(") {
+            (DerivedScriptKind::P2tr, inner)
+        } else {
+            return Err(DescriptorError::UnsupportedScript);
+        };
+        let key = inner
+            .strip_suffix(')')
+            .ok_or(DescriptorError::UnsupportedScript)?;
+        // A script tree or nested script is not a single key
+        if key.contains([',', '(', ')']) {
+            return Err(DescriptorError::UnsupportedScript);
+        }
+        // The origin of the key is not needed to derive scripts
+        let key = match key.strip_prefix('[') {
+            Some(origin) => origin
+                .split_once(']')
+                .map(|(_, key)| key)
+                .ok_or(DescriptorError::InvalidPath)?,
+            None => key,
+        };
+        let mut steps = key.split('/');
+        let xpub = Xpub::from_str(steps.next().unwrap_or_default())
+            .map_err(DescriptorError::InvalidKey)?;
+        let steps = steps.collect::<Vec<_>>();
+        let (wildcard, steps) = steps.split_last().ok_or(DescriptorError::InvalidPath)?;
+        if *wildcard != "*" {
+            return Err(DescriptorError::InvalidPath);
+        }
+        let mut paths = vec![Vec::new()];
+        let mut multipath = false;
+        for step in steps {
+            match step
+                .strip_prefix('<')
+                .and_then(|step| step.strip_suffix('>'))
+            {
+                Some(values) => {
+                    if multipath {
+                        return Err(DescriptorError::InvalidPath);
+                    }
+                    multipath = true;
+                    let values = values
+                        .split(';')
+                        .map(normal_child)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let path = paths.pop().unwrap_or_default();
+                    paths = values
+                        .into_iter()
+                        .map(|value| {
+                            let mut path = path.clone();
+                            path.push(value);
+                            path
+                        })
+                        .collect();
+                }
+                None => {
+                    let child = normal_child(step)?;
+                    for path in paths.iter_mut() {
+                        path.push(child);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            kind,
+            xpub,
+            paths: paths.into_iter().map(DerivationPath::from).collect(),
+        })
+    }
+
+    fn watch(
+        &self,
+        external: &DerivationPath,
+        internal: &DerivationPath,
+    ) -> Result<XpubWatch, DescriptorError> {
+        if external == internal {
+            return Err(DescriptorError::KeychainMismatch);
+        }
+        XpubWatch::new(self.xpub, self.kind, external, internal)
+            .map_err(|_| DescriptorError::InvalidPath)
+    }
+}
+
+// An unhardened step of a derivation path
+fn normal_child(step: &str) -> Result<ChildNumber, DescriptorError> {
+    let index = step
+        .parse::<u32>()
+        .map_err(|_| DescriptorError::InvalidPath)?;
+    ChildNumber::from_normal_idx(index).map_err(|_| DescriptorError::InvalidPath)
+}
+
+// The checksum of a descriptor, as described in BIP 380
+fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    fn polymod(checksum: u64, value: u64) -> u64 {
+        let top = checksum >> 35;
+        let mut checksum = ((checksum & 0x7_ffff_ffff) << 5) ^ value;
+        for (i, generator) in CHECKSUM_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+        checksum
+    }
+    let mut checksum = 1;
+    let mut groups = Vec::with_capacity(3);
+    for c in descriptor.chars() {
+        let position = DESCRIPTOR_CHARSET.find(c)? as u64;
+        checksum = polymod(checksum, position & 31);
+        groups.push(position >> 5);
+        if groups.len() == 3 {
+            checksum = polymod(checksum, groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.as_slice() {
+        [group] => checksum = polymod(checksum, *group),
+        [first, second] => checksum = polymod(checksum, first * 3 + second),
+        _ => (),
+    }
+    for _ in 0..8 {
+        checksum = polymod(checksum, 0);
+    }
+    checksum ^= 1;
+    Some(
+        (0..8)
+            .map(|i| CHECKSUM_CHARSET[((checksum >> (5 * (7 - i))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+// The scripts derived for one keychain and the index of each
+#[derive(Debug)]
+struct DerivedKeychain {
+    keychain: Keychain,
+    xpub: Xpub,
+    derived: u32,
+    last_used: Option<u32>,
+    scripts: HashMap<ScriptBuf, u32>,
+}
+
+// Scripts derived from an extended public key, extended as the scripts are used
+#[derive(Debug)]
+pub(crate) struct Derivations {
+    secp: Secp256k1<VerifyOnly>,
+    kind: DerivedScriptKind,
+    gap_limit: u32,
+    keychains: [DerivedKeychain; 2],
+    // Only this many scripts are derived during the first phase of a recovery
+    probe_scripts: Option<u32>,
+    // The first block to pay to a probed script
+    first_active: Option<u32>,
+}
+
+impl Derivations {
+    pub(crate) fn new(watch: XpubWatch) -> Self {
+        let keychain = |keychain: Keychain, xpub: Xpub| DerivedKeychain {
+            keychain,
+            xpub,
+            derived: 0,
+            last_used: watch.last_used.get(&keychain).copied(),
+            scripts: HashMap::new(),
+        };
+        Self {
+            secp: Secp256k1::verification_only(),
+            kind: watch.kind,
+            gap_limit: watch.gap_limit,
+            keychains: [
+                keychain(Keychain::External, watch.external),
+                keychain(Keychain::Internal, watch.internal),
+            ],
+            probe_scripts: watch.probe_scripts,
+            first_active: None,
+        }
+    }
+
+    // End the first phase of a recovery, returning the first block found to pay to a probed
+    // script. Scripts up to the gap limit are derived by the next call to `extend`.
+    pub(crate) fn finish_probe(&mut self) -> Option<Option<u32>> {
+        self.probe_scripts.take()?;
+        Some(self.first_active.take())
+    }
+
+    // Derive scripts up to the gap limit past the last used index of each keychain, returning
+    // the scripts that were not derived before
+    pub(crate) fn extend(&mut self) -> Vec<ScriptBuf> {
+        let mut new_scripts = Vec::new();
+        for keychain in self.keychains.iter_mut() {
+            let mut target = keychain
+                .last_used
+                .map_or(0, |index| index.saturating_add(1))
+                .saturating_add(self.gap_limit);
+            if let Some(probe_scripts) = self.probe_scripts {
+                target = target.min(probe_scripts);
+            }
+            while keychain.derived < target {
+                let index = keychain.derived;
+                keychain.derived += 1;
+                // Deriving a normal child only fails with a negligible probability
+                let child = match keychain
+                    .xpub
+                    .ckd_pub(&self.secp, ChildNumber::Normal { index })
+                {
+                    Ok(child) => child,
+                    Err(_) => continue,
+                };
+                let script = match self.kind {
+                    DerivedScriptKind::P2wpkh => {
+                        ScriptBuf::new_p2wpkh(&CompressedPublicKey(child.public_key).wpubkey_hash())
+                    }
+                    DerivedScriptKind::P2tr => {
+                        ScriptBuf::new_p2tr(&self.secp, child.to_x_only_pub(), None)
+                    }
+                };
+                keychain.scripts.insert(script.clone(), index);
+                new_scripts.push(script);
+            }
+        }
+        new_scripts
+    }
+
+    // Find outputs paying to derived scripts past the last used index of their keychain
+    pub(crate) fn scan(&mut self, height: u32, block: &Block) -> Vec<(Keychain, u32)> {
+        let mut newly_used = Vec::new();
+        for keychain in self.keychains.iter_mut() {
+            let used = block
+                .txdata
+                .iter()
+                .flat_map(|tx| tx.output.iter())
+                .filter_map(|output| keychain.scripts.get(&output.script_pubkey).copied())
+                .max();
+            if used.is_some() && self.probe_scripts.is_some() {
+                self.first_active = Some(self.first_active.map_or(height, |h| h.min(height)));
+            }
+            if let Some(index) = used {
+                if keychain.last_used.map_or(true, |last| index > last) {
+                    keychain.last_used = Some(index);
+                    newly_used.push((keychain.keychain, index));
+                }
+            }
+        }
+        newly_used
+    }
+}
+
+impl Drop for Derivations {
+    fn drop(&mut self) {
+        for keychain in self.keychains.iter_mut() {
+            zeroize_chain_code(&mut keychain.xpub);
+            zeroize_scripts(keychain.scripts.drain().map(|(script, _)| script));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::{bip32::Xpriv, constants::genesis_block, Network};
+
+    use super::*;
+
+    #[test]
+    fn test_derivations_extend_when_used() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv);
+        let external = DerivationPath::from_str("m/0").unwrap();
+        let internal = DerivationPath::from_str("m/1").unwrap();
+        let hardened = DerivationPath::from_str("m/0'").unwrap();
+        assert!(XpubWatch::new(xpub, DerivedScriptKind::P2wpkh, &hardened, &internal).is_err());
+        let watch = XpubWatch::new(xpub, DerivedScriptKind::P2wpkh, &external, &internal)
+            .unwrap()
+            .gap_limit(5)
+            .last_used(Keychain::Internal, 1);
+        let mut derivations = Derivations::new(watch);
+        let scripts = derivations.extend();
+        assert_eq!(scripts.len(), 5 + 7);
+        assert!(derivations.extend().is_empty());
+        // Pay to the fourth external script
+        let external_3 = scripts[3].clone();
+        assert!(external_3.is_p2wpkh());
+        let mut block = genesis_block(Network::Regtest);
+        block.txdata[0].output[0].script_pubkey = external_3;
+        assert_eq!(derivations.scan(0, &block), vec![(Keychain::External, 3)]);
+        assert!(derivations.scan(0, &block).is_empty());
+        assert_eq!(derivations.extend().len(), 4);
+        // An index at or before the last used index is not reported
+        block.txdata[0].output[0].script_pubkey = scripts[5].clone();
+        assert!(derivations.scan(0, &block).is_empty());
+        assert!(derivations.finish_probe().is_none());
+        let taproot = XpubWatch::new(xpub, DerivedScriptKind::P2tr, &external, &internal).unwrap();
+        assert!(Derivations::new(taproot)
+            .extend()
+            .iter()
+            .all(|script| script.is_p2tr()));
+    }
+
+    #[test]
+    fn test_descriptors_are_watched() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv);
+        // From the output of Bitcoin Core
+        assert_eq!(
+            descriptor_checksum("wpkh([97f17dca/0'/0'/0']02749483607dafb30c66bd93ece4474be65745ce538c2d70e8e246f17e7a4e0c0c)").as_deref(),
+            Some("m9n56cx0")
+        );
+        let descriptor = format!("wpkh([d34db33f/84h/1h/0h]{xpub}/<0;1>/*)");
+        let checksum = descriptor_checksum(&descriptor).unwrap();
+        let watch = XpubWatch::from_descriptor(&format!("{descriptor}#{checksum}")).unwrap();
+        let external = DerivationPath::from_str("m/0").unwrap();
+        let internal = DerivationPath::from_str("m/1").unwrap();
+        let expected =
+            XpubWatch::new(xpub, DerivedScriptKind::P2wpkh, &external, &internal).unwrap();
+        assert_eq!(
+            Derivations::new(watch).extend(),
+            Derivations::new(expected).extend()
+        );
+        assert!(matches!(
+            XpubWatch::from_descriptor(&format!("{descriptor}#qqqqqqqq")),
+            Err(DescriptorError::Checksum)
+        ));
+        let watch =
+            XpubWatch::from_descriptors(&format!("tr({xpub}/0/*)"), &format!("tr({xpub}/1/*)"))
+                .unwrap();
+        assert!(Derivations::new(watch)
+            .extend()
+            .iter()
+            .all(|script| script.is_p2tr()));
+        // The error parsing the key is kept as the source
+        let error = XpubWatch::from_descriptor("wpkh(xpub/<0;1>/*)").unwrap_err();
+        assert!(matches!(error, DescriptorError::InvalidKey(_)));
+        assert!(std::error::Error::source(&error).is_some());
+        // Unsupported scripts, hardened steps, and unpaired keychains are rejected
+        assert!(matches!(
+            XpubWatch::from_descriptor(&format!("pkh({xpub}/<0;1>/*)")),
+            Err(DescriptorError::UnsupportedScript)
+        ));
+        assert!(matches!(
+            XpubWatch::from_descriptor(&format!("wpkh({xpub}/0h/<0;1>/*)")),
+            Err(DescriptorError::InvalidPath)
+        ));
+        assert!(matches!(
+            XpubWatch::from_descriptor(&format!("wpkh({xpub}/0/*)")),
+            Err(DescriptorError::KeychainMismatch)
+        ));
+        assert!(matches!(
+            XpubWatch::from_descriptors(&format!("wpkh({xpub}/0/*)"), &format!("tr({xpub}/1/*)")),
+            Err(DescriptorError::KeychainMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_probe_finds_first_activity() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv);
+        let external = DerivationPath::from_str("m/0").unwrap();
+        let internal = DerivationPath::from_str("m/1").unwrap();
+        let watch = XpubWatch::new(xpub, DerivedScriptKind::P2wpkh, &external, &internal)
+            .unwrap()
+            .gap_limit(10)
+            .two_phase_recovery(2);
+        let mut derivations = Derivations::new(watch);
+        let probed = derivations.extend();
+        assert_eq!(probed.len(), 4);
+        let mut block = genesis_block(Network::Regtest);
+        block.txdata[0].output[0].script_pubkey = probed[1].clone();
+        derivations.scan(9, &block);
+        // The probe is not extended past the probed scripts
+        assert!(derivations.extend().is_empty());
+        derivations.scan(7, &block);
+        assert_eq!(derivations.finish_probe(), Some(Some(7)));
+        assert_eq!(derivations.extend().len(), 2 * 10 - 4 + 2);
+        assert!(derivations.finish_probe().is_none());
+    }
+}
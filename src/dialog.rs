@@ -1,47 +1,412 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "journal")]
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
 
-use super::messages::{Event, Info, Warning};
-use crate::LogLevel;
+use super::messages::{Event, EventMask, Info, Warning};
+#[cfg(feature = "journal")]
+use crate::journal::{EventJournal, JournalEntry};
+use crate::{LogLevel, Subsystem};
+
+const SLOW_DATABASE_MILLIS: u64 = 500;
+const REDACTED: &str = "[redacted]";
+
+// The levels of the node and of each subsystem, which may be changed while the node is running
+#[derive(Debug)]
+struct LogLevels {
+    node: LogLevel,
+    subsystems: HashMap<Subsystem, LogLevel>,
+}
 
 #[derive(Debug, Clone)]
 pub(crate) struct Dialog {
-    pub(crate) log_level: LogLevel,
+    levels: Arc<RwLock<LogLevels>>,
     log_tx: Sender<String>,
     info_tx: Sender<Info>,
     warn_tx: UnboundedSender<Warning>,
     event_tx: UnboundedSender<Event>,
+    event_mask: EventMask,
+    // Events that could not be sent because the client dropped its receiver
+    events_dropped: Arc<AtomicUsize>,
+    #[cfg(feature = "journal")]
+    journal: Option<UnboundedSender<JournalEntry>>,
+    // Transactions and blocks of the wallet are included in logs and info
+    log_sensitive: bool,
 }
 
 impl Dialog {
     pub(crate) fn new(
         log_level: LogLevel,
+        subsystem_levels: HashMap<Subsystem, LogLevel>,
         log_tx: Sender<String>,
         info_tx: Sender<Info>,
         warn_tx: UnboundedSender<Warning>,
         event_tx: UnboundedSender<Event>,
     ) -> Self {
         Self {
-            log_level,
+            levels: Arc::new(RwLock::new(LogLevels {
+                node: log_level,
+                subsystems: subsystem_levels,
+            })),
             log_tx,
             info_tx,
             warn_tx,
             event_tx,
+            event_mask: EventMask::ALL,
+            events_dropped: Arc::new(AtomicUsize::new(0)),
+            #[cfg(feature = "journal")]
+            journal: None,
+            log_sensitive: false,
+        }
+    }
+
+    // Only send the events in the mask
+    pub(crate) fn with_event_mask(self, event_mask: EventMask) -> Self {
+        Self { event_mask, ..self }
+    }
+
+    // Record events in a journal as they are sent
+    #[cfg(feature = "journal")]
+    pub(crate) fn with_journal(self, journal: UnboundedSender<JournalEntry>) -> Self {
+        Self {
+            journal: Some(journal),
+            ..self
         }
     }
 
+    // Include the transactions and blocks of the wallet in logs and info
+    pub(crate) fn with_sensitive_data(self, log_sensitive: bool) -> Self {
+        Self {
+            log_sensitive,
+            ..self
+        }
+    }
+
+    // A value that identifies the wallet, to be written to a log message
+    pub(crate) fn redact(&self, value: impl Display) -> String {
+        if self.log_sensitive {
+            value.to_string()
+        } else {
+            REDACTED.into()
+        }
+    }
+
+    // The same channels with the log levels of another node
+    pub(crate) fn with_levels(
+        self,
+        log_level: LogLevel,
+        subsystem_levels: HashMap<Subsystem, LogLevel>,
+    ) -> Self {
+        Self {
+            levels: Arc::new(RwLock::new(LogLevels {
+                node: log_level,
+                subsystems: subsystem_levels,
+            })),
+            ..self
+        }
+    }
+
+    // Change the level of the node, and of the given subsystems
+    pub(crate) fn set_levels(
+        &self,
+        log_level: Option<LogLevel>,
+        subsystem_levels: HashMap<Subsystem, LogLevel>,
+    ) {
+        let mut levels = self.levels.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(log_level) = log_level {
+            levels.node = log_level;
+        }
+        levels.subsystems.extend(subsystem_levels);
+    }
+
+    // The configured level of a subsystem, falling back to the level of the node
+    pub(crate) fn level_of(&self, subsystem: Subsystem) -> LogLevel {
+        let levels = self.levels.read().unwrap_or_else(PoisonError::into_inner);
+        levels
+            .subsystems
+            .get(&subsystem)
+            .copied()
+            .unwrap_or(levels.node)
+    }
+
+    // Are requests to peers and their responses reported for the subsystem
+    pub(crate) fn reports_requests(&self, subsystem: Subsystem) -> bool {
+        matches!(self.level_of(subsystem), LogLevel::Requests)
+    }
+
     pub(crate) async fn send_dialog(&self, dialog: impl Into<String>) {
         let _ = self.log_tx.send(dialog.into()).await;
     }
 
     pub(crate) fn send_warning(&self, warning: Warning) {
+        // The scripts are those of the wallet
+        #[cfg(not(feature = "filter-control"))]
+        let warning = match warning {
+            Warning::BroadScripts {
+                false_positives,
+                blocks,
+                excluded,
+                ..
+            } if !self.log_sensitive => Warning::BroadScripts {
+                scripts: Vec::new(),
+                false_positives,
+                blocks,
+                excluded,
+            },
+            warning => warning,
+        };
         let _ = self.warn_tx.send(warning);
     }
 
     pub(crate) async fn send_info(&self, info: Info) {
+        if !self.log_sensitive && info.is_sensitive() {
+            return;
+        }
         let _ = self.info_tx.send(info).await;
     }
 
     pub(crate) fn send_event(&self, message: Event) {
-        let _ = self.event_tx.send(message);
+        if !self.event_mask.allows(&message) {
+            return;
+        }
+        #[cfg(feature = "journal")]
+        if let Some(journal) = &self.journal {
+            if let Some(entry) = JournalEntry::event(&message) {
+                let _ = journal.send(entry);
+            }
+        }
+        if self.event_tx.send(message).is_err() {
+            self.events_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // The number of events dropped since this was last called
+    pub(crate) fn take_events_dropped(&self) -> usize {
+        self.events_dropped.swap(0, Ordering::Relaxed)
+    }
+
+    // Send the events of a previous run that the client did not acknowledge, then write the
+    // entries of this run to the journal on a task of its own
+    #[cfg(feature = "journal")]
+    pub(crate) fn start_journal(
+        &self,
+        mut journal: EventJournal,
+        entries: UnboundedReceiver<JournalEntry>,
+    ) {
+        for event in journal.take_replay() {
+            if self.event_tx.send(event).is_err() {
+                self.events_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // The writer must not hold a sender, or it would wait for its own entries
+        let dialog = Self {
+            journal: None,
+            ..self.clone()
+        };
+        crate::runtime::spawn(journal.write_entries(entries, dialog));
+    }
+
+    #[cfg(feature = "journal")]
+    pub(crate) fn acknowledge_events(&self, count: usize) {
+        if let Some(journal) = &self.journal {
+            let _ = journal.send(JournalEntry::Acknowledged(count));
+        }
+    }
+
+    // Warn the client if a database operation started at the given instant was slow
+    pub(crate) fn check_database_latency(&self, operation: &str, started: Instant) {
+        let elapsed = started.elapsed();
+        if elapsed > Duration::from_millis(SLOW_DATABASE_MILLIS) {
+            self.send_warning(Warning::SlowDatabase {
+                operation: operation.into(),
+                elapsed,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::address::AddrV2;
+    use tokio::sync::mpsc;
+
+    use crate::RequestKind;
+
+    use super::*;
+
+    #[test]
+    fn test_masked_events_are_dropped() {
+        let (log_tx, _) = mpsc::channel::<String>(1);
+        let (info_tx, _) = mpsc::channel::<Info>(1);
+        let (warn_tx, _) = mpsc::unbounded_channel::<Warning>();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel::<Event>();
+        let dialog = Dialog::new(
+            LogLevel::Debug,
+            HashMap::new(),
+            log_tx,
+            info_tx,
+            warn_tx,
+            event_tx,
+        )
+        .with_event_mask(EventMask::ALL.without(EventMask::BLOCKS_DISCONNECTED));
+        dialog.send_event(Event::BlocksDisconnected(Vec::new()));
+        assert!(event_rx.try_recv().is_err());
+        let dialog = dialog.with_event_mask(EventMask::BLOCKS_DISCONNECTED);
+        dialog.send_event(Event::BlocksDisconnected(Vec::new()));
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(Event::BlocksDisconnected(_))
+        ));
+        // Events sent after the client dropped its receiver are counted
+        drop(event_rx);
+        dialog.send_event(Event::BlocksDisconnected(Vec::new()));
+        dialog.send_event(Event::BlocksDisconnected(Vec::new()));
+        assert_eq!(dialog.clone().take_events_dropped(), 2);
+        assert_eq!(dialog.take_events_dropped(), 0);
+        // Levels changed while running are seen by every handle to the dialog
+        let shared = dialog.clone();
+        dialog.set_levels(
+            Some(LogLevel::Warning),
+            HashMap::from([(Subsystem::Peers, LogLevel::Requests)]),
+        );
+        assert_eq!(shared.level_of(Subsystem::Chain), LogLevel::Warning);
+        assert_eq!(shared.level_of(Subsystem::Peers), LogLevel::Requests);
+        assert!(shared.reports_requests(Subsystem::Peers));
+        // Requests are only reported at their own level
+        dialog.set_levels(
+            Some(LogLevel::Debug),
+            HashMap::from([(Subsystem::Peers, LogLevel::Debug)]),
+        );
+        assert!(!shared.reports_requests(Subsystem::Peers));
+    }
+
+    #[tokio::test]
+    async fn test_messages_respect_subsystem_levels() {
+        let (log_tx, mut log_rx) = mpsc::channel::<String>(10);
+        let (info_tx, mut info_rx) = mpsc::channel::<Info>(10);
+        let (warn_tx, _) = mpsc::unbounded_channel::<Warning>();
+        let (event_tx, _) = mpsc::unbounded_channel::<Event>();
+        let dialog = Dialog::new(
+            LogLevel::Warning,
+            HashMap::from([(Subsystem::Node, LogLevel::Debug)]),
+            log_tx,
+            info_tx,
+            warn_tx,
+            event_tx,
+        );
+        // A subsystem may be louder than the node
+        crate::log!(dialog, Subsystem::Node, "node");
+        crate::log!(dialog, Subsystem::Peers, "peers");
+        crate::info!(dialog, Subsystem::Node, Info::DatabaseCompacted);
+        crate::info!(dialog, Subsystem::Database, Info::DatabaseCompacted);
+        assert_eq!(log_rx.try_recv().unwrap(), "node");
+        assert!(log_rx.try_recv().is_err());
+        assert!(matches!(info_rx.try_recv(), Ok(Info::DatabaseCompacted)));
+        assert!(info_rx.try_recv().is_err());
+        // Or quieter
+        dialog.set_levels(
+            Some(LogLevel::Debug),
+            HashMap::from([(Subsystem::Node, LogLevel::Warning)]),
+        );
+        crate::log!(dialog, Subsystem::Node, "node");
+        crate::info!(dialog, Subsystem::Node, Info::DatabaseCompacted);
+        assert!(log_rx.try_recv().is_err());
+        assert!(info_rx.try_recv().is_err());
+        crate::log!(dialog, Subsystem::Chain, "chain");
+        assert_eq!(log_rx.try_recv().unwrap(), "chain");
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_data_is_redacted() {
+        let (log_tx, _) = mpsc::channel::<String>(1);
+        let (info_tx, mut info_rx) = mpsc::channel::<Info>(8);
+        let (warn_tx, _) = mpsc::unbounded_channel::<Warning>();
+        let (event_tx, _) = mpsc::unbounded_channel::<Event>();
+        let dialog = Dialog::new(
+            LogLevel::Debug,
+            HashMap::new(),
+            log_tx,
+            info_tx,
+            warn_tx,
+            event_tx,
+        );
+        let hash = bitcoin::BlockHash::all_zeros();
+        assert_eq!(dialog.redact(hash), REDACTED);
+        // Info that identifies a transaction or block of the wallet is not sent by default
+        let block_request = || RequestKind::Block(hash);
+        dialog.send_info(Info::BlockRequested(hash)).await;
+        dialog
+            .send_info(Info::TxGossiped(bitcoin::Wtxid::all_zeros()))
+            .await;
+        dialog
+            .send_info(Info::RequestSent {
+                kind: block_request(),
+                start_height: None,
+                addr: AddrV2::Ipv4(Ipv4Addr::LOCALHOST),
+                port: 8333,
+            })
+            .await;
+        dialog.send_info(Info::ConnectionsMet).await;
+        assert!(matches!(info_rx.try_recv(), Ok(Info::ConnectionsMet)));
+        assert!(info_rx.try_recv().is_err());
+        let dialog = dialog.with_sensitive_data(true);
+        assert_eq!(dialog.redact(hash), hash.to_string());
+        dialog.send_info(Info::BlockRequested(hash)).await;
+        assert!(matches!(info_rx.try_recv(), Ok(Info::BlockRequested(_))));
+        dialog
+            .send_info(Info::RequestSent {
+                kind: block_request(),
+                start_height: None,
+                addr: AddrV2::Ipv4(Ipv4Addr::LOCALHOST),
+                port: 8333,
+            })
+            .await;
+        assert!(matches!(info_rx.try_recv(), Ok(Info::RequestSent { .. })));
+    }
+
+    #[test]
+    #[cfg(not(feature = "filter-control"))]
+    fn test_broad_scripts_are_withheld() {
+        let (log_tx, _) = mpsc::channel::<String>(1);
+        let (info_tx, _) = mpsc::channel::<Info>(1);
+        let (warn_tx, mut warn_rx) = mpsc::unbounded_channel::<Warning>();
+        let (event_tx, _) = mpsc::unbounded_channel::<Event>();
+        let dialog = Dialog::new(
+            LogLevel::Debug,
+            HashMap::new(),
+            log_tx,
+            info_tx,
+            warn_tx,
+            event_tx,
+        );
+        let broad_scripts = || Warning::BroadScripts {
+            scripts: vec![bitcoin::ScriptBuf::new()],
+            false_positives: 9,
+            blocks: 10,
+            excluded: false,
+        };
+        dialog.send_warning(broad_scripts());
+        assert!(matches!(
+            warn_rx.try_recv(),
+            Ok(Warning::BroadScripts { scripts, false_positives: 9, .. }) if scripts.is_empty()
+        ));
+        let dialog = dialog.with_sensitive_data(true);
+        dialog.send_warning(broad_scripts());
+        assert!(matches!(
+            warn_rx.try_recv(),
+            Ok(Warning::BroadScripts { scripts, .. }) if scripts.len() == 1
+        ));
     }
 }
@@ -1,6 +1,10 @@
 use std::fmt::{Debug, Display};
 
-use crate::impl_sourceless_error;
+use bitcoin::{Address, Network};
+
+#[cfg(feature = "rusqlite")]
+use crate::db::error::SqlInitializationError;
+use crate::{impl_sourceless_error, HeaderCheckpoint, ShutdownReason, TrustedPeer};
 
 /// Errors that prevent the node from running.
 #[derive(Debug)]
@@ -9,6 +13,12 @@ pub enum NodeError<H: Debug + Display, P: Debug + Display> {
     HeaderDatabase(HeaderPersistenceError<H>),
     /// The persistence layer experienced a critical error.
     PeerDatabase(PeerManagerError<P>),
+    /// Consecutive peers did not serve compact block filters, as configured by
+    /// [`MissingFiltersPolicy::Fail`](crate::MissingFiltersPolicy::Fail).
+    NoFilterPeers {
+        /// The number of peers in a row that did not serve filters.
+        attempts: u32,
+    },
 }
 
 impl<H: Debug + Display, P: Debug + Display> core::fmt::Display for NodeError<H, P> {
@@ -16,6 +26,12 @@ impl<H: Debug + Display, P: Debug + Display> core::fmt::Display for NodeError<H,
         match self {
             NodeError::HeaderDatabase(e) => write!(f, "block headers: {e}"),
             NodeError::PeerDatabase(e) => write!(f, "peer manager: {e}"),
+            NodeError::NoFilterPeers { attempts } => {
+                write!(
+                    f,
+                    "{attempts} peers in a row did not serve compact block filters."
+                )
+            }
         }
     }
 }
@@ -26,6 +42,18 @@ impl<H: Debug + Display, P: Debug + Display> std::error::Error for NodeError<H,
     }
 }
 
+impl<H: Debug + Display, P: Debug + Display> From<&NodeError<H, P>> for ShutdownReason {
+    fn from(value: &NodeError<H, P>) -> Self {
+        match value {
+            NodeError::HeaderDatabase(e) => ShutdownReason::HeaderDatabase(e.to_string()),
+            NodeError::PeerDatabase(e) => ShutdownReason::PeerDatabase(e.to_string()),
+            NodeError::NoFilterPeers { attempts } => ShutdownReason::NoFilterPeers {
+                attempts: *attempts,
+            },
+        }
+    }
+}
+
 impl<H: Debug + Display, P: Debug + Display> From<HeaderPersistenceError<H>> for NodeError<H, P> {
     fn from(value: HeaderPersistenceError<H>) -> Self {
         NodeError::HeaderDatabase(value)
@@ -102,6 +130,8 @@ impl<H: Debug + Display> std::error::Error for HeaderPersistenceError<H> {
 pub enum ClientError {
     /// The channel to the node was likely closed and dropped from memory.
     SendError,
+    /// The channel to the client was likely closed by the node and dropped from memory.
+    RecvError,
 }
 
 impl core::fmt::Display for ClientError {
@@ -110,6 +140,10 @@ impl core::fmt::Display for ClientError {
             ClientError::SendError => {
                 write!(f, "the receiver of this message was dropped from memory.")
             }
+            ClientError::RecvError => write!(
+                f,
+                "the channel to the client was likely closed by the node and dropped from memory."
+            ),
         }
     }
 }
@@ -131,6 +165,8 @@ pub enum FetchHeaderError {
     RecvError,
     /// The header at the requested height does not yet exist.
     UnknownHeight,
+    /// The header with the requested hash is not in the chain of most work.
+    UnknownHash,
 }
 
 impl core::fmt::Display for FetchHeaderError {
@@ -152,12 +188,186 @@ impl core::fmt::Display for FetchHeaderError {
             FetchHeaderError::UnknownHeight => {
                 write!(f, "the header at the requested height does not yet exist.")
             }
+            FetchHeaderError::UnknownHash => {
+                write!(
+                    f,
+                    "the header with the requested hash is not in the chain of most work."
+                )
+            }
         }
     }
 }
 
 impl_sourceless_error!(FetchHeaderError);
 
+/// Errors occuring when the client is changing the configuration of a running node.
+#[derive(Debug)]
+pub enum UpdateConfigError {
+    /// The channel to the node was likely closed and dropped from memory.
+    /// This implies the node is not running.
+    SendError,
+    /// More peers are required to agree on the tip than the node would maintain connections to.
+    /// None of the changes were applied.
+    TipAgreement {
+        /// The number of peers required to agree on the tip.
+        agreeing: u8,
+        /// The number of connections the node would maintain.
+        required_peers: u8,
+    },
+    /// The channel to the client was likely closed by the node and dropped from memory.
+    RecvError,
+}
+
+impl core::fmt::Display for UpdateConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateConfigError::SendError => {
+                write!(f, "the receiver of this message was dropped from memory.")
+            }
+            UpdateConfigError::TipAgreement {
+                agreeing,
+                required_peers,
+            } => write!(
+                f,
+                "{agreeing} peers must agree on the tip, but only {required_peers} connections would be maintained."
+            ),
+            UpdateConfigError::RecvError => write!(
+                f,
+                "the channel to the client was likely closed by the node and dropped from memory."
+            ),
+        }
+    }
+}
+
+impl_sourceless_error!(UpdateConfigError);
+
+/// Errors occuring when the client is adding addresses to the node.
+#[derive(Debug)]
+pub enum AddAddressError {
+    /// The channel to the node was likely closed and dropped from memory.
+    /// This implies the node is not running.
+    SendError,
+    /// An address is not valid for the network of the node. None of the addresses were added.
+    NetworkMismatch {
+        /// The first address that is not valid for the network.
+        address: Address,
+        /// The network of the node.
+        network: Network,
+    },
+    /// The channel to the client was likely closed by the node and dropped from memory.
+    RecvError,
+}
+
+impl core::fmt::Display for AddAddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddAddressError::SendError => {
+                write!(f, "the receiver of this message was dropped from memory.")
+            }
+            AddAddressError::NetworkMismatch { address, network } => {
+                write!(f, "the address {address} is not valid for {network}.")
+            }
+            AddAddressError::RecvError => write!(
+                f,
+                "the channel to the client was likely closed by the node and dropped from memory."
+            ),
+        }
+    }
+}
+
+impl_sourceless_error!(AddAddressError);
+
+/// Errors parsing an output descriptor to watch.
+#[cfg(not(feature = "filter-control"))]
+#[derive(Debug)]
+pub enum DescriptorError {
+    /// The checksum following the descriptor does not match it.
+    Checksum,
+    /// Only descriptors of a single key, `wpkh` or `tr` without a script path, may be watched.
+    UnsupportedScript,
+    /// The key is not a valid extended public key.
+    InvalidKey(bitcoin::bip32::Error),
+    /// The derivation path does not end in an unhardened wildcard, or has a hardened step that
+    /// cannot be derived from a public key.
+    InvalidPath,
+    /// The descriptors of the external and internal keychains do not share a key and script
+    /// kind, or a single descriptor does not have a path for each keychain.
+    KeychainMismatch,
+}
+
+#[cfg(not(feature = "filter-control"))]
+impl core::fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DescriptorError::Checksum => write!(f, "the descriptor checksum is not valid."),
+            DescriptorError::UnsupportedScript => write!(
+                f,
+                "only wpkh and tr descriptors of a single key are supported."
+            ),
+            DescriptorError::InvalidKey(e) => {
+                write!(f, "the extended public key is not valid: {e}")
+            }
+            DescriptorError::InvalidPath => write!(
+                f,
+                "the derivation path must end in an unhardened wildcard with no hardened steps."
+            ),
+            DescriptorError::KeychainMismatch => write!(
+                f,
+                "the descriptors do not describe an external and internal keychain of one key."
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "filter-control"))]
+impl std::error::Error for DescriptorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DescriptorError::InvalidKey(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors occuring when the client is exporting headers to a file.
+#[derive(Debug)]
+pub enum ExportHeadersError {
+    /// The headers could not be fetched from the node.
+    Fetch(FetchHeaderError),
+    /// The file could not be written.
+    Io(std::io::Error),
+}
+
+impl core::fmt::Display for ExportHeadersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportHeadersError::Fetch(e) => write!(f, "the headers could not be fetched: {e}"),
+            ExportHeadersError::Io(e) => write!(f, "the file could not be written: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportHeadersError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportHeadersError::Fetch(e) => Some(e),
+            ExportHeadersError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<FetchHeaderError> for ExportHeadersError {
+    fn from(value: FetchHeaderError) -> Self {
+        ExportHeadersError::Fetch(value)
+    }
+}
+
+impl From<std::io::Error> for ExportHeadersError {
+    fn from(value: std::io::Error) -> Self {
+        ExportHeadersError::Io(value)
+    }
+}
+
 /// Errors occuring when the client is fetching blocks from the node.
 #[derive(Debug)]
 pub enum FetchBlockError {
@@ -173,6 +383,8 @@ pub enum FetchBlockError {
     RecvError,
     /// The hash is not a member of the chain of most work.
     UnknownHash,
+    /// No peer served the block after it was requested several times.
+    NotServed,
 }
 
 impl core::fmt::Display for FetchBlockError {
@@ -194,6 +406,9 @@ impl core::fmt::Display for FetchBlockError {
             FetchBlockError::UnknownHash => {
                 write!(f, "the hash is not a member of the chain of most work.")
             }
+            FetchBlockError::NotServed => {
+                write!(f, "no peer served the block after repeated requests.")
+            }
         }
     }
 }
@@ -225,3 +440,167 @@ impl core::fmt::Display for FetchFeeRateError {
 }
 
 impl_sourceless_error!(FetchFeeRateError);
+
+/// Reasons a test connection to a peer could not be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityError {
+    /// The connection was not opened within the handshake timeout.
+    Timeout,
+    /// The peer refused the connection or could not be reached.
+    Unreachable,
+    /// The Socks5 proxy could not be reached.
+    ProxyUnreachable,
+    /// The Socks5 proxy requires authentication or does not speak Socks5.
+    ProxyUnsupported,
+}
+
+impl core::fmt::Display for ConnectivityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectivityError::Timeout => {
+                write!(f, "the connection was not opened in time.")
+            }
+            ConnectivityError::Unreachable => {
+                write!(
+                    f,
+                    "the peer refused the connection or could not be reached."
+                )
+            }
+            ConnectivityError::ProxyUnreachable => write!(f, "the proxy could not be reached."),
+            ConnectivityError::ProxyUnsupported => write!(
+                f,
+                "the proxy requires authentication or does not speak Socks5."
+            ),
+        }
+    }
+}
+
+impl_sourceless_error!(ConnectivityError);
+
+/// Errors that occur when fetching a block from a [`BlockSource`](crate::BlockSource).
+#[cfg(feature = "rest")]
+#[derive(Debug)]
+pub enum BlockSourceError {
+    /// The source does not have the requested block.
+    NotFound,
+    /// The source could not be reached or did not respond in time.
+    Unavailable,
+    /// The source responded with data that is not the requested block.
+    InvalidResponse,
+}
+
+#[cfg(feature = "rest")]
+impl core::fmt::Display for BlockSourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockSourceError::NotFound => {
+                write!(f, "the source does not have the requested block.")
+            }
+            BlockSourceError::Unavailable => {
+                write!(
+                    f,
+                    "the source could not be reached or did not respond in time."
+                )
+            }
+            BlockSourceError::InvalidResponse => {
+                write!(
+                    f,
+                    "the source responded with data that is not the requested block."
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rest")]
+impl_sourceless_error!(BlockSourceError);
+
+/// Errors that occur when building a node from an invalid configuration.
+#[derive(Debug)]
+pub enum BuilderError {
+    /// The network does not have a set of known checkpoints to sync from.
+    UnsupportedNetwork(Network),
+    /// The anchor checkpoint conflicts with a known checkpoint for the network.
+    CheckpointMismatch(HeaderCheckpoint),
+    /// Zero peers were required, but no trusted peers were added.
+    NoPeers,
+    /// The connection type cannot reach this trusted peer, for instance a Tor address without a
+    /// proxy that supports Tor.
+    UnreachablePeer(TrustedPeer),
+    /// More peers were required to agree on the tip than the node maintains connections to.
+    TipAgreement {
+        /// The number of peers required to agree on the tip.
+        agreeing: u8,
+        /// The number of connections the node maintains.
+        required_peers: u8,
+    },
+    /// The extended public key to watch is for a different network than the node.
+    #[cfg(not(feature = "filter-control"))]
+    XpubNetwork,
+    /// The default databases could not be opened.
+    #[cfg(feature = "rusqlite")]
+    Database(SqlInitializationError),
+    /// The event journal could not be opened or read.
+    #[cfg(feature = "journal")]
+    EventJournal(std::io::Error),
+}
+
+impl core::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuilderError::UnsupportedNetwork(network) => {
+                write!(f, "there are no known checkpoints for {network}.")
+            }
+            BuilderError::CheckpointMismatch(checkpoint) => write!(
+                f,
+                "the checkpoint {} at height {} conflicts with the known checkpoints for the network.",
+                checkpoint.hash, checkpoint.height
+            ),
+            BuilderError::NoPeers => {
+                write!(f, "zero peers were required, but no trusted peers were added.")
+            }
+            BuilderError::UnreachablePeer(peer) => write!(
+                f,
+                "the connection type cannot reach the trusted peer {:?}.",
+                peer.address
+            ),
+            BuilderError::TipAgreement {
+                agreeing,
+                required_peers,
+            } => write!(
+                f,
+                "{agreeing} peers must agree on the tip, but only {required_peers} connections are maintained."
+            ),
+            #[cfg(not(feature = "filter-control"))]
+            BuilderError::XpubNetwork => write!(
+                f,
+                "the extended public key to watch is for a different network than the node."
+            ),
+            #[cfg(feature = "rusqlite")]
+            BuilderError::Database(e) => write!(f, "the database could not be opened: {e}"),
+            #[cfg(feature = "journal")]
+            BuilderError::EventJournal(e) => {
+                write!(f, "the event journal could not be opened: {e}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "rusqlite")]
+            BuilderError::Database(e) => Some(e),
+            #[cfg(feature = "journal")]
+            BuilderError::EventJournal(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl From<SqlInitializationError> for BuilderError {
+    fn from(value: SqlInitializationError) -> Self {
+        BuilderError::Database(value)
+    }
+}
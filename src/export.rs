@@ -0,0 +1,167 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use bitcoin::{block::Header, consensus, p2p::Magic, BlockHash, Network, Work};
+
+use crate::chain::{checkpoints::HeaderCheckpoint, IndexedHeader};
+
+const CSV_COLUMNS: &str = "height,hash,time,bits,nonce";
+// The length of a serialized attestation
+const ATTESTATION_LEN: usize = 160;
+
+/// The file format of exported block headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderExportFormat {
+    /// An array of objects with the height, hash, time, bits and nonce of each header.
+    Json,
+    /// A header row followed by the height, hash, time, bits and nonce of each header.
+    Csv,
+}
+
+/// A block header in the chain of most work, with its position relative to a checkpoint and the
+/// tip, as seen by the node. Protocols that refer to a block in signed statements, such as oracle
+/// attestations for discreet log contracts, may sign the fixed length serialization from
+/// [`HeaderAttestation::to_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderAttestation {
+    /// The network of the chain.
+    pub network: Network,
+    /// The attested header and its height.
+    pub header: IndexedHeader,
+    /// The checkpoint the work is counted from. This is the most recent known checkpoint at or
+    /// below the header, if the node holds it in memory, or else the oldest block the node holds.
+    pub checkpoint: HeaderCheckpoint,
+    /// The work of the blocks after the checkpoint, up to and including the attested header.
+    pub work: Work,
+    /// The height of the tip of the chain of most work.
+    pub tip_height: u32,
+}
+
+impl HeaderAttestation {
+    /// The number of blocks in the chain of most work that build on the header, including the
+    /// header itself.
+    pub fn confirmations(&self) -> u32 {
+        self.tip_height.saturating_sub(self.header.height) + 1
+    }
+
+    /// Serialize the attestation to 160 bytes: the network magic, the 80 byte header, and the
+    /// height of the header, followed by the height and hash of the checkpoint, the work as a
+    /// 32 byte big endian integer, and the height of the tip. Heights are four byte little
+    /// endian integers, and hashes are in their consensus byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ATTESTATION_LEN);
+        bytes.extend(self.network.magic().to_bytes());
+        bytes.extend(consensus::serialize(&self.header.header));
+        bytes.extend(self.header.height.to_le_bytes());
+        bytes.extend(self.checkpoint.height.to_le_bytes());
+        bytes.extend(consensus::serialize(&self.checkpoint.hash));
+        bytes.extend(self.work.to_be_bytes());
+        bytes.extend(self.tip_height.to_le_bytes());
+        bytes
+    }
+
+    /// Read an attestation serialized with [`HeaderAttestation::to_bytes`]. Returns `None` if the
+    /// bytes are not a valid attestation.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ATTESTATION_LEN {
+            return None;
+        }
+        let u32_at = |start: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(bytes[start..start + 4].try_into().ok()?))
+        };
+        let magic = Magic::from_bytes(bytes[0..4].try_into().ok()?);
+        let network = Network::from_magic(magic)?;
+        let header: Header = consensus::deserialize(&bytes[4..84]).ok()?;
+        let checkpoint_hash: BlockHash = consensus::deserialize(&bytes[92..124]).ok()?;
+        Some(Self {
+            network,
+            header: IndexedHeader::new(u32_at(84)?, header),
+            checkpoint: HeaderCheckpoint::new(u32_at(88)?, checkpoint_hash),
+            work: Work::from_be_bytes(bytes[124..156].try_into().ok()?),
+            tip_height: u32_at(156)?,
+        })
+    }
+}
+
+// Serialize the headers in the requested format
+pub(crate) fn render(headers: &BTreeMap<u32, Header>, format: HeaderExportFormat) -> String {
+    let mut out = String::new();
+    match format {
+        HeaderExportFormat::Json => {
+            out.push('[');
+            for (index, (height, header)) in headers.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "{{\"height\":{height},\"hash\":\"{}\",\"time\":{},\"bits\":{},\"nonce\":{}}}",
+                    header.block_hash(),
+                    header.time,
+                    header.bits.to_consensus(),
+                    header.nonce
+                );
+            }
+            out.push_str("]\n");
+        }
+        HeaderExportFormat::Csv => {
+            out.push_str(CSV_COLUMNS);
+            out.push('\n');
+            for (height, header) in headers {
+                let _ = writeln!(
+                    out,
+                    "{height},{},{},{},{}",
+                    header.block_hash(),
+                    header.time,
+                    header.bits.to_consensus(),
+                    header.nonce
+                );
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, Network};
+
+    use super::*;
+
+    #[test]
+    fn test_render_headers() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let hash = genesis.block_hash();
+        let headers = BTreeMap::from([(0, genesis)]);
+        assert_eq!(
+            render(&headers, HeaderExportFormat::Csv),
+            format!("height,hash,time,bits,nonce\n0,{hash},1296688602,545259519,2\n")
+        );
+        assert_eq!(
+            render(&headers, HeaderExportFormat::Json),
+            format!(
+                "[{{\"height\":0,\"hash\":\"{hash}\",\"time\":1296688602,\"bits\":545259519,\"nonce\":2}}]\n"
+            )
+        );
+        assert_eq!(
+            render(&BTreeMap::new(), HeaderExportFormat::Json),
+            "[]\n".to_string()
+        );
+    }
+
+    #[test]
+    fn test_attestation_round_trip() {
+        let genesis = genesis_block(Network::Signet).header;
+        let attestation = HeaderAttestation {
+            network: Network::Signet,
+            header: IndexedHeader::new(10, genesis),
+            checkpoint: HeaderCheckpoint::new(0, genesis.block_hash()),
+            work: genesis.work() + genesis.work(),
+            tip_height: 15,
+        };
+        assert_eq!(attestation.confirmations(), 6);
+        let bytes = attestation.to_bytes();
+        assert_eq!(bytes.len(), ATTESTATION_LEN);
+        assert_eq!(HeaderAttestation::from_bytes(&bytes), Some(attestation));
+        assert!(HeaderAttestation::from_bytes(&bytes[1..]).is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt::Debug;
+
+use bitcoin::{bip158::BlockFilter, BlockHash};
+
+/// Decide which blocks to download from their compact block filters, in place of checking the
+/// filters for the scripts added to the node.
+///
+/// Filters are downloaded and checked against the chain of filter headers as usual, and each
+/// filter is then passed to [`FilterMatcher::matches`] once. Blocks that match are downloaded and
+/// sent to the client. The node never learns which scripts are being matched, so a matcher may
+/// derive scripts lazily, such as from a range of an extended public key, or use a probabilistic
+/// set of scripts.
+///
+/// A matcher may be a closure over the height, hash, and filter of a block.
+pub trait FilterMatcher: Send + Sync {
+    /// Return true if the block at `height` with `block_hash` should be downloaded.
+    fn matches(&mut self, height: u32, block_hash: BlockHash, filter: &BlockFilter) -> bool;
+}
+
+impl<F> FilterMatcher for F
+where
+    F: FnMut(u32, BlockHash, &BlockFilter) -> bool + Send + Sync,
+{
+    fn matches(&mut self, height: u32, block_hash: BlockHash, filter: &BlockFilter) -> bool {
+        self(height, block_hash, filter)
+    }
+}
+
+impl Debug for dyn FilterMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FilterMatcher")
+    }
+}
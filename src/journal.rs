@@ -0,0 +1,663 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bitcoin::{
+    bip158::FilterHeader,
+    block::Header,
+    consensus::{Decodable, Encodable},
+    Block, BlockHash, OutPoint, Transaction, TxOut, Txid, VarInt,
+};
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+#[cfg(not(feature = "filter-control"))]
+use crate::Keychain;
+use crate::{
+    chain::IndexedHeader, dialog::Dialog, Event, FilterCheckpoint, HeaderCheckpoint, IndexedBlock,
+    SyncUpdate, Utxo, Warning,
+};
+
+pub(crate) const FILE_NAME: &str = "events.journal";
+const COMPACTED_FILE_NAME: &str = "events.journal.tmp";
+// Compact the journal, and then stop recording events, past this size
+const MAX_FILE_BYTES: u64 = 128 * 1024 * 1024;
+// Flush the journal to disk after this many events, if no checkpoint was reached before
+const EVENTS_PER_SYNC: usize = 100;
+
+// The kind of each record
+const BLOCK: u8 = 0;
+const SYNCED: u8 = 1;
+const BLOCKS_DISCONNECTED: u8 = 2;
+const TX_UNCONFIRMED_BY_REORG: u8 = 3;
+#[cfg(not(feature = "filter-control"))]
+const NEW_DERIVATION_USED: u8 = 4;
+#[cfg(not(feature = "filter-control"))]
+const FILTER_MATCHED: u8 = 5;
+const MEMPOOL_TRANSACTION: u8 = 6;
+#[cfg(not(feature = "filter-control"))]
+const OUTPOINT_SPENT: u8 = 7;
+const UTXO_CREATED: u8 = 8;
+const UTXO_SPENT: u8 = 9;
+// An event that was sent while the journal was full, which is counted but not sent again
+const DROPPED: u8 = 10;
+const UTXOS_REORGANIZED: u8 = 11;
+const ACKNOWLEDGED: u8 = u8::MAX;
+
+// A change to the journal, written in the order it was sent by the dialog
+#[derive(Debug)]
+pub(crate) enum JournalEntry {
+    Event(Vec<u8>),
+    Acknowledged(usize),
+}
+
+impl JournalEntry {
+    // Events that are only meaningful to the running node, such as a shutdown, are not recorded
+    pub(crate) fn event(event: &Event) -> Option<Self> {
+        encode_event(event).map(JournalEntry::Event)
+    }
+}
+
+// An append-only file of the events sent to the client, so events the client did not finish
+// processing before a crash may be sent again when the node starts.
+//
+// Each record is the length of the record, a kind, and the consensus encoding of the event. The
+// client acknowledges events in the order they were sent, which appends a record with the number
+// of events acknowledged. Once every event is acknowledged the file is emptied. A record cut short
+// by a crash is removed when the journal is opened.
+//
+// The file is flushed to disk when the client acknowledges events, when the node is synced, and
+// after a number of events. When the file grows too large the acknowledged events are removed,
+// and if that is not enough, events are no longer recorded until the client acknowledges more.
+#[derive(Debug)]
+pub(crate) struct EventJournal {
+    dir: PathBuf,
+    file: File,
+    len: u64,
+    max_len: u64,
+    unacknowledged: usize,
+    unsynced: usize,
+    // Events from a previous run that were not acknowledged
+    replay: Vec<Event>,
+}
+
+impl EventJournal {
+    pub(crate) fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(FILE_NAME))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut events = VecDeque::new();
+        let mut reader = bytes.as_slice();
+        let mut valid_len = 0;
+        while let Some(record) = next_payload(&mut reader).and_then(decode_record) {
+            match record {
+                Record::Event(event) => events.push_back(event),
+                Record::Acknowledged(count) => {
+                    events.drain(..count.min(events.len()));
+                }
+            }
+            valid_len = bytes.len() - reader.len();
+        }
+        if valid_len < bytes.len() {
+            file.set_len(valid_len as u64)?;
+        }
+        let unacknowledged = events.len();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            len: valid_len as u64,
+            max_len: MAX_FILE_BYTES,
+            unacknowledged,
+            unsynced: 0,
+            replay: events.into_iter().flatten().collect(),
+        })
+    }
+
+    // The events of a previous run to send again, which are already in the journal
+    pub(crate) fn take_replay(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.replay)
+    }
+
+    // Write the entries sent by the dialog until every sender is dropped. The file is only
+    // accessed on the blocking thread pool, so a slow disk never stalls the node.
+    pub(crate) async fn write_entries(
+        mut self,
+        mut entries: UnboundedReceiver<JournalEntry>,
+        dialog: Dialog,
+    ) {
+        while let Some(entry) = entries.recv().await {
+            let mut batch = vec![entry];
+            while let Ok(entry) = entries.try_recv() {
+                batch.push(entry);
+            }
+            let written = crate::runtime::spawn_blocking(move || {
+                let warnings = self.write_batch(batch);
+                (self, warnings)
+            })
+            .await;
+            let (journal, warnings) = match written {
+                Some(written) => written,
+                None => return,
+            };
+            self = journal;
+            for warning in warnings {
+                dialog.send_warning(Warning::FailedPersistence { warning });
+            }
+        }
+    }
+
+    // Write a batch of entries, flushing the file if a checkpoint was reached
+    fn write_batch(&mut self, batch: Vec<JournalEntry>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut checkpoint = false;
+        for entry in batch {
+            match entry {
+                JournalEntry::Event(payload) => {
+                    checkpoint |= payload.first() == Some(&SYNCED);
+                    if let Err(e) = self.append(payload) {
+                        warnings.push(format!("An event could not be written to the journal: {e}"));
+                    }
+                }
+                JournalEntry::Acknowledged(count) => {
+                    checkpoint = true;
+                    if let Err(e) = self.acknowledge(count) {
+                        warnings.push(format!(
+                            "Acknowledged events could not be written to the journal: {e}"
+                        ));
+                    }
+                }
+            }
+        }
+        if checkpoint || self.unsynced >= EVENTS_PER_SYNC {
+            match self.file.sync_data() {
+                Ok(()) => self.unsynced = 0,
+                Err(e) => warnings.push(format!("The event journal could not be flushed: {e}")),
+            }
+        }
+        warnings
+    }
+
+    // Record an event, or only that an event was sent if the journal is full
+    fn append(&mut self, payload: Vec<u8>) -> io::Result<()> {
+        // The event is counted even if it is not recorded, as the client will acknowledge it
+        self.unacknowledged += 1;
+        self.unsynced += 1;
+        let record_len = 4 + payload.len() as u64;
+        if self.len + record_len > self.max_len {
+            self.compact()?;
+        }
+        if self.len + record_len > self.max_len {
+            self.write_record(&[DROPPED])?;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the journal is full until more events are acknowledged.",
+            ));
+        }
+        self.write_record(&payload)
+    }
+
+    // The client finished processing the oldest `count` events
+    fn acknowledge(&mut self, count: usize) -> io::Result<()> {
+        let count = count.min(self.unacknowledged);
+        if count == 0 {
+            return Ok(());
+        }
+        self.unacknowledged -= count;
+        if self.unacknowledged == 0 {
+            self.len = 0;
+            return self.file.set_len(0);
+        }
+        let mut payload = vec![ACKNOWLEDGED];
+        (count as u32).consensus_encode(&mut payload)?;
+        self.write_record(&payload)
+    }
+
+    // Rewrite the journal with only the events that are not acknowledged
+    fn compact(&mut self) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        File::open(self.dir.join(FILE_NAME))?.read_to_end(&mut bytes)?;
+        let mut reader = bytes.as_slice();
+        let mut payloads = VecDeque::new();
+        while let Some(payload) = next_payload(&mut reader) {
+            match payload.split_first() {
+                Some((&ACKNOWLEDGED, mut count)) => {
+                    let count = u32::consensus_decode(&mut count)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    payloads.drain(..(count as usize).min(payloads.len()));
+                }
+                _ => payloads.push_back(payload),
+            }
+        }
+        let compacted_path = self.dir.join(COMPACTED_FILE_NAME);
+        let mut compacted = File::create(&compacted_path)?;
+        let mut len = 0;
+        for payload in payloads {
+            let record = record(payload);
+            compacted.write_all(&record)?;
+            len += record.len() as u64;
+        }
+        compacted.sync_all()?;
+        fs::rename(&compacted_path, self.dir.join(FILE_NAME))?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.dir.join(FILE_NAME))?;
+        self.len = len;
+        Ok(())
+    }
+
+    fn write_record(&mut self, payload: &[u8]) -> io::Result<()> {
+        let record = record(payload);
+        self.file.write_all(&record)?;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn unacknowledged(&self) -> usize {
+        self.unacknowledged
+    }
+}
+
+// A record that was read from the journal. Dropped events are counted for acknowledgements.
+enum Record {
+    Event(Option<Event>),
+    Acknowledged(usize),
+}
+
+fn record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(4 + payload.len());
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(payload);
+    record
+}
+
+// Read the next complete payload, or none if the journal ends or the record is cut short
+fn next_payload<'a>(reader: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if reader.len() < 4 {
+        return None;
+    }
+    let len = u32::from_le_bytes(reader[..4].try_into().expect("4 byte slice")) as usize;
+    let payload = reader.get(4..4 + len)?;
+    *reader = &reader[4 + len..];
+    Some(payload)
+}
+
+fn encode_event(event: &Event) -> Option<Vec<u8>> {
+    let mut payload = Vec::new();
+    let encoded = match event {
+        Event::Block(indexed_block) => {
+            payload.push(BLOCK);
+            indexed_block
+                .height
+                .consensus_encode(&mut payload)
+                .and_then(|_| indexed_block.block.consensus_encode(&mut payload))
+        }
+        Event::Synced(update) => {
+            payload.push(SYNCED);
+            update
+                .tip
+                .height
+                .consensus_encode(&mut payload)
+                .and_then(|_| update.tip.hash.consensus_encode(&mut payload))
+                .and_then(|_| {
+                    encode_headers(
+                        update
+                            .recent_history
+                            .iter()
+                            .map(|(height, header)| (*height, header)),
+                        &mut payload,
+                    )
+                })
+                .and_then(|_| encode_filter(update.tip_filter.as_ref(), &mut payload))
+                .and_then(|_| encode_filter(update.anchor_filter.as_ref(), &mut payload))
+        }
+        Event::BlocksDisconnected(headers) => {
+            payload.push(BLOCKS_DISCONNECTED);
+            encode_headers(
+                headers
+                    .iter()
+                    .map(|indexed| (indexed.height, &indexed.header)),
+                &mut payload,
+            )
+        }
+        Event::TxUnconfirmedByReorg(txid) => {
+            payload.push(TX_UNCONFIRMED_BY_REORG);
+            txid.consensus_encode(&mut payload)
+        }
+        #[cfg(not(feature = "filter-control"))]
+        Event::NewDerivationUsed { keychain, index } => {
+            let keychain: u8 = match keychain {
+                Keychain::External => 0,
+                Keychain::Internal => 1,
+            };
+            payload.push(NEW_DERIVATION_USED);
+            keychain
+                .consensus_encode(&mut payload)
+                .and_then(|_| index.consensus_encode(&mut payload))
+        }
+        #[cfg(not(feature = "filter-control"))]
+        Event::FilterMatched { height, hash } => {
+            payload.push(FILTER_MATCHED);
+            height
+                .consensus_encode(&mut payload)
+                .and_then(|_| hash.consensus_encode(&mut payload))
+        }
+        Event::MempoolTransaction(transaction) => {
+            payload.push(MEMPOOL_TRANSACTION);
+            transaction.consensus_encode(&mut payload)
+        }
+        #[cfg(not(feature = "filter-control"))]
+        Event::OutpointSpent {
+            outpoint,
+            txid,
+            height,
+        } => {
+            payload.push(OUTPOINT_SPENT);
+            outpoint
+                .consensus_encode(&mut payload)
+                .and_then(|_| txid.consensus_encode(&mut payload))
+                .and_then(|_| height.consensus_encode(&mut payload))
+        }
+        Event::UtxoCreated(utxo) => {
+            payload.push(UTXO_CREATED);
+            encode_utxo(utxo, &mut payload)
+        }
+        Event::UtxoSpent { utxo, txid } => {
+            payload.push(UTXO_SPENT);
+            encode_utxo(utxo, &mut payload).and_then(|_| txid.consensus_encode(&mut payload))
+        }
+        Event::UtxosReorganized { removed, unspent } => {
+            payload.push(UTXOS_REORGANIZED);
+            encode_utxos(removed, &mut payload).and_then(|_| encode_utxos(unspent, &mut payload))
+        }
+        // Filters are sent again when they are synced, and a shutdown only concerns this run
+        Event::ShutdownInitiated(_) => return None,
+        #[cfg(feature = "filter-control")]
+        Event::IndexedFilter(_) => return None,
+    };
+    // Writing to a vector is infallible
+    encoded.ok().map(|_| payload)
+}
+
+fn encode_headers<'a>(
+    headers: impl ExactSizeIterator<Item = (u32, &'a Header)>,
+    payload: &mut Vec<u8>,
+) -> Result<usize, bitcoin::io::Error> {
+    let mut len = VarInt(headers.len() as u64).consensus_encode(payload)?;
+    for (height, header) in headers {
+        len += height.consensus_encode(payload)?;
+        len += header.consensus_encode(payload)?;
+    }
+    Ok(len)
+}
+
+fn encode_filter(
+    filter: Option<&FilterCheckpoint>,
+    payload: &mut Vec<u8>,
+) -> Result<usize, bitcoin::io::Error> {
+    match filter {
+        Some(filter) => {
+            let mut len = 1u8.consensus_encode(payload)?;
+            len += filter.block.height.consensus_encode(payload)?;
+            len += filter.block.hash.consensus_encode(payload)?;
+            len += filter.filter_header.consensus_encode(payload)?;
+            Ok(len)
+        }
+        None => 0u8.consensus_encode(payload),
+    }
+}
+
+fn decode_record(mut payload: &[u8]) -> Option<Record> {
+    let reader = &mut payload;
+    let kind = u8::consensus_decode(reader).ok()?;
+    match kind {
+        ACKNOWLEDGED => {
+            let count = u32::consensus_decode(reader).ok()?;
+            return Some(Record::Acknowledged(count as usize));
+        }
+        DROPPED => return Some(Record::Event(None)),
+        _ => (),
+    }
+    let event = match kind {
+        BLOCK => {
+            let height = u32::consensus_decode(reader).ok()?;
+            let block = Block::consensus_decode(reader).ok()?;
+            Event::Block(IndexedBlock::new(height, block))
+        }
+        SYNCED => {
+            let height = u32::consensus_decode(reader).ok()?;
+            let hash = BlockHash::consensus_decode(reader).ok()?;
+            let recent_history = decode_headers(reader)?
+                .into_iter()
+                .map(|indexed| (indexed.height, indexed.header))
+                .collect::<BTreeMap<u32, Header>>();
+            let tip = HeaderCheckpoint::new(height, hash);
+            let tip_filter = decode_filter(reader)?;
+            let anchor_filter = decode_filter(reader)?;
+            Event::Synced(
+                SyncUpdate::new(tip, recent_history).with_filters(tip_filter, anchor_filter),
+            )
+        }
+        BLOCKS_DISCONNECTED => Event::BlocksDisconnected(decode_headers(reader)?),
+        TX_UNCONFIRMED_BY_REORG => {
+            let txid = Txid::consensus_decode(reader).ok()?;
+            Event::TxUnconfirmedByReorg(txid)
+        }
+        #[cfg(not(feature = "filter-control"))]
+        NEW_DERIVATION_USED => {
+            let keychain = match u8::consensus_decode(reader).ok()? {
+                0 => Keychain::External,
+                1 => Keychain::Internal,
+                _ => return None,
+            };
+            let index = u32::consensus_decode(reader).ok()?;
+            Event::NewDerivationUsed { keychain, index }
+        }
+        #[cfg(not(feature = "filter-control"))]
+        FILTER_MATCHED => {
+            let height = u32::consensus_decode(reader).ok()?;
+            let hash = BlockHash::consensus_decode(reader).ok()?;
+            Event::FilterMatched { height, hash }
+        }
+        MEMPOOL_TRANSACTION => {
+            let transaction = Transaction::consensus_decode(reader).ok()?;
+            Event::MempoolTransaction(transaction)
+        }
+        #[cfg(not(feature = "filter-control"))]
+        OUTPOINT_SPENT => {
+            let outpoint = OutPoint::consensus_decode(reader).ok()?;
+            let txid = Txid::consensus_decode(reader).ok()?;
+            let height = u32::consensus_decode(reader).ok()?;
+            Event::OutpointSpent {
+                outpoint,
+                txid,
+                height,
+            }
+        }
+        UTXO_CREATED => Event::UtxoCreated(decode_utxo(reader)?),
+        UTXO_SPENT => {
+            let utxo = decode_utxo(reader)?;
+            let txid = Txid::consensus_decode(reader).ok()?;
+            Event::UtxoSpent { utxo, txid }
+        }
+        UTXOS_REORGANIZED => {
+            let removed = decode_utxos(reader)?;
+            let unspent = decode_utxos(reader)?;
+            Event::UtxosReorganized { removed, unspent }
+        }
+        _ => return None,
+    };
+    Some(Record::Event(Some(event)))
+}
+
+fn encode_utxo(utxo: &Utxo, payload: &mut Vec<u8>) -> Result<usize, bitcoin::io::Error> {
+    let mut len = utxo.outpoint.consensus_encode(payload)?;
+    len += utxo.txout.consensus_encode(payload)?;
+    len += utxo.height.consensus_encode(payload)?;
+    len += utxo.block_hash.consensus_encode(payload)?;
+    Ok(len)
+}
+
+fn encode_utxos(utxos: &[Utxo], payload: &mut Vec<u8>) -> Result<usize, bitcoin::io::Error> {
+    let mut len = VarInt(utxos.len() as u64).consensus_encode(payload)?;
+    for utxo in utxos {
+        len += encode_utxo(utxo, payload)?;
+    }
+    Ok(len)
+}
+
+fn decode_utxos(reader: &mut &[u8]) -> Option<Vec<Utxo>> {
+    let len = VarInt::consensus_decode(reader).ok()?.0;
+    let mut utxos = Vec::new();
+    for _ in 0..len {
+        utxos.push(decode_utxo(reader)?);
+    }
+    Some(utxos)
+}
+
+fn decode_utxo(reader: &mut &[u8]) -> Option<Utxo> {
+    let outpoint = OutPoint::consensus_decode(reader).ok()?;
+    let txout = TxOut::consensus_decode(reader).ok()?;
+    let height = u32::consensus_decode(reader).ok()?;
+    let block_hash = BlockHash::consensus_decode(reader).ok()?;
+    Some(Utxo {
+        outpoint,
+        txout,
+        height,
+        block_hash,
+    })
+}
+
+fn decode_headers(reader: &mut &[u8]) -> Option<Vec<IndexedHeader>> {
+    let len = VarInt::consensus_decode(reader).ok()?.0;
+    let mut headers = Vec::new();
+    for _ in 0..len {
+        let height = u32::consensus_decode(reader).ok()?;
+        let header = Header::consensus_decode(reader).ok()?;
+        headers.push(IndexedHeader::new(height, header));
+    }
+    Some(headers)
+}
+
+fn decode_filter(reader: &mut &[u8]) -> Option<Option<FilterCheckpoint>> {
+    match u8::consensus_decode(reader).ok()? {
+        0 => Some(None),
+        1 => {
+            let height = u32::consensus_decode(reader).ok()?;
+            let hash = BlockHash::consensus_decode(reader).ok()?;
+            let filter_header = FilterHeader::consensus_decode(reader).ok()?;
+            Some(Some(FilterCheckpoint::new(
+                HeaderCheckpoint::new(height, hash),
+                filter_header,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, hashes::Hash, Network};
+
+    use super::*;
+
+    fn write(journal: &mut EventJournal, entries: impl IntoIterator<Item = JournalEntry>) {
+        let warnings = journal.write_batch(entries.into_iter().collect());
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    fn event(event: Event) -> JournalEntry {
+        JournalEntry::event(&event).unwrap()
+    }
+
+    #[test]
+    fn test_unacknowledged_events_are_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let block = genesis_block(Network::Regtest);
+        let header = IndexedHeader::new(0, block.header);
+        let txid = Txid::all_zeros();
+        let mut journal = EventJournal::open(dir.path()).unwrap();
+        assert!(journal.take_replay().is_empty());
+        assert!(
+            JournalEntry::event(&Event::ShutdownInitiated(crate::ShutdownReason::Requested))
+                .is_none()
+        );
+        write(
+            &mut journal,
+            [
+                event(Event::Block(IndexedBlock::new(0, block.clone()))),
+                event(Event::BlocksDisconnected(vec![header])),
+                event(Event::TxUnconfirmedByReorg(txid)),
+                JournalEntry::Acknowledged(1),
+            ],
+        );
+        drop(journal);
+        // A record cut short by a crash is dropped
+        let path = dir.path().join(FILE_NAME);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend([200, 0, 0, 0, BLOCK]);
+        fs::write(&path, &bytes).unwrap();
+        let mut journal = EventJournal::open(dir.path()).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), bytes.len() - 5);
+        let replay = journal.take_replay();
+        assert_eq!(replay.len(), 2);
+        assert!(
+            matches!(&replay[0], Event::BlocksDisconnected(headers) if headers == &vec![header])
+        );
+        assert!(matches!(replay[1], Event::TxUnconfirmedByReorg(id) if id == txid));
+        assert_eq!(journal.unacknowledged(), 2);
+        // The journal is emptied once every event is acknowledged
+        write(&mut journal, [JournalEntry::Acknowledged(5)]);
+        assert_eq!(journal.unacknowledged(), 0);
+        assert!(fs::read(&path).unwrap().is_empty());
+        write(
+            &mut journal,
+            [event(Event::Block(IndexedBlock::new(0, block.clone())))],
+        );
+        drop(journal);
+        let mut journal = EventJournal::open(dir.path()).unwrap();
+        assert!(
+            matches!(&journal.take_replay()[..], [Event::Block(indexed)] if *indexed.block == block)
+        );
+    }
+
+    #[test]
+    fn test_journal_size_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let block = genesis_block(Network::Regtest);
+        let block_event = || event(Event::Block(IndexedBlock::new(0, block.clone())));
+        let mut journal = EventJournal::open(dir.path()).unwrap();
+        let record_len = match block_event() {
+            JournalEntry::Event(payload) => 4 + payload.len() as u64,
+            JournalEntry::Acknowledged(_) => unreachable!(),
+        };
+        journal.max_len = 2 * record_len + 10;
+        write(&mut journal, [block_event(), block_event()]);
+        // Acknowledged events are removed to make room for another
+        write(&mut journal, [JournalEntry::Acknowledged(1), block_event()]);
+        assert_eq!(fs::read(&path).unwrap().len() as u64, 2 * record_len);
+        assert_eq!(journal.unacknowledged(), 2);
+        // A full journal only counts the event, so acknowledgements still line up
+        let warnings = journal.write_batch(vec![
+            block_event(),
+            event(Event::TxUnconfirmedByReorg(Txid::all_zeros())),
+        ]);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(journal.unacknowledged(), 4);
+        write(&mut journal, [JournalEntry::Acknowledged(1)]);
+        drop(journal);
+        let mut journal = EventJournal::open(dir.path()).unwrap();
+        assert_eq!(journal.unacknowledged(), 3);
+        assert_eq!(journal.take_replay().len(), 1);
+    }
+}
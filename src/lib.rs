@@ -64,7 +64,7 @@
 //!             }
 //!         }
 //!     }
-//!     requester.shutdown();
+//!     let _ = requester.shutdown().await;
 //! }
 //! ```
 //!
@@ -72,7 +72,30 @@
 //!
 //! `rusqlite`: use the default `rusqlite` database implementations. Default and recommend feature.
 //!
+//! `redb`: database implementations with [`redb`](https://docs.rs/redb), written in pure Rust, for targets where
+//! cross-compiling the C dependency of SQL Lite is a burden. Pass [`RedbPeerDb`] and [`RedbHeaderDb`] to
+//! [`NodeBuilder::build_with_databases`], and a [`RedbCFHeaderDb`] to `NodeBuilder::cf_header_store`. Requires a more recent compiler than the rest of the crate.
+//!
+//! `i2p`: connect to peers on the I2P network through the SAM bridge of a local I2P router, configured with
+//! `NodeBuilder::i2p_sam_bridge`. I2P peers may be added with `TrustedPeer::from_i2p`, and I2P addresses gossiped by
+//! other peers are dialed as well.
+//!
 //! `filter-control`: check filters and request blocks directly. Recommended for silent payments or strict chain ordering implementations.
+//!
+//! `rest`: fetch blocks from a block source configured with `NodeBuilder::trusted_rest_node` or
+//! `NodeBuilder::block_source` before asking peers. Enabled by default.
+//!
+//! `journal`: persist events to disk with `NodeBuilder::journal_events` until they are acknowledged. Enabled by default.
+//!
+//! `stream`: implement `futures_core::Stream` for [`BlockStream`]. Enabled by default.
+//!
+//! `zeroize`: overwrite the buffers that held the scripts of the wallet with zeros before they are freed. Enabled by
+//! default.
+//!
+//! Building with `default-features = false` is the minimal profile, which has no database dependency, requests blocks
+//! only from peers and does not journal events. It is meant for small binaries on watch-class devices or WASM targets. Headers and peers are kept in
+//! memory with [`MemoryHeaderDb`] and [`MemoryPeerDb`], passed to [`NodeBuilder::build_with_databases`], so the node
+//! syncs from its checkpoint and finds peers over DNS each time it starts.
 
 #![warn(missing_docs)]
 pub mod chain;
@@ -81,32 +104,85 @@ pub mod db;
 mod network;
 mod prelude;
 
+#[cfg(feature = "testing")]
+#[doc(hidden)]
+pub mod bench;
+
+#[cfg(feature = "rest")]
+mod block_source;
 mod broadcaster;
 /// Convenient way to build a compact filters node.
 pub mod builder;
 pub(crate) mod channel_messages;
+mod checkpoint_provider;
 /// Structures to communicate with a node.
 pub mod client;
 /// Node configuration options.
 pub(crate) mod config;
+#[cfg(not(feature = "filter-control"))]
+mod derivation;
 pub(crate) mod dialog;
 /// Errors associated with a node.
 pub mod error;
+mod export;
+mod filter_matcher;
+#[cfg(feature = "journal")]
+mod journal;
+mod mempool;
 /// Messages the node may send a client.
 pub mod messages;
 /// The structure that communicates with the Bitcoin P2P network and collects data.
 pub mod node;
+mod peer_selector;
+mod runtime;
+#[cfg(feature = "testing")]
+mod simulation;
+mod zeroize;
+
+/// Receive each [`IndexedBlock`] that matches the scripts as it is downloaded.
+///
+/// Blocks may be awaited one at a time with [`BlockStream::recv`], or, with the `stream` feature,
+/// consumed as a `futures_core::Stream`. The node does not download more blocks while the stream
+/// is full.
+#[derive(Debug)]
+pub struct BlockStream {
+    receiver: Receiver<IndexedBlock>,
+}
+
+impl BlockStream {
+    pub(crate) fn new(receiver: Receiver<IndexedBlock>) -> Self {
+        Self { receiver }
+    }
+
+    /// Wait for the next block. Returns `None` once the node has stopped running or the stream
+    /// was replaced.
+    pub async fn recv(&mut self) -> Option<IndexedBlock> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(feature = "stream")]
+impl futures_core::Stream for BlockStream {
+    type Item = IndexedBlock;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
 
 /// Receive an [`IndexedBlock`] from a request.
-#[cfg(feature = "filter-control")]
 pub type BlockReceiver = tokio::sync::oneshot::Receiver<Result<IndexedBlock, FetchBlockError>>;
 
-#[cfg(feature = "filter-control")]
 use crate::error::FetchBlockError;
 #[cfg(feature = "filter-control")]
 use chain::Filter;
 
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 // Re-exports
 #[doc(inline)]
@@ -116,27 +192,62 @@ pub use chain::checkpoints::{
 
 #[cfg(feature = "rusqlite")]
 #[doc(inline)]
-pub use db::sqlite::{headers::SqliteHeaderDb, peers::SqlitePeerDb};
+pub use db::sqlite::{
+    cfheaders::SqliteCFHeaderDb, filters::SqliteFilterDb, headers::SqliteHeaderDb,
+    peers::SqlitePeerDb,
+};
 
+#[cfg(feature = "redb")]
 #[doc(inline)]
-pub use db::traits::{HeaderStore, PeerStore};
+pub use db::redb::{cfheaders::RedbCFHeaderDb, headers::RedbHeaderDb, peers::RedbPeerDb};
+
+#[doc(inline)]
+pub use chain::view::ChainView;
+
+#[doc(inline)]
+pub use db::memory::{MemoryCFHeaderDb, MemoryHeaderDb, MemoryPeerDb};
+
+#[doc(inline)]
+pub use db::traits::{CFHeaderStore, FilterStore, HeaderStore, PeerStore};
 
 #[doc(inline)]
 pub use tokio::sync::mpsc::Receiver;
 #[doc(inline)]
 pub use tokio::sync::mpsc::UnboundedReceiver;
 
+#[doc(inline)]
+#[cfg(feature = "rest")]
+pub use crate::block_source::BlockSource;
+
 #[doc(inline)]
 pub use {
     crate::builder::NodeBuilder,
-    crate::client::{Client, Requester},
-    crate::error::{ClientError, NodeError},
-    crate::messages::{Event, Info, Progress, RejectPayload, SyncUpdate, Warning},
+    crate::checkpoint_provider::CheckpointProvider,
+    crate::client::{Client, ClientChannels, Requester},
+    crate::config::ConfigDelta,
+    crate::error::{BuilderError, ClientError, ConnectivityError, NodeError},
+    crate::export::{HeaderAttestation, HeaderExportFormat},
+    crate::filter_matcher::FilterMatcher,
+    crate::messages::{
+        ConnectivityCheck, Event, EventMask, FilterCheckpoint, FilterSpotCheck, Info,
+        IntegrityFailure, IntegrityIssue, IntegrityReport, Misbehavior, PeerActivity,
+        PeerDiversity, PeerInfo, PeerNetwork, PeerState, PendingRequest, PhaseReport, Progress,
+        RejectPayload, RequestKind, ScanStats, ScriptShardStats, ScriptTx, ShutdownReason,
+        ShutdownReport, SyncReport, SyncUpdate, Transport, Utxo, Warning,
+    },
     crate::network::PeerTimeoutConfig,
     crate::node::Node,
+    crate::peer_selector::{PeerSelector, RandomPeerSelector},
 };
 
-#[cfg(feature = "filter-control")]
+#[cfg(not(feature = "filter-control"))]
+#[doc(inline)]
+pub use crate::derivation::{DerivedScriptKind, Keychain, XpubWatch};
+
+#[cfg(feature = "testing")]
+#[doc(inline)]
+pub use crate::simulation::SimulatedReorg;
+
 #[doc(inline)]
 pub use bitcoin::bip158::BlockFilter;
 #[doc(inline)]
@@ -148,17 +259,29 @@ pub use bitcoin::{
 pub extern crate tokio;
 
 /// A Bitcoin [`Block`] with associated height.
+///
+/// The block is shared behind an [`Arc`], so cloning an [`IndexedBlock`], or an [`Event`] holding
+/// one, does not copy the block. This allows a block to be passed to multiple consumers or
+/// retained in a cache cheaply.
 #[derive(Debug, Clone)]
 pub struct IndexedBlock {
     /// The height or index in the chain.
     pub height: u32,
     /// The Bitcoin block with some matching script.
-    pub block: Block,
+    pub block: Arc<Block>,
 }
 
 impl IndexedBlock {
     pub(crate) fn new(height: u32, block: Block) -> Self {
-        Self { height, block }
+        Self {
+            height,
+            block: Arc::new(block),
+        }
+    }
+
+    /// Take the block, copying it only if it is shared with another [`IndexedBlock`].
+    pub fn into_block(self) -> Block {
+        Arc::try_unwrap(self.block).unwrap_or_else(|block| Block::clone(&block))
     }
 }
 
@@ -245,11 +368,49 @@ impl TxBroadcast {
 pub enum TxBroadcastPolicy {
     /// Broadcast the transaction to all peers at the same time.
     AllPeers,
-    /// Broadcast the transaction to a single random peer, optimal for user privacy.
+    /// Broadcast the transaction to a single peer chosen by the [`PeerSelector`], which is random
+    /// by default and optimal for user privacy.
     #[default]
     RandomPeer,
 }
 
+/// What the node does when the peers it connects to do not serve compact block filters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingFiltersPolicy {
+    /// Disconnect from the peer and wait for a connection to a peer that serves filters.
+    #[default]
+    Wait,
+    /// Disconnect from the peer and look for peers that serve filters, by asking the remaining
+    /// connections for new addresses, or by querying DNS seeds if there are none.
+    Search,
+    /// Stop the node with [`NodeError::NoFilterPeers`] after this many peers in a row do not
+    /// serve filters.
+    Fail(u32),
+}
+
+/// Bundled settings for common deployments of a node, applied with
+/// [`NodeBuilder::preset`](crate::NodeBuilder::preset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// A wallet on a phone or other battery-powered device with an unreliable connection. Few
+    /// connections are kept, peers are polled rarely, timeouts allow for slow networks, the peer
+    /// store is kept small, and a single block is processed at a time.
+    MobileWallet,
+    /// A long-running service with a stable connection, such as a Lightning node or a wallet
+    /// backend. More connections are kept, peers must agree on the tip, the tip is polled often,
+    /// peers that are far behind are rejected, and peers that serve filters are searched for.
+    Server,
+    /// A node that trusts its peers as little as possible. Many connections are kept and must
+    /// agree on the tip, connections are rotated often, filters are spot checked against their
+    /// blocks, deep reorganizations are checked on startup, the proof of work of every header is
+    /// checked, and wallet data is redacted from logs.
+    Paranoid,
+    /// A node for tests against a local regtest node on the default port. A single connection is
+    /// made to the local node with short timeouts and polling intervals, and wallet data is
+    /// included in logs.
+    Regtest,
+}
+
 /// A peer on the Bitcoin P2P network
 ///
 /// # Building peers
@@ -318,6 +479,19 @@ impl TrustedPeer {
         }
     }
 
+    /// Create a new trusted peer from the 32 byte hash of an I2P destination, the `<hash>` in
+    /// `<hash>.b32.i2p`. Connecting to the peer requires a SAM bridge, configured with
+    /// `NodeBuilder::i2p_sam_bridge`.
+    #[cfg(feature = "i2p")]
+    pub fn from_i2p(hash: [u8; 32]) -> Self {
+        Self {
+            address: AddrV2::I2p(hash),
+            // I2P streams have no ports, and peers announce the port as zero
+            port: Some(0),
+            known_services: ServiceFlags::NONE,
+        }
+    }
+
     /// The IP address of the trusted peer.
     pub fn address(&self) -> AddrV2 {
         self.address.clone()
@@ -386,12 +560,32 @@ pub enum LogLevel {
     /// node operation.
     #[default]
     Debug,
+    /// Send info and warning messages, and [`Info::RequestSent`] and [`Info::ResponseReceived`]
+    /// for every request to a peer, but omit debug strings. Useful to troubleshoot slow or
+    /// unresponsive peers without the volume of debug strings. The requests are only reported at
+    /// this level.
+    Requests,
     /// Send info and warning messages, but omit debug strings - including their memory allocations. Ideal for a production application that uses minimal logging.
     Info,
     /// Send warnings only.
     Warning,
 }
 
+/// An area of the node that emits messages. Each subsystem may be configured with its own [`LogLevel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    /// Connections and messages to and from remote peers.
+    Peers,
+    /// Block headers, reorganizations, and blocks.
+    Chain,
+    /// Compact block filter headers and filters.
+    Filters,
+    /// Loading and persisting data.
+    Database,
+    /// Starting the node, changes to its configuration, and its sync state.
+    Node,
+}
+
 /// The state of the node with respect to connected peers.
 #[derive(Debug, Clone, Copy)]
 pub enum NodeState {
@@ -425,9 +619,27 @@ impl core::fmt::Display for NodeState {
     }
 }
 
+/// A change in [`NodeState`] and the time the node entered the state.
+#[derive(Debug, Clone, Copy)]
+pub struct StateTransition {
+    /// The state the node entered.
+    pub state: NodeState,
+    /// The time the node entered the state.
+    pub entered: SystemTime,
+}
+
+impl StateTransition {
+    pub(crate) fn new(state: NodeState) -> Self {
+        Self {
+            state,
+            entered: SystemTime::now(),
+        }
+    }
+}
+
 macro_rules! log {
-    ($dialog:expr, $expr:expr) => {
-        match $dialog.log_level {
+    ($dialog:expr, $subsystem:expr, $expr:expr) => {
+        match $dialog.level_of($subsystem) {
             crate::LogLevel::Debug => $dialog.send_dialog($expr).await,
             _ => (),
         }
@@ -435,9 +647,10 @@ macro_rules! log {
 }
 
 macro_rules! info {
-    ($dialog:expr, $expr:expr) => {
-        match $dialog.log_level {
+    ($dialog:expr, $subsystem:expr, $expr:expr) => {
+        match $dialog.level_of($subsystem) {
             crate::LogLevel::Debug => $dialog.send_info($expr).await,
+            crate::LogLevel::Requests => $dialog.send_info($expr).await,
             crate::LogLevel::Info => $dialog.send_info($expr).await,
             _ => (),
         }
@@ -0,0 +1,91 @@
+use std::collections::{HashSet, VecDeque};
+
+use bitcoin::{p2p::message_blockdata::Inventory, Transaction};
+
+// The number of announced transactions to remember
+const MAX_SEEN: usize = 50_000;
+
+// Transactions announced by peers. Each transaction is requested from the first peer to announce
+// it, and announcements are remembered for a while, so a transaction is not downloaded again as
+// other peers announce it.
+#[derive(Debug, Clone)]
+pub(crate) struct Mempool {
+    seen: HashSet<Inventory>,
+    order: VecDeque<Inventory>,
+    requested: HashSet<Inventory>,
+}
+
+impl Mempool {
+    pub(crate) fn new() -> Self {
+        Self {
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    // The transactions to request of the announcing peer, which have not been announced before
+    pub(crate) fn announced(&mut self, inventory: Vec<Inventory>) -> Vec<Inventory> {
+        let mut requests = Vec::new();
+        for inv in inventory {
+            let request = match inv {
+                Inventory::WTx(wtxid) => Inventory::WTx(wtxid),
+                Inventory::Transaction(txid) => Inventory::WitnessTransaction(txid),
+                _ => continue,
+            };
+            if !self.seen.insert(inv) {
+                continue;
+            }
+            self.order.push_back(inv);
+            self.requested.insert(inv);
+            requests.push(request);
+        }
+        while self.order.len() > MAX_SEEN {
+            if let Some(evicted) = self.order.pop_front() {
+                self.seen.remove(&evicted);
+                self.requested.remove(&evicted);
+            }
+        }
+        requests
+    }
+
+    // Was this transaction requested and not received yet
+    pub(crate) fn received(&mut self, transaction: &Transaction) -> bool {
+        self.requested
+            .remove(&Inventory::WTx(transaction.compute_wtxid()))
+            || self
+                .requested
+                .remove(&Inventory::Transaction(transaction.compute_txid()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::p2p::message_blockdata::Inventory;
+
+    use super::Mempool;
+
+    #[test]
+    fn test_announced_transactions_are_requested_once() {
+        let block = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let coinbase = block.txdata[0].clone();
+        let wtxid = coinbase.compute_wtxid();
+        let txid = coinbase.compute_txid();
+        let mut mempool = Mempool::new();
+        // Unsolicited transactions are ignored
+        assert!(!mempool.received(&coinbase));
+        let requests = mempool.announced(vec![
+            Inventory::WTx(wtxid),
+            Inventory::Block(block.block_hash()),
+        ]);
+        assert_eq!(requests, vec![Inventory::WTx(wtxid)]);
+        assert!(mempool.announced(vec![Inventory::WTx(wtxid)]).is_empty());
+        assert!(mempool.received(&coinbase));
+        assert!(!mempool.received(&coinbase));
+        // Peers that do not relay by witness hash are asked for the witness data
+        let mut mempool = Mempool::new();
+        let requests = mempool.announced(vec![Inventory::Transaction(txid)]);
+        assert_eq!(requests, vec![Inventory::WitnessTransaction(txid)]);
+        assert!(mempool.received(&coinbase));
+    }
+}
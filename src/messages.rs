@@ -1,17 +1,29 @@
-use std::{collections::BTreeMap, ops::Range, time::Duration};
+#[cfg(not(feature = "filter-control"))]
+use std::collections::HashSet;
+use std::{collections::BTreeMap, net::SocketAddr, ops::Range, time::Duration};
 
-#[cfg(feature = "filter-control")]
-use bitcoin::BlockHash;
-use bitcoin::{block::Header, p2p::message_network::RejectReason, FeeRate, ScriptBuf, Txid, Wtxid};
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::Address;
+use bitcoin::{
+    bip158::FilterHeader,
+    block::Header,
+    p2p::{address::AddrV2, message_network::RejectReason, ServiceFlags},
+    BlockHash, FeeRate, OutPoint, ScriptBuf, Transaction, TxOut, Txid, Wtxid,
+};
 
 #[cfg(feature = "filter-control")]
 use crate::IndexedFilter;
+#[cfg(not(feature = "filter-control"))]
+use crate::Keychain;
 use crate::{
     chain::{checkpoints::HeaderCheckpoint, IndexedHeader},
-    IndexedBlock, NodeState, TrustedPeer, TxBroadcast,
+    config::ConfigDelta,
+    IndexedBlock, NodeState, StateTransition, TrustedPeer, TxBroadcast,
 };
 
-use super::error::{FetchBlockError, FetchHeaderError};
+#[cfg(not(feature = "filter-control"))]
+use super::error::AddAddressError;
+use super::error::{ConnectivityError, FetchBlockError, FetchHeaderError, UpdateConfigError};
 
 /// Informational messages emitted by a node
 #[derive(Debug, Clone)]
@@ -26,8 +38,116 @@ pub enum Info {
     /// peer, and the peer responded with `getdata`. The transaction was then serialized and sent
     /// over the wire. This is a strong indication the transaction will propagate, but not
     /// guaranteed. You may receive duplicate messages for a given `wtxid` given your broadcast
-    /// policy.
+    /// policy. Only sent if [`NodeBuilder::log_sensitive_data`](crate::NodeBuilder::log_sensitive_data)
+    /// is enabled.
     TxGossiped(Wtxid),
+    /// A rescan was cancelled before completing, and the node is following the tip of the chain.
+    RescanCancelled,
+    /// The node completed a version handshake with a peer.
+    PeerConnected {
+        /// The network address of the peer.
+        addr: AddrV2,
+        /// The port the peer is listening on.
+        port: u16,
+    },
+    /// A block was announced by a peer that is not yet in the chain of most work.
+    BlockAnnounced(BlockHash),
+    /// The node is requesting compact filter headers from a peer.
+    FilterHeadersRequested {
+        /// The height of the first header requested.
+        start_height: u32,
+        /// The hash of the last block in the range.
+        stop_hash: BlockHash,
+    },
+    /// The node is requesting compact block filters from a peer.
+    FiltersRequested {
+        /// The height of the first filter requested.
+        start_height: u32,
+        /// The hash of the last block in the range.
+        stop_hash: BlockHash,
+    },
+    /// The node is requesting a block that matched a filter or was requested by the client. Only
+    /// sent if [`NodeBuilder::log_sensitive_data`](crate::NodeBuilder::log_sensitive_data) is
+    /// enabled.
+    BlockRequested(BlockHash),
+    /// Changes to the chain of block headers were written to the header store.
+    DatabaseFlushed {
+        /// The number of headers written.
+        rows: usize,
+    },
+    /// The header and peer stores were compacted.
+    DatabaseCompacted,
+    /// The headers loaded from the database on startup were checked.
+    IntegrityReport(IntegrityReport),
+    /// A peer sent its version message, advertising what it supports. This is emitted before the
+    /// node decides to keep the connection.
+    PeerNegotiated(PeerInfo),
+    /// The sync stopped making progress, so the node disconnected from the peers it was waiting on
+    /// and requested the data again.
+    StallRecovered {
+        /// The state the node was stalled in.
+        state: NodeState,
+        /// The time since a peer last answered a request.
+        stalled_for: Duration,
+        /// The number of peers that were disconnected.
+        peers_rotated: usize,
+    },
+    /// The chain of most work was extended by headers received from a peer, either announced
+    /// directly or in response to a request.
+    NewTip(HeaderCheckpoint),
+    /// The first block to pay to a script of the node was found and written to the header store.
+    /// Filters before this block are no longer checked, including during a rescan.
+    BirthdayFound(HeaderCheckpoint),
+    /// A filter sampled during the sync was checked against its block.
+    FilterSpotCheck(FilterSpotCheck),
+    /// A connected peer started doing something else, such as serving filters after headers.
+    PeerState(PeerState),
+    /// A request was sent to a peer. Only sent when the log level of
+    /// [`Subsystem::Peers`](crate::Subsystem::Peers) is
+    /// [`LogLevel::Requests`](crate::LogLevel::Requests).
+    RequestSent {
+        /// What was requested.
+        kind: RequestKind,
+        /// The height of the first header or filter requested, for a range of filter headers or
+        /// filters.
+        start_height: Option<u32>,
+        /// The network address of the peer.
+        addr: AddrV2,
+        /// The port the peer is listening on.
+        port: u16,
+    },
+    /// A peer answered a request. Only sent when the log level of
+    /// [`Subsystem::Peers`](crate::Subsystem::Peers) is
+    /// [`LogLevel::Requests`](crate::LogLevel::Requests).
+    ResponseReceived {
+        /// What was requested.
+        kind: RequestKind,
+        /// The network address of the peer.
+        addr: AddrV2,
+        /// The port the peer is listening on.
+        port: u16,
+        /// The time between sending the request and receiving the response.
+        latency: Duration,
+    },
+}
+
+impl Info {
+    // Does the message identify a transaction or block of the wallet
+    pub(crate) fn is_sensitive(&self) -> bool {
+        matches!(
+            self,
+            Info::TxGossiped(_)
+                | Info::BlockRequested(_)
+                | Info::RequestSent {
+                    kind: RequestKind::Block(_),
+                    ..
+                }
+                | Info::ResponseReceived {
+                    kind: RequestKind::Block(_),
+                    ..
+                }
+        )
+    }
 }
 
 impl core::fmt::Display for Info {
@@ -36,6 +156,67 @@ impl core::fmt::Display for Info {
             Info::StateChange(s) => write!(f, "{s}"),
             Info::TxGossiped(txid) => write!(f, "Transaction gossiped: {txid}"),
             Info::ConnectionsMet => write!(f, "Required connections met"),
+            Info::RescanCancelled => write!(f, "Rescan cancelled"),
+            Info::PeerConnected { addr, port } => write!(f, "Connected to peer: {addr:?}:{port}"),
+            Info::BlockAnnounced(hash) => write!(f, "New block: {hash}"),
+            Info::FilterHeadersRequested {
+                start_height,
+                stop_hash,
+            } => write!(
+                f,
+                "Requesting filter headers from height {start_height} to {stop_hash}"
+            ),
+            Info::FiltersRequested {
+                start_height,
+                stop_hash,
+            } => write!(
+                f,
+                "Requesting filters from height {start_height} to {stop_hash}"
+            ),
+            Info::BlockRequested(hash) => write!(f, "Next block in queue: {hash}"),
+            Info::DatabaseFlushed { rows } => write!(f, "Wrote {rows} headers to the database"),
+            Info::DatabaseCompacted => write!(f, "Compacted the databases"),
+            Info::IntegrityReport(report) => write!(f, "{report}"),
+            Info::FilterSpotCheck(check) => write!(f, "{check}"),
+            Info::PeerState(state) => write!(f, "{state}"),
+            Info::RequestSent {
+                kind,
+                start_height,
+                addr,
+                port,
+            } => match start_height {
+                Some(height) => write!(
+                    f,
+                    "Requested {kind:?} starting at height {height} from {addr:?}:{port}"
+                ),
+                None => write!(f, "Requested {kind:?} from {addr:?}:{port}"),
+            },
+            Info::ResponseReceived {
+                kind,
+                addr,
+                port,
+                latency,
+            } => write!(
+                f,
+                "Received {kind:?} from {addr:?}:{port} after {}ms",
+                latency.as_millis()
+            ),
+            Info::PeerNegotiated(info) => write!(f, "{info}"),
+            Info::StallRecovered {
+                state,
+                stalled_for,
+                peers_rotated,
+            } => write!(
+                f,
+                "Recovered from a stall of {} seconds by disconnecting from {peers_rotated} peers. {state}",
+                stalled_for.as_secs()
+            ),
+            Info::NewTip(tip) => write!(f, "New chain tip: {} at height {}", tip.hash, tip.height),
+            Info::BirthdayFound(birthday) => write!(
+                f,
+                "Wallet birthday found: {} at height {}",
+                birthday.hash, birthday.height
+            ),
             Info::Progress(p) => {
                 let progress_percent = p.percentage_complete();
                 write!(f, "Percent complete: {progress_percent}")
@@ -55,9 +236,214 @@ pub enum Event {
     Synced(SyncUpdate),
     /// Blocks were reorganized out of the chain.
     BlocksDisconnected(Vec<IndexedHeader>),
+    /// A transaction broadcast by the node was confirmed in a block that was reorganized out of
+    /// the chain. The transaction is broadcast again and is tracked until it is found in a block
+    /// of the new chain. Transactions confirmed more than 100 blocks deep are no longer tracked.
+    TxUnconfirmedByReorg(Txid),
+    /// The node is stopping, and this is the last event it sends. Supervisors may use the reason
+    /// to tell a requested shutdown from a fatal error.
+    ShutdownInitiated(ShutdownReason),
     /// A compact block filter with associated height and block hash.
     #[cfg(feature = "filter-control")]
     IndexedFilter(IndexedFilter),
+    /// A block paid to a script derived from a watched extended public key, past the last used
+    /// index of the keychain. More scripts were derived to keep the gap limit.
+    #[cfg(not(feature = "filter-control"))]
+    NewDerivationUsed {
+        /// The keychain the script was derived for.
+        keychain: Keychain,
+        /// The new last used index of the keychain.
+        index: u32,
+    },
+    /// The compact block filter of a block matched the scripts of the node, but the block is not
+    /// downloaded, as configured by
+    /// [`NodeBuilder::filter_matches_only`](crate::NodeBuilder::filter_matches_only). The block
+    /// may not pay to or spend from the scripts, as filters have a small false positive rate.
+    #[cfg(not(feature = "filter-control"))]
+    FilterMatched {
+        /// The height of the block.
+        height: u32,
+        /// The hash of the block.
+        hash: BlockHash,
+    },
+    /// An unconfirmed transaction relayed by a peer pays to or spends from the scripts of the
+    /// node, when monitoring the mempool with
+    /// [`NodeBuilder::monitor_mempool`](crate::NodeBuilder::monitor_mempool). The transaction may
+    /// never confirm, as it may be replaced or evicted from the mempools of peers.
+    MempoolTransaction(Transaction),
+    /// A block spent an outpoint watched with
+    /// [`Requester::add_outpoints`](crate::Requester::add_outpoints) or
+    /// [`NodeBuilder::add_outpoints`](crate::NodeBuilder::add_outpoints). The outpoint is no
+    /// longer watched, so it should be added again if the block is reorganized out of the chain.
+    #[cfg(not(feature = "filter-control"))]
+    OutpointSpent {
+        /// The outpoint that was spent.
+        outpoint: OutPoint,
+        /// The transaction that spent the outpoint.
+        txid: Txid,
+        /// The height of the block that spent the outpoint.
+        height: u32,
+    },
+    /// A block created an output paying to a script of the node, when tracking unspent outputs
+    /// with [`NodeBuilder::track_utxos`](crate::NodeBuilder::track_utxos).
+    UtxoCreated(Utxo),
+    /// A block spent an output paying to a script of the node, when tracking unspent outputs with
+    /// [`NodeBuilder::track_utxos`](crate::NodeBuilder::track_utxos).
+    UtxoSpent {
+        /// The output that was spent.
+        utxo: Utxo,
+        /// The transaction that spent the output.
+        txid: Txid,
+    },
+    /// Blocks were reorganized out of the chain, when tracking unspent outputs with
+    /// [`NodeBuilder::track_utxos`](crate::NodeBuilder::track_utxos). The outputs created in those
+    /// blocks no longer exist, and the outputs they spent are unspent again.
+    UtxosReorganized {
+        /// The outputs created in the disconnected blocks.
+        removed: Vec<Utxo>,
+        /// The outputs spent in the disconnected blocks.
+        unspent: Vec<Utxo>,
+    },
+}
+
+/// The kinds of [`Event`] a node sends to the client. Events that are not in the mask are
+/// dropped by the node, so applications that only consume some events do not pay to pass large
+/// events, such as full blocks, through the channel.
+///
+/// Masks may be combined with `|`.
+///
+/// ```
+/// use kyoto::EventMask;
+///
+/// let mask = EventMask::SYNCED | EventMask::BLOCKS_DISCONNECTED;
+/// assert!(mask.contains(EventMask::SYNCED));
+/// assert!(!mask.contains(EventMask::BLOCK));
+/// assert_eq!(
+///     EventMask::ALL.without(EventMask::BLOCK),
+///     mask | EventMask::INDEXED_FILTER
+///         | EventMask::NEW_DERIVATION_USED
+///         | EventMask::TX_UNCONFIRMED_BY_REORG
+///         | EventMask::SHUTDOWN_INITIATED
+///         | EventMask::FILTER_MATCHED
+///         | EventMask::MEMPOOL_TRANSACTION
+///         | EventMask::OUTPOINT_SPENT
+///         | EventMask::UTXO_CREATED
+///         | EventMask::UTXO_SPENT
+///         | EventMask::UTXOS_REORGANIZED
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventMask(u16);
+
+impl EventMask {
+    /// No events.
+    pub const NONE: EventMask = EventMask(0);
+    /// [`Event::Block`].
+    pub const BLOCK: EventMask = EventMask(1);
+    /// [`Event::Synced`].
+    pub const SYNCED: EventMask = EventMask(1 << 1);
+    /// [`Event::BlocksDisconnected`].
+    pub const BLOCKS_DISCONNECTED: EventMask = EventMask(1 << 2);
+    /// `Event::IndexedFilter`, which is only sent with the `filter-control` feature.
+    pub const INDEXED_FILTER: EventMask = EventMask(1 << 3);
+    /// `Event::NewDerivationUsed`, which is not sent with the `filter-control` feature.
+    pub const NEW_DERIVATION_USED: EventMask = EventMask(1 << 4);
+    /// [`Event::TxUnconfirmedByReorg`].
+    pub const TX_UNCONFIRMED_BY_REORG: EventMask = EventMask(1 << 5);
+    /// [`Event::ShutdownInitiated`].
+    pub const SHUTDOWN_INITIATED: EventMask = EventMask(1 << 6);
+    /// `Event::FilterMatched`, which is not sent with the `filter-control` feature.
+    pub const FILTER_MATCHED: EventMask = EventMask(1 << 7);
+    /// [`Event::MempoolTransaction`].
+    pub const MEMPOOL_TRANSACTION: EventMask = EventMask(1 << 8);
+    /// `Event::OutpointSpent`, which is not sent with the `filter-control` feature.
+    pub const OUTPOINT_SPENT: EventMask = EventMask(1 << 9);
+    /// [`Event::UtxoCreated`].
+    pub const UTXO_CREATED: EventMask = EventMask(1 << 10);
+    /// [`Event::UtxoSpent`].
+    pub const UTXO_SPENT: EventMask = EventMask(1 << 11);
+    /// [`Event::UtxosReorganized`].
+    pub const UTXOS_REORGANIZED: EventMask = EventMask(1 << 12);
+    /// Every event.
+    pub const ALL: EventMask = EventMask(0b1_1111_1111_1111);
+
+    /// Does this mask include every event in `other`.
+    pub fn contains(self, other: EventMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// This mask without the events in `other`.
+    pub fn without(self, other: EventMask) -> EventMask {
+        EventMask(self.0 & !other.0)
+    }
+
+    pub(crate) fn allows(self, event: &Event) -> bool {
+        let kind = match event {
+            Event::Block(_) => EventMask::BLOCK,
+            Event::Synced(_) => EventMask::SYNCED,
+            Event::BlocksDisconnected(_) => EventMask::BLOCKS_DISCONNECTED,
+            Event::TxUnconfirmedByReorg(_) => EventMask::TX_UNCONFIRMED_BY_REORG,
+            Event::ShutdownInitiated(_) => EventMask::SHUTDOWN_INITIATED,
+            #[cfg(feature = "filter-control")]
+            Event::IndexedFilter(_) => EventMask::INDEXED_FILTER,
+            #[cfg(not(feature = "filter-control"))]
+            Event::NewDerivationUsed { .. } => EventMask::NEW_DERIVATION_USED,
+            #[cfg(not(feature = "filter-control"))]
+            Event::FilterMatched { .. } => EventMask::FILTER_MATCHED,
+            Event::MempoolTransaction(_) => EventMask::MEMPOOL_TRANSACTION,
+            #[cfg(not(feature = "filter-control"))]
+            Event::OutpointSpent { .. } => EventMask::OUTPOINT_SPENT,
+            Event::UtxoCreated(_) => EventMask::UTXO_CREATED,
+            Event::UtxoSpent { .. } => EventMask::UTXO_SPENT,
+            Event::UtxosReorganized { .. } => EventMask::UTXOS_REORGANIZED,
+        };
+        self.contains(kind)
+    }
+}
+
+impl Default for EventMask {
+    fn default() -> Self {
+        EventMask::ALL
+    }
+}
+
+impl core::ops::BitOr for EventMask {
+    type Output = EventMask;
+
+    fn bitor(self, rhs: EventMask) -> EventMask {
+        EventMask(self.0 | rhs.0)
+    }
+}
+
+/// Why the node stopped running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The client asked the node to stop.
+    Requested,
+    /// The header store failed, with a description of the error.
+    HeaderDatabase(String),
+    /// The peer store failed, with a description of the error.
+    PeerDatabase(String),
+    /// Consecutive peers did not serve compact block filters, as configured by
+    /// [`MissingFiltersPolicy::Fail`](crate::MissingFiltersPolicy::Fail).
+    NoFilterPeers {
+        /// The number of peers in a row that did not serve filters.
+        attempts: u32,
+    },
+}
+
+impl core::fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ShutdownReason::Requested => write!(f, "the client requested a shutdown."),
+            ShutdownReason::HeaderDatabase(e) => write!(f, "block headers: {e}"),
+            ShutdownReason::PeerDatabase(e) => write!(f, "peer manager: {e}"),
+            ShutdownReason::NoFilterPeers { attempts } => write!(
+                f,
+                "{attempts} peers in a row did not serve compact block filters."
+            ),
+        }
+    }
 }
 
 /// The node has synced to a new tip of the chain.
@@ -65,8 +451,14 @@ pub enum Event {
 pub struct SyncUpdate {
     /// Last known tip of the blockchain
     pub tip: HeaderCheckpoint,
-    /// Ten recent headers ending with the tip
+    /// Recent headers ending with the tip, ten by default
     pub recent_history: BTreeMap<u32, Header>,
+    /// The compact filter header of the tip, which commits to the filter of every block up to
+    /// the tip. There is none if the node only syncs block headers.
+    pub tip_filter: Option<FilterCheckpoint>,
+    /// The compact filter header of the block the filter headers were synced from, which is the
+    /// block before the first filter header requested, or the genesis block.
+    pub anchor_filter: Option<FilterCheckpoint>,
 }
 
 impl SyncUpdate {
@@ -74,18 +466,447 @@ impl SyncUpdate {
         Self {
             tip,
             recent_history,
+            tip_filter: None,
+            anchor_filter: None,
         }
     }
 
+    pub(crate) fn with_filters(
+        mut self,
+        tip_filter: Option<FilterCheckpoint>,
+        anchor_filter: Option<FilterCheckpoint>,
+    ) -> Self {
+        self.tip_filter = tip_filter;
+        self.anchor_filter = anchor_filter;
+        self
+    }
+
     /// Get the tip of the blockchain after this sync.
     pub fn tip(&self) -> HeaderCheckpoint {
         self.tip
     }
 
-    /// Get the ten most recent blocks in chronological order after this sync.
+    /// Get the most recent blocks in chronological order after this sync. There are ten, or the
+    /// number set with [`NodeBuilder::reorg_depth`](crate::NodeBuilder::reorg_depth) if it is
+    /// larger.
     pub fn recent_history(&self) -> &BTreeMap<u32, Header> {
         &self.recent_history
     }
+
+    /// Get the compact filter header of the tip after this sync.
+    pub fn tip_filter(&self) -> Option<FilterCheckpoint> {
+        self.tip_filter
+    }
+
+    /// Get the compact filter header the filter headers were synced from.
+    ///
+    /// Filter headers commit to every filter before them, so a wallet may record the filter
+    /// headers of a sync and compare them to a later session. A different filter header for the
+    /// same block means the filter history served by peers has changed.
+    pub fn anchor_filter(&self) -> Option<FilterCheckpoint> {
+        self.anchor_filter
+    }
+}
+
+/// The compact filter header of a block in the chain.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterCheckpoint {
+    /// The block the filter header commits to.
+    pub block: HeaderCheckpoint,
+    /// The filter header, which commits to the filter of the block and every block before it.
+    pub filter_header: FilterHeader,
+}
+
+impl FilterCheckpoint {
+    pub(crate) fn new(block: HeaderCheckpoint, filter_header: FilterHeader) -> Self {
+        Self {
+            block,
+            filter_header,
+        }
+    }
+}
+
+/// Time and bandwidth spent in each phase of syncing since the node started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Downloading block headers.
+    pub headers: PhaseReport,
+    /// Downloading compact block filter headers.
+    pub filter_headers: PhaseReport,
+    /// Downloading compact block filters.
+    pub filters: PhaseReport,
+    /// Downloading blocks with relevant transactions.
+    pub blocks: PhaseReport,
+}
+
+impl SyncReport {
+    pub(crate) fn phase_mut(&mut self, state: NodeState) -> Option<&mut PhaseReport> {
+        match state {
+            NodeState::Behind => Some(&mut self.headers),
+            NodeState::HeadersSynced => Some(&mut self.filter_headers),
+            NodeState::FilterHeadersSynced => Some(&mut self.filters),
+            NodeState::FiltersSynced => Some(&mut self.blocks),
+            NodeState::TransactionsSynced => None,
+        }
+    }
+}
+
+/// Statistics for a shard of the scripts that compact block filters are checked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScriptShardStats {
+    /// The number of scripts in the shard.
+    pub scripts: usize,
+    /// The number of filters checked for the scripts in the shard.
+    pub filters_checked: u64,
+    /// The number of filters that matched a script in the shard.
+    pub matches: u64,
+    /// The time spent checking filters for the scripts in the shard.
+    pub time_matching: Duration,
+}
+
+/// Counts of the filters and blocks the node scanned since it started, to estimate the bandwidth
+/// spent on blocks that matched a filter without paying to the scripts of the node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// The number of filters checked for the scripts of the node.
+    pub filters_scanned: u64,
+    /// The number of filters that matched.
+    pub filters_matched: u64,
+    /// The number of blocks downloaded, including blocks requested by the client.
+    pub blocks_downloaded: u64,
+    /// Blocks downloaded for a filter match that pay to a script of the node, or spend an output
+    /// known to the script index.
+    pub true_positives: u64,
+    /// Blocks downloaded for a filter match with no relevant transaction. Blocks that only spend
+    /// from a script are counted here unless the scripts are indexed.
+    pub false_positives: u64,
+}
+
+impl ScanStats {
+    /// The fraction of blocks downloaded for a filter match with no relevant transaction.
+    pub fn false_positive_rate(&self) -> f64 {
+        let classified = self.true_positives + self.false_positives;
+        if classified == 0 {
+            return 0.;
+        }
+        self.false_positives as f64 / classified as f64
+    }
+}
+
+/// A transaction that paid to or spent from a script, found in a block the node scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScriptTx {
+    /// The transaction ID.
+    pub txid: Txid,
+    /// The height of the block the transaction was confirmed in.
+    pub height: u32,
+    /// The hash of the block the transaction was confirmed in.
+    pub block_hash: BlockHash,
+    /// The index of the output paying to the script, or `None` if the transaction spends an
+    /// output that paid to the script.
+    pub vout: Option<u32>,
+}
+
+/// An unspent output paying to a script of the node, found in a block the node scanned.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Utxo {
+    /// The transaction ID and index of the output.
+    pub outpoint: OutPoint,
+    /// The value and script of the output.
+    pub txout: TxOut,
+    /// The height of the block the output was created in.
+    pub height: u32,
+    /// The hash of the block the output was created in.
+    pub block_hash: BlockHash,
+}
+
+/// What the node persisted before it stopped running.
+#[derive(Debug, Clone, Copy)]
+pub struct ShutdownReport {
+    /// The tip of the block headers written to the header store. This is `None` if a write to
+    /// the header store failed while the node was running.
+    pub tip: Option<HeaderCheckpoint>,
+    /// The highest block with a compact block filter that was checked. Progress through the
+    /// filters is not written to the header store, so this block may be used as the checkpoint
+    /// the next time the node is built to resume checking filters from this block.
+    pub filters_checked_to: Option<HeaderCheckpoint>,
+    /// Blocks that matched a filter or were requested, but were not downloaded.
+    pub blocks_dropped: usize,
+    /// Transactions that were not broadcast.
+    pub transactions_dropped: usize,
+    /// Events that were dropped because the receiver of the client was dropped while the node
+    /// was still sending them.
+    pub events_dropped: usize,
+}
+
+/// The protocol details a peer advertised in its version message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The network address of the peer.
+    pub addr: AddrV2,
+    /// The port the peer is listening on.
+    pub port: u16,
+    /// The protocol version of the peer.
+    pub version: u32,
+    /// The services the peer claims to offer.
+    pub services: ServiceFlags,
+    /// The software the peer is running, for instance `/Satoshi:28.0.0/`.
+    pub user_agent: String,
+    /// The height of the chain of most work when the peer connected.
+    pub start_height: u32,
+}
+
+impl core::fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Peer {:?}:{} version: {}, services: {}, user agent: {}, height: {}",
+            self.addr, self.port, self.version, self.services, self.user_agent, self.start_height
+        )
+    }
+}
+
+/// What a connected peer is currently doing for the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerState {
+    /// The network address of the peer.
+    pub addr: AddrV2,
+    /// The port the peer is listening on.
+    pub port: u16,
+    /// What the peer is doing.
+    pub activity: PeerActivity,
+}
+
+impl core::fmt::Display for PeerState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Peer {:?}:{} is {}", self.addr, self.port, self.activity)
+    }
+}
+
+/// The activity of a connected peer, from the requests it has not answered yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerActivity {
+    /// The version handshake with the peer is not complete.
+    Handshaking,
+    /// The peer was asked for block headers.
+    Headers,
+    /// The peer was asked for compact filter headers.
+    FilterHeaders,
+    /// The peer was asked for compact block filters.
+    Filters,
+    /// The peer was asked for a block.
+    Blocks,
+    /// The peer has answered every request.
+    Idle,
+}
+
+impl core::fmt::Display for PeerActivity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PeerActivity::Handshaking => write!(f, "handshaking"),
+            PeerActivity::Headers => write!(f, "serving headers"),
+            PeerActivity::FilterHeaders => write!(f, "serving filter headers"),
+            PeerActivity::Filters => write!(f, "serving filters"),
+            PeerActivity::Blocks => write!(f, "serving blocks"),
+            PeerActivity::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+/// How the connected peers are spread over transports, networks, netgroups and software. Peers
+/// that have not completed the version handshake are not counted.
+///
+/// A node connected to few netgroups or a single implementation is easier to isolate or eclipse,
+/// so operators may use this report to check their peers are not concentrated on one provider.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerDiversity {
+    /// The number of peers counted.
+    pub peers: usize,
+    /// Peers connected with the unencrypted, version one transport.
+    pub v1: usize,
+    /// Peers connected with the encrypted, version two transport described in BIP 324.
+    pub v2: usize,
+    /// The number of peers on each network.
+    pub networks: BTreeMap<PeerNetwork, usize>,
+    /// The number of peers in each netgroup, such as the first two bytes of an IPv4 address.
+    pub netgroups: BTreeMap<String, usize>,
+    /// The number of peers running each software, named by the last entry of the user agent
+    /// without the version, for instance `Satoshi` for `/Satoshi:28.0.0/`.
+    pub user_agents: BTreeMap<String, usize>,
+}
+
+impl core::fmt::Display for PeerDiversity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} peers, {} v2 transport, {} netgroups, {} user agents",
+            self.peers,
+            self.v2,
+            self.netgroups.len(),
+            self.user_agents.len()
+        )
+    }
+}
+
+/// A way a peer may misbehave. Each misbehavior adds to the score of the peer, and a peer is
+/// banned once its score is high enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehavior {
+    /// The peer sent block headers that do not follow the consensus rules or link to the chain.
+    InvalidHeaders,
+    /// The peer sent filter headers the node did not request or that conflict with the chain.
+    InvalidFilterHeaders,
+    /// The peer sent a compact block filter that does not match its filter header.
+    InvalidFilter,
+    /// The peer sent a block that is not committed to by its header.
+    InvalidBlock,
+    /// The peer sent more messages than the node requested.
+    UnsolicitedMessages,
+    /// The peer held a request without answering it.
+    Unresponsive,
+}
+
+impl core::fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Misbehavior::InvalidHeaders => write!(f, "invalid block headers"),
+            Misbehavior::InvalidFilterHeaders => write!(f, "invalid filter headers"),
+            Misbehavior::InvalidFilter => write!(f, "an invalid compact block filter"),
+            Misbehavior::InvalidBlock => write!(f, "an invalid block"),
+            Misbehavior::UnsolicitedMessages => write!(f, "unsolicited messages"),
+            Misbehavior::Unresponsive => write!(f, "not answering requests"),
+        }
+    }
+}
+
+/// The network a peer is reached on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PeerNetwork {
+    /// An IPv4 address.
+    Ipv4,
+    /// An IPv6 address.
+    Ipv6,
+    /// A Tor onion service.
+    Tor,
+    /// An I2P destination.
+    I2p,
+    /// A CJDNS address.
+    Cjdns,
+    /// A network the node does not recognize.
+    Unknown,
+}
+
+impl From<&AddrV2> for PeerNetwork {
+    fn from(addr: &AddrV2) -> Self {
+        match addr {
+            AddrV2::Ipv4(_) => PeerNetwork::Ipv4,
+            AddrV2::Ipv6(_) => PeerNetwork::Ipv6,
+            AddrV2::TorV2(_) | AddrV2::TorV3(_) => PeerNetwork::Tor,
+            AddrV2::I2p(_) => PeerNetwork::I2p,
+            AddrV2::Cjdns(_) => PeerNetwork::Cjdns,
+            AddrV2::Unknown(_, _) => PeerNetwork::Unknown,
+        }
+    }
+}
+
+/// The way the node opens connections to peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// A direct TCP connection.
+    ClearNet,
+    /// A connection through a Socks5 proxy, such as a local Tor daemon.
+    Socks5Proxy(SocketAddr),
+}
+
+impl core::fmt::Display for Transport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Transport::ClearNet => write!(f, "clearnet"),
+            Transport::Socks5Proxy(proxy) => write!(f, "Socks5 proxy at {proxy}"),
+        }
+    }
+}
+
+/// The result of opening a connection to a peer, to diagnose connectivity problems.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityCheck {
+    /// The way the connection was opened.
+    pub transport: Transport,
+    /// The network address of the peer.
+    pub addr: AddrV2,
+    /// The port the peer is listening on.
+    pub port: u16,
+    /// The time taken to open the connection, or the reason it could not be opened.
+    pub result: Result<Duration, ConnectivityError>,
+}
+
+impl core::fmt::Display for ConnectivityCheck {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.result {
+            Ok(latency) => write!(
+                f,
+                "Peer {:?}:{} reached over {} in {}ms",
+                self.addr,
+                self.port,
+                self.transport,
+                latency.as_millis()
+            ),
+            Err(e) => write!(
+                f,
+                "Peer {:?}:{} could not be reached over {}: {e}",
+                self.addr, self.port, self.transport
+            ),
+        }
+    }
+}
+
+/// A request sent to a peer that has not been answered yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRequest {
+    /// What was requested.
+    pub kind: RequestKind,
+    /// The network address of the peer.
+    pub addr: AddrV2,
+    /// The port the peer is listening on.
+    pub port: u16,
+    /// The time since the request was sent.
+    pub age: Duration,
+}
+
+/// The data requested from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    /// Block headers that extend the chain of most work.
+    Headers,
+    /// Compact filter headers ending at a block.
+    FilterHeaders {
+        /// The hash of the last block in the range.
+        stop_hash: BlockHash,
+    },
+    /// Compact block filters ending at a block. Filters are sent one at a time, so the request is
+    /// pending until the filter for the last block arrives.
+    Filters {
+        /// The hash of the last block in the range.
+        stop_hash: BlockHash,
+    },
+    /// The block with the given hash.
+    Block(BlockHash),
+}
+
+impl RequestKind {
+    pub(crate) fn same_kind(&self, other: &RequestKind) -> bool {
+        core::mem::discriminant(self).eq(&core::mem::discriminant(other))
+    }
+}
+
+/// Time and bandwidth spent in a single phase of syncing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseReport {
+    /// The total time the node spent in this phase.
+    pub elapsed: Duration,
+    /// The number of bytes received for this phase, as serialized on the wire.
+    /// Bytes are counted by message type, so messages received while in another phase are included.
+    pub bytes: u64,
 }
 
 /// The progress of the node during the block filter download process.
@@ -116,6 +937,83 @@ impl Progress {
     }
 }
 
+/// The result of checking the headers loaded from the database on startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The number of headers that passed every check.
+    pub checked: u32,
+    /// The first header that failed a check, if any.
+    pub failure: Option<IntegrityFailure>,
+}
+
+/// The result of checking a filter served by peers against its block.
+///
+/// A filter cannot be rebuilt from the block alone, as it also commits to the scripts the block
+/// spends, so the check only confirms every output script of the block is in the filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterSpotCheck {
+    /// The height of the block.
+    pub height: u32,
+    /// The hash of the block.
+    pub block_hash: BlockHash,
+    /// Every output script of the block was found in the filter.
+    pub passed: bool,
+}
+
+impl core::fmt::Display for FilterSpotCheck {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let outcome = if self.passed {
+            "contains every output of"
+        } else {
+            "is missing outputs of"
+        };
+        write!(
+            f,
+            "The filter at height {} {outcome} block {}",
+            self.height, self.block_hash
+        )
+    }
+}
+
+/// A stored header that failed an integrity check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrityFailure {
+    /// The height of the first bad header.
+    pub height: u32,
+    /// The check the header failed.
+    pub issue: IntegrityIssue,
+}
+
+/// The reason a stored header failed an integrity check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// The header does not link to the previous header.
+    DoesNotLink,
+    /// The header does not have the expected proof of work.
+    InvalidWork,
+    /// The header does not match the known checkpoint at this height.
+    CheckpointMismatch,
+}
+
+impl core::fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.failure {
+            None => write!(f, "Checked {} headers from the database", self.checked),
+            Some(IntegrityFailure { height, issue }) => {
+                let reason = match issue {
+                    IntegrityIssue::DoesNotLink => "does not link to the previous header",
+                    IntegrityIssue::InvalidWork => "has invalid proof of work",
+                    IntegrityIssue::CheckpointMismatch => "does not match a known checkpoint",
+                };
+                write!(
+                    f,
+                    "The header at height {height} {reason}. Start from a checkpoint below height {height} or delete the header database to repair."
+                )
+            }
+        }
+    }
+}
+
 /// An attempt to broadcast a transaction failed.
 #[derive(Debug, Clone, Copy)]
 pub struct RejectPayload {
@@ -134,28 +1032,86 @@ impl RejectPayload {
 /// Commands to issue a node.
 #[derive(Debug)]
 pub(crate) enum ClientMessage {
-    /// Stop the node.
-    Shutdown,
+    /// Stop the node, reporting what was persisted if a sender is provided.
+    Shutdown(Option<ShutdownSender>),
     /// Broadcast a [`crate::Transaction`] with a [`crate::TxBroadcastPolicy`].
     Broadcast(TxBroadcast),
     /// Add more Bitcoin [`ScriptBuf`] to look for.
     #[allow(dead_code)]
     AddScript(ScriptBuf),
+    /// Add the scripts of addresses to look for, if they are valid for the network.
+    #[cfg(not(feature = "filter-control"))]
+    AddAddresses(AddressRequest),
+    /// Stop looking for the scripts.
+    #[cfg(not(feature = "filter-control"))]
+    RemoveScripts(HashSet<ScriptBuf>),
+    /// Look for the spends of the outpoints.
+    #[cfg(not(feature = "filter-control"))]
+    AddOutpoints(HashSet<OutPoint>),
     /// Starting at the configured anchor checkpoint, look for block inclusions with newly added scripts.
     Rescan,
+    /// Stop an in-progress rescan.
+    CancelRescan,
+    /// Compact the databases once the node is synced.
+    CompactDatabase,
     /// Explicitly request a block from the node.
-    #[cfg(feature = "filter-control")]
     GetBlock(BlockRequest),
+    /// Change the configuration of the running node.
+    UpdateConfig(ConfigRequest),
+    /// The client finished processing this many of the oldest journaled events.
+    #[cfg(feature = "journal")]
+    AcknowledgeEvents(usize),
     /// Set a new connection timeout.
     SetDuration(Duration),
     /// Add another known peer to connect to.
     AddPeer(TrustedPeer),
+    /// Disconnect from a peer and never connect to it again.
+    BanPeer(AddrV2),
     /// Request a header from a specified height.
     GetHeader(HeaderRequest),
     /// Request a range of headers.
     GetHeaderBatch(BatchHeaderRequest),
+    /// Request a header and its height by the hash of the block.
+    GetHeaderByHash(HashHeaderRequest),
+    /// Request an attestation to a header in the chain of most work.
+    AttestHeader(AttestationRequest),
     /// Request the broadcast minimum fee rate.
     GetBroadcastMinFeeRate(FeeRateSender),
+    /// Request the time and bandwidth spent syncing.
+    GetSyncReport(SyncReportSender),
+    /// Request the most recent changes in node state.
+    GetStateHistory(StateHistorySender),
+    /// Request the protocol details of the connected peers.
+    GetConnectedPeers(ConnectedPeersSender),
+    /// Request the unanswered requests sent to peers.
+    GetPendingRequests(PendingRequestsSender),
+    /// Request what each connected peer is doing.
+    GetPeerStates(PeerStatesSender),
+    /// Request a summary of the diversity of connected peers.
+    GetPeerDiversity(PeerDiversitySender),
+    /// Request the median offset of the clocks of peers from the local clock.
+    GetTimeOffset(TimeOffsetSender),
+    /// Open test connections to peers with the configured transport.
+    TestConnectivity(ConnectivitySender),
+    /// Request the statistics of each shard of scripts.
+    GetScriptShardStats(ScriptShardStatsSender),
+    /// Request the counts of filters and blocks scanned.
+    GetScanStats(ScanStatsSender),
+    /// Request the transactions found for a script.
+    GetScriptHistory(ScriptHistoryRequest),
+    /// Request the unspent outputs paying to the scripts.
+    ListUnspent(UtxosSender),
+    /// Request a read-only view of the chain of most work.
+    ChainView(ChainViewSender),
+    /// Wait for the chain of most work to reach a height.
+    NotifyAtHeight(HeightTrigger),
+    /// Wait for a transaction to be buried under a number of blocks.
+    NotifyConfirmations(ConfirmationTrigger),
+    /// Send the events of a reorganization to the client without changing the chain.
+    #[cfg(feature = "testing")]
+    SimulateReorg(crate::SimulatedReorg),
+    /// Send matched blocks over a dedicated channel.
+    BlockStream(tokio::sync::mpsc::Sender<IndexedBlock>),
     /// Send an empty message to see if the node is running.
     NoOp,
 }
@@ -174,6 +1130,20 @@ impl HeaderRequest {
     }
 }
 
+type HashHeaderSender = tokio::sync::oneshot::Sender<Result<IndexedHeader, FetchHeaderError>>;
+
+#[derive(Debug)]
+pub(crate) struct HashHeaderRequest {
+    pub(crate) oneshot: HashHeaderSender,
+    pub(crate) hash: BlockHash,
+}
+
+impl HashHeaderRequest {
+    pub(crate) fn new(oneshot: HashHeaderSender, hash: BlockHash) -> Self {
+        Self { oneshot, hash }
+    }
+}
+
 type BatchHeaderSender =
     tokio::sync::oneshot::Sender<Result<BTreeMap<u32, Header>, FetchHeaderError>>;
 
@@ -189,24 +1159,139 @@ impl BatchHeaderRequest {
     }
 }
 
+#[cfg(not(feature = "filter-control"))]
+type AddressSender = tokio::sync::oneshot::Sender<Result<(), AddAddressError>>;
+
+#[cfg(not(feature = "filter-control"))]
+#[derive(Debug)]
+pub(crate) struct AddressRequest {
+    pub(crate) oneshot: AddressSender,
+    pub(crate) addresses: Vec<Address>,
+}
+
+#[cfg(not(feature = "filter-control"))]
+impl AddressRequest {
+    pub(crate) fn new(oneshot: AddressSender, addresses: Vec<Address>) -> Self {
+        Self { oneshot, addresses }
+    }
+}
+
+type ScriptHistorySender = tokio::sync::oneshot::Sender<Vec<ScriptTx>>;
+
+#[derive(Debug)]
+pub(crate) struct ScriptHistoryRequest {
+    pub(crate) oneshot: ScriptHistorySender,
+    pub(crate) script: ScriptBuf,
+}
+
+impl ScriptHistoryRequest {
+    pub(crate) fn new(oneshot: ScriptHistorySender, script: ScriptBuf) -> Self {
+        Self { oneshot, script }
+    }
+}
+
+type ConfigSender = tokio::sync::oneshot::Sender<Result<(), UpdateConfigError>>;
+
+#[derive(Debug)]
+pub(crate) struct ConfigRequest {
+    pub(crate) oneshot: ConfigSender,
+    pub(crate) delta: ConfigDelta,
+}
+
+impl ConfigRequest {
+    pub(crate) fn new(oneshot: ConfigSender, delta: ConfigDelta) -> Self {
+        Self { oneshot, delta }
+    }
+}
+
 pub(crate) type BlockSender = tokio::sync::oneshot::Sender<Result<IndexedBlock, FetchBlockError>>;
 
 pub(crate) type FeeRateSender = tokio::sync::oneshot::Sender<FeeRate>;
 
-#[cfg(feature = "filter-control")]
+pub(crate) type SyncReportSender = tokio::sync::oneshot::Sender<SyncReport>;
+
+pub(crate) type StateHistorySender = tokio::sync::oneshot::Sender<Vec<StateTransition>>;
+
+pub(crate) type ConnectedPeersSender = tokio::sync::oneshot::Sender<Vec<PeerInfo>>;
+
+pub(crate) type PendingRequestsSender = tokio::sync::oneshot::Sender<Vec<PendingRequest>>;
+
+pub(crate) type PeerStatesSender = tokio::sync::oneshot::Sender<Vec<PeerState>>;
+
+pub(crate) type PeerDiversitySender = tokio::sync::oneshot::Sender<PeerDiversity>;
+
+pub(crate) type TimeOffsetSender = tokio::sync::oneshot::Sender<i64>;
+
+pub(crate) type ConnectivitySender = tokio::sync::oneshot::Sender<Vec<ConnectivityCheck>>;
+
+pub(crate) type ScriptShardStatsSender = tokio::sync::oneshot::Sender<Vec<ScriptShardStats>>;
+
+pub(crate) type UtxosSender = tokio::sync::oneshot::Sender<Vec<Utxo>>;
+
+pub(crate) type ChainViewSender = tokio::sync::oneshot::Sender<crate::ChainView>;
+
+pub(crate) type ScanStatsSender = tokio::sync::oneshot::Sender<ScanStats>;
+
+pub(crate) type ShutdownSender = tokio::sync::oneshot::Sender<ShutdownReport>;
+
 #[derive(Debug)]
 pub(crate) struct BlockRequest {
     pub(crate) oneshot: BlockSender,
     pub(crate) hash: BlockHash,
 }
 
-#[cfg(feature = "filter-control")]
 impl BlockRequest {
     pub(crate) fn new(oneshot: BlockSender, hash: BlockHash) -> Self {
         Self { oneshot, hash }
     }
 }
 
+type AttestationSender =
+    tokio::sync::oneshot::Sender<Result<crate::HeaderAttestation, FetchHeaderError>>;
+
+#[derive(Debug)]
+pub(crate) struct AttestationRequest {
+    pub(crate) oneshot: AttestationSender,
+    pub(crate) hash: BlockHash,
+}
+
+impl AttestationRequest {
+    pub(crate) fn new(oneshot: AttestationSender, hash: BlockHash) -> Self {
+        Self { oneshot, hash }
+    }
+}
+
+pub(crate) type IndexedHeaderSender = tokio::sync::oneshot::Sender<IndexedHeader>;
+
+#[derive(Debug)]
+pub(crate) struct HeightTrigger {
+    pub(crate) oneshot: IndexedHeaderSender,
+    pub(crate) height: u32,
+}
+
+impl HeightTrigger {
+    pub(crate) fn new(oneshot: IndexedHeaderSender, height: u32) -> Self {
+        Self { oneshot, height }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConfirmationTrigger {
+    pub(crate) oneshot: IndexedHeaderSender,
+    pub(crate) txid: Txid,
+    pub(crate) depth: u32,
+}
+
+impl ConfirmationTrigger {
+    pub(crate) fn new(oneshot: IndexedHeaderSender, txid: Txid, depth: u32) -> Self {
+        Self {
+            oneshot,
+            txid,
+            depth,
+        }
+    }
+}
+
 /// Warnings a node may issue while running.
 #[derive(Debug, Clone)]
 pub enum Warning {
@@ -219,10 +1304,37 @@ pub enum Warning {
     },
     /// A connection to a peer timed out.
     PeerTimedOut,
+    /// A connected peer did not answer a request or send any data for a while, and was replaced.
+    /// The peer is less likely to be selected again, and is banned if it is replaced three times
+    /// or misbehaves in other ways.
+    UnresponsivePeer {
+        /// The network address of the peer.
+        addr: AddrV2,
+        /// The port the peer is listening on.
+        port: u16,
+    },
+    /// A peer misbehaved often enough to be banned. The peer is disconnected, and the ban is
+    /// saved to the peer store so the node does not connect to the peer again.
+    PeerBanned {
+        /// The network address of the peer.
+        addr: AddrV2,
+        /// The port the peer is listening on.
+        port: u16,
+        /// The misbehavior that resulted in the ban.
+        misbehavior: Misbehavior,
+    },
     /// The node was unable to connect to a peer in the database.
     CouldNotConnect,
     /// A connection was maintained, but the peer does not signal for compact block filers.
     NoCompactFilters,
+    /// A peer advertised a starting height too far below the chain of most work to serve recent
+    /// blocks and filters, so the connection was dropped.
+    PeerBehind {
+        /// The height the peer advertised in its version message.
+        peer_height: u32,
+        /// The height the node expects, from the header chain or the most recent checkpoint.
+        expected_height: u32,
+    },
     /// The node has been waiting for new `inv` and will find new peers to avoid block withholding.
     PotentialStaleTip,
     /// A peer sent us a peer-to-peer message the node did not request.
@@ -232,6 +1344,27 @@ pub enum Warning {
     InvalidStartHeight,
     /// The headers in the database do not link together. Recoverable by deleting the database.
     CorruptedHeaders,
+    /// Most blocks downloaded for a filter match had no relevant transaction, and these scripts
+    /// matched many of them. Scripts that other wallets commonly pay to or spend from, such as
+    /// short or anyone-can-spend scripts, cause a block to be downloaded for every use.
+    #[cfg(not(feature = "filter-control"))]
+    BroadScripts {
+        /// The scripts that matched the most blocks with no relevant transaction, most first. Empty
+        /// unless [`NodeBuilder::log_sensitive_data`](crate::NodeBuilder::log_sensitive_data) is
+        /// enabled.
+        scripts: Vec<ScriptBuf>,
+        /// The number of blocks with no relevant transaction.
+        false_positives: u32,
+        /// The number of blocks downloaded for a filter match.
+        blocks: u32,
+        /// The scripts are no longer checked, as configured with
+        /// [`NodeBuilder::exclude_broad_scripts`](crate::NodeBuilder::exclude_broad_scripts).
+        excluded: bool,
+    },
+    /// A filter sampled during the sync is missing an output of its block, so the filters served
+    /// by peers are invalid. The filter headers were agreed on by the connected peers, so the
+    /// peers may be colluding.
+    InvalidFilter(FilterSpotCheck),
     /// A transaction got rejected, likely for being an insufficient fee or non-standard transaction.
     TransactionRejected {
         /// The transaction ID and reject reason, if it exists.
@@ -244,7 +1377,7 @@ pub enum Warning {
     },
     /// The peer sent us a potential fork.
     EvaluatingFork,
-    /// The peer database has no values.
+    /// The peer database has no values. The node queries DNS seeds for new peers.
     EmptyPeerDatabase,
     /// An unexpected error occurred processing a peer-to-peer message.
     UnexpectedSyncError {
@@ -253,6 +1386,31 @@ pub enum Warning {
     },
     /// A channel that was supposed to receive a message was dropped.
     ChannelDropped,
+    /// A peer advertised a height far past the newest checkpoint, even after asking the
+    /// [`CheckpointProvider`](crate::CheckpointProvider), if one was configured. This likely means
+    /// the version of the crate is outdated, and syncing from the newest checkpoint may take longer
+    /// than expected.
+    OutdatedCheckpoints {
+        /// The newest checkpoint known to the node.
+        checkpoint: HeaderCheckpoint,
+        /// The height the peer advertised in its version message.
+        peer_height: u32,
+    },
+    /// The clocks of peers are far from the local clock, and no peer agrees with the local clock,
+    /// so the date or time of the device is likely wrong. The offset is too large to adjust the
+    /// local clock by, so header timestamps are checked against the local clock.
+    ClockSkew {
+        /// The median offset of the clocks of peers from the local clock, in seconds. A positive
+        /// offset means the local clock is behind.
+        offset_secs: i64,
+    },
+    /// A database operation took longer than expected, which may indicate failing storage.
+    SlowDatabase {
+        /// The operation that was performed.
+        operation: String,
+        /// The time the operation took to complete.
+        elapsed: Duration,
+    },
 }
 
 impl core::fmt::Display for Warning {
@@ -274,9 +1432,29 @@ impl core::fmt::Display for Warning {
             Warning::CouldNotConnect => {
                 write!(f, "An attempted connection failed or timed out.")
             }
+            Warning::PeerBanned {
+                addr,
+                port,
+                misbehavior,
+            } => {
+                write!(f, "Banned peer {addr:?}:{port} for {misbehavior}.")
+            }
+            Warning::UnresponsivePeer { addr, port } => write!(
+                f,
+                "Peer {addr:?}:{port} did not answer a request and was replaced."
+            ),
             Warning::NoCompactFilters => {
                 write!(f, "A connected peer does not serve compact block filters.")
             }
+            Warning::PeerBehind {
+                peer_height,
+                expected_height,
+            } => {
+                write!(
+                    f,
+                    "A peer at height {peer_height} is too far behind the expected height {expected_height}."
+                )
+            }
             Warning::PotentialStaleTip => {
                 write!(
                     f,
@@ -297,6 +1475,32 @@ impl core::fmt::Display for Warning {
             Warning::CorruptedHeaders => {
                 write!(f, "The headers in the database do not link together.")
             }
+            #[cfg(not(feature = "filter-control"))]
+            Warning::BroadScripts {
+                scripts,
+                false_positives,
+                blocks,
+                excluded,
+            } => {
+                write!(
+                    f,
+                    "{false_positives} of {blocks} matched blocks had no relevant transaction"
+                )?;
+                if scripts.is_empty() {
+                    write!(f, ".")?;
+                } else {
+                    write!(f, ", mostly matched by {} scripts.", scripts.len())?;
+                }
+                if *excluded {
+                    write!(f, " The scripts are no longer checked.")?;
+                }
+                Ok(())
+            }
+            Warning::InvalidFilter(check) => write!(
+                f,
+                "The filter served for block {} at height {} is missing an output of the block.",
+                check.block_hash, check.height
+            ),
             Warning::PeerTimedOut => {
                 write!(f, "A connection to a peer timed out.")
             }
@@ -312,6 +1516,29 @@ impl core::fmt::Display for Warning {
                     "A channel that was supposed to receive a message was dropped."
                 )
             }
+            Warning::OutdatedCheckpoints {
+                checkpoint,
+                peer_height,
+            } => {
+                write!(
+                    f,
+                    "The newest checkpoint at height {} is far below a peer at height {peer_height}.",
+                    checkpoint.height
+                )
+            }
+            Warning::ClockSkew { offset_secs } => {
+                write!(
+                    f,
+                    "The clocks of peers are {offset_secs} seconds from the local clock. Check the date and time of the device."
+                )
+            }
+            Warning::SlowDatabase { operation, elapsed } => {
+                write!(
+                    f,
+                    "A database operation was slow: {operation} took {} ms",
+                    elapsed.as_millis()
+                )
+            }
         }
     }
 }
@@ -0,0 +1,102 @@
+use std::sync::Mutex;
+
+// The smallest buffer worth pooling. Anything smaller is kept by the reader of each peer.
+const MIN_CLASS_BYTES: usize = 1024 * 512;
+// Blocks are bounded by weight to 4MB, so larger messages are rare enough to allocate
+const MAX_CLASS_BYTES: usize = 1024 * 1024 * 4;
+// Size classes double from the smallest to the largest: 512KiB, 1MiB, 2MiB, 4MiB
+const NUM_CLASSES: usize = 4;
+// The most idle buffers kept for each class
+const MAX_BUFFERS_PER_CLASS: usize = 4;
+
+// Buffers for large messages, such as full blocks, shared by the readers of every peer.
+//
+// During a block download, each block would otherwise allocate and free a buffer of up to a few
+// megabytes. Buffers are grouped into size classes of powers of two, so a buffer returned after
+// one block may be handed out for the next block of a similar size.
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    classes: Mutex<[Vec<Vec<u8>>; NUM_CLASSES]>,
+}
+
+impl BufferPool {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // An empty buffer with a capacity of at least `len` bytes
+    pub(crate) fn take(&self, len: usize) -> Vec<u8> {
+        let class = match Self::class_of(len) {
+            Some(class) => class,
+            None => return Vec::with_capacity(len),
+        };
+        if let Ok(mut classes) = self.classes.lock() {
+            if let Some(buffer) = classes[class].pop() {
+                return buffer;
+            }
+        }
+        Vec::with_capacity(MIN_CLASS_BYTES << class)
+    }
+
+    // Return a buffer for reuse. Buffers that do not fit a class, or a class that is full, are
+    // dropped.
+    pub(crate) fn give(&self, mut buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        if !(MIN_CLASS_BYTES..=MAX_CLASS_BYTES).contains(&capacity) {
+            return;
+        }
+        // The largest class the buffer can serve
+        let multiple = capacity / MIN_CLASS_BYTES;
+        let class = (usize::BITS - 1 - multiple.leading_zeros()) as usize;
+        if let Ok(mut classes) = self.classes.lock() {
+            if classes[class].len() < MAX_BUFFERS_PER_CLASS {
+                buffer.clear();
+                classes[class].push(buffer);
+            }
+        }
+    }
+
+    // The smallest class that fits `len` bytes
+    fn class_of(len: usize) -> Option<usize> {
+        if len > MAX_CLASS_BYTES {
+            return None;
+        }
+        let class = len.max(MIN_CLASS_BYTES).next_power_of_two() / MIN_CLASS_BYTES;
+        Some(class.trailing_zeros() as usize)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn idle(&self) -> usize {
+        self.classes.lock().unwrap().iter().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused_by_size() {
+        let pool = BufferPool::new();
+        let block = pool.take(1024 * 1024 + 1);
+        assert!(block.capacity() >= 1024 * 1024 * 2);
+        let ptr = block.as_ptr();
+        pool.give(block);
+        assert_eq!(pool.idle(), 1);
+        // A smaller class does not take the buffer of a larger class
+        let small = pool.take(1024 * 600);
+        assert_ne!(small.as_ptr(), ptr);
+        assert_eq!(pool.idle(), 1);
+        let reused = pool.take(1024 * 1024 * 2);
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(pool.idle(), 0);
+        // Buffers that are too large are not pooled
+        let huge = pool.take(MAX_CLASS_BYTES + 1);
+        pool.give(huge);
+        assert_eq!(pool.idle(), 0);
+        for _ in 0..MAX_BUFFERS_PER_CLASS + 1 {
+            pool.give(Vec::with_capacity(MIN_CLASS_BYTES));
+        }
+        assert_eq!(pool.idle(), MAX_BUFFERS_PER_CLASS);
+    }
+}
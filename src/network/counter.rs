@@ -66,6 +66,10 @@ impl MessageCounter {
         self.block -= 1;
     }
 
+    pub(crate) fn got_pong(&mut self) {
+        self.timer.untrack();
+    }
+
     pub(crate) fn got_reject(&mut self) {
         self.tx -= 1;
     }
@@ -97,6 +101,10 @@ impl MessageCounter {
         self.block += 1;
     }
 
+    pub(crate) fn sent_ping(&mut self) {
+        self.timer.track();
+    }
+
     pub(crate) fn sent_tx(&mut self) {
         self.tx += 1;
     }
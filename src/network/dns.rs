@@ -7,10 +7,11 @@ use bitcoin::{
 use std::{
     io::Read,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
 };
 use tokio::net::UdpSocket;
 
-use super::error::DNSQueryError;
+use super::{error::DNSQueryError, socks::create_socks5, ConnectionType};
 
 const SIGNET_SEEDS: &[&str; 2] = &["seed.dlsouza.lol", "seed.signet.bitcoin.sprovoost.nl"];
 
@@ -47,6 +48,9 @@ pub(crate) const DNS_RESOLVER_PORT: u16 = 53;
 const LOCAL_HOST: &str = "0.0.0.0:0";
 
 const HEADER_BYTES: usize = 12;
+// A proxy that accepts the connection but never relays a response would otherwise stall the
+// bootstrap indefinitely
+const PROXIED_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
 
 const RECURSIVE_FLAGS: [u8; 2] = [
     0x01, 0x00, // Default flags with recursive resolver
@@ -88,21 +92,25 @@ impl From<DnsResolver> for SocketAddr {
 pub(crate) struct Dns<'a> {
     seeds: Vec<&'a str>,
     dns_resolver: DnsResolver,
+    connector: ConnectionType,
 }
 
-impl Dns<'_> {
-    pub fn new(network: Network, dns_resolver: DnsResolver) -> Self {
-        let seeds = match network {
-            Network::Bitcoin => MAINNET_SEEDS.to_vec(),
-            Network::Testnet => TESTNET_SEEDS.to_vec(),
-            Network::Signet => SIGNET_SEEDS.to_vec(),
-            Network::Regtest => Vec::with_capacity(0),
-            Network::Testnet4 => TESTNET4_SEEDS.to_vec(),
-            _ => unreachable!(),
+impl<'a> Dns<'a> {
+    // Query the configured seeds, or the default seeds of the network if there are none
+    pub fn new(
+        network: Network,
+        seeds: Option<&'a [String]>,
+        dns_resolver: DnsResolver,
+        connector: ConnectionType,
+    ) -> Self {
+        let seeds = match seeds {
+            Some(seeds) => seeds.iter().map(String::as_str).collect(),
+            None => default_seeds(network),
         };
         Self {
             seeds,
             dns_resolver,
+            connector,
         }
     }
 
@@ -110,10 +118,17 @@ impl Dns<'_> {
         let mut ip_addrs: Vec<IpAddr> = vec![];
         for host in &self.seeds {
             for filter in SERVICE_BITS_PREFIX {
-                if let Ok(addrs) = DNSQuery::new(host, filter)
-                    .lookup(self.dns_resolver.into())
-                    .await
-                {
+                let query = DNSQuery::new(host, filter);
+                let addrs = match self.connector {
+                    ConnectionType::ClearNet => query.lookup(self.dns_resolver.into()).await,
+                    // Nothing is resolved locally, the query is sent to the resolver over the proxy
+                    ConnectionType::Socks5Proxy(proxy) => {
+                        query
+                            .lookup_proxied(proxy, self.dns_resolver.into(), PROXIED_LOOKUP_TIMEOUT)
+                            .await
+                    }
+                };
+                if let Ok(addrs) = addrs {
                     ip_addrs.extend(addrs);
                 }
             }
@@ -122,6 +137,17 @@ impl Dns<'_> {
     }
 }
 
+fn default_seeds(network: Network) -> Vec<&'static str> {
+    match network {
+        Network::Bitcoin => MAINNET_SEEDS.to_vec(),
+        Network::Testnet => TESTNET_SEEDS.to_vec(),
+        Network::Signet => SIGNET_SEEDS.to_vec(),
+        Network::Regtest => Vec::with_capacity(0),
+        Network::Testnet4 => TESTNET4_SEEDS.to_vec(),
+        _ => unreachable!(),
+    }
+}
+
 struct DNSQuery {
     message_id: [u8; 2],
     message: Vec<u8>,
@@ -174,6 +200,54 @@ impl DNSQuery {
         Ok(ips)
     }
 
+    // DNS over TCP, as UDP cannot be sent through a Socks5 proxy. Messages are prefixed with their
+    // length as two bytes.
+    async fn lookup_proxied(
+        &self,
+        proxy: SocketAddr,
+        dns_resolver: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Vec<IpAddr>, DNSQueryError> {
+        tokio::time::timeout(timeout, self.exchange_proxied(proxy, dns_resolver))
+            .await
+            .map_err(|_| DNSQueryError::Timeout)?
+    }
+
+    async fn exchange_proxied(
+        &self,
+        proxy: SocketAddr,
+        dns_resolver: SocketAddr,
+    ) -> Result<Vec<IpAddr>, DNSQueryError> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut tcp_stream = create_socks5(proxy, dns_resolver.ip(), dns_resolver.port())
+            .await
+            .map_err(|_| DNSQueryError::Proxy)?;
+        let length = (self.message.len() as u16).to_be_bytes();
+        tcp_stream
+            .write_all(&length)
+            .await
+            .map_err(|_| DNSQueryError::Proxy)?;
+        tcp_stream
+            .write_all(&self.message)
+            .await
+            .map_err(|_| DNSQueryError::Proxy)?;
+        let mut length = [0u8; 2];
+        tcp_stream
+            .read_exact(&mut length)
+            .await
+            .map_err(|_| DNSQueryError::UnexpectedEOF)?;
+        let mut response_buf = vec![0u8; u16::from_be_bytes(length) as usize];
+        tcp_stream
+            .read_exact(&mut response_buf)
+            .await
+            .map_err(|_| DNSQueryError::UnexpectedEOF)?;
+        if response_buf.len() < HEADER_BYTES {
+            return Err(DNSQueryError::MalformedHeader);
+        }
+        let ips = self.parse_message(&response_buf)?;
+        Ok(ips)
+    }
+
     fn parse_message(&self, mut response: &[u8]) -> Result<Vec<IpAddr>, DNSQueryError> {
         let mut ips = Vec::with_capacity(10);
         let mut buf: [u8; 2] = [0, 0];
@@ -266,7 +340,9 @@ mod test {
         let socket_addr = "1.1.1.1:53".parse::<SocketAddr>().unwrap();
         let addrs = Dns::new(
             bitcoin::network::Network::Bitcoin,
+            None,
             DnsResolver { socket_addr },
+            ConnectionType::ClearNet,
         )
         .bootstrap()
         .await;
@@ -274,4 +350,118 @@ mod test {
         let first = addrs.first().unwrap();
         println!("Example IP: {first:?}");
     }
+
+    #[tokio::test]
+    async fn dns_queried_through_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let seed = Ipv4Addr::new(8, 8, 4, 4);
+        // A Socks5 proxy that answers the DNS query itself
+        tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[4..8], [1, 1, 1, 1]);
+            stream
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let mut response = query[..2].to_vec();
+            response.extend([0x81, 0x80, 0x00, 0x01, 0x00, 0x01]);
+            response.extend(COUNTS[2..].iter());
+            response.extend_from_slice(&query[HEADER_BYTES..]);
+            // A pointer to the name in the question, an A record, the TTL, and the address
+            response.extend([0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c]);
+            response.extend([0x00, 0x04]);
+            response.extend(seed.octets());
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&response).await.unwrap();
+        });
+        let query = DNSQuery::new("seed.bitcoin.sipa.be", SERVICE_BITS_PREFIX[0]);
+        let addrs = query
+            .lookup_proxied(
+                proxy_addr,
+                DnsResolver::default().into(),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![IpAddr::V4(seed)]);
+    }
+
+    #[tokio::test]
+    async fn dns_proxied_lookup_times_out() {
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        // A proxy that accepts the connection and never responds
+        tokio::spawn(async move {
+            let (_stream, _) = proxy.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let query = DNSQuery::new("seed.bitcoin.sipa.be", SERVICE_BITS_PREFIX[0]);
+        let result = query
+            .lookup_proxied(
+                proxy_addr,
+                DnsResolver::default().into(),
+                Duration::from_millis(100),
+            )
+            .await;
+        assert!(matches!(result, Err(DNSQueryError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn dns_queries_configured_seeds() {
+        let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = resolver.local_addr().unwrap();
+        let seed = Ipv4Addr::new(8, 8, 4, 4);
+        // A resolver that answers any query with a single address
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (amt, from) = resolver.recv_from(&mut buf).await.unwrap();
+                let query = &buf[..amt];
+                let name = String::from_utf8_lossy(&query[HEADER_BYTES..]);
+                assert!(name.contains("seed") && name.contains("example"));
+                let mut response = query[..2].to_vec();
+                response.extend([0x81, 0x80, 0x00, 0x01, 0x00, 0x01]);
+                response.extend(COUNTS[2..].iter());
+                response.extend_from_slice(&query[HEADER_BYTES..]);
+                response.extend([0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c]);
+                response.extend([0x00, 0x04]);
+                response.extend(seed.octets());
+                let _ = resolver.send_to(&response, from).await;
+            }
+        });
+        // Regtest has no seeds of its own
+        let seeds = vec!["seed.example.com".to_string()];
+        let addrs = Dns::new(
+            Network::Regtest,
+            Some(&seeds),
+            DnsResolver { socket_addr },
+            ConnectionType::ClearNet,
+        )
+        .bootstrap()
+        .await;
+        assert_eq!(addrs, vec![IpAddr::V4(seed); SERVICE_BITS_PREFIX.len()]);
+        let addrs = Dns::new(
+            Network::Regtest,
+            None,
+            DnsResolver { socket_addr },
+            ConnectionType::ClearNet,
+        )
+        .bootstrap()
+        .await;
+        assert!(addrs.is_empty());
+    }
 }
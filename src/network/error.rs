@@ -1,3 +1,5 @@
+#[cfg(feature = "rest")]
+use crate::error::BlockSourceError;
 use crate::impl_sourceless_error;
 
 #[derive(Debug)]
@@ -40,6 +42,8 @@ pub(crate) enum PeerError {
     Reader,
     UnreachableSocketAddr,
     Socks5(Socks5Error),
+    #[cfg(feature = "i2p")]
+    I2p(SamError),
 }
 
 impl core::fmt::Display for PeerError {
@@ -72,6 +76,10 @@ impl core::fmt::Display for PeerError {
             PeerError::Socks5(err) => {
                 write!(f, "could not connect via Socks5 proxy: {err}")
             }
+            #[cfg(feature = "i2p")]
+            PeerError::I2p(err) => {
+                write!(f, "could not connect via the I2P router: {err}")
+            }
         }
     }
 }
@@ -113,6 +121,48 @@ impl From<std::io::Error> for Socks5Error {
     }
 }
 
+#[cfg(feature = "i2p")]
+#[derive(Debug, Clone)]
+pub(crate) enum SamError {
+    Unreachable,
+    Timeout,
+    UnexpectedReply,
+    InvalidSession,
+    SessionPending,
+    Rejected(String),
+    IO,
+}
+
+#[cfg(feature = "i2p")]
+impl core::fmt::Display for SamError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SamError::Unreachable => write!(f, "the SAM bridge of the router is unreachable."),
+            SamError::Timeout => write!(f, "the router did not respond in time."),
+            SamError::UnexpectedReply => write!(f, "the router sent an unexpected reply."),
+            SamError::InvalidSession => write!(f, "the router no longer knows the session."),
+            SamError::SessionPending => {
+                write!(f, "the session with the router is still being created.")
+            }
+            SamError::Rejected(result) => write!(f, "the router rejected the request: {result}."),
+            SamError::IO => write!(
+                f,
+                "reading or writing to the TCP stream failed unexpectedly."
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "i2p")]
+impl_sourceless_error!(SamError);
+
+#[cfg(feature = "i2p")]
+impl From<std::io::Error> for SamError {
+    fn from(_value: std::io::Error) -> Self {
+        SamError::IO
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum DNSQueryError {
     MessageID,
@@ -121,6 +171,8 @@ pub(crate) enum DNSQueryError {
     Udp,
     MalformedHeader,
     UnexpectedEOF,
+    Proxy,
+    Timeout,
 }
 
 impl core::fmt::Display for DNSQueryError {
@@ -134,8 +186,80 @@ impl core::fmt::Display for DNSQueryError {
             DNSQueryError::Udp => write!(f, "reading or writing from the UDP connection failed."),
             DNSQueryError::MessageID => write!(f, "mismatch of message ID."),
             DNSQueryError::Question => write!(f, "the question of the message does not match."),
+            DNSQueryError::Proxy => {
+                write!(f, "the query could not be sent through the Socks5 proxy.")
+            }
+            DNSQueryError::Timeout => write!(f, "the resolver did not respond in time."),
         }
     }
 }
 
 impl_sourceless_error!(DNSQueryError);
+
+#[cfg(feature = "rest")]
+#[derive(Debug)]
+pub(crate) enum RestError {
+    ConnectionFailed,
+    Timeout,
+    IO,
+    Status(u16),
+    MalformedResponse,
+    Deserialization,
+    UnexpectedBlock,
+    ResponseTooLarge,
+}
+
+#[cfg(feature = "rest")]
+impl core::fmt::Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestError::ConnectionFailed => write!(f, "could not connect to the REST server."),
+            RestError::Timeout => write!(f, "the REST server did not respond in time."),
+            RestError::IO => write!(
+                f,
+                "reading or writing to the TCP stream failed unexpectedly."
+            ),
+            RestError::Status(code) => write!(f, "the REST server responded with status {code}."),
+            RestError::MalformedResponse => write!(f, "the HTTP response could not be parsed."),
+            RestError::Deserialization => {
+                write!(
+                    f,
+                    "the response body could not be deserialized into a block."
+                )
+            }
+            RestError::UnexpectedBlock => {
+                write!(f, "the REST server responded with a different block.")
+            }
+            RestError::ResponseTooLarge => {
+                write!(f, "the response is larger than any block.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rest")]
+impl_sourceless_error!(RestError);
+
+#[cfg(feature = "rest")]
+impl From<std::io::Error> for RestError {
+    fn from(_value: std::io::Error) -> Self {
+        RestError::IO
+    }
+}
+
+#[cfg(feature = "rest")]
+impl From<RestError> for BlockSourceError {
+    fn from(value: RestError) -> Self {
+        match value {
+            RestError::ConnectionFailed | RestError::Timeout | RestError::IO => {
+                BlockSourceError::Unavailable
+            }
+            RestError::Status(404) => BlockSourceError::NotFound,
+            RestError::Status(_) => BlockSourceError::Unavailable,
+            RestError::MalformedResponse
+            | RestError::Deserialization
+            | RestError::UnexpectedBlock
+            | RestError::ResponseTooLarge => BlockSourceError::InvalidResponse,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Partial implementation of the SAM v3.1 protocol, to open streams to I2P peers through a router
+// ref: https://geti2p.net/en/docs/api/samv3
+
+use std::{net::SocketAddr, time::Duration};
+
+use bitcoin::hex::DisplayHex;
+use bitcoin::key::rand::{thread_rng, RngCore};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+use super::error::SamError;
+
+// Building the tunnels of a new session may take much longer than connecting to a peer
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+// Replies with a private destination are the longest, at around a kilobyte
+const MAX_LINE_LEN: usize = 4096;
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+// The base32 address of an I2P destination, from the hash of the destination
+pub(crate) fn b32_address(hash: &[u8; 32]) -> String {
+    let mut address = String::with_capacity(60);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for byte in hash {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            address.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        address.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    address.push_str(".b32.i2p");
+    address
+}
+
+// A bridge to an I2P router. Streams are opened with a session that lasts as long as its control
+// connection to the router, so the session is created once and used for every peer. Building the
+// tunnels of a session is slow, so the session is created on a task of its own and peers are only
+// dialed once it is ready.
+#[derive(Debug)]
+pub(crate) struct SamBridge {
+    sam: SocketAddr,
+    session: Option<SamSession>,
+    pending_session: Option<JoinHandle<Result<SamSession, SamError>>>,
+}
+
+#[derive(Debug)]
+struct SamSession {
+    id: String,
+    _control: TcpStream,
+}
+
+impl SamBridge {
+    pub(crate) fn new(sam: SocketAddr) -> Self {
+        Self {
+            sam,
+            session: None,
+            pending_session: None,
+        }
+    }
+
+    // Open a stream to the destination with the hash, the payload of an I2P address
+    pub(crate) async fn connect(
+        &mut self,
+        hash: &[u8; 32],
+        handshake_timeout: Duration,
+    ) -> Result<TcpStream, SamError> {
+        let id = self.session_id().await?.ok_or(SamError::SessionPending)?;
+        let stream = tokio::time::timeout(handshake_timeout, self.open_stream(&id, hash))
+            .await
+            .map_err(|_| SamError::Timeout)?;
+        // The router forgets a session if its control connection is closed
+        if let Err(SamError::InvalidSession) = stream {
+            self.session = None;
+        }
+        stream
+    }
+
+    // The identifier of the session, or `None` while the session is created in the background
+    async fn session_id(&mut self) -> Result<Option<String>, SamError> {
+        if let Some(session) = self.session.as_ref() {
+            return Ok(Some(session.id.clone()));
+        }
+        match self.pending_session.take() {
+            Some(handle) if handle.is_finished() => {
+                let session = handle.await.map_err(|_| SamError::Unreachable)??;
+                let id = session.id.clone();
+                self.session = Some(session);
+                Ok(Some(id))
+            }
+            Some(handle) => {
+                self.pending_session = Some(handle);
+                Ok(None)
+            }
+            None => {
+                let sam = self.sam;
+                self.pending_session = Some(tokio::spawn(async move {
+                    tokio::time::timeout(SESSION_TIMEOUT, create_session(sam))
+                        .await
+                        .map_err(|_| SamError::Timeout)?
+                }));
+                Ok(None)
+            }
+        }
+    }
+
+    async fn open_stream(&self, id: &str, hash: &[u8; 32]) -> Result<TcpStream, SamError> {
+        let mut stream = hello(self.sam).await?;
+        let command = format!("NAMING LOOKUP NAME={}\n", b32_address(hash));
+        stream.write_all(command.as_bytes()).await?;
+        let reply = read_line(&mut stream).await?;
+        check_reply(&reply, "NAMING REPLY")?;
+        let destination = value_of(&reply, "VALUE").ok_or(SamError::UnexpectedReply)?;
+        let command = format!("STREAM CONNECT ID={id} DESTINATION={destination} SILENT=false\n");
+        stream.write_all(command.as_bytes()).await?;
+        let reply = read_line(&mut stream).await?;
+        check_reply(&reply, "STREAM STATUS")?;
+        // The stream is now connected to the peer
+        Ok(stream)
+    }
+}
+
+// Create a session on the router, held open by its control connection
+async fn create_session(sam: SocketAddr) -> Result<SamSession, SamError> {
+    let mut control = hello(sam).await?;
+    let mut id = [0u8; 8];
+    thread_rng().fill_bytes(&mut id);
+    let id = format!("kyoto{}", id.to_lower_hex_string());
+    // A transient destination is generated by the router and is not reused between sessions
+    let command =
+        format!("SESSION CREATE STYLE=STREAM ID={id} DESTINATION=TRANSIENT SIGNATURE_TYPE=7\n");
+    control.write_all(command.as_bytes()).await?;
+    let reply = read_line(&mut control).await?;
+    check_reply(&reply, "SESSION STATUS")?;
+    Ok(SamSession {
+        id,
+        _control: control,
+    })
+}
+
+// Open a connection to the router and agree on a version of the protocol
+async fn hello(sam: SocketAddr) -> Result<TcpStream, SamError> {
+    let mut stream = TcpStream::connect(sam)
+        .await
+        .map_err(|_| SamError::Unreachable)?;
+    stream.write_all(b"HELLO VERSION MIN=3.1 MAX=3.1\n").await?;
+    let reply = read_line(&mut stream).await?;
+    check_reply(&reply, "HELLO REPLY")?;
+    Ok(stream)
+}
+
+// Read a reply one byte at a time, so no bytes of a stream that follows are buffered
+async fn read_line(stream: &mut TcpStream) -> Result<String, SamError> {
+    let mut line = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        if line.len() == MAX_LINE_LEN {
+            return Err(SamError::UnexpectedReply);
+        }
+        line.push(byte);
+    }
+    String::from_utf8(line).map_err(|_| SamError::UnexpectedReply)
+}
+
+fn value_of<'a>(reply: &'a str, key: &str) -> Option<&'a str> {
+    reply.split_whitespace().find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        k.eq(key).then_some(v)
+    })
+}
+
+fn check_reply(reply: &str, expected: &str) -> Result<(), SamError> {
+    if !reply.starts_with(expected) {
+        return Err(SamError::UnexpectedReply);
+    }
+    match value_of(reply, "RESULT") {
+        Some("OK") => Ok(()),
+        Some("INVALID_ID") => Err(SamError::InvalidSession),
+        Some(result) => Err(SamError::Rejected(result.to_string())),
+        None => Err(SamError::UnexpectedReply),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_b32_address() {
+        let mut hash = [0u8; 32];
+        hash[..6].copy_from_slice(b"foobar");
+        let address = b32_address(&hash);
+        assert!(address.starts_with("mzxw6ytboi"));
+        assert_eq!(address.len(), 52 + ".b32.i2p".len());
+    }
+
+    #[tokio::test]
+    async fn test_sam_stream() {
+        let router = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sam = router.local_addr().unwrap();
+        // A router that accepts a session and a stream, then echoes the stream
+        tokio::spawn(async move {
+            let (mut control, _) = router.accept().await.unwrap();
+            assert!(read_line(&mut control).await.unwrap().starts_with("HELLO"));
+            control
+                .write_all(b"HELLO REPLY RESULT=OK VERSION=3.1\n")
+                .await
+                .unwrap();
+            let create = read_line(&mut control).await.unwrap();
+            assert!(create.contains("DESTINATION=TRANSIENT"));
+            control
+                .write_all(b"SESSION STATUS RESULT=OK DESTINATION=private\n")
+                .await
+                .unwrap();
+            let (mut stream, _) = router.accept().await.unwrap();
+            read_line(&mut stream).await.unwrap();
+            stream
+                .write_all(b"HELLO REPLY RESULT=OK VERSION=3.1\n")
+                .await
+                .unwrap();
+            let lookup = read_line(&mut stream).await.unwrap();
+            assert!(lookup.ends_with(".b32.i2p"));
+            stream
+                .write_all(b"NAMING REPLY RESULT=OK NAME=peer.b32.i2p VALUE=peer\n")
+                .await
+                .unwrap();
+            let connect = read_line(&mut stream).await.unwrap();
+            assert_eq!(value_of(&connect, "DESTINATION"), Some("peer"));
+            assert_eq!(value_of(&create, "ID"), value_of(&connect, "ID"));
+            stream
+                .write_all(b"STREAM STATUS RESULT=OK\n")
+                .await
+                .unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            let _ = control.read_u8().await;
+        });
+        let mut bridge = SamBridge::new(sam);
+        // Peers are not dialed until the session is created
+        assert!(matches!(
+            bridge.connect(&[1; 32], Duration::from_secs(5)).await,
+            Err(SamError::SessionPending)
+        ));
+        let mut stream = loop {
+            match bridge.connect(&[1; 32], Duration::from_secs(5)).await {
+                Ok(stream) => break stream,
+                Err(SamError::SessionPending) => {
+                    tokio::time::sleep(Duration::from_millis(10)).await
+                }
+                Err(e) => panic!("{e}"),
+            }
+        };
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert!(bridge.session.is_some());
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use bitcoin::p2p::address::AddrV2;
+
+use crate::messages::Misbehavior;
+
+// Peers are banned once their score reaches this threshold
+const BAN_SCORE: u32 = 100;
+
+impl Misbehavior {
+    // Invalid data is banned on the first offense. Peers that do not respond are banned the third
+    // time they are replaced, as blocks may take a while to arrive over slow connections.
+    fn penalty(&self) -> u32 {
+        match self {
+            Misbehavior::InvalidHeaders
+            | Misbehavior::InvalidFilterHeaders
+            | Misbehavior::InvalidFilter
+            | Misbehavior::InvalidBlock => BAN_SCORE,
+            Misbehavior::UnsolicitedMessages => BAN_SCORE / 2,
+            Misbehavior::Unresponsive => BAN_SCORE / 3 + 1,
+        }
+    }
+}
+
+// The misbehavior scores of the peers the node connected to since it started. Bans are persisted
+// through the peer store, so the scores are kept in memory.
+#[derive(Debug, Default)]
+pub(crate) struct MisbehaviorScores {
+    scores: HashMap<AddrV2, u32>,
+}
+
+impl MisbehaviorScores {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Add the penalty for the misbehavior, returning if the peer should be banned
+    pub(crate) fn penalize(&mut self, addr: &AddrV2, misbehavior: Misbehavior) -> bool {
+        let score = self.scores.entry(addr.clone()).or_default();
+        *score = score.saturating_add(misbehavior.penalty());
+        *score >= BAN_SCORE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_misbehavior_adds_up_to_a_ban() {
+        let mut scores = MisbehaviorScores::new();
+        let addr = AddrV2::Ipv4(Ipv4Addr::new(1, 1, 1, 1));
+        let other = AddrV2::Ipv4(Ipv4Addr::new(2, 2, 2, 2));
+        assert!(!scores.penalize(&addr, Misbehavior::Unresponsive));
+        assert!(!scores.penalize(&addr, Misbehavior::Unresponsive));
+        assert!(scores.penalize(&addr, Misbehavior::Unresponsive));
+        assert!(!scores.penalize(&other, Misbehavior::UnsolicitedMessages));
+        assert!(!scores.penalize(&other, Misbehavior::Unresponsive));
+        assert!(scores.penalize(&other, Misbehavior::UnsolicitedMessages));
+        let invalid = AddrV2::Ipv4(Ipv4Addr::new(3, 3, 3, 3));
+        assert!(scores.penalize(&invalid, Misbehavior::InvalidHeaders));
+    }
+}
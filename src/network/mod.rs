@@ -11,18 +11,28 @@ use bitcoin::{
 use socks::create_socks5;
 use tokio::{net::TcpStream, time::Instant};
 
-use error::PeerError;
+use error::{PeerError, Socks5Error};
 
+use crate::{error::ConnectivityError, ConnectivityCheck, Transport};
+
+pub(crate) mod buffer_pool;
 pub(crate) mod counter;
 pub(crate) mod dns;
 #[allow(dead_code)]
 pub(crate) mod error;
+#[cfg(feature = "i2p")]
+pub(crate) mod i2p;
+pub(crate) mod misbehavior;
+pub(crate) mod network_time;
 pub(crate) mod outbound_messages;
 pub(crate) mod parsers;
 pub(crate) mod peer;
 pub(crate) mod peer_map;
+pub(crate) mod pending;
 #[allow(dead_code)]
 pub(crate) mod reader;
+#[cfg(feature = "rest")]
+pub(crate) mod rest;
 pub(crate) mod socks;
 
 pub const PROTOCOL_VERSION: u32 = 70016;
@@ -34,6 +44,9 @@ const MESSAGE_TIMEOUT_SECS: u64 = 5;
 //                    sec  min  hour
 const TWO_HOUR: u64 = 60 * 60 * 2;
 const TCP_CONNECTION_TIMEOUT: u64 = 2;
+const PING_INTERVAL_SECS: u64 = 60 * 2;
+pub(crate) const TIP_POLL_INTERVAL_SECS: u64 = 60 * 5;
+pub(crate) const STALL_TIMEOUT_SECS: u64 = 60 * 3;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) struct PeerId(pub(crate) u32);
@@ -65,6 +78,8 @@ pub struct PeerTimeoutConfig {
     pub(crate) max_connection_time: Duration,
     /// How much time does the peer have to make the initial TCP handshake
     pub(crate) handshake_timeout: Duration,
+    /// How long a peer may be silent before it is sent a ping
+    pub(crate) ping_interval: Duration,
 }
 
 impl PeerTimeoutConfig {
@@ -78,6 +93,7 @@ impl PeerTimeoutConfig {
             response_timeout,
             max_connection_time,
             handshake_timeout,
+            ping_interval: Duration::from_secs(PING_INTERVAL_SECS),
         }
     }
 }
@@ -88,17 +104,24 @@ impl Default for PeerTimeoutConfig {
             response_timeout: Duration::from_secs(MESSAGE_TIMEOUT_SECS),
             max_connection_time: Duration::from_secs(TWO_HOUR),
             handshake_timeout: Duration::from_secs(TCP_CONNECTION_TIMEOUT),
+            ping_interval: Duration::from_secs(PING_INTERVAL_SECS),
         }
     }
 }
 
 pub(crate) struct LastBlockMonitor {
     last_block: Option<Instant>,
+    last_poll: Instant,
+    poll_interval: Duration,
 }
 
 impl LastBlockMonitor {
-    pub(crate) fn new() -> Self {
-        Self { last_block: None }
+    pub(crate) fn new(poll_interval: Duration) -> Self {
+        Self {
+            last_block: None,
+            last_poll: Instant::now(),
+            poll_interval,
+        }
     }
 
     pub(crate) fn reset(&mut self) {
@@ -111,6 +134,40 @@ impl LastBlockMonitor {
         }
         false
     }
+
+    // Returns true at most once per interval, when the tip should be polled with `getheaders`
+    pub(crate) fn poll_due(&mut self) -> bool {
+        if Instant::now().duration_since(self.last_poll) > self.poll_interval {
+            self.last_poll = Instant::now();
+            return true;
+        }
+        false
+    }
+}
+
+// Detects when peers have stopped answering requests while the node is syncing
+pub(crate) struct StallWatchdog {
+    last_progress: Instant,
+    timeout: Duration,
+}
+
+impl StallWatchdog {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            last_progress: Instant::now(),
+            timeout,
+        }
+    }
+
+    pub(crate) fn progressed(&mut self) {
+        self.last_progress = Instant::now()
+    }
+
+    // The time since the last progress, if longer than the timeout
+    pub(crate) fn stalled(&self) -> Option<Duration> {
+        let elapsed = Instant::now().duration_since(self.last_progress);
+        (elapsed > self.timeout).then_some(elapsed)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -162,6 +219,73 @@ impl ConnectionType {
             }
         }
     }
+
+    pub(crate) fn transport(&self) -> Transport {
+        match self {
+            Self::ClearNet => Transport::ClearNet,
+            Self::Socks5Proxy(proxy) => Transport::Socks5Proxy(*proxy),
+        }
+    }
+
+    // Time opening a connection to the peer, closing it without a handshake
+    pub(crate) async fn check(
+        &self,
+        addr: AddrV2,
+        port: u16,
+        handshake_timeout: Duration,
+    ) -> ConnectivityCheck {
+        let result = self.open(&addr, port, handshake_timeout).await;
+        ConnectivityCheck {
+            transport: self.transport(),
+            addr,
+            port,
+            result,
+        }
+    }
+
+    async fn open(
+        &self,
+        addr: &AddrV2,
+        port: u16,
+        handshake_timeout: Duration,
+    ) -> Result<Duration, ConnectivityError> {
+        let ip_addr = match addr {
+            AddrV2::Ipv4(ip) => IpAddr::V4(*ip),
+            AddrV2::Ipv6(ip) => IpAddr::V6(*ip),
+            _ => return Err(ConnectivityError::Unreachable),
+        };
+        match &self {
+            Self::ClearNet => {
+                let start = Instant::now();
+                tokio::time::timeout(handshake_timeout, TcpStream::connect((ip_addr, port)))
+                    .await
+                    .map_err(|_| ConnectivityError::Timeout)?
+                    .map_err(|_| ConnectivityError::Unreachable)?;
+                Ok(start.elapsed())
+            }
+            Self::Socks5Proxy(proxy) => {
+                // Reach the proxy first, so a proxy that is down is not mistaken for a peer
+                tokio::time::timeout(handshake_timeout, TcpStream::connect(proxy))
+                    .await
+                    .map_err(|_| ConnectivityError::ProxyUnreachable)?
+                    .map_err(|_| ConnectivityError::ProxyUnreachable)?;
+                let start = Instant::now();
+                tokio::time::timeout(handshake_timeout, create_socks5(*proxy, ip_addr, port))
+                    .await
+                    .map_err(|_| ConnectivityError::Timeout)?
+                    .map_err(|e| match e {
+                        Socks5Error::WrongVersion | Socks5Error::AuthRequired => {
+                            ConnectivityError::ProxyUnsupported
+                        }
+                        Socks5Error::ConnectionTimeout => ConnectivityError::ProxyUnreachable,
+                        Socks5Error::ConnectionFailed | Socks5Error::IO => {
+                            ConnectivityError::Unreachable
+                        }
+                    })?;
+                Ok(start.elapsed())
+            }
+        }
+    }
 }
 
 pub(crate) struct V1Header {
@@ -193,7 +317,9 @@ mod tests {
     use std::net::Ipv4Addr;
 
     use bitcoin::p2p::address::AddrV2;
+    use tokio::net::TcpListener;
 
+    use super::*;
     use crate::prelude::Netgroup;
 
     #[test]
@@ -201,4 +327,29 @@ mod tests {
         let peer = AddrV2::Ipv4(Ipv4Addr::new(95, 217, 198, 121));
         assert_eq!("95.217".to_string(), peer.netgroup());
     }
+
+    #[tokio::test]
+    async fn test_connectivity_checks() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let localhost = AddrV2::Ipv4(Ipv4Addr::LOCALHOST);
+        let timeout = Duration::from_secs(TCP_CONNECTION_TIMEOUT);
+        let check = ConnectionType::ClearNet
+            .check(localhost.clone(), port, timeout)
+            .await;
+        assert_eq!(check.transport, Transport::ClearNet);
+        assert!(check.result.is_ok());
+        // Nothing listens on the port once the listener is dropped
+        drop(listener);
+        let check = ConnectionType::ClearNet
+            .check(localhost.clone(), port, timeout)
+            .await;
+        assert_eq!(check.result, Err(ConnectivityError::Unreachable));
+        let proxy = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let check = ConnectionType::Socks5Proxy(proxy)
+            .check(localhost, port, timeout)
+            .await;
+        assert_eq!(check.transport, Transport::Socks5Proxy(proxy));
+        assert_eq!(check.result, Err(ConnectivityError::ProxyUnreachable));
+    }
 }
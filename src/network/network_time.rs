@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use bitcoin::p2p::address::AddrV2;
+
+use crate::prelude::Median;
+
+// Only the first peers are sampled, as in Bitcoin Core
+const MAX_SAMPLES: usize = 200;
+// The local clock is not adjusted until this many peers are sampled
+const MIN_SAMPLES: usize = 5;
+// The largest adjustment made to the local clock, so peers cannot move the time of the node far
+const MAX_ADJUSTMENT_SECS: i64 = 70 * 60;
+// A peer with a clock this close to the local clock suggests the local clock is correct
+const CLOSE_OFFSET_SECS: i64 = 5 * 60;
+
+// The offsets of the clocks of peers from the local clock, reported in the version message of each
+// peer. The network-adjusted time is the local clock moved by the median offset, like Bitcoin
+// Core. Each address is sampled once, so a peer cannot skew the median by reconnecting.
+#[derive(Debug, Default)]
+pub(crate) struct NetworkTime {
+    offsets: HashMap<AddrV2, i64>,
+    warned: bool,
+}
+
+impl NetworkTime {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Sample the offset of a peer, returning the median offset if it shows the local clock is
+    // likely wrong. The local clock is reported at most once.
+    pub(crate) fn add(&mut self, addr: &AddrV2, offset: i64) -> Option<i64> {
+        if self.offsets.len() >= MAX_SAMPLES || self.offsets.contains_key(addr) {
+            return None;
+        }
+        self.offsets.insert(addr.clone(), offset);
+        if self.warned || self.offsets.len() < MIN_SAMPLES {
+            return None;
+        }
+        let median = self.median();
+        let close = self
+            .offsets
+            .values()
+            .any(|offset| offset.abs() <= CLOSE_OFFSET_SECS);
+        if median.abs() > MAX_ADJUSTMENT_SECS && !close {
+            self.warned = true;
+            return Some(median);
+        }
+        None
+    }
+
+    // The offset of the network-adjusted time from the local clock, in seconds. The local clock
+    // is used until enough peers are sampled, or if the peers disagree with it by too much.
+    pub(crate) fn offset(&self) -> i64 {
+        if self.offsets.len() < MIN_SAMPLES {
+            return 0;
+        }
+        let median = self.median();
+        if median.abs() > MAX_ADJUSTMENT_SECS {
+            return 0;
+        }
+        median
+    }
+
+    fn median(&self) -> i64 {
+        let mut offsets = self.offsets.values().copied().collect::<Vec<i64>>();
+        offsets.median()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn addr(n: u8) -> AddrV2 {
+        AddrV2::Ipv4(Ipv4Addr::new(n, n, n, n))
+    }
+
+    #[test]
+    fn test_network_adjusted_time() {
+        let mut time = NetworkTime::new();
+        for n in 0..4 {
+            assert!(time.add(&addr(n), 60).is_none());
+        }
+        // Too few peers are sampled to adjust the clock
+        assert_eq!(time.offset(), 0);
+        // A peer that reconnects is not sampled again
+        assert!(time.add(&addr(0), -600).is_none());
+        assert!(time.add(&addr(4), -600).is_none());
+        assert_eq!(time.offset(), 60);
+        // The local clock is hours behind every peer
+        let mut time = NetworkTime::new();
+        for n in 0..4 {
+            assert!(time.add(&addr(n), 3 * 60 * 60).is_none());
+        }
+        assert_eq!(time.add(&addr(4), 3 * 60 * 60), Some(3 * 60 * 60));
+        assert_eq!(time.offset(), 0);
+        assert!(time.add(&addr(5), 3 * 60 * 60).is_none());
+    }
+}
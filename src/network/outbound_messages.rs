@@ -10,6 +10,7 @@ use bitcoin::{
     p2p::{
         message::{NetworkMessage, RawNetworkMessage},
         message_blockdata::{GetHeadersMessage, Inventory},
+        message_compact_blocks::SendCmpct,
         message_filter::{GetCFHeaders, GetCFilters},
         message_network::VersionMessage,
         Address, ServiceFlags,
@@ -21,6 +22,9 @@ use crate::{channel_messages::GetBlockConfig, prelude::default_port_from_network
 
 use super::{error::PeerError, KYOTO_VERSION, PROTOCOL_VERSION, RUST_BITCOIN_VERSION};
 
+// Compact blocks committing to witness data (BIP 152)
+const COMPACT_BLOCKS_VERSION: u64 = 2;
+
 // Responsible for serializing messages to write over the wire, either encrypted or plaintext.
 pub(crate) struct MessageGenerator {
     pub network: Network,
@@ -46,8 +50,12 @@ impl MessageGenerator {
         }
     }
 
-    pub(crate) fn version_message(&mut self, port: Option<u16>) -> Result<Vec<u8>, PeerError> {
-        let msg = NetworkMessage::Version(make_version(port, &self.network));
+    pub(crate) fn version_message(
+        &mut self,
+        port: Option<u16>,
+        relay: bool,
+    ) -> Result<Vec<u8>, PeerError> {
+        let msg = NetworkMessage::Version(make_version(port, &self.network, relay));
         self.serialize(msg)
     }
 
@@ -71,6 +79,19 @@ impl MessageGenerator {
         self.serialize(msg)
     }
 
+    pub(crate) fn send_headers(&mut self) -> Result<Vec<u8>, PeerError> {
+        let msg = NetworkMessage::SendHeaders;
+        self.serialize(msg)
+    }
+
+    pub(crate) fn send_compact_blocks(&mut self) -> Result<Vec<u8>, PeerError> {
+        let msg = NetworkMessage::SendCmpct(SendCmpct {
+            send_compact: true,
+            version: COMPACT_BLOCKS_VERSION,
+        });
+        self.serialize(msg)
+    }
+
     pub(crate) fn headers(
         &mut self,
         locator_hashes: Vec<BlockHash>,
@@ -98,6 +119,16 @@ impl MessageGenerator {
         self.serialize(msg)
     }
 
+    pub(crate) fn transactions(&mut self, inventory: Vec<Inventory>) -> Result<Vec<u8>, PeerError> {
+        let msg = NetworkMessage::GetData(inventory);
+        self.serialize(msg)
+    }
+
+    pub(crate) fn ping(&mut self, nonce: u64) -> Result<Vec<u8>, PeerError> {
+        let msg = NetworkMessage::Ping(nonce);
+        self.serialize(msg)
+    }
+
     pub(crate) fn pong(&mut self, nonce: u64) -> Result<Vec<u8>, PeerError> {
         let msg = NetworkMessage::Pong(nonce);
         self.serialize(msg)
@@ -130,7 +161,7 @@ fn encrypt_plaintext(
         .map_err(|_| PeerError::MessageEncryption)
 }
 
-fn make_version(port: Option<u16>, network: &Network) -> VersionMessage {
+fn make_version(port: Option<u16>, network: &Network, relay: bool) -> VersionMessage {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("time went backwards")
@@ -152,7 +183,7 @@ fn make_version(port: Option<u16>, network: &Network) -> VersionMessage {
             "Kyoto Light Client / {KYOTO_VERSION} / rust-bitcoin {RUST_BITCOIN_VERSION}"
         ),
         start_height: 0,
-        relay: false,
+        relay,
     }
 }
 
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use bip324::serde::NetworkMessage;
 use bip324::{PacketReader, PacketType};
 use bitcoin::consensus::{deserialize, deserialize_partial};
@@ -5,20 +7,44 @@ use bitcoin::p2p::message::RawNetworkMessage;
 use bitcoin::Network;
 use tokio::io::AsyncReadExt;
 
+use super::buffer_pool::BufferPool;
 use super::error::PeerReadError;
 use super::V1Header;
 
 const MAX_MESSAGE_BYTES: u32 = 1024 * 1024 * 32;
+const V1_HEADER_BYTES: usize = 24;
+// Headers and filters fit well within this, so larger messages are read into a pooled buffer
+const MAX_RETAINED_BUFFER_BYTES: usize = 1024 * 256;
+
+pub(crate) enum ReadTransport {
+    V1 { network: Network },
+    V2 { decryptor: PacketReader },
+}
 
-pub(crate) enum MessageParser<R: AsyncReadExt + Send + Sync + Unpin> {
-    V2(R, PacketReader),
-    V1(R, Network),
+pub(crate) struct MessageParser<R: AsyncReadExt + Send + Sync + Unpin> {
+    stream: R,
+    transport: ReadTransport,
+    buffer: ReadBuffer,
 }
 
 impl<R: AsyncReadExt + Send + Sync + Unpin> MessageParser<R> {
+    pub fn new(stream: R, transport: ReadTransport, pool: Arc<BufferPool>) -> Self {
+        Self {
+            stream,
+            transport,
+            buffer: ReadBuffer::new(pool),
+        }
+    }
+
     pub async fn read_message(&mut self) -> Result<Option<NetworkMessage>, PeerReadError> {
-        match self {
-            MessageParser::V2(stream, decryptor) => {
+        let MessageParser {
+            stream,
+            transport,
+            buffer,
+        } = self;
+        buffer.clear();
+        match transport {
+            ReadTransport::V2 { decryptor } => {
                 let mut len_buf = [0; 3];
                 let _ = stream
                     .read_exact(&mut len_buf)
@@ -28,13 +54,9 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> MessageParser<R> {
                 if message_len > MAX_MESSAGE_BYTES as usize {
                     return Err(PeerReadError::TooManyMessages);
                 }
-                let mut response_message = vec![0; message_len];
-                let _ = stream
-                    .read_exact(&mut response_message)
-                    .await
-                    .map_err(|_| PeerReadError::ReadBuffer)?;
+                buffer.read_exact(stream, message_len).await?;
                 let msg = decryptor
-                    .decrypt_payload(&response_message, None)
+                    .decrypt_payload(buffer.as_slice(), None)
                     .map_err(|_| PeerReadError::DecryptionFailed)?;
                 match msg.packet_type() {
                     PacketType::Genuine => {
@@ -45,13 +67,9 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> MessageParser<R> {
                     PacketType::Decoy => Ok(None),
                 }
             }
-            MessageParser::V1(stream, network) => {
-                let mut message_buf = vec![0_u8; 24];
-                let _ = stream
-                    .read_exact(&mut message_buf)
-                    .await
-                    .map_err(|_| PeerReadError::ReadBuffer)?;
-                let header: V1Header = deserialize_partial(&message_buf)
+            ReadTransport::V1 { network } => {
+                buffer.read_exact(stream, V1_HEADER_BYTES).await?;
+                let header: V1Header = deserialize_partial(buffer.as_slice())
                     .map_err(|_| PeerReadError::Deserialization)?
                     .0;
                 // Nonsense for our network
@@ -62,16 +80,148 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> MessageParser<R> {
                 if header.length > MAX_MESSAGE_BYTES {
                     return Err(PeerReadError::Deserialization);
                 }
-                let mut contents_buf = vec![0_u8; header.length as usize];
-                let _ = stream
-                    .read_exact(&mut contents_buf)
-                    .await
-                    .map_err(|_| PeerReadError::ReadBuffer)?;
-                message_buf.extend_from_slice(&contents_buf);
+                // The payload is read directly after the header, so the message is decoded in place
+                buffer.read_exact(stream, header.length as usize).await?;
                 let message: RawNetworkMessage =
-                    deserialize(&message_buf).map_err(|_| PeerReadError::Deserialization)?;
+                    deserialize(buffer.as_slice()).map_err(|_| PeerReadError::Deserialization)?;
                 Ok(Some(message.into_payload()))
             }
         }
     }
 }
+
+// A buffer that is reused for every message read from a peer. During a filter sync, peers send
+// thousands of small messages in a row, which would otherwise each allocate. Large messages are
+// read into a buffer borrowed from a pool shared by all peers, and the small buffer is set aside
+// until the message is handled.
+#[derive(Debug)]
+struct ReadBuffer {
+    bytes: Vec<u8>,
+    spare: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl ReadBuffer {
+    fn new(pool: Arc<BufferPool>) -> Self {
+        Self {
+            bytes: Vec::new(),
+            spare: Vec::new(),
+            pool,
+        }
+    }
+
+    // Read exactly `len` bytes from the stream onto the end of the buffer
+    async fn read_exact<R: AsyncReadExt + Unpin>(
+        &mut self,
+        stream: &mut R,
+        len: usize,
+    ) -> Result<(), PeerReadError> {
+        let start = self.bytes.len();
+        let end = start + len;
+        if end > MAX_RETAINED_BUFFER_BYTES && end > self.bytes.capacity() {
+            let mut pooled = self.pool.take(end);
+            pooled.extend_from_slice(&self.bytes);
+            self.spare = std::mem::replace(&mut self.bytes, pooled);
+        }
+        self.bytes.resize(end, 0);
+        let _ = stream
+            .read_exact(&mut self.bytes[start..])
+            .await
+            .map_err(|_| PeerReadError::ReadBuffer)?;
+        Ok(())
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // Prepare for the next message, returning the buffer of a large message such as a block
+    fn clear(&mut self) {
+        if self.bytes.capacity() > MAX_RETAINED_BUFFER_BYTES {
+            let pooled = std::mem::replace(&mut self.bytes, std::mem::take(&mut self.spare));
+            self.pool.give(pooled);
+        }
+        self.bytes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{consensus::serialize, constants::genesis_block, p2p::Magic, ScriptBuf};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_buffer_is_reused_across_messages() {
+        let network = Network::Regtest;
+        let mut bytes = Vec::new();
+        for message in [
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(42),
+            NetworkMessage::Pong(42),
+        ] {
+            bytes.extend(serialize(&RawNetworkMessage::new(
+                Magic::from(network),
+                message,
+            )));
+        }
+        let mut parser = MessageParser::new(
+            bytes.as_slice(),
+            ReadTransport::V1 { network },
+            Arc::new(BufferPool::new()),
+        );
+        assert!(matches!(
+            parser.read_message().await,
+            Ok(Some(NetworkMessage::Verack))
+        ));
+        assert!(matches!(
+            parser.read_message().await,
+            Ok(Some(NetworkMessage::Ping(42)))
+        ));
+        assert!(matches!(
+            parser.read_message().await,
+            Ok(Some(NetworkMessage::Pong(42)))
+        ));
+        assert_eq!(parser.buffer.as_slice().len(), V1_HEADER_BYTES + 8);
+        assert!(parser.read_message().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_large_messages_use_pooled_buffers() {
+        let network = Network::Regtest;
+        let mut block = genesis_block(network);
+        let mut output = block.txdata[0].output[0].clone();
+        output.script_pubkey = ScriptBuf::from_bytes(vec![0x51; MAX_RETAINED_BUFFER_BYTES]);
+        block.txdata[0].output.push(output);
+        let mut bytes = Vec::new();
+        for message in [
+            NetworkMessage::Block(block.clone()),
+            NetworkMessage::Ping(42),
+            NetworkMessage::Block(block.clone()),
+        ] {
+            bytes.extend(serialize(&RawNetworkMessage::new(
+                Magic::from(network),
+                message,
+            )));
+        }
+        let pool = Arc::new(BufferPool::new());
+        let mut parser = MessageParser::new(
+            bytes.as_slice(),
+            ReadTransport::V1 { network },
+            pool.clone(),
+        );
+        assert!(
+            matches!(parser.read_message().await, Ok(Some(NetworkMessage::Block(b))) if b == block)
+        );
+        assert!(matches!(
+            parser.read_message().await,
+            Ok(Some(NetworkMessage::Ping(42)))
+        ));
+        assert_eq!(pool.idle(), 1);
+        assert!(parser.buffer.as_slice().len() < MAX_RETAINED_BUFFER_BYTES);
+        assert!(
+            matches!(parser.read_message().await, Ok(Some(NetworkMessage::Block(b))) if b == block)
+        );
+        assert_eq!(pool.idle(), 0);
+    }
+}
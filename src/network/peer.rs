@@ -2,7 +2,11 @@ extern crate tokio;
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use bip324::{AsyncProtocol, PacketReader, PacketWriter, Role};
-use bitcoin::{p2p::ServiceFlags, Network, Transaction, Wtxid};
+use bitcoin::{
+    key::rand::{thread_rng, RngCore},
+    p2p::ServiceFlags,
+    Network, Transaction, Wtxid,
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
@@ -14,15 +18,16 @@ use tokio::{
 use crate::{
     channel_messages::{MainThreadMessage, PeerMessage, PeerThreadMessage, ReaderMessage},
     dialog::Dialog,
-    messages::Warning,
-    Info,
+    messages::{Misbehavior, Warning},
+    Info, Subsystem,
 };
 
 use super::{
+    buffer_pool::BufferPool,
     counter::MessageCounter,
     error::PeerError,
     outbound_messages::{MessageGenerator, Transport},
-    parsers::MessageParser,
+    parsers::{MessageParser, ReadTransport},
     reader::Reader,
     PeerId, PeerTimeoutConfig,
 };
@@ -39,10 +44,16 @@ pub(crate) struct Peer {
     services: ServiceFlags,
     dialog: Arc<Dialog>,
     timeout_config: PeerTimeoutConfig,
+    buffer_pool: Arc<BufferPool>,
     tx_queue: HashMap<Wtxid, Transaction>,
+    // Ask the peer to announce the transactions it relays
+    relay: bool,
+    last_message: Instant,
+    ping_nonce: Option<u64>,
 }
 
 impl Peer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         nonce: PeerId,
         network: Network,
@@ -51,6 +62,8 @@ impl Peer {
         services: ServiceFlags,
         dialog: Arc<Dialog>,
         timeout_config: PeerTimeoutConfig,
+        buffer_pool: Arc<BufferPool>,
+        relay: bool,
     ) -> Self {
         let message_counter = MessageCounter::new(timeout_config.response_timeout);
         Self {
@@ -62,7 +75,11 @@ impl Peer {
             services,
             dialog,
             timeout_config,
+            buffer_pool,
             tx_queue: HashMap::new(),
+            relay,
+            last_message: Instant::now(),
+            ping_nonce: None,
         }
     }
 
@@ -81,6 +98,7 @@ impl Peer {
             if let Err(ref e) = handshake_result {
                 crate::log!(
                     self.dialog,
+                    Subsystem::Peers,
                     format!("Failed to establish an encrypted connection: {e}")
                 );
                 self.dialog.send_warning(Warning::CouldNotConnect);
@@ -90,18 +108,34 @@ impl Peer {
                 network: self.network,
                 transport: Transport::V2 { encryptor },
             };
-            let reader = Reader::new(MessageParser::V2(reader, decryptor), tx);
+            let reader = Reader::new(
+                MessageParser::new(
+                    reader,
+                    ReadTransport::V2 { decryptor },
+                    Arc::clone(&self.buffer_pool),
+                ),
+                tx,
+            );
             (outbound_messages, reader)
         } else {
             let outbound_messages = MessageGenerator {
                 network: self.network,
                 transport: Transport::V1,
             };
-            let reader = Reader::new(MessageParser::V1(reader, self.network), tx);
+            let reader = Reader::new(
+                MessageParser::new(
+                    reader,
+                    ReadTransport::V1 {
+                        network: self.network,
+                    },
+                    Arc::clone(&self.buffer_pool),
+                ),
+                tx,
+            );
             (outbound_messages, reader)
         };
 
-        let message = outbound_messages.version_message(None)?;
+        let message = outbound_messages.version_message(None, self.relay)?;
         self.write_bytes(&mut writer, message).await?;
         self.message_counter.sent_version();
         let read_handle = tokio::spawn(async move {
@@ -110,25 +144,45 @@ impl Peer {
                 .await
                 .map_err(|_| PeerError::Reader)
         });
+        let mut reported_unsolicited = false;
         loop {
             if read_handle.is_finished() {
                 return Ok(());
             }
-            if self.message_counter.unsolicited() {
+            // The node scores the peer while it is still connected, then tells it to disconnect
+            if self.message_counter.unsolicited() && !reported_unsolicited {
                 self.dialog.send_warning(Warning::UnsolicitedMessage);
-                return Ok(());
+                self.main_thread_sender
+                    .send(PeerThreadMessage {
+                        nonce: self.nonce,
+                        message: PeerMessage::Misbehaved(Misbehavior::UnsolicitedMessages),
+                    })
+                    .await
+                    .map_err(|_| PeerError::ThreadChannel)?;
+                reported_unsolicited = true;
             }
             if self.message_counter.unresponsive() {
                 self.dialog.send_warning(Warning::PeerTimedOut);
                 return Ok(());
             }
             if Instant::now().duration_since(start_time) > self.timeout_config.max_connection_time {
-                crate::log!(self.dialog, format!(
+                crate::log!(self.dialog, Subsystem::Peers, format!(
                     "The connection to peer {} has been maintained for over {} seconds, finding a new peer",
                     self.nonce, self.timeout_config.max_connection_time.as_secs(),
                 ));
                 return Ok(());
             }
+            // Check the connection is still alive if the peer has gone quiet
+            if self.ping_nonce.is_none()
+                && Instant::now().duration_since(self.last_message)
+                    > self.timeout_config.ping_interval
+            {
+                let nonce = thread_rng().next_u64();
+                let message = outbound_messages.ping(nonce)?;
+                self.write_bytes(&mut writer, message).await?;
+                self.message_counter.sent_ping();
+                self.ping_nonce = Some(nonce);
+            }
             select! {
                 // The peer sent us a message
                 peer_message = tokio::time::timeout(Duration::from_secs(MESSAGE_TIMEOUT), rx.recv()) => {
@@ -181,6 +235,7 @@ impl Peer {
     where
         W: AsyncWrite + Send + Unpin,
     {
+        self.last_message = Instant::now();
         match message {
             ReaderMessage::Version(version) => {
                 self.message_counter.got_version();
@@ -258,12 +313,32 @@ impl Peer {
                     .map_err(|_| PeerError::ThreadChannel)?;
                 Ok(())
             }
+            ReaderMessage::TxAnnounced(inventory) => {
+                self.main_thread_sender
+                    .send(PeerThreadMessage {
+                        nonce: self.nonce,
+                        message: PeerMessage::TxAnnounced(inventory),
+                    })
+                    .await
+                    .map_err(|_| PeerError::ThreadChannel)?;
+                Ok(())
+            }
+            ReaderMessage::Transaction(transaction) => {
+                self.main_thread_sender
+                    .send(PeerThreadMessage {
+                        nonce: self.nonce,
+                        message: PeerMessage::Transaction(transaction),
+                    })
+                    .await
+                    .map_err(|_| PeerError::ThreadChannel)?;
+                Ok(())
+            }
             ReaderMessage::TxRequests(requests) => {
                 for wtxid in requests {
                     if let Some(transaction) = self.tx_queue.remove(&wtxid) {
                         let msg = message_generator.broadcast_transaction(transaction)?;
                         self.write_bytes(writer, msg).await?;
-                        crate::info!(self.dialog, Info::TxGossiped(wtxid))
+                        crate::info!(self.dialog, Subsystem::Peers, Info::TxGossiped(wtxid))
                     }
                 }
                 Ok(())
@@ -277,7 +352,13 @@ impl Peer {
                 self.write_bytes(writer, message).await?;
                 Ok(())
             }
-            ReaderMessage::Pong(_) => Ok(()),
+            ReaderMessage::Pong(nonce) => {
+                if self.ping_nonce.eq(&Some(nonce)) {
+                    self.message_counter.got_pong();
+                    self.ping_nonce = None;
+                }
+                Ok(())
+            }
             ReaderMessage::FeeFilter(fee) => {
                 self.main_thread_sender
                     .send(PeerThreadMessage {
@@ -321,6 +402,14 @@ impl Peer {
                 let message = message_generator.wtxid_relay()?;
                 self.write_bytes(writer, message).await?;
             }
+            MainThreadMessage::SendHeaders => {
+                let message = message_generator.send_headers()?;
+                self.write_bytes(writer, message).await?;
+            }
+            MainThreadMessage::SendCompactBlocks => {
+                let message = message_generator.send_compact_blocks()?;
+                self.write_bytes(writer, message).await?;
+            }
             MainThreadMessage::GetHeaders(config) => {
                 self.message_counter.sent_header();
                 let message = message_generator.headers(config.locators, config.stop_hash)?;
@@ -348,6 +437,10 @@ impl Peer {
                 self.tx_queue.insert(wtxid, transaction);
                 self.write_bytes(writer, message).await?;
             }
+            MainThreadMessage::GetTransactions(inventory) => {
+                let message = message_generator.transactions(inventory)?;
+                self.write_bytes(writer, message).await?;
+            }
             MainThreadMessage::Verack => {
                 let message = message_generator.verack()?;
                 self.write_bytes(writer, message).await?;
@@ -380,19 +473,25 @@ impl Peer {
     {
         crate::log!(
             self.dialog,
+            Subsystem::Peers,
             "Initiating a handshake for encrypted messaging"
         );
         let handshake =
             AsyncProtocol::new(self.network, Role::Initiator, None, None, reader, writer).await;
         match handshake {
             Ok(proto) => {
-                crate::log!(self.dialog, "Established an encrypted connection");
+                crate::log!(
+                    self.dialog,
+                    Subsystem::Peers,
+                    "Established an encrypted connection"
+                );
                 let (reader, writer) = proto.into_split();
                 Ok((reader.decoder(), writer.encoder()))
             }
             Err(e) => {
                 crate::log!(
                     self.dialog,
+                    Subsystem::Peers,
                     format!("V2 handshake failed with description {e}")
                 );
                 Err(PeerError::HandshakeFailed)
@@ -2,15 +2,13 @@ use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use bitcoin::{
-    key::rand,
-    p2p::{address::AddrV2, ServiceFlags},
+    p2p::{address::AddrV2, message_network::VersionMessage, ServiceFlags},
     FeeRate, Network,
 };
-use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
 use tokio::{
     sync::{
         mpsc::{self, Sender},
@@ -21,18 +19,34 @@ use tokio::{
 
 use crate::{
     chain::HeightMonitor,
-    channel_messages::{CombinedAddr, MainThreadMessage, PeerThreadMessage},
+    channel_messages::{CombinedAddr, MainThreadMessage, PeerMessage, PeerThreadMessage},
+    config::ConfigDelta,
     db::{traits::PeerStore, PeerStatus, PersistedPeer},
     dialog::Dialog,
     error::PeerManagerError,
     network::{dns::DnsResolver, error::PeerError, peer::Peer, PeerId, PeerTimeoutConfig},
-    prelude::{default_port_from_network, Median, Netgroup},
-    PeerStoreSizeConfig, TrustedPeer, Warning,
+    prelude::{default_port_from_network, user_agent_family, Netgroup},
+    ConnectivityCheck, Info, Misbehavior, PeerActivity, PeerDiversity, PeerInfo, PeerNetwork,
+    PeerSelector, PeerState, PeerStoreSizeConfig, PendingRequest, RequestKind, Subsystem,
+    TrustedPeer, Warning,
 };
 
-use super::ConnectionType;
+#[cfg(feature = "i2p")]
+use super::i2p::SamBridge;
+use super::{
+    buffer_pool::BufferPool, misbehavior::MisbehaviorScores, network_time::NetworkTime,
+    pending::PendingRequests, ConnectionType,
+};
+
+// The most peers to open test connections to
+const MAX_CONNECTIVITY_CHECKS: usize = 3;
+// Peers may go this many response timeouts without sending data before they are replaced, as
+// blocks may take a while to arrive over slow connections
+const UNRESPONSIVE_TIMEOUTS: u32 = 6;
 
 const MAX_TRIES: usize = 50;
+// Time allowed for a peer task to finish after it is told to disconnect
+const DISCONNECT_TIMEOUT_SECS: u64 = 2;
 
 // Preferred peers to connect to based on the user configuration
 type Whitelist = Vec<TrustedPeer>;
@@ -40,15 +54,47 @@ type Whitelist = Vec<TrustedPeer>;
 // A peer that is or was connected to the node
 #[derive(Debug)]
 pub(crate) struct ManagedPeer {
-    net_time: i64,
     address: AddrV2,
     port: u16,
     service_flags: ServiceFlags,
+    // The encrypted transport is used when the peer is known to offer it before connecting
+    encrypted: bool,
     broadcast_min: FeeRate,
+    negotiated: Option<PeerInfo>,
+    // The activity last reported to the client
+    reported: Option<PeerActivity>,
     ptx: Sender<MainThreadMessage>,
     handle: JoinHandle<Result<(), PeerError>>,
 }
 
+// Report a request sent to a peer, when requests are reported for the peers subsystem
+async fn report_request(
+    dialog: &Dialog,
+    kind: Option<RequestKind>,
+    message: &MainThreadMessage,
+    peer: &ManagedPeer,
+) {
+    let kind = match kind {
+        Some(kind) => kind,
+        None => return,
+    };
+    if !dialog.reports_requests(Subsystem::Peers) {
+        return;
+    }
+    let start_height = match message {
+        MainThreadMessage::GetFilterHeaders(get_cf_headers) => Some(get_cf_headers.start_height),
+        MainThreadMessage::GetFilters(get_filters) => Some(get_filters.start_height),
+        _ => None,
+    };
+    let request = Info::RequestSent {
+        kind,
+        start_height,
+        addr: peer.address.clone(),
+        port: peer.port,
+    };
+    dialog.send_info(request).await;
+}
+
 // The `PeerMap` manages connections with peers, adds and bans peers, and manages the peer database
 #[derive(Debug)]
 pub(crate) struct PeerMap<P: PeerStore> {
@@ -65,6 +111,22 @@ pub(crate) struct PeerMap<P: PeerStore> {
     net_groups: HashSet<String>,
     timeout_config: PeerTimeoutConfig,
     dns_resolver: DnsResolver,
+    // Seeds to query in place of the defaults of the network
+    dns_seeds: Option<Vec<String>>,
+    selector: Box<dyn PeerSelector>,
+    pending: PendingRequests,
+    buffer_pool: Arc<BufferPool>,
+    // Ask peers to announce the transactions they relay
+    transaction_relay: bool,
+    // Peers only have to serve block headers, not compact block filters
+    headers_only: bool,
+    // Peers are banned once they misbehave often enough
+    scores: MisbehaviorScores,
+    // The clock offsets reported by peers
+    network_time: NetworkTime,
+    // Dial I2P peers through the SAM bridge of a router
+    #[cfg(feature = "i2p")]
+    i2p: Option<SamBridge>,
 }
 
 #[allow(dead_code)]
@@ -81,6 +143,7 @@ impl<P: PeerStore> PeerMap<P> {
         timeout_config: PeerTimeoutConfig,
         height_monitor: Arc<Mutex<HeightMonitor>>,
         dns_resolver: DnsResolver,
+        selector: Box<dyn PeerSelector>,
     ) -> Self {
         Self {
             current_id: PeerId(0),
@@ -96,13 +159,69 @@ impl<P: PeerStore> PeerMap<P> {
             net_groups: HashSet::new(),
             timeout_config,
             dns_resolver,
+            dns_seeds: None,
+            selector,
+            pending: PendingRequests::new(),
+            buffer_pool: Arc::new(BufferPool::new()),
+            transaction_relay: false,
+            headers_only: false,
+            scores: MisbehaviorScores::new(),
+            network_time: NetworkTime::new(),
+            #[cfg(feature = "i2p")]
+            i2p: None,
+        }
+    }
+
+    pub fn with_transaction_relay(mut self, relay: bool) -> Self {
+        self.transaction_relay = relay;
+        self
+    }
+
+    pub fn with_headers_only(mut self, headers_only: bool) -> Self {
+        self.headers_only = headers_only;
+        self
+    }
+
+    pub fn with_dns_seeds(mut self, seeds: Option<Vec<String>>) -> Self {
+        self.dns_seeds = seeds;
+        self
+    }
+
+    #[cfg(feature = "i2p")]
+    pub fn with_i2p(mut self, sam: Option<std::net::SocketAddr>) -> Self {
+        self.i2p = sam.map(SamBridge::new);
+        self
+    }
+
+    fn can_connect(&self, addr: &AddrV2) -> bool {
+        #[cfg(feature = "i2p")]
+        if matches!(addr, AddrV2::I2p(_)) {
+            return self.i2p.is_some();
         }
+        self.connector.can_connect(addr)
+    }
+
+    async fn connect(
+        &mut self,
+        addr: AddrV2,
+        port: u16,
+    ) -> Result<tokio::net::TcpStream, PeerError> {
+        let handshake_timeout = self.timeout_config.handshake_timeout;
+        #[cfg(feature = "i2p")]
+        if let (AddrV2::I2p(hash), Some(i2p)) = (&addr, self.i2p.as_mut()) {
+            return i2p
+                .connect(hash, handshake_timeout)
+                .await
+                .map_err(PeerError::I2p);
+        }
+        self.connector.connect(addr, port, handshake_timeout).await
     }
 
     // Remove any finished connections
     pub async fn clean(&mut self) {
         self.map.retain(|_, peer| !peer.handle.is_finished());
         let active = self.map.keys().copied().collect::<Vec<PeerId>>();
+        self.pending.retain(&active);
         let mut height_lock = self.heights.lock().await;
         height_lock.retain(&active);
     }
@@ -124,20 +243,34 @@ impl<P: PeerStore> PeerMap<P> {
             .count()
     }
 
-    // Get the median time adjustment for the currently connected peers
+    // The peers that serve compact block filters
+    pub fn filter_peers(&self) -> Vec<PeerId> {
+        self.map
+            .iter()
+            .filter(|(_, peer)| !peer.handle.is_finished())
+            .filter(|(_, peer)| peer.service_flags.has(ServiceFlags::COMPACT_FILTERS))
+            .map(|(nonce, _)| *nonce)
+            .collect()
+    }
+
+    // The offset of the network-adjusted time from the local clock, in seconds
     pub fn median_time_adjustment(&self) -> i64 {
-        let mut time_offsets: Vec<i64> = self.map.values().map(|peer| peer.net_time).collect();
-        time_offsets.median()
+        self.network_time.offset()
     }
 
-    // Set the time offset of a connected peer
+    // Sample the clock of a peer from the time in its version message
     pub fn set_offset(&mut self, peer: PeerId, time: i64) {
-        if let Some(peer) = self.map.get_mut(&peer) {
+        if let Some(peer) = self.map.get(&peer) {
             let now = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("time went backwards")
                 .as_secs();
-            peer.net_time = time - now as i64;
+            let offset = time.saturating_sub(now as i64);
+            if let Some(offset) = self.network_time.add(&peer.address, offset) {
+                self.dialog.send_warning(Warning::ClockSkew {
+                    offset_secs: offset,
+                });
+            }
         }
     }
 
@@ -146,6 +279,23 @@ impl<P: PeerStore> PeerMap<P> {
         self.timeout_config.response_timeout = duration;
     }
 
+    // Apply the timeouts changed by the client, which are given to new connections
+    pub fn update_timeouts(&mut self, delta: &ConfigDelta) {
+        let config = &mut self.timeout_config;
+        if let Some(response_timeout) = delta.response_timeout {
+            config.response_timeout = response_timeout;
+        }
+        if let Some(handshake_timeout) = delta.handshake_timeout {
+            config.handshake_timeout = handshake_timeout;
+        }
+        if let Some(max_connection_time) = delta.max_connection_time {
+            config.max_connection_time = max_connection_time;
+        }
+        if let Some(ping_interval) = delta.ping_interval {
+            config.ping_interval = ping_interval;
+        }
+    }
+
     // Add a new trusted peer to the whitelist
     pub fn add_trusted_peer(&mut self, peer: TrustedPeer) {
         self.whitelist.push(peer);
@@ -163,31 +313,31 @@ impl<P: PeerStore> PeerMap<P> {
             loaded_peer.services,
             Arc::clone(&self.dialog),
             self.timeout_config,
+            Arc::clone(&self.buffer_pool),
+            self.transaction_relay,
         );
-        if !self.connector.can_connect(&loaded_peer.addr) {
+        if !self.can_connect(&loaded_peer.addr) {
             return Err(PeerError::UnreachableSocketAddr);
         }
         crate::log!(
             self.dialog,
+            Subsystem::Peers,
             format!("Connecting to {:?}:{}", loaded_peer.addr, loaded_peer.port)
         );
         let connection = self
-            .connector
-            .connect(
-                loaded_peer.addr.clone(),
-                loaded_peer.port,
-                self.timeout_config.handshake_timeout,
-            )
+            .connect(loaded_peer.addr.clone(), loaded_peer.port)
             .await?;
         let handle = tokio::spawn(async move { peer.run(connection).await });
         self.map.insert(
             self.current_id,
             ManagedPeer {
                 service_flags: loaded_peer.services,
+                encrypted: loaded_peer.services.has(ServiceFlags::P2P_V2),
                 address: loaded_peer.addr,
                 port: loaded_peer.port,
                 broadcast_min: FeeRate::BROADCAST_MIN,
-                net_time: 0,
+                negotiated: None,
+                reported: None,
                 ptx,
                 handle,
             },
@@ -195,6 +345,41 @@ impl<P: PeerStore> PeerMap<P> {
         Ok(())
     }
 
+    // Open test connections to a few peers with the configured transport. Connected and configured
+    // peers are tried first, then a peer from the database. The connections are opened by the
+    // returned future, so they may be awaited without holding the peer map.
+    pub async fn connectivity_checks(
+        &mut self,
+    ) -> impl std::future::Future<Output = Vec<ConnectivityCheck>> + Send + 'static {
+        let mut targets = self
+            .map
+            .values()
+            .map(|peer| (peer.address.clone(), peer.port))
+            .collect::<Vec<(AddrV2, u16)>>();
+        let default_port = default_port_from_network(&self.network);
+        targets.extend(
+            self.whitelist
+                .iter()
+                .map(|peer| (peer.address.clone(), peer.port.unwrap_or(default_port))),
+        );
+        if targets.is_empty() {
+            if let Ok(peer) = self.db.lock().await.random().await {
+                targets.push((peer.addr, peer.port));
+            }
+        }
+        let connector = self.connector;
+        targets.retain(|(addr, _)| connector.can_connect(addr));
+        targets.truncate(MAX_CONNECTIVITY_CHECKS);
+        let handshake_timeout = self.timeout_config.handshake_timeout;
+        async move {
+            let mut checks = Vec::with_capacity(targets.len());
+            for (addr, port) in targets {
+                checks.push(connector.check(addr, port, handshake_timeout).await);
+            }
+            checks
+        }
+    }
+
     // Set the minimum fee rate this peer will accept
     pub fn set_broadcast_min(&mut self, nonce: PeerId, fee_rate: FeeRate) {
         if let Some(peer) = self.map.get_mut(&nonce) {
@@ -209,6 +394,142 @@ impl<P: PeerStore> PeerMap<P> {
         }
     }
 
+    // Record what a peer advertised in its version message
+    pub fn set_version(&mut self, nonce: PeerId, version: &VersionMessage) -> Option<PeerInfo> {
+        let peer = self.map.get_mut(&nonce)?;
+        let info = PeerInfo {
+            addr: peer.address.clone(),
+            port: peer.port,
+            version: version.version,
+            services: version.services,
+            user_agent: version.user_agent.clone(),
+            start_height: u32::try_from(version.start_height).unwrap_or(0),
+        };
+        peer.negotiated = Some(info.clone());
+        Some(info)
+    }
+
+    // Record a message from a peer, returning true if it answered one of our requests
+    pub async fn received(&mut self, nonce: PeerId, message: &PeerMessage) -> bool {
+        let answered = self.pending.received(nonce, message);
+        if let Some(peer) = self.map.get(&nonce) {
+            if self.dialog.reports_requests(Subsystem::Peers) {
+                for (kind, latency) in &answered {
+                    let response = Info::ResponseReceived {
+                        kind: *kind,
+                        addr: peer.address.clone(),
+                        port: peer.port,
+                        latency: *latency,
+                    };
+                    self.dialog.send_info(response).await;
+                }
+            }
+        }
+        !answered.is_empty()
+    }
+
+    // The peers that have not answered one of our requests
+    pub fn waiting_on(&self) -> Vec<PeerId> {
+        self.pending.peers()
+    }
+
+    // The requests that peers have not answered yet
+    pub fn pending_requests(&self) -> Vec<PendingRequest> {
+        self.pending
+            .iter()
+            .filter_map(|(nonce, kind, sent)| {
+                self.map.get(nonce).map(|peer| PendingRequest {
+                    kind: *kind,
+                    addr: peer.address.clone(),
+                    port: peer.port,
+                    age: sent.elapsed(),
+                })
+            })
+            .collect()
+    }
+
+    // What each connected peer is doing
+    pub fn peer_states(&self) -> Vec<PeerState> {
+        self.map
+            .iter()
+            .filter(|(_, peer)| !peer.handle.is_finished())
+            .map(|(nonce, peer)| PeerState {
+                addr: peer.address.clone(),
+                port: peer.port,
+                activity: Self::activity(&self.pending, *nonce, peer),
+            })
+            .collect()
+    }
+
+    // The peers that are doing something else since they were last reported
+    pub fn changed_peer_states(&mut self) -> Vec<PeerState> {
+        let mut changed = Vec::new();
+        for (nonce, peer) in self.map.iter_mut() {
+            if peer.handle.is_finished() {
+                continue;
+            }
+            let activity = Self::activity(&self.pending, *nonce, peer);
+            if peer.reported.ne(&Some(activity)) {
+                peer.reported = Some(activity);
+                changed.push(PeerState {
+                    addr: peer.address.clone(),
+                    port: peer.port,
+                    activity,
+                });
+            }
+        }
+        changed
+    }
+
+    fn activity(pending: &PendingRequests, nonce: PeerId, peer: &ManagedPeer) -> PeerActivity {
+        match peer.negotiated {
+            Some(_) => pending.activity(nonce),
+            None => PeerActivity::Handshaking,
+        }
+    }
+
+    // The protocol details of the peers we are connected to
+    pub fn connected_peers(&self) -> Vec<PeerInfo> {
+        self.map
+            .values()
+            .filter(|peer| !peer.handle.is_finished())
+            .filter_map(|peer| peer.negotiated.clone())
+            .collect()
+    }
+
+    // How the peers we are connected to are spread over transports, networks and software
+    pub fn peer_diversity(&self) -> PeerDiversity {
+        let mut diversity = PeerDiversity::default();
+        for peer in self.map.values() {
+            if peer.handle.is_finished() {
+                continue;
+            }
+            let info = match &peer.negotiated {
+                Some(info) => info,
+                None => continue,
+            };
+            diversity.peers += 1;
+            if peer.encrypted {
+                diversity.v2 += 1;
+            } else {
+                diversity.v1 += 1;
+            }
+            *diversity
+                .networks
+                .entry(PeerNetwork::from(&peer.address))
+                .or_default() += 1;
+            *diversity
+                .netgroups
+                .entry(peer.address.netgroup())
+                .or_default() += 1;
+            *diversity
+                .user_agents
+                .entry(user_agent_family(&info.user_agent))
+                .or_default() += 1;
+        }
+        diversity
+    }
+
     // Set the height of a peer upon receiving the version message
     pub async fn set_height(&mut self, nonce: PeerId, height: u32) {
         let mut height_lock = self.heights.lock().await;
@@ -233,36 +554,117 @@ impl<P: PeerStore> PeerMap<P> {
     // Send a message to the specified peer
     pub async fn send_message(&mut self, nonce: PeerId, message: MainThreadMessage) {
         if let Some(peer) = self.map.get(&nonce) {
+            let kind = self.pending.sent(nonce, &message);
+            report_request(&self.dialog, kind, &message, peer).await;
             let _ = peer.ptx.send(message).await;
         }
     }
 
     // Broadcast to all connected peers, returning if at least one peer received the message.
     pub async fn broadcast(&mut self, message: MainThreadMessage) -> bool {
-        let active = self.map.values().filter(|peer| !peer.handle.is_finished());
+        let active = self
+            .map
+            .iter()
+            .filter(|(_, peer)| !peer.handle.is_finished());
         let mut sends = Vec::new();
-        for peer in active {
+        for (nonce, peer) in active {
+            let kind = self.pending.sent(*nonce, &message);
+            report_request(&self.dialog, kind, &message, peer).await;
             let res = peer.ptx.send(message.clone()).await;
             sends.push(res.is_ok());
         }
         sends.into_iter().any(|res| res)
     }
 
-    // Send to a random peer, returning true if the message was sent.
-    pub async fn send_random(&mut self, message: MainThreadMessage) -> bool {
-        let mut rng = StdRng::from_entropy();
-        if let Some((_, peer)) = self.map.iter().choose(&mut rng) {
+    // Tell every peer to disconnect and wait for the tasks to finish
+    pub async fn disconnect_all(&mut self) {
+        for peer in self.map.values() {
+            let _ = peer.ptx.try_send(MainThreadMessage::Disconnect);
+        }
+        for (_, mut peer) in self.map.drain() {
+            let timeout = Duration::from_secs(DISCONNECT_TIMEOUT_SECS);
+            if tokio::time::timeout(timeout, &mut peer.handle)
+                .await
+                .is_err()
+            {
+                peer.handle.abort();
+            }
+        }
+        self.pending.retain(&[]);
+    }
+
+    // Send to a connected peer chosen by the selector, returning true if the message was sent.
+    pub async fn send_selected(&mut self, message: MainThreadMessage) -> bool {
+        self.send_selected_excluding(message, &[]).await
+    }
+
+    // Send a block request to a peer chosen by the selector. Peers that are not sending a block
+    // already are chosen first, so blocks are downloaded from several peers at once.
+    pub async fn request_block(&mut self, message: MainThreadMessage) -> bool {
+        let (busy, idle): (Vec<PeerId>, Vec<PeerId>) = self
+            .map
+            .iter()
+            .filter(|(_, peer)| !peer.handle.is_finished() && peer.negotiated.is_some())
+            .map(|(nonce, _)| *nonce)
+            .partition(|nonce| self.pending.activity(*nonce).eq(&PeerActivity::Blocks));
+        if idle.is_empty() {
+            return self.send_selected(message).await;
+        }
+        self.send_selected_excluding(message, &busy).await
+    }
+
+    // Send to a connected peer chosen by the selector that is not one of the excluded peers.
+    pub async fn send_selected_excluding(
+        &mut self,
+        message: MainThreadMessage,
+        exclude: &[PeerId],
+    ) -> bool {
+        let (peers, infos): (Vec<(&PeerId, &ManagedPeer)>, Vec<PeerInfo>) = self
+            .map
+            .iter()
+            .filter(|(_, peer)| !peer.handle.is_finished())
+            .filter(|(nonce, _)| !exclude.contains(nonce))
+            .filter_map(|peer| peer.1.negotiated.clone().map(|info| (peer, info)))
+            .unzip();
+        if peers.is_empty() {
+            return false;
+        }
+        let index = self.selector.select(&infos);
+        if let Some((nonce, peer)) = peers.get(index) {
+            let kind = self.pending.sent(**nonce, &message);
+            report_request(&self.dialog, kind, &message, peer).await;
             let res = peer.ptx.send(message).await;
             return res.is_ok();
         }
         false
     }
 
+    // Ask the peers we completed a handshake with for new addresses, or query DNS seeds when there
+    // are none. Returns the number of peers asked.
+    pub async fn search_for_addresses(
+        &mut self,
+        exclude: PeerId,
+    ) -> Result<usize, PeerManagerError<P::Error>> {
+        let mut asked = 0;
+        for (nonce, peer) in self.map.iter() {
+            if nonce.eq(&exclude) || peer.handle.is_finished() || peer.negotiated.is_none() {
+                continue;
+            }
+            if peer.ptx.send(MainThreadMessage::GetAddr).await.is_ok() {
+                asked += 1;
+            }
+        }
+        if asked == 0 {
+            self.bootstrap().await?;
+        }
+        Ok(asked)
+    }
+
     // Pull a peer from the configuration if we have one. If not, select a random peer from the database,
     // as long as it is not from the same netgroup. If there are no peers in the database, try DNS.
     pub async fn next_peer(&mut self) -> Result<PersistedPeer, PeerManagerError<P::Error>> {
         if let Some(peer) = self.whitelist.pop() {
-            crate::log!(self.dialog, "Using a configured peer");
+            crate::log!(self.dialog, Subsystem::Peers, "Using a configured peer");
             let port = peer
                 .port
                 .unwrap_or(default_port_from_network(&self.network));
@@ -280,12 +682,13 @@ impl<P: PeerStore> PeerMap<P> {
         }
         let mut peer_manager = self.db.lock().await;
         let mut tries = 0;
-        let desired_status = PeerStatus::random();
+        let desired_status = self.selector.preferred_status();
         while tries < MAX_TRIES {
             let peer = peer_manager.random().await?;
             if self.net_groups.contains(&peer.addr.netgroup())
                 || desired_status.ne(&peer.status)
-                || !peer.services.has(ServiceFlags::COMPACT_FILTERS)
+                || !(self.headers_only || peer.services.has(ServiceFlags::COMPACT_FILTERS))
+                || !self.selector.accept(&peer)
             {
                 tries += 1;
                 continue;
@@ -308,9 +711,16 @@ impl<P: PeerStore> PeerMap<P> {
         }
     }
 
+    // Remove peers beyond the target size and reclaim unused space in the peer store
+    pub async fn compact_database(&mut self) -> Result<(), P::Error> {
+        let mut db = self.db.lock().await;
+        db.compact(self.target_db_size.clone()).await
+    }
+
     // Add peers to the database that were gossiped over the p2p network
     pub async fn add_gossiped_peers(&mut self, peers: Vec<CombinedAddr>) {
         let mut db = self.db.lock().await;
+        let started = Instant::now();
         for peer in peers {
             if let Err(e) = db
                 .update(PersistedPeer::new(
@@ -329,12 +739,22 @@ impl<P: PeerStore> PeerMap<P> {
                 });
             }
         }
+        self.dialog.check_database_latency("update peers", started);
     }
 
     // We tried this peer and successfully connected.
     pub async fn tried(&mut self, nonce: PeerId) {
         if let Some(peer) = self.map.get(&nonce) {
+            crate::info!(
+                self.dialog,
+                Subsystem::Peers,
+                Info::PeerConnected {
+                    addr: peer.address.clone(),
+                    port: peer.port,
+                }
+            );
             let mut db = self.db.lock().await;
+            let started = Instant::now();
             if let Err(e) = db
                 .update(PersistedPeer::new(
                     peer.address.clone(),
@@ -351,19 +771,104 @@ impl<P: PeerStore> PeerMap<P> {
                     ),
                 });
             }
+            self.dialog.check_database_latency("update peer", started);
+        }
+    }
+
+    // Add to the score of a peer that misbehaved, and ban it once the score is high enough.
+    // Returns if the peer was banned.
+    pub async fn penalize(&mut self, nonce: PeerId, misbehavior: Misbehavior) -> bool {
+        let (addr, port) = match self.map.get(&nonce) {
+            Some(peer) => (peer.address.clone(), peer.port),
+            None => return false,
+        };
+        if !self.scores.penalize(&addr, misbehavior) {
+            crate::log!(
+                self.dialog,
+                Subsystem::Peers,
+                format!("Peer {nonce} misbehaved: {misbehavior}")
+            );
+            return false;
+        }
+        self.update_status(nonce, PeerStatus::Ban).await;
+        self.dialog.send_warning(Warning::PeerBanned {
+            addr,
+            port,
+            misbehavior,
+        });
+        true
+    }
+
+    // Ban a peer at the request of the client, whether or not the node is connected to it
+    pub async fn ban_addr(&mut self, addr: AddrV2) {
+        self.whitelist.retain(|peer| peer.address.ne(&addr));
+        let connected = self
+            .map
+            .iter()
+            .filter(|(_, peer)| peer.address.eq(&addr))
+            .map(|(nonce, peer)| (*nonce, peer.port, peer.service_flags))
+            .collect::<Vec<(PeerId, u16, ServiceFlags)>>();
+        let (port, services) = connected
+            .first()
+            .map(|(_, port, services)| (*port, *services))
+            .unwrap_or((default_port_from_network(&self.network), ServiceFlags::NONE));
+        for (nonce, _, _) in connected {
+            self.send_message(nonce, MainThreadMessage::Disconnect)
+                .await;
+        }
+        let mut db = self.db.lock().await;
+        let started = Instant::now();
+        if let Err(e) = db
+            .update(PersistedPeer::new(
+                addr.clone(),
+                port,
+                services,
+                PeerStatus::Ban,
+            ))
+            .await
+        {
+            self.dialog.send_warning(Warning::FailedPersistence {
+                warning: format!("Encountered an error banning {addr:?}:{port} ... {e}"),
+            });
+        }
+        self.dialog.check_database_latency("update peer", started);
+    }
+
+    // Disconnect from peers that hold a request without sending any data, so they are replaced by
+    // new connections. Peers are demoted in the database, and banned if they are replaced often.
+    // Returns the number of peers replaced.
+    pub async fn replace_unresponsive(&mut self) -> usize {
+        let timeout = self.timeout_config.response_timeout * UNRESPONSIVE_TIMEOUTS;
+        let overdue = self.pending.overdue(timeout);
+        for nonce in overdue.iter() {
+            self.pending.forget(*nonce);
+            let (addr, port) = match self.map.get(nonce) {
+                Some(peer) => (peer.address.clone(), peer.port),
+                None => continue,
+            };
+            self.dialog.send_warning(Warning::UnresponsivePeer {
+                addr: addr.clone(),
+                port,
+            });
+            if !self.penalize(*nonce, Misbehavior::Unresponsive).await {
+                self.update_status(*nonce, PeerStatus::Gossiped).await;
+            }
+            self.send_message(*nonce, MainThreadMessage::Disconnect)
+                .await;
         }
+        overdue.len()
     }
 
-    // This peer misbehaved in some way.
-    pub async fn ban(&mut self, nonce: PeerId) {
+    async fn update_status(&mut self, nonce: PeerId, status: PeerStatus) {
         if let Some(peer) = self.map.get(&nonce) {
             let mut db = self.db.lock().await;
+            let started = Instant::now();
             if let Err(e) = db
                 .update(PersistedPeer::new(
                     peer.address.clone(),
                     peer.port,
                     peer.service_flags,
-                    PeerStatus::Ban,
+                    status,
                 ))
                 .await
             {
@@ -374,25 +879,36 @@ impl<P: PeerStore> PeerMap<P> {
                     ),
                 });
             }
+            self.dialog.check_database_latency("update peer", started);
         }
     }
 
     async fn bootstrap(&mut self) -> Result<(), PeerManagerError<P::Error>> {
         use crate::network::dns::Dns;
         use std::net::IpAddr;
-        crate::log!(self.dialog, "Bootstrapping peers with DNS");
+        crate::log!(
+            self.dialog,
+            Subsystem::Peers,
+            "Bootstrapping peers with DNS"
+        );
         let mut db_lock = self.db.lock().await;
-        let new_peers = Dns::new(self.network, self.dns_resolver)
-            .bootstrap()
-            .await
-            .into_iter()
-            .map(|ip| match ip {
-                IpAddr::V4(ip) => AddrV2::Ipv4(ip),
-                IpAddr::V6(ip) => AddrV2::Ipv6(ip),
-            })
-            .collect::<Vec<AddrV2>>();
+        let new_peers = Dns::new(
+            self.network,
+            self.dns_seeds.as_deref(),
+            self.dns_resolver,
+            self.connector,
+        )
+        .bootstrap()
+        .await
+        .into_iter()
+        .map(|ip| match ip {
+            IpAddr::V4(ip) => AddrV2::Ipv4(ip),
+            IpAddr::V6(ip) => AddrV2::Ipv6(ip),
+        })
+        .collect::<Vec<AddrV2>>();
         crate::log!(
             self.dialog,
+            Subsystem::Peers,
             format!("Adding {} sourced from DNS", new_peers.len())
         );
         for peer in new_peers {
@@ -400,7 +916,8 @@ impl<P: PeerStore> PeerMap<P> {
                 .update(PersistedPeer::new(
                     peer,
                     default_port_from_network(&self.network),
-                    ServiceFlags::NONE,
+                    // Seeds are only asked for peers that serve compact block filters
+                    ServiceFlags::NETWORK | ServiceFlags::COMPACT_FILTERS,
                     PeerStatus::Gossiped,
                 ))
                 .await
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    channel_messages::{MainThreadMessage, PeerMessage},
+    PeerActivity, RequestKind,
+};
+
+use super::PeerId;
+
+// Requests sent to peers that have not been answered yet, with the time each was sent. A new
+// request of the same kind to the same peer replaces the old one.
+#[derive(Debug, Default)]
+pub(crate) struct PendingRequests {
+    requests: Vec<(PeerId, RequestKind, Instant)>,
+    // The last headers, filter headers, filter or block sent by each peer
+    last_data: HashMap<PeerId, Instant>,
+}
+
+impl PendingRequests {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the kind of request, if the message is one
+    pub(crate) fn sent(
+        &mut self,
+        peer: PeerId,
+        message: &MainThreadMessage,
+    ) -> Option<RequestKind> {
+        let kind = match message {
+            MainThreadMessage::GetHeaders(_) => RequestKind::Headers,
+            MainThreadMessage::GetFilterHeaders(get_cf_headers) => RequestKind::FilterHeaders {
+                stop_hash: get_cf_headers.stop_hash,
+            },
+            MainThreadMessage::GetFilters(get_filters) => RequestKind::Filters {
+                stop_hash: get_filters.stop_hash,
+            },
+            MainThreadMessage::GetBlock(config) => RequestKind::Block(config.locator),
+            _ => return None,
+        };
+        self.requests
+            .retain(|(id, pending, _)| id.ne(&peer) || !pending.same_kind(&kind));
+        self.requests.push((peer, kind, Instant::now()));
+        Some(kind)
+    }
+
+    // Returns the requests the message answered and the time each took to answer
+    pub(crate) fn received(
+        &mut self,
+        peer: PeerId,
+        message: &PeerMessage,
+    ) -> Vec<(RequestKind, Duration)> {
+        let answered = |kind: &RequestKind| match message {
+            PeerMessage::Headers(_) => matches!(kind, RequestKind::Headers),
+            PeerMessage::FilterHeaders(_) => matches!(kind, RequestKind::FilterHeaders { .. }),
+            // A range of filters is answered once the last filter arrives
+            PeerMessage::Filter(filter) => {
+                matches!(kind, RequestKind::Filters { stop_hash } if stop_hash.eq(&filter.block_hash))
+            }
+            PeerMessage::Block(block) => {
+                matches!(kind, RequestKind::Block(hash) if hash.eq(&block.block_hash()))
+            }
+            _ => false,
+        };
+        if matches!(
+            message,
+            PeerMessage::Headers(_)
+                | PeerMessage::FilterHeaders(_)
+                | PeerMessage::Filter(_)
+                | PeerMessage::Block(_)
+        ) {
+            self.last_data.insert(peer, Instant::now());
+        }
+        let mut answered_requests = Vec::new();
+        self.requests.retain(|(id, kind, sent)| {
+            if id.eq(&peer) && answered(kind) {
+                answered_requests.push((*kind, sent.elapsed()));
+                return false;
+            }
+            true
+        });
+        answered_requests
+    }
+
+    // The peers we are waiting on for a response
+    pub(crate) fn peers(&self) -> Vec<PeerId> {
+        let mut peers = Vec::new();
+        for (id, _, _) in &self.requests {
+            if !peers.contains(id) {
+                peers.push(*id);
+            }
+        }
+        peers
+    }
+
+    // What the peer is serving, from the most recent request it has not answered
+    pub(crate) fn activity(&self, peer: PeerId) -> PeerActivity {
+        let kind = self
+            .requests
+            .iter()
+            .rev()
+            .find(|(id, _, _)| id.eq(&peer))
+            .map(|(_, kind, _)| kind);
+        match kind {
+            Some(RequestKind::Headers) => PeerActivity::Headers,
+            Some(RequestKind::FilterHeaders { .. }) => PeerActivity::FilterHeaders,
+            Some(RequestKind::Filters { .. }) => PeerActivity::Filters,
+            Some(RequestKind::Block(_)) => PeerActivity::Blocks,
+            None => PeerActivity::Idle,
+        }
+    }
+
+    // The peers that have not sent any data for longer than the timeout while a request to them
+    // is pending. Peers sending a range of filters are not overdue while the filters arrive.
+    pub(crate) fn overdue(&self, timeout: Duration) -> Vec<PeerId> {
+        self.peers()
+            .into_iter()
+            .filter(|peer| {
+                let oldest = self
+                    .requests
+                    .iter()
+                    .filter(|(id, _, _)| id.eq(peer))
+                    .map(|(_, _, sent)| *sent)
+                    .min();
+                let waiting_since = match (oldest, self.last_data.get(peer)) {
+                    (Some(sent), Some(data)) => sent.max(*data),
+                    (Some(sent), None) => sent,
+                    (None, _) => return false,
+                };
+                waiting_since.elapsed() > timeout
+            })
+            .collect()
+    }
+
+    // Forget the requests sent to peers that are no longer connected
+    pub(crate) fn retain(&mut self, peers: &[PeerId]) {
+        self.requests.retain(|(id, _, _)| peers.contains(id));
+        self.last_data.retain(|id, _| peers.contains(id));
+    }
+
+    // Forget the requests sent to a peer that is being replaced
+    pub(crate) fn forget(&mut self, peer: PeerId) {
+        self.requests.retain(|(id, _, _)| id.ne(&peer));
+        self.last_data.remove(&peer);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &(PeerId, RequestKind, Instant)> {
+        self.requests.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        constants::genesis_block,
+        hashes::Hash,
+        p2p::message_filter::{CFilter, GetCFilters},
+        BlockHash, Network,
+    };
+
+    use crate::channel_messages::{GetBlockConfig, GetHeaderConfig};
+
+    use super::*;
+
+    #[test]
+    fn test_requests_are_answered() {
+        let mut pending = PendingRequests::new();
+        let peer_one = PeerId(1);
+        let peer_two = PeerId(2);
+        let get_headers = MainThreadMessage::GetHeaders(GetHeaderConfig {
+            locators: Vec::new(),
+            stop_hash: None,
+        });
+        pending.sent(peer_one, &get_headers);
+        pending.sent(peer_one, &get_headers);
+        pending.sent(peer_two, &get_headers);
+        assert_eq!(pending.iter().count(), 2);
+        let answered = pending.received(peer_one, &PeerMessage::Headers(Vec::new()));
+        assert_eq!(answered.len(), 1);
+        assert_eq!(answered[0].0, RequestKind::Headers);
+        assert_eq!(pending.iter().count(), 1);
+        assert_eq!(pending.peers(), vec![peer_two]);
+        let stop_hash = BlockHash::from_byte_array([1; 32]);
+        let kind = pending.sent(
+            peer_one,
+            &MainThreadMessage::GetFilters(GetCFilters {
+                filter_type: 0x00,
+                start_height: 0,
+                stop_hash,
+            }),
+        );
+        assert_eq!(kind, Some(RequestKind::Filters { stop_hash }));
+        assert!(pending.sent(peer_one, &MainThreadMessage::Verack).is_none());
+        let filter = |block_hash| {
+            PeerMessage::Filter(CFilter {
+                filter_type: 0x00,
+                block_hash,
+                filter: Vec::new(),
+            })
+        };
+        assert!(pending
+            .received(peer_one, &filter(BlockHash::all_zeros()))
+            .is_empty());
+        assert_eq!(pending.iter().count(), 2);
+        assert_eq!(pending.activity(peer_one), PeerActivity::Filters);
+        pending.received(peer_one, &filter(stop_hash));
+        assert_eq!(pending.iter().count(), 1);
+        assert_eq!(pending.activity(peer_one), PeerActivity::Idle);
+        assert_eq!(pending.activity(peer_two), PeerActivity::Headers);
+        let block = genesis_block(Network::Regtest);
+        pending.sent(
+            peer_one,
+            &MainThreadMessage::GetBlock(GetBlockConfig {
+                locator: block.block_hash(),
+            }),
+        );
+        pending.retain(&[peer_one]);
+        assert_eq!(pending.iter().count(), 1);
+        pending.received(peer_one, &PeerMessage::Block(block));
+        assert_eq!(pending.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_silent_peers_are_overdue() {
+        let mut pending = PendingRequests::new();
+        let peer_one = PeerId(1);
+        let peer_two = PeerId(2);
+        let get_headers = MainThreadMessage::GetHeaders(GetHeaderConfig {
+            locators: Vec::new(),
+            stop_hash: None,
+        });
+        pending.sent(peer_one, &get_headers);
+        assert!(pending.overdue(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(pending.overdue(Duration::from_millis(10)), vec![peer_one]);
+        // Data from the peer resets the wait, even if it does not answer the request
+        let filter = PeerMessage::Filter(CFilter {
+            filter_type: 0x00,
+            block_hash: BlockHash::all_zeros(),
+            filter: Vec::new(),
+        });
+        pending.received(peer_one, &filter);
+        assert!(pending.overdue(Duration::from_millis(10)).is_empty());
+        pending.sent(peer_two, &get_headers);
+        pending.forget(peer_one);
+        assert_eq!(pending.peers(), vec![peer_two]);
+        pending.retain(&[]);
+        assert!(pending.overdue(Duration::ZERO).is_empty());
+    }
+}
@@ -83,16 +83,21 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> Reader<R> {
                     return Some(ReaderMessage::Disconnect);
                 }
                 let mut hashes = Vec::new();
+                let mut transactions = Vec::new();
                 for i in inventory {
                     match i {
                         Inventory::Block(hash) => hashes.push(hash),
                         Inventory::CompactBlock(hash) => hashes.push(hash),
                         Inventory::WitnessBlock(hash) => hashes.push(hash),
+                        Inventory::Transaction(_) | Inventory::WTx(_) => transactions.push(i),
                         _ => continue,
                     }
                 }
+                // Peers announce blocks and transactions with separate messages
                 if !hashes.is_empty() {
                     Some(ReaderMessage::NewBlocks(hashes))
+                } else if !transactions.is_empty() {
+                    Some(ReaderMessage::TxAnnounced(transactions))
                 } else {
                     None
                 }
@@ -111,7 +116,7 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> Reader<R> {
             NetworkMessage::GetBlocks(_) => None,
             NetworkMessage::GetHeaders(_) => None,
             NetworkMessage::MemPool => None,
-            NetworkMessage::Tx(_) => None,
+            NetworkMessage::Tx(transaction) => Some(ReaderMessage::Transaction(transaction)),
             NetworkMessage::Block(block) => Some(ReaderMessage::Block(block)),
             NetworkMessage::Headers(headers) => {
                 if headers.len() > MAX_HEADERS {
@@ -137,7 +142,10 @@ impl<R: AsyncReadExt + Send + Sync + Unpin> Reader<R> {
             NetworkMessage::CFCheckpt(_) => None,
             // Compact Block Relay is enabled with 70014
             NetworkMessage::SendCmpct(_) => None,
-            NetworkMessage::CmpctBlock(_) => None,
+            // The header of a block pushed in high-bandwidth mode is treated as an announcement
+            NetworkMessage::CmpctBlock(block) => {
+                Some(ReaderMessage::Headers(vec![block.compact_block.header]))
+            }
             NetworkMessage::GetBlockTxn(_) => None,
             NetworkMessage::BlockTxn(_) => None,
             NetworkMessage::Alert(_) => None,
@@ -0,0 +1,134 @@
+use std::{net::SocketAddr, time::Duration};
+
+use bitcoin::{consensus::deserialize, Block, BlockHash};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use super::error::RestError;
+use crate::{block_source::BlockSource, error::BlockSourceError, prelude::FutureResult};
+
+const REST_TIMEOUT_SECS: u64 = 10;
+// A block is at most four million bytes, with room left for the headers of the response
+const MAX_RESPONSE_BYTES: u64 = 4_000_000 + 16_384;
+const HEADER_DELIMITER: &[u8; 4] = b"\r\n\r\n";
+
+/// Fetch block bodies from a trusted Bitcoin Core node over the REST interface.
+/// The node must be ran with `-rest=1`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RestClient {
+    socket_addr: SocketAddr,
+}
+
+impl RestClient {
+    pub(crate) fn new(socket_addr: SocketAddr) -> Self {
+        Self { socket_addr }
+    }
+
+    async fn get_block(&self, hash: BlockHash) -> Result<Block, RestError> {
+        let response = timeout(
+            Duration::from_secs(REST_TIMEOUT_SECS),
+            self.get(&format!("/rest/block/{hash}.bin")),
+        )
+        .await
+        .map_err(|_| RestError::Timeout)??;
+        let body = parse_response(&response)?;
+        let block: Block = deserialize(body).map_err(|_| RestError::Deserialization)?;
+        if block.block_hash().ne(&hash) {
+            return Err(RestError::UnexpectedBlock);
+        }
+        Ok(block)
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>, RestError> {
+        let mut stream = TcpStream::connect(self.socket_addr)
+            .await
+            .map_err(|_| RestError::ConnectionFailed)?;
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.socket_addr
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream
+            .take(MAX_RESPONSE_BYTES + 1)
+            .read_to_end(&mut response)
+            .await?;
+        if response.len() as u64 > MAX_RESPONSE_BYTES {
+            return Err(RestError::ResponseTooLarge);
+        }
+        Ok(response)
+    }
+}
+
+impl BlockSource for RestClient {
+    fn get_block(&mut self, hash: BlockHash) -> FutureResult<Block, BlockSourceError> {
+        let client = *self;
+        Box::pin(async move { client.get_block(hash).await.map_err(BlockSourceError::from) })
+    }
+}
+
+// Check the status line and return the body of an HTTP response
+fn parse_response(response: &[u8]) -> Result<&[u8], RestError> {
+    let split = response
+        .windows(HEADER_DELIMITER.len())
+        .position(|window| window.eq(HEADER_DELIMITER))
+        .ok_or(RestError::MalformedResponse)?;
+    let head = std::str::from_utf8(&response[..split]).map_err(|_| RestError::MalformedResponse)?;
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(RestError::MalformedResponse)?;
+    if status != 200 {
+        return Err(RestError::Status(status));
+    }
+    Ok(&response[split + HEADER_DELIMITER.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n\x01\x02";
+        assert_eq!(parse_response(ok).unwrap(), &[0x01, 0x02]);
+        let not_found = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        assert!(matches!(
+            parse_response(not_found),
+            Err(RestError::Status(404))
+        ));
+        assert!(matches!(
+            BlockSourceError::from(RestError::Status(404)),
+            BlockSourceError::NotFound
+        ));
+        let truncated = b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream";
+        assert!(matches!(
+            parse_response(truncated),
+            Err(RestError::MalformedResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_response_size_is_capped() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            let mut response = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+            response.resize(MAX_RESPONSE_BYTES as usize + 1, 0);
+            let _ = stream.write_all(&response).await;
+        });
+        let client = RestClient::new(socket_addr);
+        assert!(matches!(
+            client.get("/rest/block/0.bin").await,
+            Err(RestError::ResponseTooLarge)
+        ));
+    }
+}
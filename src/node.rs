@@ -1,13 +1,24 @@
-use std::{ops::DerefMut, sync::Arc, time::Duration};
+#[cfg(not(feature = "rest"))]
+use std::convert::Infallible;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::DerefMut,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use bitcoin::{
     block::Header,
     p2p::{
-        message_filter::{CFHeaders, CFilter},
+        message_blockdata::Inventory,
+        message_filter::{CFHeaders, CFilter, GetCFilters},
         message_network::VersionMessage,
         ServiceFlags,
     },
-    Block, BlockHash, Network, ScriptBuf,
+    Block, BlockHash, Network, ScriptBuf, Transaction, VarInt,
 };
 use tokio::sync::{
     mpsc::{Receiver, UnboundedReceiver},
@@ -18,6 +29,10 @@ use tokio::{
     sync::mpsc::{self},
 };
 
+#[cfg(feature = "rest")]
+use crate::block_source::{BlockFetcher, BlockSource, FetchedBlock};
+#[cfg(feature = "journal")]
+use crate::journal::{EventJournal, JournalEntry};
 use crate::{
     chain::{
         chain::Chain,
@@ -27,41 +42,89 @@ use crate::{
     },
     db::traits::{HeaderStore, PeerStore},
     error::FetchHeaderError,
-    network::{peer_map::PeerMap, LastBlockMonitor, PeerId},
-    NodeState, RejectPayload, TxBroadcastPolicy,
+    network::{peer_map::PeerMap, LastBlockMonitor, PeerId, StallWatchdog},
+    ChainView, MissingFiltersPolicy, NodeState, RejectPayload, StateTransition, Subsystem,
+    TxBroadcast, TxBroadcastPolicy,
 };
 
 use super::{
-    broadcaster::Broadcaster,
+    broadcaster::{Broadcaster, Confirmation},
     channel_messages::{
         CombinedAddr, GetBlockConfig, GetHeaderConfig, MainThreadMessage, PeerMessage,
         PeerThreadMessage,
     },
-    client::Client,
-    config::NodeConfig,
+    client::{Client, ClientChannels},
+    config::{tip_agreement_reachable, ConfigDelta, NodeConfig},
     dialog::Dialog,
-    error::NodeError,
-    messages::{ClientMessage, Event, Info, SyncUpdate, Warning},
+    error::{NodeError, UpdateConfigError},
+    mempool::Mempool,
+    messages::{
+        ClientMessage, Event, Info, Misbehavior, ShutdownReason, ShutdownReport, SyncReport,
+        SyncUpdate, Warning,
+    },
 };
 
 pub(crate) const WTXID_VERSION: u32 = 70016;
 const LOOP_TIMEOUT: u64 = 1;
+// Peers announce at most eight blocks with a headers message
+const MAX_ANNOUNCED_HEADERS: usize = 8;
+const STATE_HISTORY_LEN: usize = 20;
+// Serialized sizes of the fixed length fields of messages
+const HEADER_WIRE_SIZE: usize = 81;
+const CF_HEADERS_FIXED_SIZE: usize = 65;
+const CFILTER_FIXED_SIZE: usize = 33;
 
 type PeerRequirement = usize;
 
 /// A compact block filter node. Nodes download Bitcoin block headers, block filters, and blocks to send relevant events to a client.
+///
+/// A node should be stopped with [`Requester::shutdown`](crate::Requester::shutdown). If a node
+/// is dropped without shutting down, such as when the runtime is torn down, any block headers
+/// that were not yet written are flushed to the [`HeaderStore`] on a best-effort basis.
 #[derive(Debug)]
 pub struct Node<H: HeaderStore, P: PeerStore> {
     state: Arc<RwLock<NodeState>>,
+    state_history: Arc<Mutex<VecDeque<StateTransition>>>,
+    sync_report: Arc<Mutex<SyncReport>>,
     chain: Arc<Mutex<Chain<H>>>,
     peer_map: Arc<Mutex<PeerMap<P>>>,
     tx_broadcaster: Arc<Mutex<Broadcaster>>,
-    required_peers: PeerRequirement,
+    // The block source, until its fetcher is started with the node
+    #[cfg(feature = "rest")]
+    block_source: Mutex<Option<Box<dyn BlockSource>>>,
+    #[cfg(feature = "rest")]
+    block_fetcher: Mutex<Option<BlockFetcher>>,
+    // The event journal and the entries sent to it, until it is started with the node
+    #[cfg(feature = "journal")]
+    event_journal: Mutex<Option<(EventJournal, UnboundedReceiver<JournalEntry>)>>,
+    // Announced transactions, when monitoring the mempools of peers
+    mempool: Option<Mutex<Mempool>>,
+    compaction_pending: AtomicBool,
+    tip_poll_interval: Duration,
+    compact_block_announcements: bool,
+    max_peer_lag: Option<u32>,
+    // Peers that must agree on the tip before the node is synced
+    tip_agreement: u8,
+    stall_timeout: Duration,
+    missing_filters: MissingFiltersPolicy,
+    headers_only: bool,
+    // Peers in a row that did not serve compact block filters
+    peers_without_filters: AtomicU32,
+    required_peers: AtomicUsize,
     dialog: Arc<Dialog>,
     client_recv: Arc<Mutex<UnboundedReceiver<ClientMessage>>>,
+    view: ChainView,
     peer_recv: Arc<Mutex<Receiver<PeerThreadMessage>>>,
 }
 
+impl<H: HeaderStore, P: PeerStore> Drop for Node<H, P> {
+    fn drop(&mut self) {
+        if let Ok(mut chain) = self.chain.try_lock() {
+            chain.try_flush();
+        }
+    }
+}
+
 impl<H: HeaderStore, P: PeerStore> Node<H, P> {
     pub(crate) fn new(
         network: Network,
@@ -69,33 +132,115 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         peer_store: P,
         header_store: H,
     ) -> (Self, Client) {
+        // Set up a communication channel between the node and client
+        let (log_tx, log_rx) = mpsc::channel::<String>(32);
+        let (info_tx, info_rx) = mpsc::channel::<Info>(32);
+        let (warn_tx, warn_rx) = mpsc::unbounded_channel::<Warning>();
+        let (event_tx, event_rx) = mpsc::unbounded_channel::<Event>();
+        let (ctx, crx) = mpsc::unbounded_channel::<ClientMessage>();
+        let client = Client::new(log_rx, info_rx, warn_rx, event_rx, ctx);
+        let channels = ClientChannels {
+            dialog: Dialog::new(
+                config.log_level,
+                HashMap::new(),
+                log_tx,
+                info_tx,
+                warn_tx,
+                event_tx,
+            ),
+            client_recv: Arc::new(Mutex::new(crx)),
+            view: ChainView::new(),
+        };
+        let node = Self::with_client_channels(network, config, peer_store, header_store, channels);
+        (node, client)
+    }
+
+    // Build a node that communicates with an existing client
+    pub(crate) fn with_client_channels(
+        network: Network,
+        config: NodeConfig,
+        peer_store: P,
+        header_store: H,
+        channels: ClientChannels,
+    ) -> Self {
         let NodeConfig {
             required_peers,
             white_list,
             dns_resolver,
+            dns_seeds,
             addresses,
+            script_shard_size,
+            block_workers,
+            event_mask,
+            index_scripts,
+            track_utxos,
+            detect_birthday,
+            filter_spot_checks,
+            #[cfg(feature = "journal")]
+            journal_events,
+            #[cfg(feature = "journal")]
+            event_journal,
             data_path: _,
             header_checkpoint,
+            mut checkpoint_provider,
+            reorg_depth,
             connection_type,
+            #[cfg(feature = "i2p")]
+            i2p_sam,
             target_peer_size,
             peer_timeout_config,
             log_level,
+            subsystem_log_levels,
+            log_sensitive_data,
+            #[cfg(feature = "rest")]
+            block_source,
+            filter_store,
+            cf_header_store,
+            peer_selector,
+            #[cfg(not(feature = "filter-control"))]
+            filter_matcher,
+            #[cfg(not(feature = "filter-control"))]
+            xpub_watch,
+            #[cfg(not(feature = "filter-control"))]
+            exclude_broad_scripts,
+            #[cfg(not(feature = "filter-control"))]
+            filter_matches_only,
+            #[cfg(not(feature = "filter-control"))]
+            outpoints,
+            trust_checkpoints,
+            header_time_drift,
+            tip_poll_interval,
+            compact_block_announcements,
+            max_peer_lag,
+            tip_agreement,
+            stall_timeout,
+            missing_filters,
+            headers_only,
+            monitor_mempool,
         } = config;
-        // Set up a communication channel between the node and client
-        let (log_tx, log_rx) = mpsc::channel::<String>(32);
-        let (info_tx, info_rx) = mpsc::channel::<Info>(32);
-        let (warn_tx, warn_rx) = mpsc::unbounded_channel::<Warning>();
-        let (event_tx, event_rx) = mpsc::unbounded_channel::<Event>();
-        let (ctx, crx) = mpsc::unbounded_channel::<ClientMessage>();
-        let client = Client::new(log_rx, info_rx, warn_rx, event_rx, ctx);
         // A structured way to talk to the client
-        let dialog = Arc::new(Dialog::new(log_level, log_tx, info_tx, warn_tx, event_tx));
+        let dialog = channels
+            .dialog
+            .with_levels(log_level, subsystem_log_levels)
+            .with_event_mask(event_mask)
+            .with_sensitive_data(log_sensitive_data);
+        #[cfg(feature = "journal")]
+        let (dialog, event_journal) = match event_journal {
+            Some(journal) if journal_events => {
+                let (entry_tx, entry_rx) = mpsc::unbounded_channel();
+                (dialog.with_journal(entry_tx), Some((journal, entry_rx)))
+            }
+            _ => (dialog, None),
+        };
+        let dialog = Arc::new(dialog);
         // We always assume we are behind
         let state = Arc::new(RwLock::new(NodeState::Behind));
+        let mut state_history = VecDeque::with_capacity(STATE_HISTORY_LEN);
+        state_history.push_back(StateTransition::new(NodeState::Behind));
         // Configure the peer manager
         let (mtx, mrx) = mpsc::channel::<PeerThreadMessage>(32);
         let height_monitor = Arc::new(Mutex::new(HeightMonitor::new()));
-        let peer_map = Arc::new(Mutex::new(PeerMap::new(
+        let peer_map = PeerMap::new(
             mtx,
             network,
             peer_store,
@@ -106,65 +251,162 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
             peer_timeout_config,
             Arc::clone(&height_monitor),
             dns_resolver,
-        )));
+            peer_selector,
+        )
+        .with_transaction_relay(monitor_mempool)
+        .with_headers_only(headers_only)
+        .with_dns_seeds(dns_seeds);
+        #[cfg(feature = "i2p")]
+        let peer_map = peer_map.with_i2p(i2p_sam);
+        let peer_map = Arc::new(Mutex::new(peer_map));
         // Set up the transaction broadcaster
         let tx_broadcaster = Arc::new(Mutex::new(Broadcaster::new()));
         // Prepare the header checkpoints for the chain source
         let mut checkpoints = HeaderCheckpoints::new(&network);
+        if let Some(provider) = checkpoint_provider.as_mut() {
+            checkpoints.extend(provider.checkpoints(network));
+        }
         let checkpoint = header_checkpoint.unwrap_or_else(|| checkpoints.last());
         checkpoints.prune_up_to(checkpoint);
         // Build the chain
         let chain = Chain::new(
             network,
             addresses,
+            script_shard_size,
             checkpoint,
             checkpoints,
             Arc::clone(&dialog),
             height_monitor,
             header_store,
             required_peers,
-        );
-        let chain = Arc::new(Mutex::new(chain));
-        (
-            Self {
-                state,
-                chain,
-                peer_map,
-                tx_broadcaster,
-                required_peers: required_peers.into(),
-                dialog,
-                client_recv: Arc::new(Mutex::new(crx)),
-                peer_recv: Arc::new(Mutex::new(mrx)),
-            },
-            client,
+            trust_checkpoints,
         )
+        .with_block_workers(block_workers)
+        .with_script_index(index_scripts)
+        .with_utxo_tracking(track_utxos)
+        .with_filter_store(filter_store)
+        .with_cf_header_store(cf_header_store)
+        .with_birthday_detection(detect_birthday)
+        .with_filter_spot_checks(filter_spot_checks)
+        .with_checkpoint_provider(checkpoint_provider)
+        .with_reorg_depth(reorg_depth)
+        .with_header_time_drift(header_time_drift)
+        .with_chain_view(channels.view.clone());
+        #[cfg(not(feature = "filter-control"))]
+        let chain = chain
+            .with_filter_matcher(filter_matcher)
+            .with_xpub_watch(xpub_watch)
+            .with_broad_script_exclusion(exclude_broad_scripts)
+            .with_filter_matches_only(filter_matches_only)
+            .with_outpoints(outpoints);
+        let chain = Arc::new(Mutex::new(chain));
+        Self {
+            state,
+            state_history: Arc::new(Mutex::new(state_history)),
+            sync_report: Arc::new(Mutex::new(SyncReport::default())),
+            chain,
+            peer_map,
+            tx_broadcaster,
+            #[cfg(feature = "rest")]
+            block_source: Mutex::new(block_source),
+            #[cfg(feature = "rest")]
+            block_fetcher: Mutex::new(None),
+            #[cfg(feature = "journal")]
+            event_journal: Mutex::new(event_journal),
+            mempool: monitor_mempool.then(|| Mutex::new(Mempool::new())),
+            compaction_pending: AtomicBool::new(false),
+            tip_poll_interval,
+            compact_block_announcements,
+            max_peer_lag,
+            tip_agreement,
+            stall_timeout,
+            missing_filters,
+            headers_only,
+            peers_without_filters: AtomicU32::new(0),
+            required_peers: AtomicUsize::new(required_peers.into()),
+            dialog,
+            client_recv: channels.client_recv,
+            view: channels.view,
+            peer_recv: Arc::new(Mutex::new(mrx)),
+        }
+    }
+
+    /// Take the channels to the [`Client`] of this node, so another node may be built for the
+    /// same client with [`NodeBuilder::rebuild`](crate::NodeBuilder::rebuild). This is intended
+    /// for a node that has been shut down, for instance to stop networking while a mobile
+    /// application is in the background and resume it later without replacing the client.
+    pub fn into_client_channels(self) -> ClientChannels {
+        ClientChannels {
+            dialog: Dialog::clone(&self.dialog),
+            client_recv: Arc::clone(&self.client_recv),
+            view: self.view.clone(),
+        }
     }
 
     /// Run the node continuously. Typically run on a separate thread than the underlying application.
     ///
     /// # Errors
     ///
-    /// A node will cease running if a fatal error is encountered with either the [`PeerStore`] or [`HeaderStore`].
+    /// A node will cease running if a fatal error is encountered with either the [`PeerStore`] or [`HeaderStore`],
+    /// or if peers do not serve compact block filters when configured with [`MissingFiltersPolicy::Fail`].
+    ///
+    /// Whether the node stops on request or from an error, the last event it sends is
+    /// [`Event::ShutdownInitiated`] with the reason.
     pub async fn run(&self) -> Result<(), NodeError<H::Error, P::Error>> {
-        crate::log!(self.dialog, "Starting node");
+        let result = self.run_until_stopped().await;
+        let reason = match &result {
+            Ok(()) => ShutdownReason::Requested,
+            Err(e) => ShutdownReason::from(e),
+        };
+        self.dialog.send_event(Event::ShutdownInitiated(reason));
+        result
+    }
+
+    async fn run_until_stopped(&self) -> Result<(), NodeError<H::Error, P::Error>> {
+        crate::log!(self.dialog, Subsystem::Node, "Starting node");
+        #[cfg(feature = "journal")]
+        if let Some((journal, entries)) = self.event_journal.lock().await.take() {
+            self.dialog.start_journal(journal, entries);
+        }
         crate::log!(
             self.dialog,
+            Subsystem::Node,
             format!(
                 "Configured connection requirement: {} peers",
-                self.required_peers
+                self.required_peers()
             )
         );
         self.fetch_headers().await?;
-        let mut last_block = LastBlockMonitor::new();
+        self.load_broadcasts().await;
+        #[cfg(feature = "rest")]
+        let mut fetched_blocks = self.start_block_fetcher().await;
+        #[cfg(not(feature = "rest"))]
+        let mut fetched_blocks = ();
+        let mut last_block = LastBlockMonitor::new(self.tip_poll_interval);
+        let mut watchdog = StallWatchdog::new(self.stall_timeout);
         let mut peer_recv = self.peer_recv.lock().await;
         let mut client_recv = self.client_recv.lock().await;
         loop {
             // Try to advance the state of the node
             self.advance_state(&mut last_block).await;
+            // Replace peers that hold our requests without answering
+            self.replace_unresponsive_peers().await;
+            // Drop a filter header dispute the peers did not answer in time
+            self.expire_dispute().await;
+            // Rotate peers if none have answered our requests for a while
+            self.recover_stall(&mut watchdog).await;
+            // Compact the databases while there is no chain data to process
+            self.compact_databases().await;
+            // Report the peers that started doing something else
+            self.report_peer_states().await;
             // Connect to more peers if we need them and remove old connections
             self.dispatch().await?;
             // If there are blocks we need in the queue, we should request them of a random peer
             self.get_blocks().await;
+            // Save the transactions that are not confirmed before they are sent
+            self.persist_broadcasts().await;
+            // Save the scan results changed since the last block, such as by a reorganization
+            self.chain.lock().await.write_scan_results().await;
             // If we have a transaction to broadcast and we are connected to peers, we should broadcast them
             self.broadcast_transactions().await;
             // Either handle a message from a remote peer or from our client
@@ -172,22 +414,31 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                 peer = tokio::time::timeout(Duration::from_secs(LOOP_TIMEOUT), peer_recv.recv()) => {
                     match peer {
                         Ok(Some(peer_thread)) => {
+                            self.record_bytes(&peer_thread.message).await;
+                            if self.peer_map.lock().await.received(peer_thread.nonce, &peer_thread.message).await {
+                                watchdog.progressed();
+                            }
                             match peer_thread.message {
                                 PeerMessage::Version(version) => {
                                     {
                                         let mut peer_map = self.peer_map.lock().await;
                                         peer_map.set_offset(peer_thread.nonce, version.timestamp);
                                         peer_map.set_services(peer_thread.nonce, version.services);
-                                        peer_map.set_height(peer_thread.nonce, version.start_height as u32).await;
+                                        peer_map.set_height(peer_thread.nonce, u32::try_from(version.start_height).unwrap_or(0)).await;
+                                        if let Some(info) = peer_map.set_version(peer_thread.nonce, &version) {
+                                            crate::info!(self.dialog, Subsystem::Peers, Info::PeerNegotiated(info));
+                                        }
                                     }
                                     let response = self.handle_version(peer_thread.nonce, version).await?;
                                     self.send_message(peer_thread.nonce, response).await;
-                                    crate::log!(self.dialog, format!("[{}]: version", peer_thread.nonce));
+                                    crate::log!(self.dialog, Subsystem::Peers, format!("[{}]: version", peer_thread.nonce));
                                 }
                                 PeerMessage::Addr(addresses) => self.handle_new_addrs(addresses).await,
                                 PeerMessage::Headers(headers) => {
-                                    last_block.reset();
-                                    crate::log!(self.dialog, format!("[{}]: headers", peer_thread.nonce));
+                                    if !headers.is_empty() {
+                                        last_block.reset();
+                                    }
+                                    crate::log!(self.dialog, Subsystem::Peers, format!("[{}]: headers", peer_thread.nonce));
                                     match self.handle_headers(peer_thread.nonce, headers).await {
                                         Some(response) => {
                                             self.send_message(peer_thread.nonce, response).await;
@@ -196,7 +447,7 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                     }
                                 }
                                 PeerMessage::FilterHeaders(cf_headers) => {
-                                    crate::log!(self.dialog, format!("[{}]: filter headers", peer_thread.nonce));
+                                    crate::log!(self.dialog, Subsystem::Peers, format!("[{}]: filter headers", peer_thread.nonce));
                                     match self.handle_cf_headers(peer_thread.nonce, cf_headers).await {
                                         Some(response) => {
                                             self.broadcast(response).await;
@@ -219,7 +470,7 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                     None => continue,
                                 },
                                 PeerMessage::NewBlocks(blocks) => {
-                                    crate::log!(self.dialog, format!("[{}]: inv", peer_thread.nonce));
+                                    crate::log!(self.dialog, Subsystem::Peers, format!("[{}]: inv", peer_thread.nonce));
                                     match self.handle_inventory_blocks(peer_thread.nonce, blocks).await {
                                         Some(response) => {
                                             self.broadcast(response).await;
@@ -231,32 +482,68 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                     let mut peer_map = self.peer_map.lock().await;
                                     peer_map.set_broadcast_min(peer_thread.nonce, feerate);
                                 }
+                                PeerMessage::TxAnnounced(inventory) => {
+                                    if let Some(response) = self.handle_inventory_transactions(inventory).await {
+                                        self.send_message(peer_thread.nonce, response).await;
+                                    }
+                                }
+                                PeerMessage::Transaction(transaction) => self.handle_transaction(transaction).await,
+                                PeerMessage::Misbehaved(misbehavior) => {
+                                    self.peer_map.lock().await.penalize(peer_thread.nonce, misbehavior).await;
+                                    self.send_message(peer_thread.nonce, MainThreadMessage::Disconnect).await;
+                                }
                             }
                         },
                         _ => continue,
                     }
                 },
+                fetched = next_fetched_block(&mut fetched_blocks) => self.handle_fetched_block(fetched).await,
                 message = client_recv.recv() => {
                     if let Some(message) = message {
                         match message {
-                            ClientMessage::Shutdown => return Ok(()),
-                            ClientMessage::Broadcast(transaction) => self.tx_broadcaster.lock().await.add(transaction),
-                            ClientMessage::AddScript(script) =>  self.add_script(script).await,
-                            ClientMessage::Rescan => {
-                                if let Some(response) = self.rescan().await {
-                                    self.broadcast(response).await;
+                            ClientMessage::Shutdown(request) => {
+                                let report = self.shutdown().await;
+                                if let Some(request) = request {
+                                    if request.send(report).is_err() {
+                                        self.dialog.send_warning(Warning::ChannelDropped);
+                                    }
                                 }
+                                return Ok(());
                             },
-                            #[cfg(feature = "filter-control")]
+                            ClientMessage::Broadcast(transaction) => self.add_broadcast(transaction).await,
+                            ClientMessage::AddScript(script) =>  self.add_script(script).await,
+                            #[cfg(not(feature = "filter-control"))]
+                            ClientMessage::RemoveScripts(scripts) => self.chain.lock().await.remove_scripts(scripts),
+                            #[cfg(not(feature = "filter-control"))]
+                            ClientMessage::AddOutpoints(outpoints) => self.chain.lock().await.put_outpoints(outpoints),
+                            #[cfg(not(feature = "filter-control"))]
+                            ClientMessage::AddAddresses(request) => {
+                                let mut chain = self.chain.lock().await;
+                                let send_result = request.oneshot.send(chain.put_addresses(request.addresses));
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::Rescan => self.rescan().await,
+                            ClientMessage::CancelRescan => self.cancel_rescan().await,
+                            ClientMessage::CompactDatabase => self.compaction_pending.store(true, Ordering::Relaxed),
                             ClientMessage::GetBlock(hash) => {
                                 let mut state = self.state.write().await;
                                 if matches!(*state, NodeState::TransactionsSynced) {
-                                    *state = NodeState::FiltersSynced
+                                    self.transition(&mut state, NodeState::FiltersSynced).await;
                                 }
                                 drop(state);
                                 let mut chain = self.chain.lock().await;
                                 chain.get_block(hash).await;
                             },
+                            ClientMessage::UpdateConfig(request) => {
+                                let send_result = request.oneshot.send(self.update_config(request.delta).await);
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            #[cfg(feature = "journal")]
+                            ClientMessage::AcknowledgeEvents(count) => self.dialog.acknowledge_events(count),
                             ClientMessage::SetDuration(duration) => {
                                 let mut peer_map = self.peer_map.lock().await;
                                 peer_map.set_duration(duration);
@@ -265,6 +552,7 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                 let mut peer_map = self.peer_map.lock().await;
                                 peer_map.add_trusted_peer(peer);
                             },
+                            ClientMessage::BanPeer(addr) => self.peer_map.lock().await.ban_addr(addr).await,
                             ClientMessage::GetHeader(request) => {
                                 let mut chain = self.chain.lock().await;
                                 let header_opt = chain.fetch_header(request.height).await.map_err(|e| FetchHeaderError::DatabaseOptFailed { error: e.to_string() }).and_then(|opt| opt.ok_or(FetchHeaderError::UnknownHeight));
@@ -273,6 +561,22 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                     self.dialog.send_warning(Warning::ChannelDropped);
                                 };
                             },
+                            ClientMessage::GetHeaderByHash(request) => {
+                                let mut chain = self.chain.lock().await;
+                                let header_opt = chain.fetch_header_by_hash(request.hash).await.map_err(|e| FetchHeaderError::DatabaseOptFailed { error: e.to_string() }).and_then(|opt| opt.ok_or(FetchHeaderError::UnknownHash));
+                                let send_result = request.oneshot.send(header_opt);
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::AttestHeader(request) => {
+                                let chain = self.chain.lock().await;
+                                let attestation = chain.attest(request.hash).ok_or(FetchHeaderError::UnknownHash);
+                                let send_result = request.oneshot.send(attestation);
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
                             ClientMessage::GetHeaderBatch(request) => {
                                 let chain = self.chain.lock().await;
                                 let range_opt = chain.fetch_header_range(request.range).await.map_err(|e| FetchHeaderError::DatabaseOptFailed { error: e.to_string() });
@@ -289,6 +593,111 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                                     self.dialog.send_warning(Warning::ChannelDropped);
                                 };
                             }
+                            ClientMessage::GetSyncReport(request) => {
+                                let report = self.sync_report().await;
+                                let send_result = request.send(report);
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetStateHistory(request) => {
+                                let history = self.state_history.lock().await;
+                                let send_result = request.send(history.iter().copied().collect());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetConnectedPeers(request) => {
+                                let peer_map = self.peer_map.lock().await;
+                                let send_result = request.send(peer_map.connected_peers());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetPendingRequests(request) => {
+                                let peer_map = self.peer_map.lock().await;
+                                let send_result = request.send(peer_map.pending_requests());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetPeerStates(request) => {
+                                let peer_map = self.peer_map.lock().await;
+                                let send_result = request.send(peer_map.peer_states());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetPeerDiversity(request) => {
+                                let peer_map = self.peer_map.lock().await;
+                                let send_result = request.send(peer_map.peer_diversity());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetTimeOffset(request) => {
+                                let peer_map = self.peer_map.lock().await;
+                                let send_result = request.send(peer_map.median_time_adjustment());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::TestConnectivity(request) => {
+                                let checks = self.peer_map.lock().await.connectivity_checks().await;
+                                let dialog = Arc::clone(&self.dialog);
+                                tokio::spawn(async move {
+                                    if request.send(checks.await).is_err() {
+                                        dialog.send_warning(Warning::ChannelDropped);
+                                    }
+                                });
+                            },
+                            ClientMessage::GetScriptShardStats(request) => {
+                                let chain = self.chain.lock().await;
+                                let send_result = request.send(chain.script_shard_stats());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetScanStats(request) => {
+                                let chain = self.chain.lock().await;
+                                let send_result = request.send(chain.scan_stats());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::GetScriptHistory(request) => {
+                                let chain = self.chain.lock().await;
+                                let history = chain.script_history(&request.script);
+                                let send_result = request.oneshot.send(history);
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::ListUnspent(request) => {
+                                let chain = self.chain.lock().await;
+                                let send_result = request.send(chain.unspent());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::ChainView(request) => {
+                                let send_result = request.send(self.view.clone());
+                                if send_result.is_err() {
+                                    self.dialog.send_warning(Warning::ChannelDropped);
+                                };
+                            },
+                            ClientMessage::NotifyAtHeight(trigger) => self.chain.lock().await.notify_at_height(trigger),
+                            ClientMessage::NotifyConfirmations(trigger) => self.chain.lock().await.notify_confirmations(trigger),
+                            #[cfg(feature = "testing")]
+                            ClientMessage::SimulateReorg(reorg) => {
+                                for event in reorg.into_events() {
+                                    self.dialog.send_event(event);
+                                }
+                            },
+                            ClientMessage::BlockStream(stream) => {
+                                let mut chain = self.chain.lock().await;
+                                chain.set_block_stream(stream);
+                            },
                             ClientMessage::NoOp => (),
                         }
                     }
@@ -309,10 +718,10 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         peer_map.broadcast(message).await;
     }
 
-    // Send a message to a random peer
-    async fn send_random(&self, message: MainThreadMessage) {
+    // Request a block from a peer chosen by the peer selector
+    async fn request_block(&self, message: MainThreadMessage) {
         let mut peer_map = self.peer_map.lock().await;
-        peer_map.send_random(message).await;
+        peer_map.request_block(message).await;
     }
 
     // Connect to a new peer if we are not connected to enough
@@ -335,14 +744,93 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         Ok(())
     }
 
-    // If there are blocks in the queue, we should request them of a random peer
+    // Request the blocks in the queue, up to the number of blocks allowed in flight
     async fn get_blocks(&self) {
-        if let Some(block_request) = self.pop_block_queue().await {
-            crate::log!(self.dialog, "Sending block request to random peer");
-            self.send_random(block_request).await;
+        while let Some(block_hash) = self.pop_block_queue().await {
+            #[cfg(feature = "rest")]
+            if let Some(fetcher) = self.block_fetcher.lock().await.as_mut() {
+                if fetcher.fetch(block_hash) {
+                    crate::log!(
+                        self.dialog,
+                        Subsystem::Chain,
+                        "Requesting block from the configured block source"
+                    );
+                    continue;
+                }
+            }
+            crate::log!(
+                self.dialog,
+                Subsystem::Chain,
+                "Sending block request to a selected peer"
+            );
+            self.request_block(MainThreadMessage::GetBlock(GetBlockConfig {
+                locator: block_hash,
+            }))
+            .await;
         }
     }
 
+    // Fetch blocks from the configured block source on a task of its own
+    #[cfg(feature = "rest")]
+    async fn start_block_fetcher(&self) -> Option<UnboundedReceiver<FetchedBlock>> {
+        let source = self.block_source.lock().await.take()?;
+        let (fetcher, fetched_blocks) = BlockFetcher::spawn(source);
+        *self.block_fetcher.lock().await = Some(fetcher);
+        Some(fetched_blocks)
+    }
+
+    // Check a block fetched from the block source, requesting it from a peer if the source did not
+    // provide a valid block
+    #[cfg(feature = "rest")]
+    async fn handle_fetched_block(&self, (block_hash, fetched): FetchedBlock) {
+        if let Some(fetcher) = self.block_fetcher.lock().await.as_mut() {
+            fetcher.answered(&block_hash);
+        }
+        match fetched {
+            Ok(block) => {
+                self.sync_report.lock().await.blocks.bytes += block.total_size() as u64;
+                let mut chain = self.chain.lock().await;
+                let confirmed = self.confirmed_broadcasts(&chain, &block).await;
+                match chain.check_send_block(block) {
+                    Ok(_) => {
+                        if let Some(confirmed) = confirmed {
+                            self.confirm_broadcasts(confirmed).await;
+                        }
+                        chain.write_scan_results().await;
+                        return;
+                    }
+                    Err(e) => self.dialog.send_warning(Warning::UnexpectedSyncError {
+                        warning: format!("Unexpected block scanning error: {e}"),
+                    }),
+                }
+            }
+            Err(e) => {
+                crate::log!(
+                    self.dialog,
+                    Subsystem::Chain,
+                    format!(
+                        "Block source failed to provide block {}: {e}",
+                        self.dialog.redact(block_hash)
+                    )
+                );
+            }
+        }
+        crate::log!(
+            self.dialog,
+            Subsystem::Chain,
+            "Sending block request to a selected peer"
+        );
+        self.request_block(MainThreadMessage::GetBlock(GetBlockConfig {
+            locator: block_hash,
+        }))
+        .await;
+    }
+
+    #[cfg(not(feature = "rest"))]
+    async fn handle_fetched_block(&self, fetched: Infallible) {
+        match fetched {}
+    }
+
     // Broadcast transactions according to the configured policy
     async fn broadcast_transactions(&self) {
         let mut broadcaster = self.tx_broadcaster.lock().await;
@@ -350,13 +838,14 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
             return;
         }
         let mut peer_map = self.peer_map.lock().await;
-        if peer_map.live().ge(&self.required_peers) {
+        if peer_map.live().ge(&self.required_peers()) {
             for transaction in broadcaster.queue() {
                 let txid = transaction.tx.compute_txid();
                 let did_broadcast = match transaction.broadcast_policy {
                     TxBroadcastPolicy::AllPeers => {
                         crate::log!(
                             self.dialog,
+                            Subsystem::Peers,
                             format!("Sending transaction to {} connected peers", peer_map.live())
                         );
                         peer_map
@@ -364,9 +853,13 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                             .await
                     }
                     TxBroadcastPolicy::RandomPeer => {
-                        crate::log!(self.dialog, "Sending transaction to a random peer");
+                        crate::log!(
+                            self.dialog,
+                            Subsystem::Peers,
+                            "Sending transaction to a random peer"
+                        );
                         peer_map
-                            .send_random(MainThreadMessage::BroadcastTx(transaction.tx))
+                            .send_selected(MainThreadMessage::BroadcastTx(transaction.tx))
                             .await
                     }
                 };
@@ -379,6 +872,90 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         }
     }
 
+    // Queue the transactions that were broadcast and not confirmed when the node last stopped, and
+    // track the transactions confirmed recently in case their block is reorganized
+    async fn load_broadcasts(&self) {
+        let broadcasts = self.chain.lock().await.load_broadcasts().await;
+        let mut broadcaster = self.tx_broadcaster.lock().await;
+        let expired = broadcaster.load(broadcasts);
+        if !broadcaster.is_empty() {
+            crate::log!(
+                self.dialog,
+                Subsystem::Peers,
+                format!(
+                    "Broadcasting {} unconfirmed transactions again",
+                    broadcaster.len()
+                )
+            );
+        }
+        drop(broadcaster);
+        for txid in expired {
+            crate::log!(
+                self.dialog,
+                Subsystem::Peers,
+                format!(
+                    "Transaction {} was sent too many times without confirming",
+                    self.dialog.redact(txid)
+                )
+            );
+        }
+    }
+
+    // Queue a transaction from the client, which replaces the transactions spending its inputs
+    async fn add_broadcast(&self, transaction: TxBroadcast) {
+        let replaced = self.tx_broadcaster.lock().await.add(transaction);
+        for txid in replaced {
+            crate::log!(
+                self.dialog,
+                Subsystem::Peers,
+                format!("Transaction {} was replaced", self.dialog.redact(txid))
+            );
+        }
+    }
+
+    // Stop sending the transactions found in a block, along with the transactions it conflicts with
+    async fn confirm_broadcasts(&self, confirmation: Confirmation) {
+        let conflicts = self.tx_broadcaster.lock().await.confirm(confirmation);
+        for txid in conflicts {
+            crate::log!(
+                self.dialog,
+                Subsystem::Peers,
+                format!(
+                    "Transaction {} conflicts with a block",
+                    self.dialog.redact(txid)
+                )
+            );
+        }
+    }
+
+    // Write the broadcast transactions if any were added or confirmed
+    async fn persist_broadcasts(&self) {
+        let broadcasts = match self.tx_broadcaster.lock().await.take_changed() {
+            Some(broadcasts) => broadcasts,
+            None => return,
+        };
+        let mut chain = self.chain.lock().await;
+        chain.write_broadcasts(broadcasts).await;
+    }
+
+    // The unconfirmed transactions in a block the node asked for, which are confirmed once the
+    // block is checked against its header
+    async fn confirmed_broadcasts(&self, chain: &Chain<H>, block: &Block) -> Option<Confirmation> {
+        let height = chain.requested_height(&block.block_hash())?;
+        Some(self.tx_broadcaster.lock().await.confirmed_in(height, block))
+    }
+
+    // Broadcast the transactions confirmed in blocks that were reorganized out of the chain again
+    async fn unconfirm_broadcasts(&self, disconnected: Vec<BlockHash>) {
+        if disconnected.is_empty() {
+            return;
+        }
+        let unconfirmed = self.tx_broadcaster.lock().await.unconfirm(&disconnected);
+        for txid in unconfirmed {
+            self.dialog.send_event(Event::TxUnconfirmedByReorg(txid));
+        }
+    }
+
     // Try to continue with the syncing process
     async fn advance_state(&self, last_block: &mut LastBlockMonitor) {
         let mut state = self.state.write().await;
@@ -386,42 +963,74 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
             NodeState::Behind => {
                 let header_chain = self.chain.lock().await;
                 if header_chain.is_synced().await {
-                    crate::info!(self.dialog, Info::StateChange(NodeState::HeadersSynced));
-                    *state = NodeState::HeadersSynced;
+                    if self.headers_only {
+                        if !header_chain.is_tip_agreed(self.tip_agreement).await {
+                            return;
+                        }
+                        // There are no filters or blocks to sync, so the node is at the tip
+                        self.transition(&mut state, NodeState::TransactionsSynced)
+                            .await;
+                        let update = SyncUpdate::new(
+                            HeaderCheckpoint::new(
+                                header_chain.header_chain.height(),
+                                header_chain.header_chain.tip_hash(),
+                            ),
+                            header_chain.recent_history(),
+                        );
+                        self.dialog.send_event(Event::Synced(update));
+                    } else {
+                        self.transition(&mut state, NodeState::HeadersSynced).await;
+                    }
                 }
             }
             NodeState::HeadersSynced => {
                 let header_chain = self.chain.lock().await;
                 if header_chain.is_cf_headers_synced() {
-                    crate::info!(
-                        self.dialog,
-                        Info::StateChange(NodeState::FilterHeadersSynced)
-                    );
-                    *state = NodeState::FilterHeadersSynced;
+                    self.transition(&mut state, NodeState::FilterHeadersSynced)
+                        .await;
                 }
             }
             NodeState::FilterHeadersSynced => {
-                let header_chain = self.chain.lock().await;
-                if header_chain.is_filters_synced() {
-                    crate::info!(self.dialog, Info::StateChange(NodeState::FiltersSynced));
-                    *state = NodeState::FiltersSynced;
+                let mut chain = self.chain.lock().await;
+                if chain.is_filters_synced() {
+                    self.transition(&mut state, NodeState::FiltersSynced).await;
+                } else {
+                    // Peers that connected or finished their range take the next range
+                    self.request_filters(&mut chain).await;
                 }
             }
             NodeState::FiltersSynced => {
-                let chain = self.chain.lock().await;
-                if chain.block_queue_empty() {
-                    *state = NodeState::TransactionsSynced;
+                let mut chain = self.chain.lock().await;
+                // Scripts derived from a matched block are checked against the filters after it
+                if !chain.is_filters_synced() {
+                    self.transition(&mut state, NodeState::FilterHeadersSynced)
+                        .await;
+                    self.request_filters(&mut chain).await;
+                    return;
+                }
+                if chain.block_queue_empty() && chain.finish_coarse_scan() {
+                    crate::log!(
+                        self.dialog,
+                        Subsystem::Chain,
+                        "Checking filters for every derived script from the first active block"
+                    );
+                    self.transition(&mut state, NodeState::FilterHeadersSynced)
+                        .await;
+                    self.request_filters(&mut chain).await;
+                    return;
+                }
+                if chain.block_queue_empty() && chain.is_tip_agreed(self.tip_agreement).await {
+                    chain.rescan_complete();
+                    self.transition(&mut state, NodeState::TransactionsSynced)
+                        .await;
                     let update = SyncUpdate::new(
                         HeaderCheckpoint::new(
                             chain.header_chain.height(),
                             chain.header_chain.tip_hash(),
                         ),
-                        chain.last_ten(),
-                    );
-                    crate::info!(
-                        self.dialog,
-                        Info::StateChange(NodeState::TransactionsSynced)
-                    );
+                        chain.recent_history(),
+                    )
+                    .with_filters(chain.tip_filter(), chain.anchor_filter());
                     self.dialog.send_event(Event::Synced(update));
                 }
             }
@@ -430,21 +1039,209 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                     self.dialog.send_warning(Warning::PotentialStaleTip);
                     crate::log!(
                         self.dialog,
+                        Subsystem::Peers,
                         "Disconnecting from remote nodes to find new connections"
                     );
                     self.broadcast(MainThreadMessage::Disconnect).await;
                     last_block.reset();
+                } else if last_block.poll_due() {
+                    let chain = self.chain.lock().await;
+                    let next_headers = GetHeaderConfig {
+                        locators: chain.header_chain.locators(),
+                        stop_hash: None,
+                    };
+                    self.broadcast(MainThreadMessage::GetHeaders(next_headers))
+                        .await;
                 }
             }
         }
     }
 
+    // Disconnect from the peers we are waiting on and request the next data again if the sync
+    // has not progressed
+    async fn recover_stall(&self, watchdog: &mut StallWatchdog) {
+        let state = self.state.read().await;
+        let mut chain = self.chain.lock().await;
+        let mut peer_map = self.peer_map.lock().await;
+        // Following the tip is covered by the stale tip check, and connections are handled separately
+        if matches!(*state, NodeState::TransactionsSynced) || peer_map.live().eq(&0) {
+            watchdog.progressed();
+            return;
+        }
+        let stalled_for = match watchdog.stalled() {
+            Some(stalled_for) => stalled_for,
+            None => return,
+        };
+        watchdog.progressed();
+        let waiting_on = peer_map.waiting_on();
+        for nonce in waiting_on.iter() {
+            peer_map
+                .send_message(*nonce, MainThreadMessage::Disconnect)
+                .await;
+        }
+        drop(peer_map);
+        chain.clear_compact_filter_queue();
+        if let Some(message) = self.next_stateful_message(chain.deref_mut()).await {
+            self.broadcast(message).await;
+        }
+        crate::info!(
+            self.dialog,
+            Subsystem::Node,
+            Info::StallRecovered {
+                state: *state,
+                stalled_for,
+                peers_rotated: waiting_on.len(),
+            }
+        );
+    }
+
+    // Replace peers that do not answer, and send the request they were holding to the others
+    async fn replace_unresponsive_peers(&self) {
+        let state = self.state.read().await;
+        let mut chain = self.chain.lock().await;
+        let mut peer_map = self.peer_map.lock().await;
+        if peer_map.replace_unresponsive().await == 0
+            || matches!(*state, NodeState::TransactionsSynced)
+        {
+            return;
+        }
+        chain.clear_compact_filter_queue();
+        if let Some(message) = self.next_stateful_message(chain.deref_mut()).await {
+            peer_map.broadcast(message).await;
+        }
+    }
+
+    // Move to a new state, recording when the state was entered
+    async fn transition(&self, state: &mut NodeState, new_state: NodeState) {
+        *state = new_state;
+        let mut history = self.state_history.lock().await;
+        if let Some(last) = history.back() {
+            let mut report = self.sync_report.lock().await;
+            if let Some(phase) = report.phase_mut(last.state) {
+                phase.elapsed += last.entered.elapsed().unwrap_or_default();
+            }
+        }
+        if history.len() == STATE_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(StateTransition::new(new_state));
+        drop(history);
+        crate::info!(self.dialog, Subsystem::Node, Info::StateChange(new_state));
+    }
+
+    // Send what each peer is doing if it changed since the last report
+    async fn report_peer_states(&self) {
+        let changed = self.peer_map.lock().await.changed_peer_states();
+        for state in changed {
+            crate::info!(self.dialog, Subsystem::Peers, Info::PeerState(state));
+        }
+    }
+
+    // Compact the header and peer stores if the client requested it and the node is synced
+    async fn compact_databases(&self) {
+        let state = self.state.read().await;
+        if !matches!(*state, NodeState::TransactionsSynced)
+            || !self.compaction_pending.swap(false, Ordering::Relaxed)
+        {
+            return;
+        }
+        let started = Instant::now();
+        let chain = self.chain.lock().await;
+        if let Err(e) = chain.compact_database().await {
+            self.dialog.send_warning(Warning::FailedPersistence {
+                warning: format!("Could not compact the header store: {e}"),
+            });
+            return;
+        }
+        drop(chain);
+        let mut peer_map = self.peer_map.lock().await;
+        if let Err(e) = peer_map.compact_database().await {
+            self.dialog.send_warning(Warning::FailedPersistence {
+                warning: format!("Could not compact the peer store: {e}"),
+            });
+            return;
+        }
+        self.dialog
+            .check_database_latency("compact databases", started);
+        crate::info!(self.dialog, Subsystem::Database, Info::DatabaseCompacted);
+    }
+
+    // The time spent in each phase, including the time in the current phase so far
+    async fn sync_report(&self) -> SyncReport {
+        let history = self.state_history.lock().await;
+        let mut report = *self.sync_report.lock().await;
+        if let Some(last) = history.back() {
+            if let Some(phase) = report.phase_mut(last.state) {
+                phase.elapsed += last.entered.elapsed().unwrap_or_default();
+            }
+        }
+        report
+    }
+
+    // Tally the bytes received for each phase of the sync
+    async fn record_bytes(&self, message: &PeerMessage) {
+        let mut report = self.sync_report.lock().await;
+        match message {
+            PeerMessage::Headers(headers) => {
+                report.headers.bytes += (headers.len() * HEADER_WIRE_SIZE) as u64;
+            }
+            PeerMessage::FilterHeaders(cf_headers) => {
+                let num_hashes = cf_headers.filter_hashes.len();
+                let size =
+                    CF_HEADERS_FIXED_SIZE + VarInt(num_hashes as u64).size() + num_hashes * 32;
+                report.filter_headers.bytes += size as u64;
+            }
+            PeerMessage::Filter(filter) => {
+                let len = filter.filter.len();
+                let size = CFILTER_FIXED_SIZE + VarInt(len as u64).size() + len;
+                report.filters.bytes += size as u64;
+            }
+            PeerMessage::Block(block) => {
+                report.blocks.bytes += block.total_size() as u64;
+            }
+            _ => (),
+        }
+    }
+
+    // The number of connections to maintain once the headers are synced
+    fn required_peers(&self) -> PeerRequirement {
+        self.required_peers.load(Ordering::Relaxed)
+    }
+
+    // Apply changes to the configuration made while the node is running
+    async fn update_config(&self, delta: ConfigDelta) -> Result<(), UpdateConfigError> {
+        if let Some(required_peers) = delta.required_peers {
+            if !tip_agreement_reachable(self.tip_agreement, required_peers) {
+                return Err(UpdateConfigError::TipAgreement {
+                    agreeing: self.tip_agreement,
+                    required_peers,
+                });
+            }
+        }
+        self.dialog
+            .set_levels(delta.log_level, delta.subsystem_log_levels.clone());
+        if let Some(required_peers) = delta.required_peers {
+            self.required_peers
+                .store(required_peers.into(), Ordering::Relaxed);
+            let mut chain = self.chain.lock().await;
+            chain.set_required_peers(required_peers);
+        }
+        let mut peer_map = self.peer_map.lock().await;
+        peer_map.update_timeouts(&delta);
+        crate::log!(
+            self.dialog,
+            Subsystem::Node,
+            "Applied an update to the configuration"
+        );
+        Ok(())
+    }
+
     // When syncing headers we are only interested in one peer to start
     async fn next_required_peers(&self) -> PeerRequirement {
         let state = self.state.read().await;
         match *state {
             NodeState::Behind => 1,
-            _ => self.required_peers,
+            _ => self.required_peers(),
         }
     }
 
@@ -457,16 +1254,59 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                 stop_hash: None,
             };
             return Some(MainThreadMessage::GetHeaders(headers));
+        } else if self.headers_only {
+            return None;
         } else if !chain.is_cf_headers_synced() {
-            return Some(MainThreadMessage::GetFilterHeaders(
-                chain.next_cf_header_message(),
-            ));
+            let get_filter_headers = chain.next_cf_header_message();
+            crate::info!(
+                self.dialog,
+                Subsystem::Filters,
+                Info::FilterHeadersRequested {
+                    start_height: get_filter_headers.start_height,
+                    stop_hash: get_filter_headers.stop_hash,
+                }
+            );
+            return Some(MainThreadMessage::GetFilterHeaders(get_filter_headers));
         } else if !chain.is_filters_synced() {
-            return Some(MainThreadMessage::GetFilters(chain.next_filter_message()));
+            self.request_filters(chain).await;
         }
         None
     }
 
+    // Check the next filters from the filter store, and split the filters it does not have across
+    // the peers that serve them
+    async fn request_filters(&self, chain: &mut Chain<H>) {
+        self.request_filters_excluding(chain, &[]).await;
+    }
+
+    // Request the next ranges of filters from the peers that are not excluded
+    async fn request_filters_excluding(&self, chain: &mut Chain<H>, exclude: &[PeerId]) {
+        chain.check_stored_filters().await;
+        let mut peer_map = self.peer_map.lock().await;
+        let peers = peer_map
+            .filter_peers()
+            .into_iter()
+            .filter(|peer| !exclude.contains(peer))
+            .collect::<Vec<PeerId>>();
+        for (nonce, get_filters) in chain.filter_requests(&peers) {
+            let message = self.filter_request(get_filters).await;
+            peer_map.send_message(nonce, message).await;
+        }
+    }
+
+    // Inform the client of the range of filters being requested
+    async fn filter_request(&self, get_filters: GetCFilters) -> MainThreadMessage {
+        crate::info!(
+            self.dialog,
+            Subsystem::Filters,
+            Info::FiltersRequested {
+                start_height: get_filters.start_height,
+                stop_hash: get_filters.stop_hash,
+            }
+        );
+        MainThreadMessage::GetFilters(get_filters)
+    }
+
     // We accepted a handshake with a peer but we may disconnect if they do not support CBF
     async fn handle_version(
         &self,
@@ -476,18 +1316,56 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         if version_message.version < WTXID_VERSION {
             return Ok(MainThreadMessage::Disconnect);
         }
+        if let Some(max_lag) = self.max_peer_lag {
+            let expected_height = self.chain.lock().await.known_height();
+            let peer_height = version_message.start_height.max(0) as u32;
+            if peer_height.saturating_add(max_lag) < expected_height {
+                self.dialog.send_warning(Warning::PeerBehind {
+                    peer_height,
+                    expected_height,
+                });
+                return Ok(MainThreadMessage::Disconnect);
+            }
+        }
+        let peer_height = version_message.start_height.max(0) as u32;
+        self.chain
+            .lock()
+            .await
+            .check_checkpoint_freshness(peer_height);
         let state = self.state.read().await;
         match *state {
             NodeState::Behind => (),
+            _ if self.headers_only => (),
             _ => {
                 if !version_message.services.has(ServiceFlags::COMPACT_FILTERS)
                     || !version_message.services.has(ServiceFlags::NETWORK)
                 {
                     self.dialog.send_warning(Warning::NoCompactFilters);
+                    let attempts = self.peers_without_filters.fetch_add(1, Ordering::Relaxed) + 1;
+                    match self.missing_filters {
+                        MissingFiltersPolicy::Wait => (),
+                        MissingFiltersPolicy::Search => {
+                            crate::log!(
+                                self.dialog,
+                                Subsystem::Peers,
+                                "Searching for peers that serve compact block filters"
+                            );
+                            let mut peer_map = self.peer_map.lock().await;
+                            peer_map.search_for_addresses(nonce).await?;
+                        }
+                        MissingFiltersPolicy::Fail(max_attempts) => {
+                            if attempts >= max_attempts {
+                                return Err(NodeError::NoFilterPeers { attempts });
+                            }
+                        }
+                    }
                     return Ok(MainThreadMessage::Disconnect);
                 }
             }
         }
+        if version_message.services.has(ServiceFlags::COMPACT_FILTERS) {
+            self.peers_without_filters.store(0, Ordering::Relaxed);
+        }
         let mut peer_map = self.peer_map.lock().await;
         peer_map.tried(nonce).await;
         let needs_peers = peer_map.need_peers().await?;
@@ -502,16 +1380,26 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         peer_map
             .send_message(nonce, MainThreadMessage::Verack)
             .await;
+        // Ask for new blocks to be announced with headers instead of inventory (BIP 130)
+        peer_map
+            .send_message(nonce, MainThreadMessage::SendHeaders)
+            .await;
+        // Headers may arrive sooner if blocks are pushed before they are fully validated (BIP 152)
+        if self.compact_block_announcements {
+            peer_map
+                .send_message(nonce, MainThreadMessage::SendCompactBlocks)
+                .await;
+        }
         // Now we may request peers if required
         if needs_peers {
-            crate::log!(self.dialog, "Requesting new addresses");
+            crate::log!(self.dialog, Subsystem::Peers, "Requesting new addresses");
             peer_map
                 .send_message(nonce, MainThreadMessage::GetAddr)
                 .await;
         }
         // Inform the user we are connected to all required peers
-        if peer_map.live().eq(&self.required_peers) {
-            crate::info!(self.dialog, Info::ConnectionsMet);
+        if peer_map.live().eq(&self.required_peers()) {
+            crate::info!(self.dialog, Subsystem::Peers, Info::ConnectionsMet);
         }
         // Even if we start the node as caught up in terms of height, we need to check for reorgs. So we can send this unconditionally.
         let chain = self.chain.lock().await;
@@ -526,6 +1414,7 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
     async fn handle_new_addrs(&self, new_peers: Vec<CombinedAddr>) {
         crate::log!(
             self.dialog,
+            Subsystem::Peers,
             format!("Adding {} new peers to the peer database", new_peers.len())
         );
         let mut peer_map = self.peer_map.lock().await;
@@ -538,8 +1427,14 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         peer_id: PeerId,
         headers: Vec<Header>,
     ) -> Option<MainThreadMessage> {
+        let mut state = self.state.write().await;
         let mut chain = self.chain.lock().await;
-        if let Err(e) = chain.sync_chain(headers).await {
+        let tip = chain.header_chain.tip_hash();
+        let announcement = headers.len() <= MAX_ANNOUNCED_HEADERS;
+        chain.set_time_offset(self.peer_map.lock().await.median_time_adjustment());
+        let synced = chain.sync_chain(headers).await;
+        self.unconfirm_broadcasts(chain.take_disconnected()).await;
+        if let Err(e) = synced {
             match e {
                 HeaderSyncError::EmptyMessage => {
                     if !chain.is_synced().await {
@@ -547,16 +1442,48 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                     }
                     return self.next_stateful_message(chain.deref_mut()).await;
                 }
+                // An announced block may build on blocks we have not seen yet
+                HeaderSyncError::FloatingHeaders if announcement => {
+                    let next_headers = GetHeaderConfig {
+                        locators: chain.header_chain.locators(),
+                        stop_hash: None,
+                    };
+                    return Some(MainThreadMessage::GetHeaders(next_headers));
+                }
+                // The local clock may be behind, so the peer is not at fault
+                HeaderSyncError::FutureHeaders => {
+                    crate::log!(
+                        self.dialog,
+                        Subsystem::Chain,
+                        format!("Deferring headers from peer {peer_id}: {e}")
+                    );
+                    return None;
+                }
                 _ => {
                     self.dialog.send_warning(Warning::UnexpectedSyncError {
                         warning: format!("Unexpected header syncing error: {e}"),
                     });
                     let mut lock = self.peer_map.lock().await;
-                    lock.ban(peer_id).await;
+                    lock.penalize(peer_id, Misbehavior::InvalidHeaders).await;
                     return Some(MainThreadMessage::Disconnect);
                 }
             }
         }
+        if chain.header_chain.tip_hash().ne(&tip) {
+            // Headers that were not requested during sync, such as a block announcement or a
+            // response to a tip poll, mean the filters must catch up to the new tip
+            if !matches!(*state, NodeState::Behind) {
+                self.transition(&mut state, NodeState::Behind).await;
+                chain.clear_compact_filter_queue();
+            }
+            if chain.is_synced().await {
+                let new_tip = HeaderCheckpoint::new(
+                    chain.header_chain.height(),
+                    chain.header_chain.tip_hash(),
+                );
+                crate::info!(self.dialog, Subsystem::Chain, Info::NewTip(new_tip));
+            }
+        }
         self.next_stateful_message(chain.deref_mut()).await
     }
 
@@ -571,12 +1498,51 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         match chain.sync_cf_headers(peer_id, cf_headers) {
             Ok(potential_message) => match potential_message {
                 CFHeaderChanges::AddedToQueue => None,
-                CFHeaderChanges::Extended => self.next_stateful_message(chain.deref_mut()).await,
+                CFHeaderChanges::Extended => {
+                    chain.write_cf_headers().await;
+                    if let Some(filter) = chain.take_early_filter() {
+                        if let Err(e) = chain.sync_filter(filter) {
+                            self.dialog.send_warning(Warning::UnexpectedSyncError {
+                                warning: format!(
+                                    "Compact filter syncing encountered an error: {e}"
+                                ),
+                            });
+                        }
+                    }
+                    self.next_stateful_message(chain.deref_mut()).await
+                }
                 CFHeaderChanges::Conflict => {
                     self.dialog.send_warning(Warning::UnexpectedSyncError {
                         warning: "Found a conflict while peers are sending filter headers".into(),
                     });
-                    Some(MainThreadMessage::Disconnect)
+                    let (peers, get_filters, block_hash) = match chain.dispute_requests() {
+                        Some(requests) => requests,
+                        None => return Some(MainThreadMessage::Disconnect),
+                    };
+                    crate::log!(
+                        self.dialog,
+                        Subsystem::Filters,
+                        format!(
+                            "Downloading block {} to find the peer sending invalid filter headers",
+                            get_filters.start_height
+                        )
+                    );
+                    let mut peer_map = self.peer_map.lock().await;
+                    for peer in &peers {
+                        let get_filters = self.filter_request(get_filters.clone()).await;
+                        peer_map.send_message(*peer, get_filters).await;
+                    }
+                    // A peer in the dispute could withhold the block, so another peer is asked
+                    let get_block = MainThreadMessage::GetBlock(GetBlockConfig {
+                        locator: block_hash,
+                    });
+                    if !peer_map
+                        .send_selected_excluding(get_block.clone(), &peers)
+                        .await
+                    {
+                        peer_map.send_selected(get_block).await;
+                    }
+                    None
                 }
             },
             Err(e) => {
@@ -584,7 +1550,8 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                     warning: format!("Compact filter header syncing encountered an error: {e}"),
                 });
                 let mut lock = self.peer_map.lock().await;
-                lock.ban(peer_id).await;
+                lock.penalize(peer_id, Misbehavior::InvalidFilterHeaders)
+                    .await;
                 Some(MainThreadMessage::Disconnect)
             }
         }
@@ -593,12 +1560,20 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
     // Handle a new compact block filter
     async fn handle_filter(&self, peer_id: PeerId, filter: CFilter) -> Option<MainThreadMessage> {
         let mut chain = self.chain.lock().await;
+        if chain.dispute_filter(peer_id, &filter) {
+            self.settle_dispute(chain.deref_mut()).await;
+            return None;
+        }
         match chain.sync_filter(filter) {
-            Ok(potential_message) => {
-                if potential_message.is_some() {
+            Ok(completed) => {
+                if completed {
                     chain.send_chain_update().await;
+                    chain.write_filters().await;
+                    self.request_filters(&mut chain).await;
+                } else if chain.is_filters_synced() {
+                    chain.write_filters().await;
                 }
-                potential_message.map(MainThreadMessage::GetFilters)
+                None
             }
             Err(e) => {
                 self.dialog.send_warning(Warning::UnexpectedSyncError {
@@ -608,7 +1583,10 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
                     CFilterSyncError::Filter(_) => Some(MainThreadMessage::Disconnect),
                     _ => {
                         let mut lock = self.peer_map.lock().await;
-                        lock.ban(peer_id).await;
+                        lock.penalize(peer_id, Misbehavior::InvalidFilter).await;
+                        drop(lock);
+                        // A range the peer failed to answer is requested again right away
+                        self.request_filters_excluding(&mut chain, &[peer_id]).await;
                         Some(MainThreadMessage::Disconnect)
                     }
                 }
@@ -619,35 +1597,112 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
     // Scan a block for transactions.
     async fn handle_block(&self, peer_id: PeerId, block: Block) -> Option<MainThreadMessage> {
         let mut chain = self.chain.lock().await;
-        if let Err(e) = chain.check_send_block(block) {
+        let confirmed = self.confirmed_broadcasts(&chain, &block).await;
+        let scanned = match chain.dispute_block(&block) {
+            Ok(true) => {
+                self.settle_dispute(chain.deref_mut()).await;
+                return None;
+            }
+            Ok(false) => chain.check_send_block(block),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = scanned {
             self.dialog.send_warning(Warning::UnexpectedSyncError {
                 warning: format!("Unexpected block scanning error: {e}"),
             });
             let mut lock = self.peer_map.lock().await;
-            lock.ban(peer_id).await;
+            lock.penalize(peer_id, Misbehavior::InvalidBlock).await;
             return Some(MainThreadMessage::Disconnect);
         }
+        if let Some(confirmed) = confirmed {
+            self.confirm_broadcasts(confirmed).await;
+        }
+        chain.write_scan_results().await;
         None
     }
 
+    // Penalize the peers that did not send their filter for a dispute in time, and request the
+    // filter headers again
+    async fn expire_dispute(&self) {
+        let mut chain = self.chain.lock().await;
+        let unanswered = match chain.expire_dispute() {
+            Some(unanswered) => unanswered,
+            None => return,
+        };
+        self.dialog.send_warning(Warning::UnexpectedSyncError {
+            warning: "Peers did not answer in time to find the peer sending invalid filter headers"
+                .into(),
+        });
+        let mut peer_map = self.peer_map.lock().await;
+        for peer in unanswered {
+            peer_map.penalize(peer, Misbehavior::Unresponsive).await;
+            peer_map
+                .send_message(peer, MainThreadMessage::Disconnect)
+                .await;
+        }
+        drop(peer_map);
+        chain.clear_compact_filter_queue();
+        if let Some(message) = self.next_stateful_message(chain.deref_mut()).await {
+            self.broadcast(message).await;
+        }
+    }
+
+    // Ban the peers found to lie about their filter headers, and request the filter headers again
+    // from the peers that remain
+    async fn settle_dispute(&self, chain: &mut Chain<H>) {
+        let liars = match chain.resolve_dispute() {
+            Some(liars) => liars,
+            None => return,
+        };
+        let mut peer_map = self.peer_map.lock().await;
+        if liars.is_empty() {
+            // The filters may both be valid as far as the block shows, so new peers are tried
+            self.dialog.send_warning(Warning::UnexpectedSyncError {
+                warning: "Could not find the peer sending invalid filter headers".into(),
+            });
+            peer_map.broadcast(MainThreadMessage::Disconnect).await;
+            return;
+        }
+        for peer in liars {
+            crate::log!(
+                self.dialog,
+                Subsystem::Filters,
+                format!("Peer {peer} sent filter headers that do not match its filters")
+            );
+            peer_map
+                .penalize(peer, Misbehavior::InvalidFilterHeaders)
+                .await;
+            peer_map
+                .send_message(peer, MainThreadMessage::Disconnect)
+                .await;
+        }
+        drop(peer_map);
+        chain.clear_compact_filter_queue();
+        if let Some(message) = self.next_stateful_message(chain).await {
+            self.broadcast(message).await;
+        }
+    }
+
     // The block queue holds all the block hashes we may be interested in
-    async fn pop_block_queue(&self) -> Option<MainThreadMessage> {
+    async fn pop_block_queue(&self) -> Option<BlockHash> {
         let state = self.state.read().await;
-        if matches!(
+        let mut chain = self.chain.lock().await;
+        let stream_ready = chain.block_stream_ready();
+        let syncing_blocks = matches!(
             *state,
             NodeState::FilterHeadersSynced | NodeState::FiltersSynced
-        ) {
-            let mut chain = self.chain.lock().await;
+        ) && stream_ready;
+        // Blocks the client is waiting on are fetched regardless of the sync progress
+        if syncing_blocks || chain.block_requested() {
             let next_block_hash = chain.next_block();
-            return match next_block_hash {
-                Some(block_hash) => {
-                    crate::log!(self.dialog, format!("Next block in queue: {}", block_hash));
-                    Some(MainThreadMessage::GetBlock(GetBlockConfig {
-                        locator: block_hash,
-                    }))
-                }
-                None => None,
-            };
+            if let Some(block_hash) = next_block_hash {
+                crate::info!(
+                    self.dialog,
+                    Subsystem::Chain,
+                    Info::BlockRequested(block_hash)
+                );
+            }
+            return next_block_hash;
         }
         None
     }
@@ -664,24 +1719,55 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         for block in blocks.iter() {
             peer_map.increment_height(nonce).await;
             if !chain.header_chain.contains(*block) {
-                crate::log!(self.dialog, format!("New block: {}", block));
+                crate::info!(self.dialog, Subsystem::Chain, Info::BlockAnnounced(*block));
             }
         }
         match *state {
             NodeState::Behind => None,
             _ => {
                 if blocks
-                    .into_iter()
-                    .any(|block| !chain.header_chain.contains(block))
+                    .iter()
+                    .any(|block| !chain.header_chain.contains(*block))
                 {
-                    crate::info!(self.dialog, Info::StateChange(NodeState::Behind));
-                    *state = NodeState::Behind;
+                    let at_tip = matches!(*state, NodeState::TransactionsSynced);
+                    self.transition(&mut state, NodeState::Behind).await;
                     let next_headers = GetHeaderConfig {
                         locators: chain.header_chain.locators(),
                         stop_hash: None,
                     };
                     chain.clear_compact_filter_queue();
-                    Some(MainThreadMessage::GetHeaders(next_headers))
+                    // A single new block at the tip may be fetched with its filter in one pipeline.
+                    // The peer responds in order, so the header is known before the filter header.
+                    let tip_messages = match blocks.as_slice() {
+                        [block] if at_tip && !self.headers_only => {
+                            chain.tip_filter_messages(*block)
+                        }
+                        _ => None,
+                    };
+                    let (get_filter_headers, get_filters) = match tip_messages {
+                        Some(messages) => messages,
+                        None => return Some(MainThreadMessage::GetHeaders(next_headers)),
+                    };
+                    peer_map
+                        .broadcast(MainThreadMessage::GetHeaders(next_headers))
+                        .await;
+                    crate::info!(
+                        self.dialog,
+                        Subsystem::Filters,
+                        Info::FilterHeadersRequested {
+                            start_height: get_filter_headers.start_height,
+                            stop_hash: get_filter_headers.stop_hash,
+                        }
+                    );
+                    peer_map
+                        .send_message(
+                            nonce,
+                            MainThreadMessage::GetFilterHeaders(get_filter_headers),
+                        )
+                        .await;
+                    let get_filters = self.filter_request(get_filters).await;
+                    peer_map.send_message(nonce, get_filters).await;
+                    None
                 } else {
                     None
                 }
@@ -689,6 +1775,35 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
         }
     }
 
+    // Request the announced transactions that were not seen yet
+    async fn handle_inventory_transactions(
+        &self,
+        inventory: Vec<Inventory>,
+    ) -> Option<MainThreadMessage> {
+        let mempool = self.mempool.as_ref()?;
+        let requests = mempool.lock().await.announced(inventory);
+        if requests.is_empty() {
+            return None;
+        }
+        Some(MainThreadMessage::GetTransactions(requests))
+    }
+
+    // Report a requested transaction if it pays to or spends from our scripts
+    async fn handle_transaction(&self, transaction: Transaction) {
+        let mempool = match self.mempool.as_ref() {
+            Some(mempool) => mempool,
+            None => return,
+        };
+        if !mempool.lock().await.received(&transaction) {
+            return;
+        }
+        let chain = self.chain.lock().await;
+        if chain.is_relevant(&transaction) {
+            self.dialog
+                .send_event(Event::MempoolTransaction(transaction));
+        }
+    }
+
     // Add more scripts to the chain to look for. Does not imply a rescan.
     async fn add_script(&self, script: ScriptBuf) {
         let mut chain = self.chain.lock().await;
@@ -696,31 +1811,84 @@ impl<H: HeaderStore, P: PeerStore> Node<H, P> {
     }
 
     // Clear the filter hash cache and redownload the filters.
-    async fn rescan(&self) -> Option<MainThreadMessage> {
+    async fn rescan(&self) {
+        if self.headers_only {
+            return;
+        }
         let mut state = self.state.write().await;
         let mut chain = self.chain.lock().await;
         match *state {
-            NodeState::Behind => None,
-            NodeState::HeadersSynced => None,
+            NodeState::Behind => (),
+            NodeState::HeadersSynced => (),
             _ => {
                 chain.clear_filters();
-                crate::info!(
-                    self.dialog,
-                    Info::StateChange(NodeState::FilterHeadersSynced)
-                );
-                *state = NodeState::FilterHeadersSynced;
-                Some(MainThreadMessage::GetFilters(chain.next_filter_message()))
+                self.transition(&mut state, NodeState::FilterHeadersSynced)
+                    .await;
+                self.request_filters(&mut chain).await;
             }
         }
     }
 
+    // Stop checking filters and downloading blocks for a rescan, and go back to following the tip
+    async fn cancel_rescan(&self) {
+        let mut state = self.state.write().await;
+        let mut chain = self.chain.lock().await;
+        if chain.cancel_rescan() {
+            crate::info!(self.dialog, Subsystem::Filters, Info::RescanCancelled);
+            if matches!(*state, NodeState::FilterHeadersSynced) && chain.is_filters_synced() {
+                self.transition(&mut state, NodeState::FiltersSynced).await;
+            }
+        }
+    }
+
+    // Disconnect from peers and flush the header store, reporting what was persisted
+    async fn shutdown(&self) -> ShutdownReport {
+        self.peer_map.lock().await.disconnect_all().await;
+        let mut chain = self.chain.lock().await;
+        let tip = chain.flush().await;
+        let report = ShutdownReport {
+            tip,
+            filters_checked_to: chain.filters_checked_to(),
+            blocks_dropped: chain.blocks_pending(),
+            transactions_dropped: self.tx_broadcaster.lock().await.len(),
+            events_dropped: self.dialog.take_events_dropped(),
+        };
+        chain.clear_scripts();
+        report
+    }
+
     // When the application starts, fetch any headers we know about from the database.
     async fn fetch_headers(&self) -> Result<(), NodeError<H::Error, P::Error>> {
-        crate::log!(self.dialog, "Attempting to load headers from the database");
+        crate::log!(
+            self.dialog,
+            Subsystem::Database,
+            "Attempting to load headers from the database"
+        );
         let mut chain = self.chain.lock().await;
         chain
             .load_headers()
             .await
-            .map_err(NodeError::HeaderDatabase)
+            .map_err(NodeError::HeaderDatabase)?;
+        chain.load_cf_headers().await;
+        Ok(())
+    }
+}
+
+// The next block fetched from the block source, waiting forever if there is no source
+#[cfg(feature = "rest")]
+async fn next_fetched_block(
+    fetched_blocks: &mut Option<UnboundedReceiver<FetchedBlock>>,
+) -> FetchedBlock {
+    match fetched_blocks {
+        Some(receiver) => match receiver.recv().await {
+            Some(fetched) => fetched,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
     }
 }
+
+#[cfg(not(feature = "rest"))]
+async fn next_fetched_block(_: &mut ()) -> Infallible {
+    std::future::pending().await
+}
@@ -0,0 +1,103 @@
+use std::fmt::Debug;
+
+use bitcoin::key::rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    db::{PeerStatus, PersistedPeer},
+    PeerInfo,
+};
+
+/// Decide which peers the node connects to and which connected peer is sent a request.
+///
+/// When the node needs a new connection, it first asks for the [`PeerStatus`] of the peer it
+/// should look for. Peers are then drawn from the [`PeerStore`](crate::PeerStore) until one is
+/// accepted, or until a limited number of draws is exhausted. Blocks and transactions that are
+/// sent to a single peer are sent to the connected peer selected by [`PeerSelector::select`].
+///
+/// The default [`RandomPeerSelector`] makes each of these choices uniformly at random.
+pub trait PeerSelector: Debug + Send + Sync {
+    /// The status of the next peer to connect to, either a peer the node connected to before,
+    /// or a peer that was gossiped and never tried. Banned peers are never drawn.
+    fn preferred_status(&mut self) -> PeerStatus;
+
+    /// Accept a peer drawn from the peer store as the next connection. Peers that do not match
+    /// the preferred status, share a netgroup with a connected peer, or do not serve compact block
+    /// filters are rejected before this method is called.
+    fn accept(&mut self, _peer: &PersistedPeer) -> bool {
+        true
+    }
+
+    /// Select the connected peer to send a request to, as an index into `peers`. This is called
+    /// with at least one peer, and an index out of bounds drops the request.
+    fn select(&mut self, peers: &[PeerInfo]) -> usize;
+}
+
+/// Select peers uniformly at random.
+#[derive(Debug)]
+pub struct RandomPeerSelector {
+    rng: StdRng,
+}
+
+impl RandomPeerSelector {
+    /// Select peers with a random number generator seeded from the operating system.
+    pub fn new() -> Self {
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Select peers with a random number generator seeded from `seed`. The same seed and
+    /// sequence of calls always results in the same selections, which may be used in tests.
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Default for RandomPeerSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PeerSelector for RandomPeerSelector {
+    fn preferred_status(&mut self) -> PeerStatus {
+        self.rng.gen()
+    }
+
+    fn select(&mut self, peers: &[PeerInfo]) -> usize {
+        self.rng.gen_range(0..peers.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bitcoin::p2p::{address::AddrV2, ServiceFlags};
+
+    use super::*;
+
+    #[test]
+    fn test_seeded_selection_is_deterministic() {
+        let peers: Vec<PeerInfo> = (0..8)
+            .map(|i| PeerInfo {
+                addr: AddrV2::Ipv4(Ipv4Addr::new(10, 0, 0, i)),
+                port: 8333,
+                version: 70016,
+                services: ServiceFlags::COMPACT_FILTERS,
+                user_agent: "/Satoshi:28.0.0/".into(),
+                start_height: 0,
+            })
+            .collect();
+        let mut first = RandomPeerSelector::from_seed(21);
+        let mut second = RandomPeerSelector::from_seed(21);
+        for _ in 0..16 {
+            assert_eq!(first.preferred_status(), second.preferred_status());
+            let selected = first.select(&peers);
+            assert!(selected < peers.len());
+            assert_eq!(selected, second.select(&peers));
+        }
+    }
+}
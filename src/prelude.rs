@@ -1,4 +1,8 @@
-use core::{future::Future, pin::Pin};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
 
 use bitcoin::{hex::DisplayHex, p2p::address::AddrV2, Network, Work};
 
@@ -9,6 +13,27 @@ pub const MEDIAN_TIME_PAST: usize = 11;
 
 pub(crate) type FutureResult<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
 
+// Poll a future a single time, where there is no runtime to await it, such as in `Drop`. Futures
+// that are not ready on the first poll are dropped.
+pub(crate) fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    // Safety: every function of the vtable ignores the data pointer
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    match future.as_mut().poll(&mut context) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
 #[macro_export]
 /// Implement `std::error::Error` for an error with no sources.
 macro_rules! impl_sourceless_error {
@@ -84,6 +109,22 @@ impl Netgroup for AddrV2 {
     }
 }
 
+// The software named by a user agent, which is the last entry without its version and comments,
+// so forks of Bitcoin Core such as `/Satoshi:27.1.0/Knots:20240801/` are named by the fork
+pub(crate) fn user_agent_family(user_agent: &str) -> String {
+    let family = user_agent
+        .split('/')
+        .rfind(|entry| !entry.is_empty())
+        .and_then(|entry| entry.split([':', '(']).next())
+        .map(str::trim)
+        .unwrap_or_default();
+    if family.is_empty() {
+        "UNKNOWN".to_owned()
+    } else {
+        family.to_owned()
+    }
+}
+
 pub(crate) fn default_port_from_network(network: &Network) -> u16 {
     match network {
         Network::Bitcoin => 8333,
@@ -107,6 +148,50 @@ pub(crate) fn encode_qname(domain: &str, filter: &str) -> Vec<u8> {
     qname
 }
 
+// Yields to the runtime after a number of iterations of a long loop, so a large batch of work
+// does not starve peer connections and other tasks, particularly on a single threaded runtime
+pub(crate) struct YieldBudget {
+    interval: u32,
+    remaining: u32,
+}
+
+impl YieldBudget {
+    pub(crate) fn new(interval: u32) -> Self {
+        let interval = interval.max(1);
+        Self {
+            interval,
+            remaining: interval,
+        }
+    }
+
+    // Count an iteration, yielding if the budget is spent
+    pub(crate) async fn tick(&mut self) {
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.remaining = self.interval;
+            YieldNow { yielded: false }.await;
+        }
+    }
+}
+
+// Return to the executor once, so other tasks may run, without depending on a particular runtime
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
 pub(crate) trait ZerolikeExt {
     fn zero() -> Self;
 }
@@ -119,7 +204,49 @@ impl ZerolikeExt for Work {
 
 #[cfg(test)]
 mod tests {
-    use super::Median;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::{poll_once, user_agent_family, Median, YieldBudget};
+
+    #[test]
+    fn test_user_agent_family() {
+        assert_eq!(user_agent_family("/Satoshi:28.0.0/"), "Satoshi");
+        assert_eq!(
+            user_agent_family("/Satoshi:27.1.0/Knots:20240801/"),
+            "Knots"
+        );
+        assert_eq!(user_agent_family("/btcwire:0.5.0/btcd:0.24.0/"), "btcd");
+        assert_eq!(user_agent_family("/Satoshi:25.0.0(bitcore)/"), "Satoshi");
+        assert_eq!(user_agent_family("/"), "UNKNOWN");
+        assert_eq!(user_agent_family(""), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_poll_once() {
+        assert_eq!(poll_once(async { 42 }), Some(42));
+        assert_eq!(poll_once(std::future::pending::<u8>()), None);
+        let lock = tokio::sync::Mutex::new(1);
+        assert_eq!(poll_once(async { *lock.lock().await }), Some(1));
+        let _guard = lock.try_lock().unwrap();
+        assert_eq!(poll_once(async { *lock.lock().await }), None);
+    }
+
+    #[tokio::test]
+    async fn test_budget_yields() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let task_ran = Arc::clone(&ran);
+        tokio::spawn(async move { task_ran.store(true, Ordering::SeqCst) });
+        let mut budget = YieldBudget::new(4);
+        for _ in 0..3 {
+            budget.tick().await;
+        }
+        assert!(!ran.load(Ordering::SeqCst));
+        budget.tick().await;
+        assert!(ran.load(Ordering::SeqCst));
+    }
 
     #[test]
     fn test_median() {
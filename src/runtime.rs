@@ -0,0 +1,22 @@
+// The tasks the chain, the dialog and the event journal hand to the async runtime. These modules
+// only use the tokio channels and locks, which work on any executor, so the runtime itself is only
+// named here and by the node that drives them.
+
+// Run a future to completion on a task of its own
+#[cfg(feature = "journal")]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(future);
+}
+
+// Run blocking work on a thread where blocking is allowed. Resolves to `None` if the work
+// panicked or the runtime was shut down first.
+pub(crate) async fn spawn_blocking<F, R>(work: F) -> Option<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.ok()
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use crate::{chain::IndexedHeader, Event, HeaderCheckpoint, IndexedBlock, SyncUpdate};
+
+/// A reorganization of the chain to send to the client as if the node had found it, so an
+/// application may test how it handles reorganizations without mining competing chains.
+///
+/// The node sends the same events, in the same order, as for a reorganization found over the
+/// network: [`Event::BlocksDisconnected`] with the disconnected headers in ascending height,
+/// [`Event::Block`] for each block of the new chain that matched, in ascending height, then
+/// [`Event::Synced`] at the highest connected header. The chain of the node is not changed.
+///
+/// Sent with [`Requester::simulate_reorg`](crate::Requester::simulate_reorg).
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedReorg {
+    disconnected: Vec<IndexedHeader>,
+    connected: Vec<IndexedHeader>,
+    blocks: Vec<IndexedBlock>,
+}
+
+impl SimulatedReorg {
+    /// Replace the `disconnected` headers with the `connected` headers, in any order.
+    pub fn new(disconnected: Vec<IndexedHeader>, connected: Vec<IndexedHeader>) -> Self {
+        Self {
+            disconnected,
+            connected,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// A block of the new chain that matched the scripts of the node.
+    pub fn matched_block(mut self, block: IndexedBlock) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    pub(crate) fn into_events(self) -> Vec<Event> {
+        let SimulatedReorg {
+            mut disconnected,
+            connected,
+            mut blocks,
+        } = self;
+        disconnected.sort_by_key(|indexed| indexed.height);
+        blocks.sort_by_key(|indexed| indexed.height);
+        let mut events = vec![Event::BlocksDisconnected(disconnected)];
+        events.extend(blocks.into_iter().map(Event::Block));
+        let recent_history = connected
+            .iter()
+            .map(|indexed| (indexed.height, indexed.header))
+            .collect::<BTreeMap<_, _>>();
+        if let Some((height, header)) = recent_history.iter().next_back() {
+            let tip = HeaderCheckpoint::new(*height, header.block_hash());
+            let recent_history = recent_history
+                .range(height.saturating_sub(9)..)
+                .map(|(height, header)| (*height, *header))
+                .collect();
+            events.push(Event::Synced(SyncUpdate::new(tip, recent_history)));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{constants::genesis_block, Network};
+
+    use super::*;
+
+    #[test]
+    fn test_simulated_reorg_events_are_ordered() {
+        let block = genesis_block(Network::Regtest);
+        let header = |height: u32, nonce: u32| {
+            let mut header = block.header;
+            header.nonce = nonce;
+            IndexedHeader { height, header }
+        };
+        let connected = (5..=20).rev().map(|height| header(height, 1)).collect();
+        let reorg = SimulatedReorg::new(vec![header(6, 0), header(5, 0)], connected)
+            .matched_block(IndexedBlock::new(8, block.clone()))
+            .matched_block(IndexedBlock::new(7, block.clone()));
+        let events = reorg.into_events();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], Event::BlocksDisconnected(headers)
+            if headers.iter().map(|indexed| indexed.height).eq([5, 6])));
+        assert!(matches!(&events[1], Event::Block(indexed) if indexed.height == 7));
+        assert!(matches!(&events[2], Event::Block(indexed) if indexed.height == 8));
+        match &events[3] {
+            Event::Synced(update) => {
+                assert_eq!(update.tip().height, 20);
+                assert_eq!(update.tip().hash, header(20, 1).header.block_hash());
+                assert_eq!(update.recent_history().keys().copied().min(), Some(11));
+                assert_eq!(update.recent_history().len(), 10);
+            }
+            _ => panic!("expected the node to be synced"),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(not(feature = "filter-control"))]
+use std::sync::atomic::{compiler_fence, Ordering};
+
+#[cfg(not(feature = "filter-control"))]
+use bitcoin::bip32::{ChainCode, Xpub};
+use bitcoin::ScriptBuf;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+// Overwrite the buffers that held wallet data with zeros before they are freed, including any
+// spare capacity. Only the buffer that is freed is cleared: copies left behind when a script was
+// cloned, or when a collection holding it grew and moved its contents, are not.
+#[cfg(feature = "zeroize")]
+pub(crate) fn zeroize_script(script: ScriptBuf) {
+    script.into_bytes().zeroize();
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(crate) fn zeroize_script(script: ScriptBuf) {
+    drop(script);
+}
+
+pub(crate) fn zeroize_scripts(scripts: impl IntoIterator<Item = ScriptBuf>) {
+    for script in scripts {
+        zeroize_script(script);
+    }
+}
+
+// The chain code of an extended public key is enough to derive every script of the keychain
+#[cfg(not(feature = "filter-control"))]
+pub(crate) fn zeroize_chain_code(xpub: &mut Xpub) {
+    // Safety: the pointer comes from a mutable reference, so it is valid and aligned
+    unsafe { std::ptr::write_volatile(&mut xpub.chain_code, ChainCode::from([0; 32])) };
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(all(test, feature = "zeroize"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spare_capacity_is_zeroized() {
+        let mut bytes = Vec::with_capacity(8);
+        bytes.extend([0x51, 0x20, 0xff]);
+        let mut script = ScriptBuf::from_bytes(bytes);
+        script.reserve(16);
+        let mut bytes = script.into_bytes();
+        bytes.zeroize();
+        assert!(bytes.is_empty());
+        // Safety: the capacity was allocated and zeroize wrote every byte of it
+        let spare = unsafe { std::slice::from_raw_parts(bytes.as_ptr(), bytes.capacity()) };
+        assert!(spare.iter().all(|byte| *byte == 0));
+    }
+}
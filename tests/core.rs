@@ -11,7 +11,7 @@ use corepc_node::serde_json;
 use corepc_node::{anyhow, exe_path};
 use kyoto::{
     chain::checkpoints::HeaderCheckpoint, client::Client, node::Node, BlockHash, Event, LogLevel,
-    ServiceFlags, SqliteHeaderDb, SqlitePeerDb, TrustedPeer, Warning,
+    NodeState, ServiceFlags, SqliteHeaderDb, SqlitePeerDb, TrustedPeer, Warning,
 };
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -156,13 +156,12 @@ async fn live_reorg() {
             }
             kyoto::messages::Event::Synced(update) => {
                 assert_eq!(update.tip().hash, best);
-                requester.shutdown().unwrap();
                 break;
             }
             _ => {}
         }
     }
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 
@@ -216,7 +215,7 @@ async fn live_reorg_additional_sync() {
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     sync_assert(&best, &mut channel).await;
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 
@@ -247,10 +246,59 @@ async fn various_client_methods() {
     assert!(batch.is_empty());
     let _ = requester.broadcast_min_feerate().await.unwrap();
     let _ = requester.get_header(3).await.unwrap();
+    let history = requester.state_history().await.unwrap();
+    assert!(matches!(history.first().unwrap().state, NodeState::Behind));
+    assert!(matches!(
+        history.last().unwrap().state,
+        NodeState::TransactionsSynced
+    ));
+    let report = requester.sync_report().await.unwrap();
+    assert!(report.headers.bytes > 0);
+    assert!(report.filter_headers.bytes > 0);
+    assert!(report.filters.bytes > 0);
     let script = rpc.new_address().unwrap();
     requester.add_script(script).unwrap();
     assert!(requester.is_running());
-    requester.shutdown().unwrap();
+    let report = requester.shutdown().await.unwrap();
+    assert_eq!(report.tip.unwrap().hash, best);
+    assert_eq!(report.filters_checked_to.unwrap().hash, best);
+    assert_eq!(report.blocks_dropped, 0);
+    rpc.stop().unwrap();
+}
+
+#[tokio::test]
+async fn headers_only_sync() {
+    let (bitcoind, socket_addr) = start_bitcoind(true).unwrap();
+    let rpc = &bitcoind.client;
+    let tempdir = tempfile::TempDir::new().unwrap().path().to_owned();
+    let miner = rpc.new_address().unwrap();
+    mine_blocks(rpc, &miner, 10, 1).await;
+    let best = best_hash(rpc);
+    let host = (IpAddr::V4(*socket_addr.ip()), Some(socket_addr.port()));
+    let (node, client) = kyoto::builder::NodeBuilder::new(bitcoin::Network::Regtest)
+        .add_peer(host)
+        .data_dir(tempdir)
+        .headers_only()
+        .build()
+        .unwrap();
+    tokio::task::spawn(async move { node.run().await });
+    let Client {
+        requester,
+        log_rx,
+        info_rx: _,
+        warn_rx,
+        event_rx: mut channel,
+    } = client;
+    tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
+    sync_assert(&best, &mut channel).await;
+    mine_blocks(rpc, &miner, 2, 1).await;
+    let best = best_hash(rpc);
+    sync_assert(&best, &mut channel).await;
+    let report = requester.sync_report().await.unwrap();
+    assert!(report.headers.bytes > 0);
+    assert_eq!(report.filter_headers.bytes, 0);
+    assert_eq!(report.filters.bytes, 0);
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 
@@ -267,7 +315,7 @@ async fn stop_reorg_resync() {
     let other = rpc.new_address().unwrap();
     scripts.insert(other.into());
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir.clone(), None);
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -279,7 +327,7 @@ async fn stop_reorg_resync() {
     sync_assert(&best, &mut channel).await;
     let batch = requester.get_header_range(0..10).await.unwrap();
     assert!(!batch.is_empty());
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Reorganize the blocks
     let old_best = best;
     let old_height = num_blocks(rpc);
@@ -287,8 +335,9 @@ async fn stop_reorg_resync() {
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     // Spin up the node on a cold start
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir.clone(), None);
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -313,12 +362,13 @@ async fn stop_reorg_resync() {
             _ => {}
         }
     }
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     drop(handle);
     // Mine more blocks
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     // Make sure the node does not have any corrupted headers
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir, None);
     tokio::task::spawn(async move { node.run().await });
     let Client {
@@ -331,7 +381,7 @@ async fn stop_reorg_resync() {
     tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
     // The node properly syncs after persisting a reorg
     sync_assert(&best, &mut channel).await;
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 
@@ -348,7 +398,7 @@ async fn stop_reorg_two_resync() {
     let other = rpc.new_address().unwrap();
     scripts.insert(other.into());
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir.clone(), None);
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -358,7 +408,7 @@ async fn stop_reorg_two_resync() {
     } = client;
     let handle = tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
     sync_assert(&best, &mut channel).await;
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Reorganize the blocks
     let old_height = num_blocks(rpc);
     let old_best = best;
@@ -369,8 +419,9 @@ async fn stop_reorg_two_resync() {
     let best = best_hash(rpc);
     drop(handle);
     // Make sure the reorganization is caught after a cold start
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir.clone(), None);
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -395,11 +446,12 @@ async fn stop_reorg_two_resync() {
         }
     }
     drop(handle);
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Mine more blocks
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     // Make sure the node does not have any corrupted headers
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir, None);
     tokio::task::spawn(async move { node.run().await });
     let Client {
@@ -412,7 +464,7 @@ async fn stop_reorg_two_resync() {
     tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
     // The node properly syncs after persisting a reorg
     sync_assert(&best, &mut channel).await;
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 
@@ -428,7 +480,7 @@ async fn stop_reorg_start_on_orphan() {
     let other = rpc.new_address().unwrap();
     scripts.insert(other.into());
     let (node, client) = new_node(scripts.clone(), socket_addr, tempdir.clone(), None);
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -439,7 +491,7 @@ async fn stop_reorg_start_on_orphan() {
     let handle = tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
     sync_assert(&best, &mut channel).await;
     drop(handle);
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Reorganize the blocks
     let old_best = best;
     let old_height = num_blocks(rpc);
@@ -447,13 +499,14 @@ async fn stop_reorg_start_on_orphan() {
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     // Spin up the node on a cold start with a stale tip
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(
         scripts.clone(),
         socket_addr,
         tempdir.clone(),
         Some(HeaderCheckpoint::new(old_height as u32, old_best)),
     );
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -479,19 +532,20 @@ async fn stop_reorg_start_on_orphan() {
         }
     }
     drop(handle);
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Don't do anything, but reload the node from the checkpoint
     let cp = best_hash(rpc);
     let old_height = num_blocks(rpc);
     let best = best_hash(rpc);
     // Make sure the node does not have any corrupted headers
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(
         scripts.clone(),
         socket_addr,
         tempdir.clone(),
         Some(HeaderCheckpoint::new(old_height as u32, cp)),
     );
-    tokio::task::spawn(async move { node.run().await });
+    let node_handle = tokio::task::spawn(async move { node.run().await });
     let Client {
         requester,
         log_rx,
@@ -503,13 +557,14 @@ async fn stop_reorg_start_on_orphan() {
     // The node properly syncs after persisting a reorg
     sync_assert(&best, &mut channel).await;
     drop(handle);
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     // Mine more blocks and reload from the checkpoint
     let cp = best_hash(rpc);
     let old_height = num_blocks(rpc);
     mine_blocks(rpc, &miner, 2, 1).await;
     let best = best_hash(rpc);
     // Make sure the node does not have any corrupted headers
+    node_handle.await.unwrap().unwrap();
     let (node, client) = new_node(
         scripts.clone(),
         socket_addr,
@@ -527,7 +582,7 @@ async fn stop_reorg_start_on_orphan() {
     tokio::task::spawn(async move { print_logs(log_rx, warn_rx).await });
     // The node properly syncs after persisting a reorg
     sync_assert(&best, &mut channel).await;
-    requester.shutdown().unwrap();
+    requester.shutdown().await.unwrap();
     rpc.stop().unwrap();
 }
 